@@ -0,0 +1,58 @@
+//! Compares single threaded and parallel scanning by timing one-shot runs of
+//! the binary over a generated tree. Run with `cargo bench`.
+
+use std::path::Path;
+use std::process::Command;
+use std::time::{Duration, Instant};
+
+const DIRS: usize = 200;
+const FILES_PER_DIR: usize = 250;
+const RUNS: usize = 3;
+
+fn generate(root: &Path) {
+    for dir in 0..DIRS {
+        let dir = root.join(format!("dir-{dir}")).join("nested");
+        std::fs::create_dir_all(&dir).unwrap();
+
+        for file in 0..FILES_PER_DIR {
+            std::fs::write(dir.join(format!("file-{file}")), b"data").unwrap();
+        }
+    }
+}
+
+fn run(root: &Path, threads: usize) -> Duration {
+    (0..RUNS)
+        .map(|_| {
+            let started = Instant::now();
+            let status = Command::new(env!("CARGO_BIN_EXE_maxdirsize"))
+                .env_clear()
+                .arg("--once")
+                .args(["--directory", &root.display().to_string()])
+                .args(["--max-size-mb", "1024"])
+                .args(["--interval", "0"])
+                .args(["--scan-threads", &threads.to_string()])
+                .output()
+                .unwrap()
+                .status;
+            assert!(status.success());
+            started.elapsed()
+        })
+        .min()
+        .unwrap()
+}
+
+fn main() {
+    let root = std::env::temp_dir().join(format!("maxdirsize-bench-{}", std::process::id()));
+    generate(&root);
+
+    let sequential = run(&root, 1);
+    let parallel = run(&root, 0);
+
+    println!(
+        "scan of {} files: 1 thread {sequential:?}, all CPUs {parallel:?} ({:.2}x)",
+        DIRS * FILES_PER_DIR,
+        sequential.as_secs_f64() / parallel.as_secs_f64()
+    );
+
+    std::fs::remove_dir_all(&root).unwrap();
+}
@@ -0,0 +1,125 @@
+//! Adapting the time between cycles to how close the directory is to its
+//! limit, for `MIN_INTERVAL` and `MAX_INTERVAL`: cycles come quicker as the
+//! directory fills up, and further apart while it sits idle well below it.
+
+use crate::config::WatchConfig;
+use crate::{CycleOutcome, CycleReport};
+
+/// Utilization up to which cycles run every `interval_seconds`.
+pub const QUIET_PERCENT: f64 = 70.0;
+/// Utilization from which cycles run every `min_interval_seconds`.
+pub const BUSY_PERCENT: f64 = 90.0;
+
+/// The interval of a directory, as its latest cycles left it.
+#[derive(Debug, Clone)]
+pub struct AdaptiveInterval {
+    min: u64,
+    base: u64,
+    max: u64,
+    /// The interval after the latest cycle
+    current: u64,
+    /// Size after the latest cycle, to tell a quiet directory
+    last_size: Option<u64>,
+}
+
+impl AdaptiveInterval {
+    /// `None` unless `watch` sets `min_interval_seconds` or
+    /// `max_interval_seconds`.
+    pub fn new(watch: &WatchConfig) -> Option<AdaptiveInterval> {
+        if watch.min_interval_seconds.is_none() && watch.max_interval_seconds.is_none() {
+            return None;
+        }
+
+        let base = watch.interval_seconds;
+
+        Some(AdaptiveInterval {
+            min: watch.min_interval_seconds.unwrap_or(base),
+            base,
+            max: watch.max_interval_seconds.unwrap_or(base),
+            current: base,
+            last_size: None,
+        })
+    }
+
+    /// Seconds until the cycle after `report`: from `interval_seconds` below
+    /// [`QUIET_PERCENT`] of the limit down to `min_interval_seconds` at
+    /// [`BUSY_PERCENT`], and doubling up to `max_interval_seconds` with every
+    /// cycle the directory stays quiet and did not grow.
+    pub fn next(&mut self, report: &CycleReport) -> u64 {
+        if report.outcome == CycleOutcome::Failed {
+            self.current = self.base;
+            return self.current;
+        }
+
+        let percent = match report.limit_bytes {
+            0 => 0.0,
+            limit => report.size_after as f64 * 100.0 / limit as f64,
+        };
+        let grew = self
+            .last_size
+            .is_none_or(|last_size| report.size_after > last_size);
+        self.last_size = Some(report.size_after);
+
+        self.current = if percent <= QUIET_PERCENT && !grew {
+            self.current.max(self.base).saturating_mul(2).min(self.max)
+        } else if percent <= QUIET_PERCENT {
+            self.base
+        } else {
+            let busy = ((percent - QUIET_PERCENT) / (BUSY_PERCENT - QUIET_PERCENT)).min(1.0);
+            self.base - ((self.base - self.min) as f64 * busy).round() as u64
+        };
+
+        self.current
+    }
+
+    pub fn shortest(&self) -> u64 {
+        self.min.min(self.base)
+    }
+
+    /// The longest interval, for health checks to tell a late cycle.
+    pub fn longest(&self) -> u64 {
+        self.max.max(self.base)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cycle(size_after: u64) -> CycleReport {
+        CycleReport {
+            outcome: CycleOutcome::WithinLimit,
+            size_after,
+            limit_bytes: 100,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn interval_shortens_when_full_and_lengthens_when_quiet() {
+        let watch: WatchConfig = toml::from_str(
+            "directory = \"/data\"\ninterval_seconds = \"10m\"\nmin_interval_seconds = \"1m\"\nmax_interval_seconds = \"1h\"\nmax_size_bytes = 100",
+        )
+        .unwrap();
+        let mut adaptive = AdaptiveInterval::new(&watch).unwrap();
+
+        assert_eq!(adaptive.next(&cycle(50)), 600);
+        assert_eq!(adaptive.next(&cycle(80)), 330);
+        assert_eq!(adaptive.next(&cycle(95)), 60);
+
+        // Quiet from then on, doubling up to the longest
+        assert_eq!(adaptive.next(&cycle(60)), 1200);
+        assert_eq!(adaptive.next(&cycle(60)), 2400);
+        assert_eq!(adaptive.next(&cycle(60)), 3600);
+        assert_eq!(adaptive.next(&cycle(60)), 3600);
+        assert_eq!(adaptive.next(&cycle(61)), 600);
+
+        assert_eq!((adaptive.shortest(), adaptive.longest()), (60, 3600));
+        assert!(AdaptiveInterval::new(&WatchConfig {
+            min_interval_seconds: None,
+            max_interval_seconds: None,
+            ..watch
+        })
+        .is_none());
+    }
+}
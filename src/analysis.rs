@@ -0,0 +1,281 @@
+//! The whole of a watched directory, like `du`: the size and file count of
+//! every directory below it down to a depth, and how old its files are.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use crate::scan::{ScanEntry, ScanResult};
+use crate::units;
+
+/// Upper bounds of the ages files are counted in, in seconds since they
+/// were modified.
+const AGES: [(u64, &str); 6] = [
+    (3600, "less than an hour"),
+    (86400, "less than a day"),
+    (7 * 86400, "less than a week"),
+    (30 * 86400, "less than 30 days"),
+    (365 * 86400, "less than a year"),
+    (u64::MAX, "a year or more"),
+];
+
+/// A directory and what is below it, however deep.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Directory {
+    pub path: PathBuf,
+    pub size: u64,
+    pub files: u64,
+    /// 0 for the watched directory, 1 for its immediate subdirectories
+    pub depth: usize,
+}
+
+/// The files of an age.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Age {
+    pub label: &'static str,
+    pub size: u64,
+    pub files: u64,
+}
+
+/// A watched directory as the cleaner sees it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Analysis {
+    pub directory: PathBuf,
+    pub total_size: u64,
+    pub total_files: u64,
+    /// Every directory down to the depth, each followed by its
+    /// subdirectories, biggest first and by path on a tie
+    pub directories: Vec<Directory>,
+    pub ages: Vec<Age>,
+}
+
+impl Analysis {
+    /// Sums a scan of `directory` into its subdirectories down to `depth`,
+    /// and the ages of the files at `now`, in seconds since the Unix epoch.
+    /// Files with several hard links are counted once, where they were found
+    /// first.
+    pub fn new(directory: &Path, scanned: &ScanResult, depth: usize, now: u64) -> Analysis {
+        let mut sizes: BTreeMap<PathBuf, (u64, u64)> = BTreeMap::new();
+        let mut ages: Vec<Age> = AGES
+            .iter()
+            .map(|&(_, label)| Age {
+                label,
+                size: 0,
+                files: 0,
+            })
+            .collect();
+        let mut hard_links = HashSet::new();
+
+        sizes.insert(directory.to_path_buf(), (0, 0));
+
+        for entry in &scanned.entries {
+            let file = match entry {
+                ScanEntry::Folder(folder) => {
+                    if level(directory, &folder.path).is_some_and(|level| level <= depth) {
+                        sizes.entry(folder.path.clone()).or_default();
+                    }
+                    continue;
+                }
+                ScanEntry::File(file) => file,
+            };
+
+            if let Some(link) = &file.hard_link {
+                if !hard_links.insert((link.device, link.inode)) {
+                    continue;
+                }
+            }
+
+            let age = now.saturating_sub(file.modified);
+            if let Some(i) = AGES.iter().position(|&(bound, _)| age < bound) {
+                ages[i].size += file.size;
+                ages[i].files += 1;
+            }
+
+            for ancestor in file.path.ancestors().skip(1) {
+                let Some(level) = level(directory, ancestor) else {
+                    break;
+                };
+
+                if level <= depth {
+                    let (size, files) = sizes.entry(ancestor.to_path_buf()).or_default();
+                    *size += file.size;
+                    *files += 1;
+                }
+            }
+        }
+
+        let mut children: HashMap<&Path, Vec<Directory>> = HashMap::new();
+        for (path, &(size, files)) in &sizes {
+            let (Some(parent), Some(depth)) = (path.parent(), level(directory, path)) else {
+                continue;
+            };
+
+            if depth > 0 {
+                children.entry(parent).or_default().push(Directory {
+                    path: path.clone(),
+                    size,
+                    files,
+                    depth,
+                });
+            }
+        }
+
+        for siblings in children.values_mut() {
+            siblings.sort_by(|a, b| b.size.cmp(&a.size).then_with(|| a.path.cmp(&b.path)));
+        }
+
+        let (size, files) = sizes[directory];
+        let mut directories = Vec::new();
+        let mut stack = vec![Directory {
+            path: directory.to_path_buf(),
+            size,
+            files,
+            depth: 0,
+        }];
+
+        while let Some(next) = stack.pop() {
+            if let Some(siblings) = children.get(next.path.as_path()) {
+                stack.extend(siblings.iter().rev().cloned());
+            }
+            directories.push(next);
+        }
+
+        Analysis {
+            directory: directory.to_path_buf(),
+            total_size: scanned.total_size,
+            total_files: scanned.total_files,
+            directories,
+            ages,
+        }
+    }
+}
+
+/// How deep `path` is below `directory`, `None` outside of it.
+fn level(directory: &Path, path: &Path) -> Option<usize> {
+    path.strip_prefix(directory)
+        .ok()
+        .map(|relative| relative.components().count())
+}
+
+fn megabytes(bytes: u64) -> f64 {
+    bytes as f64 / units::MIB as f64
+}
+
+impl fmt::Display for Analysis {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{}: {:.2} MB in {} files",
+            self.directory.display(),
+            megabytes(self.total_size),
+            self.total_files
+        )?;
+
+        writeln!(f, "\nDirectories:")?;
+
+        for directory in &self.directories {
+            let name = match directory.depth {
+                0 => directory.path.display().to_string(),
+                _ => directory
+                    .path
+                    .file_name()
+                    .unwrap_or(directory.path.as_os_str())
+                    .to_string_lossy()
+                    .into_owned(),
+            };
+
+            writeln!(
+                f,
+                "{:>12.2} MB {:>10} files  {}{name}",
+                megabytes(directory.size),
+                directory.files,
+                "  ".repeat(directory.depth)
+            )?;
+        }
+
+        writeln!(f, "\nModified:")?;
+
+        for age in &self.ages {
+            writeln!(
+                f,
+                "{:>12.2} MB {:>10} files  {}",
+                megabytes(age.size),
+                age.files,
+                age.label
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scan::{FileInfo, FolderInfo};
+
+    fn file(path: &str, size: u64, modified: u64) -> ScanEntry {
+        ScanEntry::File(FileInfo {
+            path: PathBuf::from(path),
+            size,
+            modified,
+            accessed: modified,
+            hard_link: None,
+            inode: 0,
+        })
+    }
+
+    fn directory(path: &str, size: u64, files: u64, depth: usize) -> Directory {
+        Directory {
+            path: PathBuf::from(path),
+            size,
+            files,
+            depth,
+        }
+    }
+
+    #[test]
+    fn sums_every_directory_down_to_the_depth() {
+        let now = 1_000 * 86400;
+        let scanned = ScanResult {
+            entries: vec![
+                file("/data/top.bin", 50, now - 60),
+                file("/data/logs/a.log", 30, now - 2 * 86400),
+                file("/data/logs/old/b.log", 40, now - 400 * 86400),
+                file("/data/logs/old/deeper/c.log", 5, now - 400 * 86400),
+                file("/data/videos/c.mp4", 60, now + 60),
+                ScanEntry::Folder(FolderInfo {
+                    path: PathBuf::from("/data/empty"),
+                    files: 0,
+                }),
+            ],
+            total_size: 185,
+            total_files: 5,
+            ..Default::default()
+        };
+
+        let analysis = Analysis::new(Path::new("/data"), &scanned, 2, now);
+
+        assert_eq!(
+            analysis.directories,
+            [
+                directory("/data", 185, 5, 0),
+                directory("/data/logs", 75, 3, 1),
+                directory("/data/logs/old", 45, 2, 2),
+                directory("/data/videos", 60, 1, 1),
+                directory("/data/empty", 0, 0, 1),
+            ]
+        );
+
+        let ages: Vec<(u64, u64)> = analysis
+            .ages
+            .iter()
+            .map(|age| (age.size, age.files))
+            .collect();
+        assert_eq!(ages, [(110, 2), (0, 0), (30, 1), (0, 0), (0, 0), (45, 2)]);
+
+        assert!(analysis
+            .to_string()
+            .contains("        0.00 MB          2 files      old\n"));
+    }
+}
@@ -0,0 +1,164 @@
+//! Moving evicted files to an archive directory instead of removing them.
+
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::APP_NAME;
+
+/// Moves `path`, a file below `directory`, to the same relative path below
+/// `archive_dir`, returning where it went. Files on another filesystem are
+/// copied, checked and only then removed.
+pub fn move_file(path: &Path, directory: &Path, archive_dir: &Path) -> io::Result<PathBuf> {
+    let target = archive_dir.join(relative(path, directory)?);
+
+    move_to(path, &target)?;
+    Ok(target)
+}
+
+/// Archives `path` as [`move_file`] does, under a name of its own when an
+/// earlier eviction of the same path is still there: the eviction time in
+/// seconds since the epoch appended, e.g. `app.log.1760465234`, and a count
+/// after it if needed. Returns where it went.
+pub fn archive(path: &Path, directory: &Path, archive_dir: &Path) -> io::Result<PathBuf> {
+    let target = archive_dir.join(relative(path, directory)?);
+
+    if fs::symlink_metadata(&target).is_err() {
+        move_to(path, &target)?;
+        return Ok(target);
+    }
+
+    let evicted = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+
+    for count in 0.. {
+        let mut name = target.as_os_str().to_owned();
+        match count {
+            0 => name.push(format!(".{evicted}")),
+            count => name.push(format!(".{evicted}-{count}")),
+        }
+        let target = PathBuf::from(name);
+
+        match move_to(path, &target) {
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+            result => return result.map(|()| target),
+        }
+    }
+
+    unreachable!("an unused name is always found")
+}
+
+/// Moves `path` to `target`, refusing to replace a file there. Files on
+/// another filesystem are copied, checked and only then removed.
+pub fn move_to(path: &Path, target: &Path) -> io::Result<()> {
+    if let Some(parent) = target.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    if fs::symlink_metadata(target).is_ok() {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("{} already exists", target.display()),
+        ));
+    }
+
+    match fs::rename(path, target) {
+        Err(e) if e.kind() == io::ErrorKind::CrossesDevices => copy_and_remove(path, target),
+        result => result,
+    }
+}
+
+fn relative<'a>(path: &'a Path, directory: &Path) -> io::Result<&'a Path> {
+    path.strip_prefix(directory).map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{} is not in {}", path.display(), directory.display()),
+        )
+    })
+}
+
+/// Copies `path` to `target` through a temporary file so an interrupted copy
+/// never looks complete, and removes `path` once the copy has its size.
+fn copy_and_remove(path: &Path, target: &Path) -> io::Result<()> {
+    let metadata = fs::metadata(path)?;
+
+    let mut partial = target.as_os_str().to_owned();
+    partial.push(format!(".{APP_NAME}-partial"));
+    let partial = PathBuf::from(partial);
+
+    let copy = || -> io::Result<()> {
+        let copied = fs::copy(path, &partial)?;
+
+        let file = File::options().write(true).open(&partial)?;
+        file.set_modified(metadata.modified()?)?;
+        file.sync_all()?;
+
+        if copied != metadata.len() || file.metadata()?.len() != metadata.len() {
+            return Err(io::Error::other(format!(
+                "copied {copied} of {} bytes to {}",
+                metadata.len(),
+                partial.display()
+            )));
+        }
+
+        fs::rename(&partial, target)
+    };
+
+    if let Err(e) = copy() {
+        let _ = fs::remove_file(&partial);
+        return Err(e);
+    }
+
+    fs::remove_file(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn moves_keep_the_relative_path() {
+        let root = std::env::temp_dir().join(format!("{APP_NAME}-archive-{}", std::process::id()));
+        let directory = root.join("watched");
+        let archive_dir = root.join("archive");
+        fs::create_dir_all(directory.join("a/b")).unwrap();
+
+        for name in ["a/b/renamed", "a/b/copied"] {
+            fs::write(directory.join(name), b"contents").unwrap();
+        }
+
+        move_file(&directory.join("a/b/renamed"), &directory, &archive_dir).unwrap();
+
+        copy_and_remove(
+            &directory.join("a/b/copied"),
+            &archive_dir.join("a/b/copied"),
+        )
+        .unwrap();
+
+        for name in ["a/b/renamed", "a/b/copied"] {
+            assert!(!directory.join(name).exists());
+            assert_eq!(fs::read(archive_dir.join(name)).unwrap(), b"contents");
+        }
+
+        // A later eviction of the same path keeps the earlier one
+        fs::write(directory.join("a/b/renamed"), b"newer").unwrap();
+        let e = move_file(&directory.join("a/b/renamed"), &directory, &archive_dir).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::AlreadyExists);
+
+        let target = archive(&directory.join("a/b/renamed"), &directory, &archive_dir).unwrap();
+        assert_ne!(target, archive_dir.join("a/b/renamed"));
+        assert!(target
+            .to_string_lossy()
+            .starts_with(&*archive_dir.join("a/b/renamed.").to_string_lossy()));
+        assert_eq!(fs::read(&target).unwrap(), b"newer");
+        assert_eq!(
+            fs::read(archive_dir.join("a/b/renamed")).unwrap(),
+            b"contents"
+        );
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,81 @@
+//! Append-only record of every file and directory removed, one JSON object
+//! per line.
+
+use serde::Serialize;
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+use std::time::SystemTime;
+
+use crate::config::DeleteMode;
+use crate::policy::Reason;
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Kind {
+    File,
+    Directory,
+}
+
+/// A single removal.
+#[derive(Debug)]
+pub struct Record<'a> {
+    pub kind: Kind,
+    pub path: &'a Path,
+    /// Size in bytes, 0 for directories
+    pub size: u64,
+    pub modified: SystemTime,
+    pub reason: Reason,
+    pub mode: DeleteMode,
+    /// Set when the file was replaced with a hard link to this identical one
+    /// rather than removed
+    pub linked_to: Option<&'a Path>,
+    /// Where a file moved to `ARCHIVE_DIR` went
+    pub archived_to: Option<&'a Path>,
+}
+
+impl Record<'_> {
+    /// `mode`, or `hardlink` for a file replaced by a link.
+    pub fn method(&self) -> String {
+        match self.linked_to {
+            Some(_) => "hardlink".to_string(),
+            None => self.mode.to_string(),
+        }
+    }
+}
+
+pub struct AuditLog {
+    file: File,
+}
+
+impl AuditLog {
+    /// Opens `path` for appending, creating it if needed.
+    pub fn open(path: &Path) -> std::io::Result<AuditLog> {
+        let file = File::options().create(true).append(true).open(path)?;
+        Ok(AuditLog { file })
+    }
+
+    /// Appends `record`, timestamped with the current time.
+    pub fn record(&mut self, record: &Record) -> std::io::Result<()> {
+        let mut line = serde_json::json!({
+            "timestamp": humantime::format_rfc3339_millis(SystemTime::now()).to_string(),
+            "kind": record.kind,
+            "path": record.path.display().to_string(),
+            "size": record.size,
+            "modified": humantime::format_rfc3339_seconds(record.modified).to_string(),
+            "reason": record.reason,
+            "mode": record.method(),
+        });
+
+        if let Some(original) = record.linked_to {
+            line["linked_to"] = original.display().to_string().into();
+        }
+
+        if let Some(archived) = record.archived_to {
+            line["archived_to"] = archived.display().to_string().into();
+        }
+
+        // A single write per line keeps concurrent appenders from interleaving
+        self.file.write_all(format!("{line}\n").as_bytes())
+    }
+}
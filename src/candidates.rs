@@ -0,0 +1,215 @@
+//! Bounded selection of the files a cleanup may remove, so that huge trees
+//! are scanned without holding every file in memory.
+
+use globset::GlobSet;
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashSet};
+use std::path::Path;
+use std::sync::Mutex;
+
+use crate::eviction::EvictionPolicy;
+use crate::scan::{matches_relative, FileInfo};
+
+/// Keeps the `limit` files a cleanup would remove first out of every file
+/// offered: expired files, then in the order of the eviction policy. Files
+/// that may not be removed at all are only counted.
+pub struct Candidates<'a> {
+    limit: usize,
+    policy: &'a dyn EvictionPolicy,
+    root: &'a Path,
+    include: &'a GlobSet,
+    /// Files modified after this are too new to be removed
+    min_modified: u64,
+    /// Files modified before this are expired
+    expires_before: Option<u64>,
+    selected: Mutex<Selected>,
+}
+
+#[derive(Default)]
+struct Selected {
+    /// The candidate removed last on top
+    heap: BinaryHeap<Candidate>,
+    hard_links: HashSet<(u64, u64)>,
+    /// Size of the hard links of files already counted
+    duplicate_bytes: u64,
+    /// Candidates that did not fit
+    dropped: u64,
+}
+
+struct Candidate {
+    expired: bool,
+    score: f64,
+    file: FileInfo,
+}
+
+impl Candidate {
+    fn cmp_key(&self, other: &Candidate) -> Ordering {
+        other
+            .expired
+            .cmp(&self.expired)
+            .then(self.score.total_cmp(&other.score))
+            .then(self.file.modified.cmp(&other.file.modified))
+    }
+}
+
+impl PartialEq for Candidate {
+    fn eq(&self, other: &Candidate) -> bool {
+        self.cmp_key(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Candidate {}
+
+impl PartialOrd for Candidate {
+    fn partial_cmp(&self, other: &Candidate) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Candidate {
+    fn cmp(&self, other: &Candidate) -> Ordering {
+        self.cmp_key(other)
+    }
+}
+
+/// What was kept once the scan is over.
+pub struct Selection {
+    /// In no particular order
+    pub files: Vec<FileInfo>,
+    /// To subtract from the scanned size, see [`crate::ScanResult`]
+    pub duplicate_bytes: u64,
+    /// Removable files left out for being past the limit
+    pub dropped: u64,
+}
+
+impl<'a> Candidates<'a> {
+    pub fn new(
+        limit: usize,
+        policy: &'a dyn EvictionPolicy,
+        root: &'a Path,
+        include: &'a GlobSet,
+        min_modified: u64,
+        expires_before: Option<u64>,
+    ) -> Candidates<'a> {
+        Candidates {
+            limit,
+            policy,
+            root,
+            include,
+            min_modified,
+            expires_before,
+            selected: Mutex::default(),
+        }
+    }
+
+    /// Keeps the best candidates of `files`, returning how many files were
+    /// offered and their size.
+    pub fn offer(&self, files: Vec<FileInfo>) -> (u64, u64) {
+        let count = files.len() as u64;
+        let size = files.iter().map(|file| file.size).sum();
+
+        let offered: Vec<_> = files
+            .into_iter()
+            .map(|file| {
+                let expired = self
+                    .expires_before
+                    .is_some_and(|before| file.modified < before);
+                let removable = (expired || file.modified <= self.min_modified)
+                    && (self.include.is_empty()
+                        || matches_relative(self.include, self.root, &file.path));
+
+                (removable, expired, file)
+            })
+            .collect();
+
+        let mut selected = self.selected.lock().unwrap();
+
+        for (removable, expired, file) in offered {
+            if let Some(link) = &file.hard_link {
+                if !selected.hard_links.insert((link.device, link.inode)) {
+                    selected.duplicate_bytes += file.size;
+                }
+            }
+
+            if !removable {
+                continue;
+            }
+
+            selected.heap.push(Candidate {
+                expired,
+                score: self.policy.score(&file),
+                file,
+            });
+
+            if selected.heap.len() > self.limit {
+                selected.heap.pop();
+                selected.dropped += 1;
+            }
+        }
+
+        (count, size)
+    }
+
+    pub fn finish(self) -> Selection {
+        let selected = self.selected.into_inner().unwrap();
+
+        Selection {
+            files: selected
+                .heap
+                .into_vec()
+                .into_iter()
+                .map(|candidate| candidate.file)
+                .collect(),
+            duplicate_bytes: selected.duplicate_bytes,
+            dropped: selected.dropped,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::eviction::OldestFirst;
+    use std::path::PathBuf;
+
+    fn file(name: &str, modified: u64) -> FileInfo {
+        FileInfo {
+            path: PathBuf::from(format!("/data/{name}")),
+            size: 1,
+            modified,
+            accessed: modified,
+            hard_link: None,
+            inode: 0,
+        }
+    }
+
+    #[test]
+    fn keeps_expired_then_oldest_files() {
+        let include = GlobSet::empty();
+        let candidates = Candidates::new(
+            3,
+            &OldestFirst,
+            Path::new("/data"),
+            &include,
+            1000,
+            Some(100),
+        );
+
+        assert_eq!(
+            candidates.offer(vec![file("a", 500), file("b", 300), file("new", 2000)]),
+            (3, 3)
+        );
+        candidates.offer(vec![file("c", 400), file("expired", 50), file("d", 200)]);
+
+        let selection = candidates.finish();
+        let mut names: Vec<_> = selection
+            .files
+            .iter()
+            .map(|file| file.path.display().to_string())
+            .collect();
+        names.sort();
+
+        assert_eq!(names, ["/data/b", "/data/d", "/data/expired"]);
+        assert_eq!(selection.dropped, 2);
+    }
+}
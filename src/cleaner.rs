@@ -0,0 +1,1842 @@
+//! Running cleanups of a watched directory: scanning it, planning what to
+//! evict and removing it.
+
+use colored::*;
+use log::{debug, error, info, warn};
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::audit::{AuditLog, Kind, Record};
+use crate::candidates::Candidates;
+use crate::compress;
+use crate::config::{Deduplication, DeleteMode, EvictionPolicyKind, WatchConfig};
+use crate::dedup;
+#[cfg(feature = "desktop")]
+use crate::desktop::Desktop;
+use crate::disk::{self, AccessTimes};
+#[cfg(feature = "email")]
+use crate::email::{self, Mailer, Server};
+use crate::eviction::{self, EvictionPolicy};
+use crate::growth::Growth;
+#[cfg(feature = "history")]
+use crate::history::History;
+use crate::hook::{self, Verdict};
+use crate::logging;
+use crate::notification::{Event, Notification, LARGEST_REMOVED};
+use crate::open_files;
+#[cfg(feature = "otel")]
+use crate::otel::{Collector, Exporter, Timeline};
+use crate::policy::{self, CleanupPlan, Reason};
+use crate::priority;
+use crate::protection::Protection;
+use crate::quarantine;
+use crate::report::UsageReport;
+use crate::scan::{self, matches_relative, FileInfo, ScanEntry, ScanOptions, ScanResult};
+use crate::schedule;
+use crate::storage::{self, Storage};
+use crate::throttle::Throttle;
+use crate::watchdog::Watchdog;
+#[cfg(feature = "webhook")]
+use crate::webhook::Webhook;
+
+/// Result of a single scan and cleanup of a watched directory, ordered by
+/// severity so the worst one can be reported when running once.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum CycleOutcome {
+    #[default]
+    WithinLimit,
+    /// Over the limits during quiet hours, nothing was removed
+    Deferred,
+    CleanedUp,
+    Failed,
+}
+
+impl CycleOutcome {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            CycleOutcome::WithinLimit => "within_limit",
+            CycleOutcome::Deferred => "deferred",
+            CycleOutcome::CleanedUp => "cleaned_up",
+            CycleOutcome::Failed => "failed",
+        }
+    }
+
+    /// Process exit status for a one-shot run ending with this outcome.
+    pub fn exit_code(self) -> i32 {
+        match self {
+            CycleOutcome::WithinLimit => 0,
+            CycleOutcome::Failed => 1,
+            CycleOutcome::CleanedUp => 2,
+            CycleOutcome::Deferred => 3,
+        }
+    }
+}
+
+/// What a cycle found and did. In dry-run mode the removal counters hold
+/// what would have been removed.
+#[derive(Debug, Clone, Default)]
+pub struct CycleReport {
+    pub outcome: CycleOutcome,
+    pub dry_run: bool,
+    pub total_files: u64,
+    /// Size of the directory when it was scanned
+    pub size_before: u64,
+    /// Size of the directory after the cleanup
+    pub size_after: u64,
+    /// Size limit applied this cycle
+    pub limit_bytes: u64,
+    pub removed_files: u64,
+    pub removed_dirs: u64,
+    pub freed_bytes: u64,
+    /// Unreadable entries skipped during the scan plus failed removals
+    pub errors: u64,
+    /// Files compressed before the cleanup
+    pub compressed_files: u64,
+    /// Time the cleanup waited to stay within DELETE_RATE and
+    /// DELETE_BANDWIDTH
+    pub throttled: Duration,
+    /// Time the scan, compression and planning took
+    pub scan_duration: Duration,
+    /// Time the whole cycle took
+    pub duration: Duration,
+    /// Time until the directory reaches its limit at the pace it grew over
+    /// `full_warning_horizon`, when set and the directory grows
+    pub time_to_full: Option<Duration>,
+    /// Why the cycle failed, when it did
+    pub error: Option<String>,
+}
+
+impl CycleReport {
+    /// The figures of the cycle as logged when it finishes, for status
+    /// endpoints.
+    pub fn summary(&self) -> serde_json::Value {
+        serde_json::json!({
+            "outcome": self.outcome.as_str(),
+            "dry_run": self.dry_run,
+            "duration_seconds": self.duration.as_secs_f64(),
+            "scan_seconds": self.scan_duration.as_secs_f64(),
+            "files_scanned": self.total_files,
+            "bytes_before": self.size_before,
+            "bytes_after": self.size_after,
+            "limit_bytes": self.limit_bytes,
+            "files_deleted": self.removed_files,
+            "dirs_deleted": self.removed_dirs,
+            "bytes_freed": self.freed_bytes,
+            "files_compressed": self.compressed_files,
+            "throttled_seconds": self.throttled.as_secs_f64(),
+            "errors": self.errors,
+            "seconds_to_full": self.time_to_full.map(|time| time.as_secs()),
+        })
+    }
+}
+
+/// What [`Cleaner::compress`] did, or would have done in dry-run mode.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CompressionReport {
+    pub files: u64,
+    /// Bytes saved by compressing
+    pub saved_bytes: u64,
+    pub errors: u64,
+}
+
+/// Told about every removal, see [`Cleaner::with_removal_listener`].
+pub type RemovalListener = dyn Fn(&Record) + Send + Sync;
+
+/// Told about every cleanup about to start, see
+/// [`Cleaner::with_plan_listener`].
+pub type PlanListener = dyn Fn(&CleanupPlan) + Send + Sync;
+
+/// Keeps a single directory within its configured limits.
+///
+/// [`Cleaner::run`] performs a whole cycle; [`Cleaner::scan`],
+/// [`Cleaner::plan`] and [`Cleaner::execute`] are its individual steps, e.g.
+/// to inspect a plan before anything is removed.
+pub struct Cleaner {
+    config: WatchConfig,
+    storage: Box<dyn Storage>,
+    stop: Arc<AtomicBool>,
+    /// When the cycle in progress passes `cycle_timeout_seconds`
+    deadline: Option<Instant>,
+    /// Replaces the policy selected by the configuration
+    policy: Option<Box<dyn EvictionPolicy>>,
+    #[cfg(feature = "webhook")]
+    webhook: Option<Webhook>,
+    #[cfg(feature = "email")]
+    mailer: Option<Mailer>,
+    #[cfg(feature = "desktop")]
+    desktop: Option<Desktop>,
+    #[cfg(feature = "otel")]
+    exporter: Option<Exporter>,
+    /// Told about every removal
+    listener: Option<Box<RemovalListener>>,
+    /// Told about every cleanup about to start
+    plan_listener: Option<Box<PlanListener>>,
+    growth: Growth,
+    /// Whether the directory was last projected to reach its limit within
+    /// `full_warning_horizon`
+    filling_up: bool,
+}
+
+impl Cleaner {
+    /// In notify mode this starts watching the directory, falling back to
+    /// full scans if that fails.
+    pub fn new(config: WatchConfig) -> Cleaner {
+        check_access_times(&config);
+        check_shredding(&config);
+
+        Cleaner {
+            storage: storage::open(&config),
+            #[cfg(feature = "webhook")]
+            webhook: config
+                .webhook_url
+                .as_deref()
+                .map(|url| Webhook::new(url, config.notify_format)),
+            #[cfg(feature = "email")]
+            mailer: Server::of(&config).and_then(mailer),
+            #[cfg(feature = "desktop")]
+            desktop: config.desktop_notifications.then(Desktop::new),
+            #[cfg(feature = "otel")]
+            exporter: Collector::of(&config).map(Exporter::new),
+            config,
+            stop: Arc::default(),
+            deadline: None,
+            policy: None,
+            listener: None,
+            plan_listener: None,
+            growth: Growth::default(),
+            filling_up: false,
+        }
+    }
+
+    /// Evicts files in the order of `policy` instead of the configured
+    /// `eviction_policy`.
+    pub fn with_policy(mut self, policy: impl EvictionPolicy + 'static) -> Cleaner {
+        self.policy = Some(Box::new(policy));
+        self
+    }
+
+    /// Makes [`Cleaner::execute`] stop after the removal in progress once
+    /// `stop` is set, e.g. from a signal handler.
+    pub fn with_stop_flag(mut self, stop: Arc<AtomicBool>) -> Cleaner {
+        self.stop = stop;
+        self
+    }
+
+    /// Calls `listener` after every file and directory removed, as it is
+    /// written to the audit log. Nothing is removed in dry-run mode.
+    pub fn with_removal_listener(
+        mut self,
+        listener: impl Fn(&Record) + Send + Sync + 'static,
+    ) -> Cleaner {
+        self.listener = Some(Box::new(listener));
+        self
+    }
+
+    /// Calls `listener` with the plan of every cleanup about to remove
+    /// files, or log them in dry-run mode.
+    pub fn with_plan_listener(
+        mut self,
+        listener: impl Fn(&CleanupPlan) + Send + Sync + 'static,
+    ) -> Cleaner {
+        self.plan_listener = Some(Box::new(listener));
+        self
+    }
+
+    pub fn config(&self) -> &WatchConfig {
+        &self.config
+    }
+
+    /// Where the files of the directory live.
+    pub fn storage(&self) -> &dyn Storage {
+        self.storage.as_ref()
+    }
+
+    /// Replaces the configuration from the next cycle on. The storage, and
+    /// with it the notify index or the scan cache, is kept unless the
+    /// directory, the watch mode or the cache directory changed.
+    pub fn set_config(&mut self, config: WatchConfig) {
+        if config.directory != self.config.directory
+            || config.watch_mode != self.config.watch_mode
+            || config.scan_cache_dir != self.config.scan_cache_dir
+        {
+            self.storage = storage::open(&config);
+        }
+
+        if config.directory != self.config.directory {
+            self.growth.clear();
+            self.filling_up = false;
+        }
+
+        if config.eviction_policy != self.config.eviction_policy {
+            check_access_times(&config);
+        }
+
+        if config.delete_mode != self.config.delete_mode {
+            check_shredding(&config);
+        }
+
+        #[cfg(feature = "webhook")]
+        if config.webhook_url.as_deref() != self.webhook.as_ref().map(Webhook::url)
+            || Some(config.notify_format) != self.webhook.as_ref().map(Webhook::format)
+        {
+            self.webhook = config
+                .webhook_url
+                .as_deref()
+                .map(|url| Webhook::new(url, config.notify_format));
+        }
+
+        #[cfg(feature = "desktop")]
+        if config.desktop_notifications != self.desktop.is_some() {
+            self.desktop = config.desktop_notifications.then(Desktop::new);
+        }
+
+        #[cfg(feature = "otel")]
+        {
+            let collector = Collector::of(&config);
+
+            if collector.as_ref() != self.exporter.as_ref().map(Exporter::collector) {
+                self.exporter = None;
+                self.exporter = collector.map(Exporter::new);
+            }
+        }
+
+        #[cfg(feature = "email")]
+        {
+            let server = Server::of(&config);
+
+            if server.as_ref() != self.mailer.as_ref().map(Mailer::server) {
+                // Waits for the emails of the previous server
+                self.mailer = None;
+                self.mailer = server.and_then(mailer);
+            }
+        }
+
+        self.config = config;
+    }
+
+    /// Scans the directory, in parallel on the current rayon pool.
+    pub fn scan(&mut self) -> std::io::Result<ScanResult> {
+        let directory = Path::new(&self.config.directory);
+
+        debug!(
+            event = "scan_started", directory:% = directory.display();
+            "Scanning {}", directory.display()
+        );
+
+        let device = match self.config.one_file_system {
+            true => scan::device(&std::fs::metadata(directory)?),
+            false => None,
+        };
+
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let configured = eviction::from_config(&self.config);
+        let candidates = self.config.max_candidates.map(|limit| {
+            Candidates::new(
+                limit,
+                self.policy.as_deref().unwrap_or(configured.as_ref()),
+                directory,
+                &self.config.include,
+                now.saturating_sub(self.config.min_age_seconds),
+                self.config
+                    .max_age_seconds
+                    .map(|max_age| now.saturating_sub(max_age)),
+            )
+        });
+
+        let protection = self.config.ignore_files.then(|| Protection::new(directory));
+
+        let options = ScanOptions {
+            root: directory,
+            exclude: &self.config.exclude,
+            exclude_dirs: &self.config.exclude_dirs,
+            device,
+            follow_symlinks: self.config.follow_symlinks,
+            size_accounting: self.config.size_accounting,
+            candidates: candidates.as_ref(),
+            protection: protection.as_ref(),
+            pinned: self.config.pinned_files,
+            owners: self.config.owners.as_ref(),
+            clutter: self.config.clutter_max_age_seconds.is_some(),
+            deadline: self.deadline,
+            max_depth: self.config.max_depth,
+        };
+
+        let mut scanned = match self.config.scan_io_idle {
+            true => priority::idle_io(|| self.storage.scan(&self.config, &options)),
+            false => self.storage.scan(&self.config, &options),
+        }?;
+
+        if let Some(candidates) = candidates {
+            let selection = candidates.finish();
+
+            debug!(
+                "{}",
+                format!(
+                    "Kept {} removal candidates of {} files, leaving out {}",
+                    selection.files.len(),
+                    scanned.total_files,
+                    selection.dropped
+                )
+                .green()
+            );
+
+            scanned.total_size -= selection.duplicate_bytes;
+            scanned
+                .entries
+                .extend(selection.files.into_iter().map(ScanEntry::File));
+        }
+
+        if scanned.errors > 0 {
+            error!(
+                event = "scan_errors", directory:% = directory.display(), errors = scanned.errors;
+                "{}",
+                format!(
+                    "Skipped {} unreadable entries while scanning {}",
+                    scanned.errors,
+                    directory.display()
+                )
+                .red()
+            );
+        }
+
+        Ok(scanned)
+    }
+
+    /// Compresses the files of `scanned` not modified for
+    /// `compress_after_seconds`, updating their paths and sizes, or only logs
+    /// them in dry-run mode.
+    pub fn compress(&self, scanned: &mut ScanResult) -> CompressionReport {
+        let watch = &self.config;
+        let directory = Path::new(&watch.directory);
+
+        let mut report = CompressionReport::default();
+
+        let Some(compress_after) = watch.compress_after_seconds else {
+            return report;
+        };
+
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let compress_before = now.saturating_sub(compress_after);
+
+        for entry in &mut scanned.entries {
+            let ScanEntry::File(file) = entry else {
+                continue;
+            };
+
+            if file.modified >= compress_before
+                || compress::is_compressed(&file.path)
+                // The other links would keep the uncompressed copy around
+                || file.hard_link.is_some()
+                // A link would be replaced by a copy of what it points to
+                || !std::fs::symlink_metadata(&file.path)
+                    .is_ok_and(|metadata| metadata.file_type().is_file())
+                || !(watch.include.is_empty()
+                    || matches_relative(&watch.include, directory, &file.path))
+            {
+                continue;
+            }
+
+            if self.stop.load(Ordering::SeqCst) || self.timed_out() {
+                break;
+            }
+
+            if watch.dry_run {
+                info!(
+                    event = "file_compressed", path:% = file.path.display(), bytes = file.size, dry_run = true;
+                    "{}",
+                    format!("Dry run, would compress file: {}", file.path.display()).yellow()
+                );
+                report.files += 1;
+                continue;
+            }
+
+            match compress::compress(&file.path, watch.compression) {
+                Ok((path, length)) => {
+                    let size = std::fs::metadata(&path)
+                        .map(|metadata| scan::file_size(&metadata, watch.size_accounting))
+                        .unwrap_or(length);
+
+                    debug!(
+                        event = "file_compressed", path:% = file.path.display(), bytes = size;
+                        "{}",
+                        format!(
+                            "Compressed file: {} ({} to {size} bytes)",
+                            file.path.display(),
+                            file.size
+                        )
+                        .yellow()
+                    );
+                    report.files += 1;
+                    report.saved_bytes += file.size.saturating_sub(size);
+                    scanned.total_size = scanned.total_size - file.size + size;
+                    file.path = path;
+                    file.size = size;
+                }
+                Err(e) => {
+                    error!(
+                        event = "error", path:% = file.path.display();
+                        "{}",
+                        format!("Error compressing file: {}, {e:?}", file.path.display()).red()
+                    );
+                    report.errors += 1;
+                }
+            }
+        }
+
+        if report.files > 0 {
+            info!(
+                event = "compression_finished", directory:% = directory.display(), files = report.files, saved_bytes = report.saved_bytes;
+                "{}",
+                format!(
+                    "Compressed {} files saving {:.2} MB",
+                    report.files,
+                    report.saved_bytes as f64 / 1024.0 / 1024.0
+                )
+                .yellow()
+            );
+        }
+
+        report
+    }
+
+    /// Decides what to remove from a scan of the directory. Only fails when
+    /// the free space of the filesystem is needed and cannot be read.
+    pub fn plan(&self, scanned: ScanResult) -> std::io::Result<CleanupPlan> {
+        let limit = policy::size_limit(&self.config, scanned.total_size)?;
+        let configured;
+        let eviction = match &self.policy {
+            Some(policy) => policy.as_ref(),
+            None => {
+                configured = eviction::from_config(&self.config);
+                configured.as_ref()
+            }
+        };
+
+        Ok(policy::plan(scanned, &self.config, limit, eviction))
+    }
+
+    /// Removes what `plan` lists, or only logs it in dry-run mode.
+    pub fn execute(&self, plan: CleanupPlan) -> CycleReport {
+        let watch = &self.config;
+        let directory = Path::new(&watch.directory);
+
+        let mut errors = plan.scan_errors;
+
+        if plan.reason.is_none() && plan.files.is_empty() {
+            return CycleReport {
+                outcome: CycleOutcome::WithinLimit,
+                limit_bytes: plan.limit_bytes,
+                dry_run: watch.dry_run,
+                total_files: plan.total_files,
+                size_before: plan.total_size,
+                size_after: plan.total_size,
+                errors,
+                ..Default::default()
+            };
+        }
+
+        if let Some(window) =
+            schedule::quiet_window(&watch.quiet_hours, watch.timezone, SystemTime::now())
+        {
+            warn!(
+                event = "cleanup_deferred", directory:% = directory.display(), window:% = window, files = plan.files.len();
+                "{}",
+                format!(
+                    "{} is over its limits during the quiet hours `{window}`, not removing anything",
+                    directory.display()
+                )
+                .yellow()
+            );
+            return CycleReport {
+                outcome: CycleOutcome::Deferred,
+                limit_bytes: plan.limit_bytes,
+                dry_run: watch.dry_run,
+                total_files: plan.total_files,
+                size_before: plan.total_size,
+                size_after: plan.total_size,
+                errors,
+                ..Default::default()
+            };
+        }
+
+        let mut audit = match &watch.audit_log {
+            Some(path) if !watch.dry_run => match AuditLog::open(path) {
+                Ok(audit) => Some(audit),
+                Err(e) => {
+                    error!(
+                        event = "error", path:% = path.display();
+                        "{}",
+                        format!(
+                            "Error opening audit log {}, not removing anything: {e:?}",
+                            path.display()
+                        )
+                        .red()
+                    );
+                    return self.abort(
+                        &plan,
+                        errors + 1,
+                        format!("Error opening audit log {}: {e}", path.display()),
+                    );
+                }
+            },
+            _ => None,
+        };
+
+        let mut vars = vec![
+            ("DIRECTORY", watch.directory.clone()),
+            ("SIZE_BYTES", plan.total_size.to_string()),
+            ("LIMIT_BYTES", plan.limit_bytes.to_string()),
+            ("FILES", plan.total_files.to_string()),
+            (
+                "BYTES_TO_FREE",
+                plan.files
+                    .iter()
+                    .map(|eviction| eviction.freed_bytes)
+                    .sum::<u64>()
+                    .to_string(),
+            ),
+            ("FILES_TO_DELETE", plan.files.len().to_string()),
+        ];
+
+        if let Some(command) = watch.pre_cleanup_cmd.as_deref().filter(|_| !watch.dry_run) {
+            if let Err(e) = hook::run_command(command, &vars, hook::COMMAND_TIMEOUT) {
+                error!(
+                    event = "error", directory:% = directory.display();
+                    "{}",
+                    format!(
+                        "Error running PRE_CLEANUP_CMD, not removing anything from {}: {e}",
+                        directory.display()
+                    )
+                    .red()
+                );
+                return self.abort(
+                    &plan,
+                    errors + 1,
+                    format!("Error running PRE_CLEANUP_CMD: {e}"),
+                );
+            }
+        }
+
+        #[cfg(feature = "history")]
+        let history = match watch.history_db.as_deref() {
+            Some(path) if !watch.dry_run => open_history(path),
+            _ => None,
+        };
+
+        let mut audit_record = |record: Record| {
+            if let Some(listener) = &self.listener {
+                listener(&record);
+            }
+
+            #[cfg(feature = "history")]
+            let errors = match history
+                .as_ref()
+                .map(|history| history.record_deletion(&watch.directory, &record))
+            {
+                Some(Err(e)) => {
+                    error!(
+                        event = "error", path:% = record.path.display();
+                        "{}",
+                        format!(
+                            "Error recording the removal of {} in the history: {e}",
+                            record.path.display()
+                        )
+                        .red()
+                    );
+                    1
+                }
+                _ => 0,
+            };
+            #[cfg(not(feature = "history"))]
+            let errors = 0;
+
+            let Some(audit) = &mut audit else {
+                return errors;
+            };
+
+            match audit.record(&record) {
+                Ok(()) => errors,
+                Err(e) => {
+                    error!(
+                        event = "error", path:% = record.path.display();
+                        "{}",
+                        format!(
+                            "Error writing audit record for {}: {e:?}",
+                            record.path.display()
+                        )
+                        .red()
+                    );
+                    errors + 1
+                }
+            }
+        };
+
+        if let Some(listener) = &self.plan_listener {
+            listener(&plan);
+        }
+
+        self.notify(
+            Event::CleanupStarted,
+            Notification {
+                size_bytes: plan.total_size,
+                limit_bytes: plan.limit_bytes,
+                files: plan.total_files,
+                dry_run: watch.dry_run,
+                ..Default::default()
+            },
+        );
+
+        #[cfg(feature = "desktop")]
+        if let Some(desktop) = &self.desktop {
+            desktop.cleanup_started(
+                &watch.directory,
+                plan.files.len(),
+                plan.files.iter().map(|eviction| eviction.freed_bytes).sum(),
+                watch.dry_run,
+            );
+        }
+
+        let mut removed_files = 0;
+        let mut removed_dirs = 0;
+        let mut freed_bytes = 0;
+        // Links left of every file with several, see `freed`
+        let mut links: HashMap<(u64, u64), u64> = HashMap::new();
+        let mut failed: Vec<PathBuf> = Vec::new();
+        // For the notification of the cleanup
+        let mut largest: Vec<(String, u64)> = Vec::new();
+
+        let mut stopped = false;
+        let mut timed_out = false;
+
+        let total = plan.files.len();
+        let mut done = 0;
+
+        let open_files = match watch.skip_open_files {
+            true => open_files::open_files(),
+            false => HashMap::new(),
+        };
+
+        let mut throttle = Throttle::new(watch.delete_rate, watch.delete_bandwidth);
+
+        for batch in plan.files.chunks(self.storage.batch_size().max(1)) {
+            if !watch.dry_run {
+                throttle.wait(&self.stop);
+            }
+
+            if self.stop.load(Ordering::SeqCst) {
+                warn!(
+                    event = "cleanup_stopped", directory:% = directory.display(), remaining = total - done;
+                    "{}",
+                    format!(
+                        "Stopping cleanup of {} with {} files left to remove",
+                        directory.display(),
+                        total - done
+                    )
+                    .yellow()
+                );
+                stopped = true;
+                break;
+            }
+
+            if self.timed_out() {
+                error!(
+                    event = "cleanup_stopped", directory:% = directory.display(), remaining = total - done, reason = "timeout";
+                    "{}",
+                    format!(
+                        "Cleanup of {} exceeded CYCLE_TIMEOUT, stopping with {} files left to remove",
+                        directory.display(),
+                        total - done
+                    )
+                    .red()
+                );
+                errors += 1;
+                stopped = true;
+                timed_out = true;
+                break;
+            }
+
+            if let Some(window) =
+                schedule::quiet_window(&watch.quiet_hours, watch.timezone, SystemTime::now())
+            {
+                warn!(
+                    event = "cleanup_stopped", directory:% = directory.display(), remaining = total - done, window:% = window;
+                    "{}",
+                    format!(
+                        "Quiet hours `{window}` began, stopping cleanup of {} with {} files left to remove",
+                        directory.display(),
+                        total - done
+                    )
+                    .yellow()
+                );
+                stopped = true;
+                break;
+            }
+
+            done += batch.len();
+
+            let mut removing = Vec::with_capacity(batch.len());
+
+            for eviction in batch {
+                let file = &eviction.file;
+
+                let holder = match open_files.is_empty() {
+                    true => None,
+                    false => std::fs::canonicalize(&file.path)
+                        .ok()
+                        .and_then(|path| open_files.get(&path)),
+                };
+
+                if let Some(pid) = holder {
+                    warn!(
+                        event = "file_in_use", path:% = file.path.display(), pid;
+                        "{}",
+                        format!(
+                            "Skipping file held open by process {pid}: {}",
+                            file.path.display()
+                        )
+                        .yellow()
+                    );
+                    failed.push(file.path.clone());
+                    continue;
+                }
+
+                // Rewritten since the scan, the next cycle decides about it again
+                if !watch.dry_run
+                    && self
+                        .storage
+                        .changed_since_scan(watch, file)
+                        .unwrap_or(false)
+                {
+                    info!(
+                        event = "file_changed", path:% = file.path.display();
+                        "{}",
+                        format!(
+                            "Skipping file changed since the scan: {}",
+                            file.path.display()
+                        )
+                        .yellow()
+                    );
+                    failed.push(file.path.clone());
+                    continue;
+                }
+
+                // A copy only as long as the file kept is unchanged
+                if !watch.dry_run
+                    && eviction.duplicate_of.as_ref().is_some_and(|original| {
+                        self.storage
+                            .changed_since_scan(watch, original)
+                            .unwrap_or(true)
+                    })
+                {
+                    info!(
+                        event = "file_changed", path:% = file.path.display();
+                        "{}",
+                        format!(
+                            "Skipping copy of a file changed since the scan: {}",
+                            file.path.display()
+                        )
+                        .yellow()
+                    );
+                    failed.push(file.path.clone());
+                    continue;
+                }
+
+                if let Some(hook) = &watch.candidate_hook {
+                    match hook::consult(
+                        hook,
+                        &watch.directory,
+                        eviction,
+                        watch.dry_run,
+                        hook::TIMEOUT,
+                    ) {
+                        Ok(Verdict::Remove) => {}
+                        Ok(Verdict::Keep(reason)) => {
+                            info!(
+                                event = "file_vetoed", path:% = file.path.display(), reason = reason.as_str();
+                                "{}",
+                                format!(
+                                    "Keeping file vetoed by the candidate hook: {}, {reason}",
+                                    file.path.display()
+                                )
+                                .yellow()
+                            );
+                            failed.push(file.path.clone());
+                            continue;
+                        }
+                        Err(e) => {
+                            error!(
+                                event = "error", path:% = file.path.display();
+                                "{}",
+                                format!(
+                                    "Error running the candidate hook, keeping {}: {e}",
+                                    file.path.display()
+                                )
+                                .red()
+                            );
+                            errors += 1;
+                            failed.push(file.path.clone());
+                            continue;
+                        }
+                    }
+                }
+
+                if watch.dry_run {
+                    info!(
+                        event = "file_removed", path:% = file.path.display(), bytes = file.size, dry_run = true;
+                        "{}",
+                        format!(
+                            "Dry run, would remove file: {} ({} bytes)",
+                            file.path.display(),
+                            file.size
+                        )
+                        .yellow()
+                    );
+                    removed_files += 1;
+                    freed_bytes += eviction.freed_bytes;
+                    keep_largest(&mut largest, &file.path, file.size);
+                    continue;
+                }
+
+                if let (Some(original), Deduplication::Hardlink) =
+                    (&eviction.duplicate_of, watch.deduplicate)
+                {
+                    if let Err(e) = dedup::link(&file.path, &original.path) {
+                        error!(
+                            event = "error", path:% = file.path.display();
+                            "{}",
+                            format!(
+                                "Error linking file {} to its copy {}: {e:?}",
+                                file.path.display(),
+                                original.path.display()
+                            )
+                            .red()
+                        );
+                        errors += 1;
+                        failed.push(file.path.clone());
+                        continue;
+                    }
+
+                    debug!(
+                        event = "file_linked", path:% = file.path.display(), original:% = original.path.display();
+                        "{}",
+                        format!(
+                            "Replaced file {} with a hard link to its copy {}",
+                            file.path.display(),
+                            original.path.display()
+                        )
+                        .green()
+                    );
+                    removed_files += 1;
+                    freed_bytes += freed(&mut links, file);
+                    keep_largest(&mut largest, &file.path, file.size);
+                    errors += audit_record(Record {
+                        kind: Kind::File,
+                        path: &file.path,
+                        size: file.size,
+                        modified: UNIX_EPOCH + Duration::from_secs(file.modified),
+                        reason: eviction.reason,
+                        mode: watch.delete_mode,
+                        linked_to: Some(&original.path),
+                        archived_to: None,
+                    });
+                    throttle.removed(file.size);
+                    continue;
+                }
+
+                removing.push(eviction);
+            }
+
+            if removing.is_empty() {
+                continue;
+            }
+
+            let files: Vec<&FileInfo> = removing.iter().map(|eviction| &eviction.file).collect();
+            let results = self.storage.remove_files(watch, &files);
+
+            for (eviction, result) in removing.into_iter().zip(results) {
+                let file = &eviction.file;
+
+                let archived = match result {
+                    Ok(archived) => archived,
+                    Err(e) => {
+                        error!(
+                            event = "error", path:% = file.path.display();
+                            "{}",
+                            format!("Error removing file: {}, {e:?}", file.path.display()).red()
+                        );
+                        errors += 1;
+                        failed.push(file.path.clone());
+                        throttle.removed(0);
+                        continue;
+                    }
+                };
+
+                debug!(
+                    event = "file_removed", path:% = file.path.display(), bytes = file.size, mode:% = watch.delete_mode;
+                    "{}",
+                    format!(
+                        "Removed file ({}): {}",
+                        watch.delete_mode,
+                        file.path.display()
+                    )
+                    .red()
+                );
+                removed_files += 1;
+                freed_bytes += freed(&mut links, file);
+                keep_largest(&mut largest, &file.path, file.size);
+                errors += audit_record(Record {
+                    kind: Kind::File,
+                    path: &file.path,
+                    size: file.size,
+                    modified: UNIX_EPOCH + Duration::from_secs(file.modified),
+                    reason: eviction.reason,
+                    mode: watch.delete_mode,
+                    linked_to: None,
+                    archived_to: archived.as_deref(),
+                });
+                throttle.removed(file.size);
+            }
+        }
+
+        // Directories still holding a file that could not be removed are kept,
+        // and none are expected to be empty when the cleanup was stopped
+        let holding: HashSet<&Path> = failed
+            .iter()
+            .flat_map(|file| file.ancestors().skip(1))
+            .collect();
+
+        for path in plan
+            .dirs
+            .iter()
+            .filter(|_| !stopped)
+            .filter(|dir| !holding.contains(dir.as_path()))
+        {
+            if watch.dry_run {
+                info!(
+                    event = "dir_removed", path:% = path.display(), dry_run = true;
+                    "{}",
+                    format!("Dry run, would remove directory: {}", path.display()).yellow()
+                );
+                removed_dirs += 1;
+                continue;
+            }
+
+            let modified = std::fs::metadata(path)
+                .and_then(|metadata| metadata.modified())
+                .unwrap_or(UNIX_EPOCH);
+
+            if let Err(e) = self.storage.remove_dir(watch, path) {
+                error!(
+                    event = "error", path:% = path.display();
+                    "{}",
+                    format!("Error removing directory: {}, {e:?}", path.display()).red()
+                );
+                errors += 1;
+            } else {
+                debug!(
+                    event = "dir_removed", path:% = path.display();
+                    "{}",
+                    format!("Removed directory: {}", path.display()).red()
+                );
+                removed_dirs += 1;
+                errors += audit_record(Record {
+                    kind: Kind::Directory,
+                    path,
+                    size: 0,
+                    modified,
+                    reason: Reason::EmptyDirectory,
+                    mode: DeleteMode::Delete,
+                    linked_to: None,
+                    archived_to: None,
+                });
+            }
+        }
+
+        let freed_mb = freed_bytes as f64 / 1024.0 / 1024.0;
+
+        if watch.dry_run {
+            info!(
+                event = "cleanup_finished", directory:% = directory.display(), files = removed_files, dirs = removed_dirs, bytes = freed_bytes, dry_run = true;
+                "{}",
+                format!(
+                    "Dry run, would remove {removed_files} files and {removed_dirs} directories freeing {freed_mb:.2} MB",
+                )
+                .yellow()
+            );
+        } else {
+            let throttled = match throttle.waited.is_zero() {
+                true => String::new(),
+                false => format!(", throttled for {:.2}s", throttle.waited.as_secs_f64()),
+            };
+
+            info!(
+                event = "cleanup_finished", directory:% = directory.display(), files = removed_files, dirs = removed_dirs, bytes = freed_bytes, throttled_seconds = throttle.waited.as_secs_f64();
+                "{}",
+                format!(
+                    "Removed {removed_files} files and {removed_dirs} directories freeing {freed_mb:.2} MB{throttled}",
+                )
+                .green()
+            );
+        }
+
+        if let Some(command) = watch.post_cleanup_cmd.as_deref().filter(|_| !watch.dry_run) {
+            vars.extend([
+                ("BYTES_FREED", freed_bytes.to_string()),
+                ("FILES_DELETED", removed_files.to_string()),
+                ("DIRS_DELETED", removed_dirs.to_string()),
+                ("ERRORS", errors.to_string()),
+                ("STOPPED", stopped.to_string()),
+            ]);
+
+            if let Err(e) = hook::run_command(command, &vars, hook::COMMAND_TIMEOUT) {
+                error!(
+                    event = "error", directory:% = directory.display();
+                    "{}",
+                    format!(
+                        "Error running POST_CLEANUP_CMD after cleaning up {}: {e}",
+                        directory.display()
+                    )
+                    .red()
+                );
+                errors += 1;
+            }
+        }
+
+        let report = CycleReport {
+            outcome: match timed_out {
+                true => CycleOutcome::Failed,
+                false => CycleOutcome::CleanedUp,
+            },
+            error: timed_out
+                .then(|| format!("Cleanup of {} exceeded CYCLE_TIMEOUT", directory.display())),
+            limit_bytes: plan.limit_bytes,
+            dry_run: watch.dry_run,
+            total_files: plan.total_files,
+            size_before: plan.total_size,
+            size_after: if watch.dry_run {
+                plan.total_size
+            } else {
+                plan.total_size - freed_bytes
+            },
+            removed_files,
+            removed_dirs,
+            freed_bytes,
+            errors,
+            throttled: throttle.waited,
+            ..Default::default()
+        };
+
+        self.notify(
+            Event::CleanupFinished,
+            Notification {
+                size_bytes: report.size_after,
+                limit_bytes: report.limit_bytes,
+                files: match watch.dry_run {
+                    true => report.total_files,
+                    false => report.total_files - removed_files,
+                },
+                files_deleted: removed_files,
+                dirs_deleted: removed_dirs,
+                bytes_freed: freed_bytes,
+                errors,
+                dry_run: watch.dry_run,
+                largest_removed: largest,
+                ..Default::default()
+            },
+        );
+
+        #[cfg(feature = "desktop")]
+        if let Some(desktop) = &self.desktop {
+            desktop.cleanup_finished(&watch.directory, &report);
+        }
+
+        report
+    }
+
+    /// Reports a cleanup that failed before removing anything.
+    fn abort(&self, plan: &CleanupPlan, errors: u64, error: String) -> CycleReport {
+        self.notify(
+            Event::CleanupFailed,
+            Notification {
+                size_bytes: plan.total_size,
+                limit_bytes: plan.limit_bytes,
+                files: plan.total_files,
+                errors,
+                error: Some(error.clone()),
+                ..Default::default()
+            },
+        );
+
+        CycleReport {
+            outcome: CycleOutcome::Failed,
+            limit_bytes: plan.limit_bytes,
+            total_files: plan.total_files,
+            size_before: plan.total_size,
+            size_after: plan.total_size,
+            errors,
+            error: Some(error),
+            ..Default::default()
+        }
+    }
+
+    /// Logs the largest subdirectories and files of `scanned` when
+    /// `report_top` is set.
+    fn log_usage(&self, scanned: &ScanResult) {
+        let Some(top) = self.config.report_top else {
+            return;
+        };
+
+        let directory = Path::new(&self.config.directory);
+        let report = UsageReport::new(directory, scanned, top);
+
+        for usage in &report.subdirectories {
+            info!(
+                event = "largest_subdirectory", directory:% = directory.display(), path:% = usage.path.display(), bytes = usage.size, files = usage.files;
+                "{}",
+                format!(
+                    "Largest subdirectory: {} ({:.2} MB in {} files)",
+                    usage.path.display(),
+                    usage.size as f64 / 1024.0 / 1024.0,
+                    usage.files
+                )
+                .green()
+            );
+        }
+
+        for usage in &report.files {
+            info!(
+                event = "largest_file", directory:% = directory.display(), path:% = usage.path.display(), bytes = usage.size;
+                "{}",
+                format!(
+                    "Largest file: {} ({:.2} MB)",
+                    usage.path.display(),
+                    usage.size as f64 / 1024.0 / 1024.0
+                )
+                .green()
+            );
+        }
+    }
+
+    /// Whether the cycle in progress passed `cycle_timeout_seconds`.
+    fn timed_out(&self) -> bool {
+        self.deadline
+            .is_some_and(|deadline| Instant::now() >= deadline)
+    }
+
+    /// Purges what `DeleteMode::Quarantine` held long enough, or beyond
+    /// `quarantine_max_size_bytes`.
+    fn purge_quarantine(&self) {
+        let (DeleteMode::Quarantine, Some(quarantine_dir), false) = (
+            self.config.delete_mode,
+            &self.config.quarantine_dir,
+            self.config.dry_run,
+        ) else {
+            return;
+        };
+
+        let directory = Path::new(&self.config.directory);
+        let root = std::fs::canonicalize(directory).unwrap_or_else(|_| directory.to_path_buf());
+
+        match quarantine::purge(
+            quarantine_dir,
+            &root,
+            self.config.quarantine_retention_seconds,
+            self.config.quarantine_max_size_bytes,
+        ) {
+            Ok(purged) if purged.files > 0 => info!(
+                event = "quarantine_purged", directory:% = directory.display(), files = purged.files, bytes = purged.bytes;
+                "{}",
+                format!(
+                    "Purged {} files ({:.2} MB) of {} from quarantine",
+                    purged.files,
+                    purged.bytes as f64 / 1024.0 / 1024.0,
+                    directory.display()
+                )
+                .green()
+            ),
+            Ok(_) => {}
+            Err(e) => error!(
+                event = "error", path:% = quarantine_dir.display();
+                "{}",
+                format!("Error purging quarantine {}: {e}", quarantine_dir.display()).red()
+            ),
+        }
+    }
+
+    /// Posts `event` to the configured webhook, if any.
+    #[cfg_attr(not(feature = "webhook"), allow(unused_variables))]
+    fn notify(&self, event: Event, notification: Notification) {
+        #[cfg(feature = "webhook")]
+        if let Some(webhook) = &self.webhook {
+            webhook.send(
+                event,
+                &Notification {
+                    directory: &self.config.directory,
+                    ..notification
+                },
+            );
+        }
+    }
+
+    /// Runs a whole cycle: scan, compress, plan and execute.
+    pub fn run(&mut self) -> CycleReport {
+        let directory = Path::new(&self.config.directory).to_path_buf();
+
+        let cycle = logging::next_cycle();
+
+        info!(
+            event = "cycle_started", directory:% = directory.display(), cycle;
+            "{}",
+            format!("Running cleanup loop on {}", directory.display()).green()
+        );
+
+        let started = Instant::now();
+        let timeout = self.config.cycle_timeout_seconds.map(Duration::from_secs);
+        self.deadline = timeout.and_then(|timeout| started.checked_add(timeout));
+        let _watchdog =
+            timeout.map(|timeout| Watchdog::start(self.config.directory.clone(), timeout));
+        #[cfg(feature = "otel")]
+        let mut timeline = Timeline::start();
+
+        let mut compressed = CompressionReport::default();
+
+        let mut report = match self.scan().and_then(|mut scanned| {
+            #[cfg(feature = "otel")]
+            timeline.finish("scan");
+            self.log_usage(&scanned);
+            compressed = self.compress(&mut scanned);
+
+            #[cfg(feature = "otel")]
+            if self.config.compress_after_seconds.is_some() {
+                timeline.finish("compress");
+            }
+
+            let plan = self.plan(scanned);
+            #[cfg(feature = "otel")]
+            timeline.finish("select");
+            plan
+        }) {
+            Ok(plan) => {
+                let scan_duration = started.elapsed();
+                let report = self.execute(plan);
+                #[cfg(feature = "otel")]
+                timeline.finish("delete");
+
+                if report.outcome != CycleOutcome::Failed {
+                    self.purge_quarantine();
+                }
+
+                CycleReport {
+                    scan_duration,
+                    compressed_files: compressed.files,
+                    errors: report.errors + compressed.errors,
+                    duration: started.elapsed(),
+                    ..report
+                }
+            }
+            Err(e) => {
+                error!(
+                    event = "error", path:% = directory.display();
+                    "{}",
+                    format!("Error while reading {directory:?}: {e:?}").red()
+                );
+                let failure = format!("Error reading {}: {e}", directory.display());
+                self.notify(
+                    Event::CleanupFailed,
+                    Notification {
+                        errors: 1 + compressed.errors,
+                        dry_run: self.config.dry_run,
+                        error: Some(failure.clone()),
+                        ..Default::default()
+                    },
+                );
+                CycleReport {
+                    outcome: CycleOutcome::Failed,
+                    error: Some(failure),
+                    errors: 1 + compressed.errors,
+                    compressed_files: compressed.files,
+                    scan_duration: started.elapsed(),
+                    duration: started.elapsed(),
+                    ..Default::default()
+                }
+            }
+        };
+
+        self.deadline = None;
+
+        if report.outcome != CycleOutcome::Failed {
+            report.time_to_full = self.project(started, &report);
+        }
+
+        #[cfg(feature = "history")]
+        if let Some(history) = self.config.history_db.as_deref().and_then(open_history) {
+            if let Err(e) = history.record_cycle(&self.config.directory, &report) {
+                error!(
+                    event = "error", path:% = directory.display();
+                    "{}",
+                    format!(
+                        "Error recording the cycle of {} in the history: {e}",
+                        directory.display()
+                    )
+                    .red()
+                );
+                report.errors += 1;
+            }
+        }
+
+        #[cfg(feature = "otel")]
+        if let Some(exporter) = &self.exporter {
+            exporter.export(
+                &self.config.directory,
+                &timeline,
+                &report,
+                report.error.as_deref(),
+            );
+        }
+
+        #[cfg(feature = "email")]
+        if let Some(mailer) = &self.mailer {
+            if email::wanted(&self.config, &report) {
+                let (subject, body) = email::summary(&self.config.directory, &report);
+
+                if let Err(e) = mailer.send(&subject, body) {
+                    error!(
+                        event = "error", path:% = directory.display();
+                        "{}",
+                        format!("Error emailing the cycle of {}: {e}", directory.display()).red()
+                    );
+                }
+            }
+        }
+
+        let (before, after) = (
+            report.size_before as f64 / 1024.0 / 1024.0,
+            report.size_after as f64 / 1024.0 / 1024.0,
+        );
+
+        info!(
+            event = "cycle_finished", directory:% = directory.display(), cycle, outcome = report.outcome.as_str(), dry_run = report.dry_run,
+            duration_seconds = report.duration.as_secs_f64(), scan_seconds = report.scan_duration.as_secs_f64(),
+            files_scanned = report.total_files, bytes_before = report.size_before, bytes_after = report.size_after,
+            files_deleted = report.removed_files, dirs_deleted = report.removed_dirs, bytes_freed = report.freed_bytes, errors = report.errors;
+            "{}",
+            format!(
+                "Cycle of {} finished in {:.2}s ({}): scanned {} files in {:.2}s, {before:.2} MB before and {after:.2} MB after, {} {} files and {} directories, {} errors",
+                directory.display(),
+                report.duration.as_secs_f64(),
+                report.outcome.as_str(),
+                report.total_files,
+                report.scan_duration.as_secs_f64(),
+                match report.dry_run {
+                    true => "would remove",
+                    false => "removed",
+                },
+                report.removed_files,
+                report.removed_dirs,
+                report.errors
+            )
+            .green()
+        );
+
+        report
+    }
+
+    /// Records the growth up to the cycle started at `started` and warns
+    /// when the directory is about to reach its limit within
+    /// `full_warning_horizon`; the time until it does.
+    fn project(&mut self, started: Instant, report: &CycleReport) -> Option<Duration> {
+        let horizon = Duration::from_secs(self.config.full_warning_horizon?);
+
+        self.growth
+            .record(started, report.size_before, report.size_after, horizon);
+
+        let time_to_full = match report.limit_bytes {
+            0 => None,
+            limit => self.growth.time_to_full(limit),
+        };
+        let filling_up = time_to_full.is_some_and(|time| time <= horizon);
+        let directory = self.config.directory.as_str();
+
+        if filling_up && !self.filling_up {
+            let time = time_to_full.unwrap_or_default();
+            let rate = self.growth.rate().unwrap_or_default();
+
+            warn!(
+                event = "limit_approaching", directory, seconds_to_full = time.as_secs(), bytes_per_hour = rate * 3600.0;
+                "{}",
+                format!(
+                    "{directory} grows by {:.2} MB an hour and will reach its limit of {:.2} MB in {}",
+                    rate * 3600.0 / 1024.0 / 1024.0,
+                    report.limit_bytes as f64 / 1024.0 / 1024.0,
+                    humantime::format_duration(Duration::from_secs(time.as_secs()))
+                )
+                .yellow()
+            );
+            self.notify(
+                Event::LimitApproaching,
+                Notification {
+                    size_bytes: report.size_after,
+                    limit_bytes: report.limit_bytes,
+                    files: match report.dry_run {
+                        true => report.total_files,
+                        false => report.total_files - report.removed_files,
+                    },
+                    dry_run: report.dry_run,
+                    seconds_to_full: Some(time.as_secs()),
+                    ..Default::default()
+                },
+            );
+        } else if !filling_up && self.filling_up {
+            info!(
+                event = "limit_no_longer_approaching", directory;
+                "{}",
+                format!(
+                    "{directory} is no longer projected to reach its limit within {}",
+                    humantime::format_duration(horizon)
+                )
+                .green()
+            );
+        }
+
+        self.filling_up = filling_up;
+
+        time_to_full
+    }
+}
+
+/// The space the removal of `file` actually freed, which, like
+/// `policy::release` plans it, a file with several hard links only frees with
+/// its last link. `links` holds how many are left of each, counting only
+/// those really removed, a failed removal keeping the space in use.
+fn freed(links: &mut HashMap<(u64, u64), u64>, file: &FileInfo) -> u64 {
+    let Some(link) = &file.hard_link else {
+        return file.size;
+    };
+
+    let remaining = links.entry((link.device, link.inode)).or_insert(link.links);
+    *remaining = remaining.saturating_sub(1);
+
+    match *remaining {
+        0 => file.size,
+        _ => 0,
+    }
+}
+
+/// Adds `path` to the `LARGEST_REMOVED` largest files removed if it is one
+/// of them.
+fn keep_largest(largest: &mut Vec<(String, u64)>, path: &Path, size: u64) {
+    if largest.len() == LARGEST_REMOVED && largest.last().is_some_and(|&(_, last)| last >= size) {
+        return;
+    }
+
+    let at = largest.partition_point(|&(_, other)| other >= size);
+    largest.insert(at, (path.display().to_string(), size));
+    largest.truncate(LARGEST_REMOVED);
+}
+
+/// Connects the emails to `server`, `None` after logging why they could
+/// not be.
+#[cfg(feature = "email")]
+fn mailer(server: Server) -> Option<Mailer> {
+    let host = server.host.clone();
+
+    Mailer::new(server)
+        .inspect_err(|e| {
+            error!(
+                event = "error";
+                "{}",
+                format!("Error setting up emails through {host}: {e}").red()
+            )
+        })
+        .ok()
+}
+
+/// Opens the history database at `path`, `None` after logging why it could
+/// not be.
+#[cfg(feature = "history")]
+fn open_history(path: &Path) -> Option<History> {
+    History::open(path)
+        .inspect_err(|e| {
+            error!(
+                event = "error", path:% = path.display();
+                "{}",
+                format!("Error opening history database {}: {e}", path.display()).red()
+            )
+        })
+        .ok()
+}
+
+/// Warns when the least recently used and gdsf policies cannot rely on
+/// access times. Access times are never older than modification times, so
+/// on `noatime` mounts they go by the last modification instead.
+fn check_access_times(config: &WatchConfig) {
+    if !matches!(
+        config.eviction_policy,
+        EvictionPolicyKind::LeastRecentlyUsed | EvictionPolicyKind::Gdsf
+    ) || storage::is_remote(&config.directory)
+    {
+        return;
+    }
+
+    match disk::access_times(Path::new(&config.directory)) {
+        AccessTimes::Strict => {}
+        AccessTimes::Relative => warn!(
+            "{}",
+            format!(
+                "{} is mounted with relatime, access times are only updated about once a day",
+                config.directory
+            )
+            .yellow()
+        ),
+        AccessTimes::Disabled => warn!(
+            "{}",
+            format!(
+                "{} is mounted with noatime, falling back to modification times",
+                config.directory
+            )
+            .yellow()
+        ),
+    }
+}
+
+/// Warns of what shredding cannot reach, and louder where it reaches
+/// nothing at all.
+fn check_shredding(config: &WatchConfig) {
+    if config.delete_mode != DeleteMode::Shred {
+        return;
+    }
+
+    if disk::copy_on_write(Path::new(&config.directory)) {
+        warn!(
+            "{}",
+            format!(
+                "{} is on a copy-on-write filesystem, DELETE_MODE=shred writes new blocks and leaves the old contents on disk",
+                config.directory
+            )
+            .red()
+        );
+    } else {
+        warn!(
+            "{}",
+            format!(
+                "DELETE_MODE=shred on {}: overwritten data may still be recovered from SSDs (wear leveling), snapshots, backups or filesystems journaling data, encrypt the volume where that matters",
+                config.directory
+            )
+            .yellow()
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::APP_NAME;
+
+    #[test]
+    fn run_stops_at_margin_honoring_order() {
+        let dir = std::env::temp_dir().join(format!("{APP_NAME}-order-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        for (name, modified) in [("old", 100), ("mid", 200), ("new", 300)] {
+            let path = dir.join(name);
+            std::fs::write(&path, vec![0u8; 512 * 1024]).unwrap();
+            let file = std::fs::File::options().write(true).open(&path).unwrap();
+            file.set_modified(UNIX_EPOCH + Duration::from_secs(modified))
+                .unwrap();
+        }
+
+        let watch: WatchConfig = toml::from_str(&format!(
+            "directory = {:?}\ninterval_seconds = 1\nmax_size_bytes = 1048576\nmargin = 100",
+            dir.display().to_string()
+        ))
+        .unwrap();
+
+        let report = Cleaner::new(watch).run();
+
+        assert_eq!(report.outcome, CycleOutcome::CleanedUp);
+        assert!(!dir.join("old").exists());
+        assert!(dir.join("mid").exists());
+        assert!(dir.join("new").exists());
+
+        let summary = report.summary();
+        assert_eq!(summary["outcome"], "cleaned_up");
+        assert_eq!(summary["files_scanned"], 3);
+        assert_eq!(summary["bytes_before"], 3 * 512 * 1024);
+        assert_eq!(summary["bytes_after"], 2 * 512 * 1024);
+        assert_eq!(summary["files_deleted"], 1);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn cycles_past_their_deadline_are_aborted() {
+        let dir = std::env::temp_dir().join(format!("{APP_NAME}-deadline-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("logs")).unwrap();
+        std::fs::write(dir.join("logs/old"), vec![0u8; 2048]).unwrap();
+
+        let watch: WatchConfig = toml::from_str(&format!(
+            "directory = {:?}\ninterval_seconds = 1\nmax_size_bytes = 1024\ncycle_timeout_seconds = 0",
+            dir.display().to_string()
+        ))
+        .unwrap();
+        let mut cleaner = Cleaner::new(watch);
+
+        let report = cleaner.run();
+        assert_eq!(report.outcome, CycleOutcome::Failed);
+        assert!(dir.join("logs/old").exists());
+
+        // Past the deadline between the scan and the removals
+        let scanned = cleaner.scan().unwrap();
+        let plan = cleaner.plan(scanned).unwrap();
+        assert_eq!(plan.files.len(), 1);
+        cleaner.deadline = Some(Instant::now());
+
+        let report = cleaner.execute(plan);
+        assert_eq!(report.outcome, CycleOutcome::Failed);
+        assert!(report.error.unwrap().ends_with("exceeded CYCLE_TIMEOUT"));
+        assert_eq!(report.removed_files, 0);
+        assert!(dir.join("logs/old").exists());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn failed_cycles_carry_their_reason() {
+        let dir = std::env::temp_dir().join(format!("{APP_NAME}-aborted-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("old"), vec![0u8; 2048]).unwrap();
+
+        let watch: WatchConfig = toml::from_str(&format!(
+            "directory = {:?}\ninterval_seconds = 1\nmax_size_bytes = 1024\npre_cleanup_cmd = \"exit 3\"",
+            dir.display().to_string()
+        ))
+        .unwrap();
+
+        let report = Cleaner::new(watch).run();
+        assert_eq!(report.outcome, CycleOutcome::Failed);
+        assert!(report
+            .error
+            .unwrap()
+            .starts_with("Error running PRE_CLEANUP_CMD"));
+        assert!(dir.join("old").exists());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn execute_skips_files_changed_since_the_scan() {
+        let dir = std::env::temp_dir().join(format!("{APP_NAME}-changed-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        for name in ["rewritten", "untouched"] {
+            std::fs::write(dir.join(name), b"old").unwrap();
+        }
+
+        let watch: WatchConfig = toml::from_str(&format!(
+            "directory = {:?}\ninterval_seconds = 1\nmax_files = 0",
+            dir.display().to_string()
+        ))
+        .unwrap();
+
+        let mut cleaner = Cleaner::new(watch);
+        let scanned = cleaner.scan().unwrap();
+        let plan = cleaner.plan(scanned).unwrap();
+
+        std::fs::write(dir.join("rewritten"), b"refreshed").unwrap();
+
+        let report = cleaner.execute(plan);
+
+        assert_eq!(report.removed_files, 1);
+        assert!(dir.join("rewritten").exists());
+        assert!(!dir.join("untouched").exists());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn cleanup_commands_see_the_stats_and_a_failing_pre_command_cancels() {
+        let dir = std::env::temp_dir().join(format!("{APP_NAME}-commands-{}", std::process::id()));
+        let data = dir.join("data");
+        std::fs::create_dir_all(&data).unwrap();
+        std::fs::write(data.join("file"), b"12345").unwrap();
+
+        let watch = |pre: &str| -> WatchConfig {
+            toml::from_str(&format!(
+                "directory = {:?}\ninterval_seconds = 1\nmax_files = 0\npre_cleanup_cmd = {pre:?}\n\
+                 post_cleanup_cmd = 'echo $MAXDIRSIZE_BYTES_TO_FREE $MAXDIRSIZE_FILES_DELETED > {}'",
+                data.display().to_string(),
+                dir.join("post").display()
+            ))
+            .unwrap()
+        };
+
+        let report = Cleaner::new(watch("exit 3")).run();
+        assert_eq!(report.outcome, CycleOutcome::Failed);
+        assert!(data.join("file").exists());
+        assert!(!dir.join("post").exists());
+
+        let report = Cleaner::new(watch("true")).run();
+        assert_eq!(report.removed_files, 1);
+        assert_eq!(std::fs::read_to_string(dir.join("post")).unwrap(), "5 1\n");
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn followed_symlinks_never_lead_outside_the_directory() {
+        let root = std::env::temp_dir().join(format!("{APP_NAME}-symlinks-{}", std::process::id()));
+        let dir = root.join("watched");
+        let outside = root.join("outside");
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::create_dir_all(&outside).unwrap();
+
+        std::fs::write(outside.join("file"), vec![0u8; 1024]).unwrap();
+        std::os::unix::fs::symlink(&outside, dir.join("link")).unwrap();
+
+        let watch: WatchConfig = toml::from_str(&format!(
+            "directory = {:?}\ninterval_seconds = 1\nmax_size_bytes = 1\nfollow_symlinks = true",
+            dir.display().to_string()
+        ))
+        .unwrap();
+
+        let report = Cleaner::new(watch).run();
+
+        assert_eq!(report.total_files, 1);
+        assert_eq!(report.removed_files, 0);
+        assert!(outside.join("file").exists());
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn hard_links_free_space_with_the_last_removed() {
+        let file = |name: &str| FileInfo {
+            path: PathBuf::from(name),
+            size: 100,
+            modified: 0,
+            accessed: 0,
+            hard_link: Some(scan::HardLink {
+                device: 1,
+                inode: 2,
+                links: 2,
+            }),
+            inode: 2,
+        };
+        let mut links = HashMap::new();
+
+        assert_eq!(freed(&mut links, &file("a")), 0);
+        assert_eq!(freed(&mut links, &file("b")), 100);
+    }
+}
@@ -0,0 +1,741 @@
+//! Subcommands, run once on the configured directories instead of the
+//! cleanup loop.
+
+use clap::CommandFactory;
+use colored::*;
+use log::{error, info, warn};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use maxdirsize::analysis::Analysis;
+use maxdirsize::config::{Args, Command, DeleteMode, ServiceAction, WatchMode};
+#[cfg(feature = "history")]
+use maxdirsize::history::{Filter, History};
+use maxdirsize::nagios::{self, State, Usage};
+use maxdirsize::pin;
+use maxdirsize::plan_file::{self, DirectoryPlan, PlanFile};
+use maxdirsize::policy;
+use maxdirsize::report::UsageReport;
+use maxdirsize::restore::{self, Restorable};
+use maxdirsize::{storage, units, Cleaner, Config, CycleOutcome, WatchConfig};
+
+use crate::unit::{self, Invocation};
+
+/// Runs `command` and exits with its status.
+pub fn run(command: Command, config: &Config) -> ! {
+    let pool = match crate::scan_pool(config.scan_threads) {
+        Ok(pool) => pool,
+        Err(e) => {
+            error!(event = "error"; "Error starting scan threads: {e}");
+            std::process::exit(1);
+        }
+    };
+
+    let code = match command {
+        Command::Report { top } => report(config, &pool, top),
+        Command::Analyze { depth } => analyze(config, &pool, depth),
+        Command::Plan { output } => plan(config, &pool, output.as_deref()),
+        Command::Apply { plan } => apply(config, &plan),
+        #[cfg(feature = "history")]
+        Command::History {
+            deletions,
+            since,
+            until,
+            path,
+            min_bytes,
+            limit,
+            json,
+        } => history(
+            config,
+            deletions,
+            &Filter {
+                since,
+                until,
+                path,
+                min_bytes,
+                limit,
+            },
+            json,
+        ),
+        Command::Restore { paths, last, list } => restore(config, &paths, last, list),
+        Command::Ctl { action } => crate::ctl::run(config.control_socket.as_deref(), action),
+        Command::InstallSystemd { hardened, output } => {
+            install_systemd(config, hardened, output.as_deref())
+        }
+        Command::Check => check(config),
+        Command::CheckUsage { warn, crit } => check_usage(config, &pool, warn, crit),
+        Command::InstallLaunchd { daemon, print } => crate::launchd::install(config, daemon, print),
+        Command::Service {
+            action: ServiceAction::Install,
+        } => crate::service::install(config),
+        #[cfg(feature = "tui")]
+        Command::Tui => unreachable!("handled before loading the configuration"),
+        Command::Protect { .. }
+        | Command::Completions { .. }
+        | Command::Init { .. }
+        | Command::Service { .. } => {
+            unreachable!("handled before loading the configuration")
+        }
+    };
+
+    log::logger().flush();
+    std::process::exit(code);
+}
+
+/// Settings printed redacted by `check`.
+const SECRETS: [&str; 3] = [
+    "control_token",
+    "smtp_password",
+    "otel_exporter_otlp_headers",
+];
+
+/// Prints the effective configuration, which loaded and validated, then
+/// checks the directories it names, 1 if one is unusable. Nothing is
+/// written.
+fn check(config: &Config) -> i32 {
+    match toml::Value::try_from(config) {
+        Ok(mut effective) => {
+            redact(&mut effective);
+            print!("{}", toml::to_string_pretty(&effective).unwrap_or_default());
+        }
+        Err(e) => warn!("Error printing the configuration: {e}"),
+    }
+
+    let mut problems = Vec::new();
+
+    if let Some(lock_dir) = &config.lock_dir {
+        if lock_dir.exists() {
+            problems.extend(
+                unusable(lock_dir).map(|e| format!("LOCK_DIR {}: {e}", lock_dir.display())),
+            );
+        }
+    }
+
+    for watch in &config.directories {
+        // Remote directories are only reached by a cleanup
+        if !storage::is_remote(&watch.directory) {
+            problems.extend(
+                unusable(Path::new(&watch.directory))
+                    .map(|e| format!("Directory {}: {e}", watch.directory)),
+            );
+        }
+
+        // Created when first needed
+        for (name, dir) in [
+            ("ARCHIVE_DIR", &watch.archive_dir),
+            ("QUARANTINE_DIR", &watch.quarantine_dir),
+            ("SCAN_CACHE_DIR", &watch.scan_cache_dir),
+        ] {
+            if let Some(dir) = dir.as_ref().filter(|dir| dir.exists()) {
+                problems.extend(
+                    unusable(dir)
+                        .map(|e| format!("{name} {} of {}: {e}", dir.display(), watch.directory)),
+                );
+            }
+        }
+    }
+
+    for problem in &problems {
+        error!(event = "error"; "{problem}");
+    }
+
+    match problems.is_empty() {
+        true => {
+            info!("{}", "The configuration is valid".green());
+            0
+        }
+        false => 1,
+    }
+}
+
+/// Replaces the value of every secret setting, at the top and in every
+/// directory.
+fn redact(value: &mut toml::Value) {
+    let Some(table) = value.as_table_mut() else {
+        return;
+    };
+
+    for (key, value) in table.iter_mut() {
+        match value {
+            toml::Value::Array(directories) if key == "directories" => {
+                directories.iter_mut().for_each(redact);
+            }
+            toml::Value::Array(values) if values.is_empty() => {}
+            value if SECRETS.contains(&key.as_str()) => {
+                *value = toml::Value::String("<redacted>".to_string());
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Why `dir` is not a directory files can be removed from, if so.
+fn unusable(dir: &Path) -> Option<String> {
+    match std::fs::metadata(dir) {
+        Err(e) => return Some(e.to_string()),
+        Ok(metadata) if !metadata.is_dir() => return Some("not a directory".to_string()),
+        Ok(_) => {}
+    }
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStrExt;
+
+        let path = std::ffi::CString::new(dir.as_os_str().as_bytes()).ok()?;
+        // SAFETY: the path is nul terminated
+        if unsafe { libc::access(path.as_ptr(), libc::W_OK | libc::X_OK) } != 0 {
+            return Some(format!("not writable: {}", std::io::Error::last_os_error()));
+        }
+    }
+
+    #[cfg(not(unix))]
+    if std::fs::metadata(dir).is_ok_and(|metadata| metadata.permissions().readonly()) {
+        return Some("not writable: read-only".to_string());
+    }
+
+    None
+}
+
+/// Prints the completions of every flag and subcommand for `shell`, 1 if
+/// they could not be, e.g. piped to a program that exited.
+pub fn completions(shell: clap_complete::Shell) -> i32 {
+    let mut script = Vec::new();
+    clap_complete::generate(
+        shell,
+        &mut Args::command(),
+        maxdirsize::APP_NAME,
+        &mut script,
+    );
+
+    match std::io::stdout().lock().write_all(&script) {
+        Ok(()) => 0,
+        Err(_) => 1,
+    }
+}
+
+/// Restores the latest eviction of every file at or below `paths`, or of
+/// the `last` files evicted, or prints every one with `list`, 1 if one
+/// could not be.
+fn restore(config: &Config, paths: &[PathBuf], last: Option<usize>, list: bool) -> i32 {
+    if config.directories.iter().all(|watch| {
+        watch.quarantine_dir.is_none()
+            && (watch.audit_log.is_none() || watch.delete_mode == DeleteMode::Delete)
+    }) {
+        error!(event = "error"; "Nothing to restore from, set QUARANTINE_DIR, or AUDIT_LOG with DELETE_MODE=trash or move");
+        return 1;
+    }
+
+    let found = match restore::restorable(&config.directories) {
+        Ok(found) => found,
+        Err(e) => {
+            error!(event = "error"; "Error looking for evicted files: {e}");
+            return 1;
+        }
+    };
+
+    if list {
+        for restorable in &found {
+            println!(
+                "{} {} {} ({:.2} MB)",
+                humantime::format_rfc3339_seconds(
+                    std::time::UNIX_EPOCH + Duration::from_secs(restorable.removed)
+                ),
+                restorable.mode(),
+                restorable.path.display(),
+                restorable.size as f64 / units::MIB as f64
+            );
+        }
+
+        return 0;
+    }
+
+    let mut failed = false;
+    let mut restored: Vec<&Path> = Vec::new();
+    let put_back = |restorable: &Restorable| match restorable.restore() {
+        Ok(()) => {
+            println!("Restored {}", restorable.path.display());
+            true
+        }
+        Err(e) => {
+            error!(event = "error"; "Error restoring {}: {e}", restorable.path.display());
+            false
+        }
+    };
+
+    if let Some(last) = last {
+        for restorable in restore::newest(&found, |_| true).into_iter().take(last) {
+            restored.push(&restorable.path);
+            failed |= !put_back(restorable);
+        }
+    }
+
+    for path in paths {
+        // Journals hold paths as scanned, quarantines and trashes resolved,
+        // the file itself being gone either way
+        let absolute = std::path::absolute(path).unwrap_or_else(|_| path.clone());
+        let resolved = std::fs::canonicalize(path).unwrap_or_else(|_| {
+            match (absolute.parent(), absolute.file_name()) {
+                (Some(parent), Some(name)) => std::fs::canonicalize(parent)
+                    .map(|parent| parent.join(name))
+                    .unwrap_or_else(|_| absolute.clone()),
+                _ => absolute.clone(),
+            }
+        });
+
+        let chosen = restore::newest(&found, |evicted| {
+            !restored.contains(&evicted)
+                && (evicted.starts_with(path)
+                    || evicted.starts_with(&absolute)
+                    || evicted.starts_with(&resolved))
+        });
+
+        if chosen.is_empty() {
+            error!(event = "error"; "Nothing evicted from {} to restore", path.display());
+            failed = true;
+        }
+
+        for restorable in chosen {
+            restored.push(&restorable.path);
+            failed |= !put_back(restorable);
+        }
+    }
+
+    failed as i32
+}
+
+/// Pins `paths`, or unpins them with `remove`, 1 if one could not be.
+pub fn protect(paths: &[PathBuf], remove: bool) -> i32 {
+    let mut failed = false;
+
+    for path in paths {
+        let (result, done) = match remove {
+            false => (pin::pin(path), "Pinned"),
+            true => (pin::unpin(path), "Unpinned"),
+        };
+
+        match result {
+            Ok(()) => println!("{done} {}", path.display()),
+            Err(e) => {
+                error!(event = "error"; "Error setting {} on {}: {e}", pin::ATTRIBUTE, path.display());
+                failed = true;
+            }
+        }
+    }
+
+    failed as i32
+}
+
+/// A cleaner of `watch` that only scans, without notifying anyone.
+fn scanner(watch: &WatchConfig) -> Cleaner {
+    Cleaner::new(WatchConfig {
+        watch_mode: WatchMode::Scan,
+        report_top: None,
+        webhook_url: None,
+        smtp_host: None,
+        desktop_notifications: false,
+        otel_exporter_otlp_endpoint: None,
+        ..watch.clone()
+    })
+}
+
+/// Prints the largest files and subdirectories of every directory, 1 if one
+/// could not be scanned.
+fn report(config: &Config, pool: &rayon::ThreadPool, top: usize) -> i32 {
+    let mut failed = false;
+
+    for (i, watch) in config.directories.iter().enumerate() {
+        // Every file is needed
+        let mut cleaner = scanner(&WatchConfig {
+            max_candidates: None,
+            ..watch.clone()
+        });
+
+        match pool.install(|| cleaner.scan()) {
+            Ok(scanned) => {
+                if i > 0 {
+                    println!();
+                }
+
+                print!(
+                    "{}",
+                    UsageReport::new(Path::new(&watch.directory), &scanned, top)
+                );
+            }
+            Err(e) => {
+                error!(event = "error"; "Error reading {}: {e}", watch.directory);
+                failed = true;
+            }
+        }
+    }
+
+    failed as i32
+}
+
+/// Prints the directory tree and the ages of the files of every directory,
+/// 1 if one could not be scanned.
+fn analyze(config: &Config, pool: &rayon::ThreadPool, depth: usize) -> i32 {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let mut failed = false;
+
+    for (i, watch) in config.directories.iter().enumerate() {
+        // Every file is needed
+        let mut cleaner = scanner(&WatchConfig {
+            max_candidates: None,
+            ..watch.clone()
+        });
+
+        match pool.install(|| cleaner.scan()) {
+            Ok(scanned) => {
+                if i > 0 {
+                    println!();
+                }
+
+                print!(
+                    "{}",
+                    Analysis::new(Path::new(&watch.directory), &scanned, depth, now)
+                );
+            }
+            Err(e) => {
+                error!(event = "error"; "Error reading {}: {e}", watch.directory);
+                failed = true;
+            }
+        }
+    }
+
+    failed as i32
+}
+
+/// Prints the status line of a Nagios plugin for the utilization of every
+/// directory, returning the status of the worst.
+fn check_usage(config: &Config, pool: &rayon::ThreadPool, warn: u8, crit: u8) -> i32 {
+    if warn > crit {
+        println!("USAGE UNKNOWN - --warn must not be above --crit");
+        return State::Unknown as i32;
+    }
+
+    let directories: Vec<Usage> = config
+        .directories
+        .iter()
+        .map(|watch| {
+            let measured = pool
+                .install(|| scanner(watch).scan())
+                .and_then(|scanned| {
+                    let limit = policy::size_limit(watch, scanned.total_size)?;
+                    Ok((scanned.total_size, limit))
+                })
+                .map_err(|e| e.to_string());
+
+            Usage {
+                directory: watch.directory.clone(),
+                limit: measured
+                    .as_ref()
+                    .ok()
+                    .map(|&(_, limit)| limit)
+                    .filter(|&limit| limit != u64::MAX),
+                size: measured.map(|(size, _)| size),
+            }
+        })
+        .collect();
+
+    let (state, line) = nagios::check(&directories, warn, crit);
+    println!("{line}");
+    state as i32
+}
+
+/// Writes what a cleanup of every directory would remove to `output`, or to
+/// the standard output. The status is the one of a `--once` run, as if the
+/// plan had been applied.
+fn plan(config: &Config, pool: &rayon::ThreadPool, output: Option<&Path>) -> i32 {
+    let mut outcome = CycleOutcome::WithinLimit;
+    let mut plans = Vec::new();
+
+    for watch in &config.directories {
+        let mut cleaner = scanner(watch);
+
+        match pool
+            .install(|| cleaner.scan())
+            .and_then(|scanned| cleaner.plan(scanned))
+        {
+            Ok(plan) => {
+                if plan.reason.is_some() || !plan.files.is_empty() {
+                    outcome = outcome.max(CycleOutcome::CleanedUp);
+                }
+
+                plans.push(DirectoryPlan::new(&watch.directory, plan));
+            }
+            Err(e) => {
+                error!(event = "error"; "Error reading {}: {e}", watch.directory);
+                outcome = CycleOutcome::Failed;
+            }
+        }
+    }
+
+    let plans = PlanFile::new(plans);
+
+    let written = match output {
+        Some(path) => std::fs::File::create(path).and_then(|file| {
+            let mut writer = std::io::BufWriter::new(file);
+            serde_json::to_writer_pretty(&mut writer, &plans)?;
+            writer.flush()
+        }),
+        None => {
+            let mut stdout = std::io::stdout().lock();
+            serde_json::to_writer_pretty(&mut stdout, &plans)
+                .map_err(std::io::Error::from)
+                .and_then(|()| writeln!(stdout))
+        }
+    };
+
+    if let Err(e) = written {
+        error!(event = "error"; "Error writing the plan: {e}");
+        return 1;
+    }
+
+    if let Some(path) = output {
+        let files: usize = plans
+            .directories
+            .iter()
+            .map(|directory| directory.plan.files.len())
+            .sum();
+
+        info!(
+            "{}",
+            format!("Wrote a plan removing {files} files to {}", path.display()).green()
+        );
+    }
+
+    outcome.exit_code()
+}
+
+/// Writes the unit running the current configuration to `output`, or the
+/// standard output.
+fn install_systemd(config: &Config, hardened: bool, output: Option<&Path>) -> i32 {
+    let invocation = match Invocation::current() {
+        Ok(invocation) => invocation,
+        Err(e) => {
+            error!(event = "error"; "Error locating the executable: {e}");
+            return 1;
+        }
+    };
+    let unit = unit::render(&invocation, config, hardened);
+
+    let written = match output {
+        Some(path) => std::fs::write(path, &unit),
+        None => std::io::stdout().lock().write_all(unit.as_bytes()),
+    };
+
+    if let Err(e) = written {
+        error!(event = "error"; "Error writing the unit: {e}");
+        return 1;
+    }
+
+    if let Some(path) = output {
+        info!(
+            "{}",
+            format!(
+                "Wrote {}, start it with `systemctl daemon-reload && systemctl enable --now {}`",
+                path.display(),
+                path.file_name().unwrap_or_default().to_string_lossy()
+            )
+            .green()
+        );
+    }
+
+    0
+}
+
+/// Removes what the plan at `path` lists, once revalidated, with the status
+/// of a `--once` run.
+fn apply(config: &Config, path: &Path) -> i32 {
+    let plans = match PlanFile::read(path) {
+        Ok(plans) => plans,
+        Err(e) => {
+            error!(event = "error"; "Error reading plan {}: {e}", path.display());
+            return 1;
+        }
+    };
+
+    let stop = Arc::new(AtomicBool::new(false));
+    let (waker, _wake) = mpsc::channel();
+
+    if let Err(e) = crate::signals::spawn(
+        waker,
+        stop.clone(),
+        Duration::from_secs(config.shutdown_timeout_seconds),
+    ) {
+        error!(event = "error"; "Error installing signal handlers: {e}");
+        return 1;
+    }
+
+    let mut outcome = CycleOutcome::WithinLimit;
+
+    for DirectoryPlan {
+        directory,
+        mut plan,
+        ..
+    } in plans.directories
+    {
+        if stop.load(Ordering::SeqCst) {
+            break;
+        }
+
+        let Some(watch) = config
+            .directories
+            .iter()
+            .find(|watch| watch.directory == directory)
+        else {
+            error!(
+                event = "error";
+                "{}",
+                format!("{directory} is in the plan but not configured, skipping it").red()
+            );
+            outcome = CycleOutcome::Failed;
+            continue;
+        };
+
+        let _lock = match crate::lock(&directory, config, &stop) {
+            Ok(Some(lock)) => lock,
+            Ok(None) => break,
+            Err(e) => {
+                error!(event = "error"; "Error locking {directory}: {e}");
+                outcome = CycleOutcome::Failed;
+                continue;
+            }
+        };
+
+        let cleaner = Cleaner::new(WatchConfig {
+            watch_mode: WatchMode::Scan,
+            report_top: None,
+            ..watch.clone()
+        })
+        .with_stop_flag(stop.clone());
+
+        let planned = plan.files.len();
+        let skipped = plan_file::revalidate(&mut plan, watch, cleaner.storage());
+
+        if skipped > 0 {
+            warn!(
+                event = "plan_revalidated", directory = directory.as_str(), skipped;
+                "{}",
+                format!(
+                    "Skipping {skipped} of {planned} planned files of {directory} that no longer match the plan made {}",
+                    plans.created
+                )
+                .yellow()
+            );
+        }
+
+        outcome = outcome.max(cleaner.execute(plan).outcome);
+    }
+
+    outcome.exit_code()
+}
+
+/// Prints what the history databases of the directories recorded, newest
+/// first, 1 if none is configured or one could not be read.
+#[cfg(feature = "history")]
+fn history(config: &Config, deletions: bool, filter: &Filter, json: bool) -> i32 {
+    let mut databases: Vec<&Path> = Vec::new();
+
+    for path in config
+        .directories
+        .iter()
+        .filter_map(|watch| watch.history_db.as_deref())
+    {
+        if !databases.contains(&path) {
+            databases.push(path);
+        }
+    }
+
+    if databases.is_empty() {
+        error!(event = "error"; "No history database configured, set HISTORY_DB");
+        return 1;
+    }
+
+    let mut lines = Vec::new();
+
+    for path in databases {
+        let history = match History::open(path) {
+            Ok(history) => history,
+            Err(e) => {
+                error!(event = "error"; "Error opening history database {}: {e}", path.display());
+                return 1;
+            }
+        };
+
+        let read = match deletions {
+            true => history.deletions(filter).map(|deletions| {
+                deletions
+                    .into_iter()
+                    .map(|deletion| {
+                        let line = match json {
+                            true => serde_json::to_string(&deletion).unwrap_or_default(),
+                            false => format!(
+                                "{} {} {} ({:.2} MB), {}, {}",
+                                deletion.timestamp,
+                                deletion.kind,
+                                deletion.path,
+                                deletion.size as f64 / units::MIB as f64,
+                                deletion.reason,
+                                deletion.mode
+                            ),
+                        };
+                        (deletion.timestamp, line)
+                    })
+                    .collect::<Vec<_>>()
+            }),
+            false => history.cycles(filter).map(|cycles| {
+                cycles
+                    .into_iter()
+                    .map(|cycle| {
+                        let line = match json {
+                            true => serde_json::to_string(&cycle).unwrap_or_default(),
+                            false => format!(
+                                "{} {} {}{}: scanned {} files in {:.2}s, {:.2} MB before and {:.2} MB after, removed {} files and {} directories freeing {:.2} MB, {} errors",
+                                cycle.finished_at,
+                                cycle.directory,
+                                cycle.outcome,
+                                match cycle.dry_run {
+                                    true => " (dry run)",
+                                    false => "",
+                                },
+                                cycle.files_scanned,
+                                cycle.scan_seconds,
+                                cycle.bytes_before as f64 / units::MIB as f64,
+                                cycle.bytes_after as f64 / units::MIB as f64,
+                                cycle.files_deleted,
+                                cycle.dirs_deleted,
+                                cycle.bytes_freed as f64 / units::MIB as f64,
+                                cycle.errors
+                            ),
+                        };
+                        (cycle.finished_at, line)
+                    })
+                    .collect()
+            }),
+        };
+
+        match read {
+            Ok(read) => lines.extend(read),
+            Err(e) => {
+                error!(event = "error"; "Error reading history database {}: {e}", path.display());
+                return 1;
+            }
+        }
+    }
+
+    // The timestamps are RFC 3339 in UTC, sorting them as text sorts them by time
+    lines.sort_by(|(a, _), (b, _)| b.cmp(a));
+
+    for (_, line) in lines.into_iter().take(filter.limit) {
+        println!("{line}");
+    }
+
+    0
+}
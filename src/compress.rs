@@ -0,0 +1,170 @@
+//! Compressing files in place before they are removed.
+
+#[cfg(feature = "compression")]
+use std::fs::{self, File};
+use std::io;
+#[cfg(feature = "compression")]
+use std::io::{BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+
+use crate::config::Compression;
+#[cfg(feature = "compression")]
+use crate::APP_NAME;
+
+impl Compression {
+    /// Extension appended to the files compressed with it.
+    pub fn extension(self) -> &'static str {
+        match self {
+            Compression::Gzip => "gz",
+            Compression::Zstd => "zst",
+        }
+    }
+}
+
+/// Whether `path` was compressed by either format, in which case it is never
+/// compressed again.
+pub fn is_compressed(path: &Path) -> bool {
+    path.extension().is_some_and(|extension| {
+        [Compression::Gzip, Compression::Zstd]
+            .iter()
+            .any(|format| extension == format.extension())
+    })
+}
+
+/// Replaces `path` with a compressed copy next to it, named after it with the
+/// extension of `format` appended and keeping its modification time. Returns
+/// the path and size of the copy. Only regular files are compressed, never
+/// what a symlink points to.
+#[cfg(feature = "compression")]
+pub fn compress(path: &Path, format: Compression) -> io::Result<(PathBuf, u64)> {
+    let metadata = fs::symlink_metadata(path)?;
+
+    if !metadata.file_type().is_file() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{} is not a regular file", path.display()),
+        ));
+    }
+
+    let mut target = path.as_os_str().to_owned();
+    target.push(format!(".{}", format.extension()));
+    let target = PathBuf::from(target);
+
+    if target.exists() {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("{} already exists", target.display()),
+        ));
+    }
+
+    let mut partial = target.as_os_str().to_owned();
+    partial.push(format!(".{APP_NAME}-partial"));
+    let partial = PathBuf::from(partial);
+
+    let modified = metadata.modified()?;
+
+    let write = || -> io::Result<u64> {
+        let mut input = BufReader::new(File::open(path)?);
+        let output = BufWriter::new(File::create(&partial)?);
+
+        let file = match format {
+            Compression::Gzip => {
+                let mut encoder =
+                    flate2::write::GzEncoder::new(output, flate2::Compression::default());
+                io::copy(&mut input, &mut encoder)?;
+                encoder.finish()?.into_inner().map_err(|e| e.into_error())?
+            }
+            Compression::Zstd => {
+                let mut encoder = zstd::Encoder::new(output, 0)?;
+                io::copy(&mut input, &mut encoder)?;
+                encoder.finish()?.into_inner().map_err(|e| e.into_error())?
+            }
+        };
+
+        file.set_modified(modified)?;
+        file.sync_all()?;
+        let size = file.metadata()?.len();
+
+        fs::rename(&partial, &target)?;
+
+        Ok(size)
+    };
+
+    let size = match write() {
+        Ok(size) => size,
+        Err(e) => {
+            let _ = fs::remove_file(&partial);
+            return Err(e);
+        }
+    };
+
+    fs::remove_file(path)?;
+
+    Ok((target, size))
+}
+
+/// Built without the `compression` feature, `COMPRESS_AFTER` is refused.
+#[cfg(not(feature = "compression"))]
+pub fn compress(path: &Path, _format: Compression) -> io::Result<(PathBuf, u64)> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        format!(
+            "not compressing {}, built without the `compression` feature",
+            path.display()
+        ),
+    ))
+}
+
+#[cfg(all(test, feature = "compression"))]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    #[test]
+    fn compress_replaces_the_file_once() {
+        let dir = std::env::temp_dir().join(format!("{APP_NAME}-compress-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+
+        let contents = "a line of log output\n".repeat(1000);
+
+        for format in [Compression::Gzip, Compression::Zstd] {
+            let path = dir.join("app.log");
+            fs::write(&path, &contents).unwrap();
+
+            let (target, size) = compress(&path, format).unwrap();
+
+            assert!(!path.exists());
+            assert!(is_compressed(&target));
+            assert!(size < contents.len() as u64);
+
+            let mut decompressed = String::new();
+            let file = File::open(&target).unwrap();
+            match format {
+                Compression::Gzip => flate2::read::GzDecoder::new(file)
+                    .read_to_string(&mut decompressed)
+                    .unwrap(),
+                Compression::Zstd => zstd::Decoder::new(file)
+                    .unwrap()
+                    .read_to_string(&mut decompressed)
+                    .unwrap(),
+            };
+            assert_eq!(decompressed, contents);
+        }
+
+        #[cfg(unix)]
+        {
+            let link = dir.join("link.log");
+            std::os::unix::fs::symlink(dir.join("app.log.gz"), &link).unwrap();
+
+            let e = compress(&link, Compression::Gzip).unwrap_err();
+            assert_eq!(e.kind(), io::ErrorKind::InvalidInput);
+            assert!(fs::symlink_metadata(&link)
+                .unwrap()
+                .file_type()
+                .is_symlink());
+            assert!(!dir.join("link.log.gz").exists());
+        }
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
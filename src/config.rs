@@ -0,0 +1,2087 @@
+use clap::builder::BoolishValueParser;
+use clap::{Parser, Subcommand, ValueEnum};
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::{Component, Path, PathBuf};
+#[cfg(feature = "history")]
+use std::time::SystemTime;
+
+#[cfg(feature = "history")]
+use crate::history;
+use crate::logging::{EventFormat, LogFormat, LogTarget};
+use crate::owners::Owners;
+#[cfg(feature = "s3")]
+use crate::s3;
+use crate::schedule::{QuietWindow, Schedule, Timezone};
+use crate::sftp;
+use crate::storage;
+use crate::units;
+use crate::{APP_NAME, VERSION};
+
+fn default_margin() -> u8 {
+    85
+}
+
+fn default_weight() -> f64 {
+    1.0
+}
+
+fn default_shred_passes() -> u32 {
+    3
+}
+
+fn default_delete_threads() -> usize {
+    1
+}
+
+fn default_io_priority() -> u8 {
+    4
+}
+
+fn default_quarantine_retention() -> u64 {
+    24 * 3600
+}
+
+fn default_high_watermark() -> u8 {
+    100
+}
+
+fn default_rescan_interval() -> u64 {
+    3600
+}
+
+fn default_shutdown_timeout() -> u64 {
+    30
+}
+
+fn default_pinned_files() -> bool {
+    true
+}
+
+/// Command line flags. Every flag falls back to the environment variable of
+/// the same name, and anything left unset is taken from the config file.
+#[derive(Parser, Serialize)]
+#[command(name = APP_NAME, version = VERSION, about)]
+pub struct Args {
+    #[command(subcommand)]
+    #[serde(skip)]
+    pub command: Option<Command>,
+    /// Path to a TOML configuration file
+    #[arg(long, env = "CONFIG")]
+    #[serde(skip)]
+    pub config: Option<PathBuf>,
+    /// Time between cleanup runs, e.g. `60` or `15m`, 0 runs a single cleanup
+    #[arg(long, env = "INTERVAL", value_parser = units::parse_seconds)]
+    pub interval: Option<u64>,
+    /// Same as --interval, kept for compatibility
+    #[arg(long, env = "INTERVAL_SECONDS", value_parser = units::parse_seconds, hide = true)]
+    pub interval_seconds: Option<u64>,
+    /// Shortest time between cleanup runs, reached as the directory nears
+    /// its limit, e.g. `1m` [default: INTERVAL]
+    #[arg(long, env = "MIN_INTERVAL", value_parser = units::parse_seconds)]
+    pub min_interval: Option<u64>,
+    /// Longest time between cleanup runs, reached while the directory stays
+    /// well below its limit and does not grow, e.g. `1h` [default: INTERVAL]
+    #[arg(long, env = "MAX_INTERVAL", value_parser = units::parse_seconds)]
+    pub max_interval: Option<u64>,
+    /// Time a cleanup may take before it is aborted and reported as failed,
+    /// e.g. `30m` for a mount that may hang
+    #[arg(long, env = "CYCLE_TIMEOUT", value_parser = units::parse_seconds)]
+    pub cycle_timeout: Option<u64>,
+    /// Cron expression cleanups run on in local time instead of every
+    /// INTERVAL, e.g. `*/10 2-6 * * *` for every 10 minutes at night
+    #[arg(long, env = "SCHEDULE")]
+    pub schedule: Option<String>,
+    /// Comma separated daily windows nothing is removed during while scans
+    /// still run, e.g. `mon-fri 08:00-20:00,sat 10:00-14:00`
+    #[arg(long, env = "QUIET_HOURS", value_delimiter = ',')]
+    pub quiet_hours: Option<Vec<String>>,
+    /// IANA timezone of SCHEDULE and QUIET_HOURS, e.g. `Europe/Berlin`
+    /// [default: local]
+    #[arg(long, env = "TIMEZONE")]
+    pub timezone: Option<String>,
+    /// Directory to watch, `s3://bucket/prefix` for objects in S3 or
+    /// `sftp://user@host:port/path` for a directory of an SSH server
+    #[arg(long, env = "DIRECTORY")]
+    pub directory: Option<String>,
+    /// Endpoint of an S3 compatible store, e.g. `http://minio:9000`
+    /// [default: AWS in S3_REGION]
+    #[arg(long, env = "S3_ENDPOINT")]
+    pub s3_endpoint: Option<String>,
+    /// Region S3 requests are signed for [default: AWS_REGION or us-east-1]
+    #[arg(long, env = "S3_REGION")]
+    pub s3_region: Option<String>,
+    /// Private key ssh authenticates to `sftp://` servers with
+    /// [default: the ssh client's]
+    #[arg(long, env = "SFTP_IDENTITY_FILE")]
+    pub sftp_identity_file: Option<PathBuf>,
+    /// Known hosts file the keys of `sftp://` servers are checked against
+    /// [default: the ssh client's]
+    #[arg(long, env = "SFTP_KNOWN_HOSTS")]
+    pub sftp_known_hosts: Option<PathBuf>,
+    /// Size limit that triggers a cleanup, e.g. `250GB` or `1.5TiB`
+    #[arg(long, env = "MAX_SIZE", value_parser = units::parse_size)]
+    pub max_size: Option<u64>,
+    /// Size limit in MB that triggers a cleanup
+    #[arg(long, env = "MAX_SIZE_MB")]
+    pub max_size_mb: Option<u64>,
+    /// Size limit as a percentage of the capacity of the filesystem holding
+    /// the directory, read every cycle so it follows resizes
+    #[arg(long, env = "MAX_SIZE_PERCENT")]
+    pub max_size_percent: Option<f64>,
+    /// Clean up whenever the filesystem holding the directory has less than
+    /// this percentage of free space, with or without a size limit
+    #[arg(long, env = "MIN_FREE_PERCENT")]
+    pub min_free_percent: Option<f64>,
+    /// Number of files that triggers a cleanup, with or without a size limit
+    #[arg(long, env = "MAX_FILES")]
+    pub max_files: Option<u64>,
+    /// Percentage of the size and file limits to clean down to [default: 85]
+    #[arg(long, env = "MARGIN")]
+    pub margin: Option<u8>,
+    /// Percentage of the limits above which a cleanup starts [default: 100]
+    #[arg(long, env = "HIGH_WATERMARK")]
+    pub high_watermark: Option<u8>,
+    /// Percentage of the limits a cleanup removes files down to, overrides
+    /// MARGIN
+    #[arg(long, env = "LOW_WATERMARK")]
+    pub low_watermark: Option<u8>,
+    /// Run a single cleanup of every directory and exit
+    #[arg(long, env = "ONCE", value_parser = BoolishValueParser::new())]
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub once: bool,
+    /// Log output format [default: text]
+    #[arg(long, env = "LOG_FORMAT", value_enum)]
+    pub log_format: Option<LogFormat>,
+    /// Where logs go [default: stderr]
+    #[arg(long, env = "LOG_TARGET", value_enum)]
+    pub log_target: Option<LogTarget>,
+    /// File logs are appended to in place of standard error
+    #[arg(long, env = "LOG_FILE")]
+    pub log_file: Option<PathBuf>,
+    /// Also write every event, e.g. file_removed or cycle_finished, to
+    /// standard output whatever RUST_LOG
+    #[arg(long, env = "EVENTS", value_enum)]
+    pub events: Option<EventFormat>,
+    /// Detach from the terminal and run in the background
+    #[arg(long, env = "DAEMON", value_parser = BoolishValueParser::new())]
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub daemon: bool,
+    /// File the PID is written to, locked while running
+    #[arg(long, env = "PID_FILE")]
+    pub pid_file: Option<PathBuf>,
+    /// Address to serve Prometheus metrics on, e.g. 0.0.0.0:9200
+    #[arg(long, env = "METRICS_ADDR")]
+    pub metrics_addr: Option<String>,
+    /// StatsD server the figures of every cycle are sent to over UDP, e.g.
+    /// 127.0.0.1:8125
+    #[arg(long, env = "STATSD_ADDR")]
+    pub statsd_addr: Option<String>,
+    /// How metrics sent to STATSD_ADDR are named [default: statsd]
+    #[arg(long, env = "STATSD_FORMAT", value_enum)]
+    pub statsd_format: Option<StatsdFormat>,
+    /// JSON file the status of every directory is written to after each
+    /// cycle, e.g. /run/maxdirsize/status.json
+    #[arg(long, env = "STATUS_FILE")]
+    pub status_file: Option<PathBuf>,
+    /// Address to serve `/healthz` and `/status` on, e.g. 0.0.0.0:8080, may
+    /// be the same as METRICS_ADDR
+    #[arg(long, env = "HEALTH_ADDR")]
+    pub health_addr: Option<String>,
+    /// Address to serve the control API on, e.g. 127.0.0.1:9300
+    #[arg(long, env = "CONTROL_ADDR")]
+    pub control_addr: Option<String>,
+    /// Bearer token the control API requires
+    #[arg(long, env = "CONTROL_TOKEN", hide_env_values = true)]
+    pub control_token: Option<String>,
+    /// Unix socket `maxdirsize ctl` sends its commands to, only accessible
+    /// to the owner
+    #[arg(long, env = "CONTROL_SOCKET")]
+    pub control_socket: Option<PathBuf>,
+    /// Threads used to scan directories, 0 uses one per CPU [default: 0]
+    #[arg(long, env = "SCAN_THREADS")]
+    pub scan_threads: Option<usize>,
+    /// Log what would be removed without touching any files
+    #[arg(long, env = "DRY_RUN", value_parser = BoolishValueParser::new())]
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub dry_run: bool,
+    /// How file sizes are measured [default: apparent]
+    #[arg(long, env = "SIZE_ACCOUNTING", value_enum)]
+    pub size_accounting: Option<SizeAccounting>,
+    /// Count and clean up what symlinks point to instead of the links, only
+    /// ever removing files inside the directory
+    #[arg(long, env = "FOLLOW_SYMLINKS", value_parser = BoolishValueParser::new())]
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub follow_symlinks: bool,
+    /// Do not descend into mount points inside the directory, like `du -x`
+    #[arg(long, env = "ONE_FILE_SYSTEM", value_parser = BoolishValueParser::new())]
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub one_file_system: bool,
+    /// Allow directories such as `/`, `/etc` or the home directory, which are
+    /// otherwise refused in case of a typo
+    #[arg(long, env = "FORCE_DANGEROUS_PATH", value_parser = BoolishValueParser::new())]
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub force_dangerous_path: bool,
+    /// Leave files held open by a process for the next cycle instead of
+    /// removing them, Linux only
+    #[arg(long, env = "SKIP_OPEN_FILES", value_parser = BoolishValueParser::new())]
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub skip_open_files: bool,
+    /// Which files are removed first when over the limit
+    /// [default: oldest_first]
+    #[arg(long, env = "EVICTION_POLICY", value_enum)]
+    pub eviction_policy: Option<EvictionPolicyKind>,
+    /// Comma separated suffixes of files removed along with the file they are
+    /// named after, e.g. `.srt,.json` for `video.srt` and `video.mp4.json`
+    /// with `video.mp4`
+    #[arg(long, env = "SIDECARS", value_delimiter = ',')]
+    pub sidecars: Option<Vec<String>>,
+    /// What is evicted at once: single files, or whole immediate
+    /// subdirectories aged by their newest file [default: file]
+    #[arg(long, env = "EVICTION_UNIT", value_enum)]
+    pub eviction_unit: Option<EvictionUnit>,
+    /// What happens to copies of identical files before anything else is
+    /// evicted over a limit [default: off]
+    #[arg(long, env = "DEDUPLICATE", value_enum)]
+    pub deduplicate: Option<Deduplication>,
+    /// Weight of each hour since a file was modified for the weighted policy,
+    /// or since it was last used for the gdsf one [default: 1]
+    #[arg(long, env = "EVICTION_AGE_WEIGHT")]
+    pub eviction_age_weight: Option<f64>,
+    /// Weight of each MiB of a file for the weighted policy, hours of grace
+    /// of a 1 MiB file for the gdsf one [default: 1]
+    #[arg(long, env = "EVICTION_SIZE_WEIGHT")]
+    pub eviction_size_weight: Option<f64>,
+    /// Same as --eviction-policy, kept for compatibility
+    #[arg(long, env = "DELETION_ORDER", value_enum, hide = true)]
+    pub deletion_order: Option<EvictionPolicyKind>,
+    /// Comma separated size limits of subdirectories, enforced on top of
+    /// the overall limits, e.g. `logs=1GB,videos=50GB`
+    #[arg(long, env = "QUOTAS", value_delimiter = ',')]
+    pub quotas: Option<Vec<String>>,
+    /// Comma separated size limits of the files matching patterns relative
+    /// to the directory, enforced on top of the overall limits, e.g.
+    /// `*.log=2GB,*.core=500MB`
+    #[arg(long, env = "BUDGETS", value_delimiter = ',')]
+    pub budgets: Option<Vec<String>>,
+    /// Files modified within this long (seconds or e.g. `10m`) are never
+    /// removed [default: 0]
+    #[arg(long, env = "MIN_AGE_SECONDS", value_parser = units::parse_seconds)]
+    pub min_age_seconds: Option<u64>,
+    /// Most files a single cleanup removes, the rest wait for the next cycles
+    #[arg(long, env = "MAX_DELETIONS_PER_CYCLE")]
+    pub max_deletions_per_cycle: Option<u64>,
+    /// Most bytes a single cleanup removes, e.g. `50GB`
+    #[arg(long, env = "MAX_BYTES_PER_CYCLE", value_parser = units::parse_size)]
+    pub max_bytes_per_cycle: Option<u64>,
+    /// Keep only this many removal candidates in memory while scanning
+    /// instead of every file, for trees too big to hold, e.g. `1000000`
+    #[arg(long, env = "MAX_CANDIDATES")]
+    pub max_candidates: Option<usize>,
+    /// Log the largest files and immediate subdirectories, this many of
+    /// each, after every scan
+    #[arg(long, env = "REPORT_TOP")]
+    pub report_top: Option<usize>,
+    /// Number of most recently modified files never removed from each
+    /// immediate subdirectory, and from the directory itself
+    #[arg(long, env = "KEEP_NEWEST")]
+    pub keep_newest: Option<usize>,
+    /// Files not modified for this long, e.g. `7d`, are removed every cycle
+    /// whatever the size of the directory
+    #[arg(long, env = "MAX_AGE", value_parser = units::parse_seconds)]
+    pub max_age: Option<u64>,
+    /// Dangling symlinks and empty files not modified for this long, e.g.
+    /// `1h`, are removed every cycle whatever the size of the directory
+    #[arg(long, env = "CLUTTER_MAX_AGE", value_parser = units::parse_seconds)]
+    pub clutter_max_age: Option<u64>,
+    /// Files not modified for this long, e.g. `1d`, are compressed in place
+    /// before anything is removed
+    #[arg(long, env = "COMPRESS_AFTER", value_parser = units::parse_seconds)]
+    pub compress_after: Option<u64>,
+    /// Format files are compressed with [default: gzip]
+    #[arg(long, env = "COMPRESSION", value_enum)]
+    pub compression: Option<Compression>,
+    /// Size limit of the compressed files, e.g. `10GB`, the oldest ones are
+    /// removed above it
+    #[arg(long, env = "COMPRESSED_MAX_SIZE", value_parser = units::parse_size)]
+    pub compressed_max_size: Option<u64>,
+    /// Comma separated globs, relative to the directory, of files that are
+    /// never counted nor removed, e.g. `*.lock,important/**`
+    #[arg(long, env = "EXCLUDE_PATTERNS", value_delimiter = ',')]
+    pub exclude_patterns: Option<Vec<String>>,
+    /// Honor `.maxdirsizeignore` files (gitignore style patterns of what is
+    /// never removed) and empty `.keep` files (protecting their whole
+    /// directory) found in the directory
+    #[arg(long, env = "IGNORE_FILES", value_parser = BoolishValueParser::new())]
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub ignore_files: bool,
+    /// Comma separated users, names or ids, whose files alone may be
+    /// removed, or those of OWNER_GROUPS; the others are still counted
+    #[arg(long, env = "OWNER_USERS", value_delimiter = ',')]
+    pub owner_users: Option<Vec<String>>,
+    /// Comma separated groups, names or ids, whose files alone may be
+    /// removed, or those of OWNER_USERS
+    #[arg(long, env = "OWNER_GROUPS", value_delimiter = ',')]
+    pub owner_groups: Option<Vec<String>>,
+    /// Never remove files their owner may not write to
+    #[arg(long, env = "REQUIRE_OWNER_WRITE", value_parser = BoolishValueParser::new())]
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub require_owner_write: bool,
+    /// Never remove files pinned with `maxdirsize protect`, which costs an
+    /// extended attribute lookup per file [default: true]
+    #[arg(long, env = "PINNED_FILES", value_parser = BoolishValueParser::new())]
+    pub pinned_files: Option<bool>,
+    /// Comma separated subdirectories, relative to the directory, never
+    /// scanned: nothing below is counted nor removed, e.g. `app/data,.git`
+    #[arg(long, env = "EXCLUDE_DIRS", value_delimiter = ',')]
+    pub exclude_dirs: Option<Vec<PathBuf>>,
+    /// Levels of subdirectories scanned, 1 for only the files directly in the
+    /// directory; what is deeper is neither counted nor removed
+    #[arg(long, env = "MAX_DEPTH")]
+    pub max_depth: Option<usize>,
+    /// Comma separated globs, relative to the directory, restricting which
+    /// files may be removed; everything is still counted, e.g. `*.mp4,*.ts`
+    #[arg(long, env = "INCLUDE_PATTERNS", value_delimiter = ',')]
+    pub include_patterns: Option<Vec<String>>,
+    /// How changes are detected [default: scan]
+    #[arg(long, env = "WATCH_MODE", value_enum)]
+    pub watch_mode: Option<WatchMode>,
+    /// In notify and cache modes, time between full rescans [default: 1h]
+    #[arg(long, env = "RESCAN_INTERVAL_SECONDS", value_parser = units::parse_seconds)]
+    pub rescan_interval_seconds: Option<u64>,
+    /// Where WATCH_MODE=cache keeps listings between cycles, a file per
+    /// directory
+    #[arg(long, env = "SCAN_CACHE_DIR")]
+    pub scan_cache_dir: Option<PathBuf>,
+    /// How evicted files are removed [default: delete]
+    #[arg(long, env = "DELETE_MODE", value_enum)]
+    pub delete_mode: Option<DeleteMode>,
+    /// Times DELETE_MODE=shred overwrites files, the last time with zeros
+    /// [default: 3]
+    #[arg(long, env = "SHRED_PASSES")]
+    pub shred_passes: Option<u32>,
+    /// Where DELETE_MODE=move puts evicted files, preferably on another volume
+    #[arg(long, env = "ARCHIVE_DIR")]
+    pub archive_dir: Option<PathBuf>,
+    /// Where DELETE_MODE=quarantine holds evicted files until they are
+    /// purged, preferably on another volume
+    #[arg(long, env = "QUARANTINE_DIR")]
+    pub quarantine_dir: Option<PathBuf>,
+    /// Time quarantined files are kept before being purged for good
+    /// [default: 24h]
+    #[arg(long, env = "QUARANTINE_RETENTION", value_parser = units::parse_seconds)]
+    pub quarantine_retention: Option<u64>,
+    /// Limit of the files quarantined from the directory, the oldest being
+    /// purged early beyond it, e.g. `1GB`
+    #[arg(long, env = "QUARANTINE_MAX_SIZE", value_parser = units::parse_size)]
+    pub quarantine_max_size: Option<u64>,
+    /// Files removed at once, each on a thread of its own, e.g. `16` where
+    /// removals wait on the network as with NFS [default: 1]
+    #[arg(long, env = "DELETE_THREADS")]
+    pub delete_threads: Option<usize>,
+    /// Most files removed per second, e.g. `50` or `0.5`
+    #[arg(long, env = "DELETE_RATE")]
+    pub delete_rate: Option<f64>,
+    /// Most bytes removed per second, e.g. `100MB`
+    #[arg(long, env = "DELETE_BANDWIDTH", value_parser = units::parse_size)]
+    pub delete_bandwidth: Option<u64>,
+    /// Time a cleanup in progress gets to stop after SIGTERM or SIGINT
+    /// before the process exits anyway [default: 30s]
+    #[arg(long, env = "SHUTDOWN_TIMEOUT_SECONDS", value_parser = units::parse_seconds)]
+    pub shutdown_timeout_seconds: Option<u64>,
+    /// File every removal is appended to as a JSON line
+    #[arg(long, env = "AUDIT_LOG")]
+    pub audit_log: Option<PathBuf>,
+    /// SQLite database every cycle and removal is recorded in, as listed by
+    /// `maxdirsize history`
+    #[arg(long, env = "HISTORY_DB")]
+    pub history_db: Option<PathBuf>,
+    /// Warns, and posts to WEBHOOK_URL, when the directory grows fast
+    /// enough to reach its limit within this long, e.g. `1d`, measuring its
+    /// growth over as long
+    #[arg(long, env = "FULL_WARNING_HORIZON", value_parser = units::parse_seconds)]
+    pub full_warning_horizon: Option<u64>,
+    /// URL receiving a JSON POST when a cleanup starts, finishes or fails
+    /// or the directory is about to reach its limit
+    #[arg(long, env = "WEBHOOK_URL")]
+    pub webhook_url: Option<String>,
+    /// OTLP/HTTP endpoint every cycle is exported to as a trace and
+    /// metrics, e.g. http://localhost:4318
+    #[arg(long, env = "OTEL_EXPORTER_OTLP_ENDPOINT")]
+    pub otel_exporter_otlp_endpoint: Option<String>,
+    /// Comma separated `name=value` headers sent to the OTLP endpoint, e.g.
+    /// for authentication
+    #[arg(
+        long,
+        env = "OTEL_EXPORTER_OTLP_HEADERS",
+        value_delimiter = ',',
+        hide_env_values = true
+    )]
+    pub otel_exporter_otlp_headers: Option<Vec<String>>,
+    /// `service.name` of the exported traces and metrics [default: maxdirsize]
+    #[arg(long, env = "OTEL_SERVICE_NAME")]
+    pub otel_service_name: Option<String>,
+    /// Show a desktop notification when a cleanup is about to remove files
+    /// and once it is done
+    #[arg(long, env = "DESKTOP_NOTIFICATIONS", value_parser = BoolishValueParser::new())]
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub desktop_notifications: bool,
+    /// Shape of what is posted to WEBHOOK_URL [default: json]
+    #[arg(long, env = "NOTIFY_FORMAT", value_enum)]
+    pub notify_format: Option<NotifyFormat>,
+    /// SMTP server summaries of cleanups are emailed through, e.g.
+    /// smtp.example.com
+    #[arg(long, env = "SMTP_HOST")]
+    pub smtp_host: Option<String>,
+    /// [default: 587 with starttls, 465 with tls, 25 with none]
+    #[arg(long, env = "SMTP_PORT")]
+    pub smtp_port: Option<u16>,
+    /// How the connection to SMTP_HOST is encrypted [default: starttls]
+    #[arg(long, env = "SMTP_TLS", value_enum)]
+    pub smtp_tls: Option<SmtpTls>,
+    #[arg(long, env = "SMTP_USERNAME")]
+    pub smtp_username: Option<String>,
+    #[arg(long, env = "SMTP_PASSWORD", hide_env_values = true)]
+    pub smtp_password: Option<String>,
+    /// Sender of the emails, e.g. `maxdirsize <ops@example.com>`
+    #[arg(long, env = "EMAIL_FROM")]
+    pub email_from: Option<String>,
+    /// Comma separated recipients of the emails
+    #[arg(long, env = "EMAIL_TO", value_delimiter = ',')]
+    pub email_to: Option<Vec<String>>,
+    /// Emails a cleanup that removed at least this many files
+    #[arg(long, env = "EMAIL_MIN_FILES")]
+    pub email_min_files: Option<u64>,
+    /// Emails a cleanup that freed at least this much, e.g. `10GB`
+    #[arg(long, env = "EMAIL_MIN_BYTES", value_parser = units::parse_size)]
+    pub email_min_bytes: Option<u64>,
+    /// Emails a cycle with at least this many errors
+    #[arg(long, env = "EMAIL_MIN_ERRORS")]
+    pub email_min_errors: Option<u64>,
+    /// Script run before each removal, as `script PATH SIZE AGE_SECONDS`; a
+    /// non-zero exit status keeps the file this cycle
+    #[arg(long, env = "CANDIDATE_HOOK")]
+    pub candidate_hook: Option<PathBuf>,
+    /// Shell command run before a cleanup removes anything, which is
+    /// cancelled if it fails
+    #[arg(long, env = "PRE_CLEANUP_CMD")]
+    pub pre_cleanup_cmd: Option<String>,
+    /// Shell command run once a cleanup is done
+    #[arg(long, env = "POST_CLEANUP_CMD")]
+    pub post_cleanup_cmd: Option<String>,
+    /// Niceness set at startup, from -20 to 19, e.g. `10` to leave the CPU
+    /// to other processes first
+    #[arg(long, env = "NICE", allow_hyphen_values = true)]
+    pub nice: Option<i32>,
+    /// IO scheduling class set at startup, on Linux
+    #[arg(long, env = "IO_CLASS", value_enum)]
+    pub io_class: Option<IoClass>,
+    /// Priority within IO_CLASS, from 0 (highest) to 7 [default: 4]
+    #[arg(long, env = "IO_PRIORITY")]
+    pub io_priority: Option<u8>,
+    /// Scan with the idle IO class, only touching the disk when nothing else
+    /// does, on Linux
+    #[arg(long, env = "SCAN_IO_IDLE", value_parser = BoolishValueParser::new())]
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub scan_io_idle: bool,
+    /// Where the lock files keeping a second instance from cleaning up the
+    /// same directories are created [default: the temporary directory]
+    #[arg(long, env = "LOCK_DIR")]
+    pub lock_dir: Option<PathBuf>,
+    /// Wait for another instance cleaning up a directory to exit instead of
+    /// failing at startup
+    #[arg(long, env = "LOCK_WAIT", value_parser = BoolishValueParser::new())]
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub lock_wait: bool,
+}
+
+/// Runs something other than the cleanups, on the configured directories.
+#[derive(Subcommand, Debug, Clone)]
+pub enum Command {
+    /// Print the largest files and immediate subdirectories and exit,
+    /// without removing anything
+    Report {
+        /// How many of each to print
+        #[arg(long, default_value_t = 10)]
+        top: usize,
+    },
+    /// Print the size and file count of every directory down to a depth,
+    /// like `du`, and the ages of the files and exit, without removing
+    /// anything
+    Analyze {
+        /// How many levels of subdirectories to print
+        #[arg(long, default_value_t = 3)]
+        depth: usize,
+    },
+    /// Write a JSON plan of what a cleanup of every directory would remove
+    /// and exit, without removing anything
+    Plan {
+        /// File the plan is written to [default: the standard output]
+        #[arg(long, short)]
+        output: Option<PathBuf>,
+    },
+    /// Remove what a plan lists and exit, skipping the files changed since
+    /// it was made
+    Apply {
+        /// Plan written by `maxdirsize plan`
+        plan: PathBuf,
+    },
+    /// Print the cycles, or removals, recorded in HISTORY_DB, newest first
+    #[cfg(feature = "history")]
+    History {
+        /// List the removed files and directories instead of the cycles
+        #[arg(long)]
+        deletions: bool,
+        /// Only what happened since, e.g. `2026-10-01`, `2026-10-01 22:00`
+        /// or `2026-10-01T22:00:00Z`
+        #[arg(long, value_parser = history::parse_time)]
+        since: Option<SystemTime>,
+        /// Only what happened before
+        #[arg(long, value_parser = history::parse_time)]
+        until: Option<SystemTime>,
+        /// Only directories, or removed paths, matching this glob, e.g.
+        /// `*.log`
+        #[arg(long)]
+        path: Option<String>,
+        /// Only cycles freeing, or removals of, at least this much, e.g. `1GB`
+        #[arg(long, value_parser = units::parse_size)]
+        min_bytes: Option<u64>,
+        /// Most entries printed
+        #[arg(long, default_value_t = 50)]
+        limit: usize,
+        /// Print a JSON object per line
+        #[arg(long)]
+        json: bool,
+    },
+    /// Put evicted files back where they were, recreating their
+    /// directories: quarantined ones, and those AUDIT_LOG records as moved to
+    /// ARCHIVE_DIR or to the trash. The latest eviction of a file evicted
+    /// several times is restored
+    Restore {
+        /// Files to restore, or directories to restore everything evicted
+        /// from below
+        #[arg(required_unless_present_any = ["last", "list"])]
+        paths: Vec<PathBuf>,
+        /// Restore the files evicted last, this many of them
+        #[arg(long)]
+        last: Option<usize>,
+        /// Print what can be restored instead, oldest first
+        #[arg(long, conflicts_with_all = ["paths", "last"])]
+        list: bool,
+    },
+    /// Send a command to the daemon listening on CONTROL_SOCKET
+    Ctl {
+        #[command(subcommand)]
+        action: CtlAction,
+    },
+    /// Watch the daemon on CONTROL_SOCKET, or else CONTROL_ADDR, in a live
+    /// dashboard
+    #[cfg(feature = "tui")]
+    Tui,
+    /// Print a systemd service unit running the current configuration, from
+    /// the flags, environment variables and config file given
+    InstallSystemd {
+        /// Sandbox the service, only letting it write to the watched
+        /// directories and the files it keeps
+        #[arg(long)]
+        hardened: bool,
+        /// File the unit is written to, e.g.
+        /// `/etc/systemd/system/maxdirsize.service` [default: the standard
+        /// output]
+        #[arg(long, short)]
+        output: Option<PathBuf>,
+    },
+    /// Write and load a launchd job running the current configuration on
+    /// macOS, from the flags, environment variables and config file given
+    InstallLaunchd {
+        /// Install a LaunchDaemon for the whole machine, run as root, instead
+        /// of a LaunchAgent of the current user
+        #[arg(long)]
+        daemon: bool,
+        /// Only print the job
+        #[arg(long)]
+        print: bool,
+    },
+    /// Install, start or stop the Windows service running the current
+    /// configuration
+    Service {
+        #[command(subcommand)]
+        action: ServiceAction,
+    },
+    /// Write a commented starter config file for a directory, its limit
+    /// filled in from the size of the directory now
+    Init {
+        /// Directory to watch [default: the current directory]
+        #[arg(long)]
+        path: Option<PathBuf>,
+        /// File written, never overwritten, `-` for the standard output
+        #[arg(long, short, default_value = "maxdirsize.toml")]
+        output: PathBuf,
+    },
+    /// Scan every directory once and print its utilization of its size limit
+    /// as a Nagios plugin, exiting with 0 (OK), 1 (WARNING), 2 (CRITICAL) or
+    /// 3 (UNKNOWN)
+    CheckUsage {
+        /// Percentage of the size limit from which the status is WARNING
+        #[arg(long, default_value_t = 80)]
+        warn: u8,
+        /// Percentage of the size limit from which the status is CRITICAL
+        #[arg(long, default_value_t = 95)]
+        crit: u8,
+    },
+    /// Check the configuration from every source and the directories it
+    /// names, print the effective configuration and exit, without touching
+    /// any file
+    Check,
+    /// Print completions of the flags and subcommands for a shell
+    Completions { shell: clap_complete::Shell },
+    /// Pin files so that they are never removed, whatever their age, with
+    /// the `user.maxdirsize.protect` extended attribute
+    Protect {
+        /// Files to pin
+        #[arg(required = true)]
+        paths: Vec<PathBuf>,
+        /// Unpin them instead
+        #[arg(long)]
+        remove: bool,
+    },
+}
+
+/// A command for the daemon, sent by `maxdirsize ctl`.
+#[derive(Subcommand, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CtlAction {
+    /// Print the utilization of every directory and whether cleanups are
+    /// paused
+    Status,
+    /// Clean up every directory now
+    Run,
+    /// Hold back cleanups until resumed
+    Pause,
+    /// Resume paused cleanups
+    Resume,
+}
+
+/// What `maxdirsize service` does with the Windows service.
+#[derive(Subcommand, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ServiceAction {
+    /// Register the service, started with Windows, with the flags,
+    /// environment variables and config file given
+    Install,
+    /// Remove the service
+    Uninstall,
+    /// Start the service and wait until it runs
+    Start,
+    /// Stop the service and wait until it exits
+    Stop,
+    /// Run as the service, what the service control manager starts
+    #[command(hide = true)]
+    Run,
+}
+
+/// Linux IO scheduling class, see ioprio_set(2).
+#[derive(Deserialize, Serialize, ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+#[value(rename_all = "snake_case")]
+pub enum IoClass {
+    /// Served before anything else, needs root
+    Realtime,
+    /// Shared with the other processes by priority, the default
+    BestEffort,
+    /// Only when no other process uses the disk
+    Idle,
+}
+
+/// What happens to an evicted file.
+#[derive(Deserialize, Serialize, ValueEnum, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+#[value(rename_all = "snake_case")]
+pub enum DeleteMode {
+    /// Permanently remove the file
+    #[default]
+    Delete,
+    /// Move the file to the trash (freedesktop trash, Recycle Bin on Windows)
+    Trash,
+    /// Move the file to `archive_dir`, keeping its path relative to the
+    /// directory
+    Move,
+    /// Overwrite the file before removing it, see [`crate::shred`]
+    Shred,
+    /// Move the file to `quarantine_dir`, from where it is purged later or
+    /// restored, see [`crate::quarantine`]
+    Quarantine,
+}
+
+impl fmt::Display for DeleteMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DeleteMode::Delete => write!(f, "delete"),
+            DeleteMode::Trash => write!(f, "trash"),
+            DeleteMode::Move => write!(f, "move"),
+            DeleteMode::Shred => write!(f, "shred"),
+            DeleteMode::Quarantine => write!(f, "quarantine"),
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, ValueEnum, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+#[value(rename_all = "snake_case")]
+pub enum SizeAccounting {
+    /// The length of the file
+    #[default]
+    Apparent,
+    /// The space allocated on disk, smaller for sparse files and on
+    /// compressing filesystems (Unix only)
+    Allocated,
+}
+
+#[derive(Deserialize, Serialize, ValueEnum, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+#[value(rename_all = "snake_case")]
+pub enum Compression {
+    #[default]
+    Gzip,
+    Zstd,
+}
+
+#[derive(Deserialize, Serialize, ValueEnum, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+#[value(rename_all = "snake_case")]
+pub enum WatchMode {
+    /// Scan the whole tree every cycle
+    #[default]
+    Scan,
+    /// Keep an index updated from filesystem events, rescanning periodically
+    Notify,
+    /// Keep listings in SCAN_CACHE_DIR, listing again only the directories
+    /// modified since, rescanning periodically
+    Cache,
+}
+
+/// Naming of StatsD metrics.
+#[derive(Deserialize, Serialize, ValueEnum, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+#[value(rename_all = "snake_case")]
+pub enum StatsdFormat {
+    /// The directory in the name, e.g. `maxdirsize.var_log.size_bytes`
+    #[default]
+    Statsd,
+    /// The directory as a DogStatsD tag, e.g. `maxdirsize.size_bytes` tagged
+    /// `directory:/var/log`
+    Dogstatsd,
+}
+
+/// Payload posted to the webhook.
+#[derive(Deserialize, Serialize, ValueEnum, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+#[value(rename_all = "snake_case")]
+pub enum NotifyFormat {
+    /// The event as a JSON object
+    #[default]
+    Json,
+    /// A message for a Slack incoming webhook
+    Slack,
+    /// A message for a Discord webhook
+    Discord,
+    /// An Adaptive Card for a Microsoft Teams workflow
+    Teams,
+}
+
+/// Encryption of the connection to the SMTP server.
+#[derive(Deserialize, Serialize, ValueEnum, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+#[value(rename_all = "snake_case")]
+pub enum SmtpTls {
+    /// Upgrade a plain connection, which the server must support
+    #[default]
+    Starttls,
+    /// Connect over TLS
+    Tls,
+    /// Send in the clear, e.g. to a relay on localhost
+    None,
+}
+
+/// What a cleanup removes at once.
+#[derive(Deserialize, Serialize, ValueEnum, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+#[value(rename_all = "snake_case")]
+pub enum EvictionUnit {
+    /// Each file on its own
+    #[default]
+    File,
+    /// Each immediate subdirectory with everything below it, e.g. one per
+    /// recording session, as old as the newest file in it
+    Subdirectory,
+}
+
+/// Whether identical files are reclaimed first, see [`crate::dedup`].
+#[derive(Deserialize, Serialize, ValueEnum, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+#[value(rename_all = "snake_case")]
+pub enum Deduplication {
+    #[default]
+    Off,
+    /// Remove the copies, keeping the file evicted last
+    Delete,
+    /// Replace the copies with hard links to that file
+    Hardlink,
+}
+
+/// Order in which files are evicted.
+#[derive(Deserialize, Serialize, ValueEnum, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+#[value(rename_all = "snake_case")]
+pub enum EvictionPolicyKind {
+    /// Least recently modified first
+    #[default]
+    OldestFirst,
+    /// Most recently modified first
+    NewestFirst,
+    /// Biggest first, freeing space with the fewest removals
+    LargestFirst,
+    /// Least recently read or written first, using access times
+    #[serde(alias = "lru")]
+    #[value(alias = "lru")]
+    LeastRecentlyUsed,
+    /// Highest combination of age and size first, see EVICTION_AGE_WEIGHT
+    /// and EVICTION_SIZE_WEIGHT
+    Weighted,
+    /// Least recently used first, small files given more time than large
+    /// ones, as GreedyDual-Size-Frequency caches do
+    Gdsf,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct Config {
+    /// Every watched directory. Top level keys of the config file (and the
+    /// flags/env vars) act as defaults for each `[[directories]]` entry.
+    #[serde(default)]
+    pub directories: Vec<WatchConfig>,
+    #[serde(default)]
+    pub once: bool,
+    pub metrics_addr: Option<String>,
+    pub statsd_addr: Option<String>,
+    #[serde(default)]
+    pub statsd_format: StatsdFormat,
+    pub status_file: Option<PathBuf>,
+    pub health_addr: Option<String>,
+    pub control_addr: Option<String>,
+    /// Never serialized, so it is not logged when the configuration is
+    /// reloaded
+    #[serde(skip_serializing)]
+    pub control_token: Option<String>,
+    pub control_socket: Option<PathBuf>,
+    #[serde(default)]
+    pub log_format: LogFormat,
+    #[serde(default)]
+    pub log_target: LogTarget,
+    pub log_file: Option<PathBuf>,
+    pub events: Option<EventFormat>,
+    #[serde(default)]
+    pub daemon: bool,
+    pub pid_file: Option<PathBuf>,
+    #[serde(default)]
+    pub scan_threads: usize,
+    #[serde(
+        default = "default_shutdown_timeout",
+        deserialize_with = "units::deserialize_seconds"
+    )]
+    pub shutdown_timeout_seconds: u64,
+    /// Where lock files are created, the temporary directory if unset
+    pub lock_dir: Option<PathBuf>,
+    #[serde(default)]
+    pub lock_wait: bool,
+    pub nice: Option<i32>,
+    pub io_class: Option<IoClass>,
+    #[serde(default = "default_io_priority")]
+    pub io_priority: u8,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct WatchConfig {
+    #[serde(default, deserialize_with = "units::deserialize_seconds")]
+    pub interval_seconds: u64,
+    /// Bounds of the interval adapted to the utilization, set from
+    /// `min_interval` and `max_interval`
+    #[serde(default, deserialize_with = "units::deserialize_optional_seconds")]
+    pub min_interval_seconds: Option<u64>,
+    #[serde(default, deserialize_with = "units::deserialize_optional_seconds")]
+    pub max_interval_seconds: Option<u64>,
+    /// Set from `cycle_timeout`
+    #[serde(default, deserialize_with = "units::deserialize_optional_seconds")]
+    pub cycle_timeout_seconds: Option<u64>,
+    /// Replaces `interval_seconds` when set
+    #[serde(default)]
+    pub schedule: Option<Schedule>,
+    /// Windows nothing is removed during
+    #[serde(default)]
+    pub quiet_hours: Vec<QuietWindow>,
+    #[serde(default)]
+    pub timezone: Timezone,
+    pub directory: String,
+    /// Endpoint of the S3 compatible store of an `s3://` directory
+    pub s3_endpoint: Option<String>,
+    pub s3_region: Option<String>,
+    /// ssh options of an `sftp://` directory
+    pub sftp_identity_file: Option<PathBuf>,
+    pub sftp_known_hosts: Option<PathBuf>,
+    /// Set from `max_size` or `max_size_mb`
+    #[serde(default, deserialize_with = "units::deserialize_optional_size")]
+    pub max_size_bytes: Option<u64>,
+    /// Size limit as a percentage of the filesystem capacity
+    pub max_size_percent: Option<f64>,
+    /// Clean up when the filesystem has less than this percentage free
+    pub min_free_percent: Option<f64>,
+    /// Clean up when the directory holds more files than this
+    pub max_files: Option<u64>,
+    /// Size limits of subdirectories, relative to `directory`
+    #[serde(default, deserialize_with = "units::deserialize_sizes")]
+    pub quotas: BTreeMap<String, u64>,
+    /// Size limits of the files matching each pattern
+    #[serde(default, deserialize_with = "units::deserialize_sizes")]
+    pub budgets: BTreeMap<String, u64>,
+    /// Compiled from the `budgets` patterns, in the same order
+    #[serde(skip)]
+    pub budget_patterns: Vec<GlobSet>,
+    #[serde(default = "default_margin")]
+    pub margin: u8,
+    /// Percentage of the limits above which a cleanup starts
+    #[serde(default = "default_high_watermark")]
+    pub high_watermark: u8,
+    /// Percentage of the limits to clean down to, `margin` when unset
+    pub low_watermark: Option<u8>,
+    #[serde(default)]
+    pub dry_run: bool,
+    #[serde(default)]
+    pub size_accounting: SizeAccounting,
+    /// Symlinks are followed, otherwise they count as the links themselves
+    #[serde(default)]
+    pub follow_symlinks: bool,
+    /// Mount points inside the directory are neither counted nor cleaned
+    #[serde(default)]
+    pub one_file_system: bool,
+    /// Files held open by a process are skipped for the cycle
+    #[serde(default)]
+    pub skip_open_files: bool,
+    /// Skips the check for a directory too close to the root
+    #[serde(default)]
+    pub force_dangerous_path: bool,
+    /// Set from `eviction_policy` or `deletion_order`
+    #[serde(default)]
+    pub eviction_policy: EvictionPolicyKind,
+    #[serde(default)]
+    pub eviction_unit: EvictionUnit,
+    #[serde(default)]
+    pub deduplicate: Deduplication,
+    /// Suffixes of the files evicted with the file they are named after
+    #[serde(default)]
+    pub sidecars: Vec<String>,
+    #[serde(default = "default_weight")]
+    pub eviction_age_weight: f64,
+    #[serde(default = "default_weight")]
+    pub eviction_size_weight: f64,
+    #[serde(default)]
+    pub delete_mode: DeleteMode,
+    /// Overwrites of each file with `DeleteMode::Shred`
+    #[serde(default = "default_shred_passes")]
+    pub shred_passes: u32,
+    /// Where evicted files are moved to with `DeleteMode::Move`
+    pub archive_dir: Option<PathBuf>,
+    /// Where evicted files are held with `DeleteMode::Quarantine`
+    pub quarantine_dir: Option<PathBuf>,
+    /// Set from `quarantine_retention`, time before quarantined files are
+    /// purged
+    #[serde(
+        default = "default_quarantine_retention",
+        deserialize_with = "units::deserialize_seconds"
+    )]
+    pub quarantine_retention_seconds: u64,
+    /// Set from `quarantine_max_size`, limit of the files quarantined from
+    /// the directory
+    #[serde(default, deserialize_with = "units::deserialize_optional_size")]
+    pub quarantine_max_size_bytes: Option<u64>,
+    /// Files removed at once, in the order of the eviction policy
+    #[serde(default = "default_delete_threads")]
+    pub delete_threads: usize,
+    /// Files removed per second at most
+    pub delete_rate: Option<f64>,
+    /// Bytes removed per second at most
+    #[serde(default, deserialize_with = "units::deserialize_optional_size")]
+    pub delete_bandwidth: Option<u64>,
+    #[serde(default, deserialize_with = "units::deserialize_seconds")]
+    pub min_age_seconds: u64,
+    /// Files kept in each immediate subdirectory whatever the limits
+    pub keep_newest: Option<usize>,
+    /// Most files removed by a single cleanup
+    pub max_deletions_per_cycle: Option<u64>,
+    /// Most bytes freed by a single cleanup
+    #[serde(default, deserialize_with = "units::deserialize_optional_size")]
+    pub max_bytes_per_cycle: Option<u64>,
+    /// Files kept in memory to choose from while scanning, all when unset
+    pub max_candidates: Option<usize>,
+    /// Largest files and subdirectories logged after every scan
+    pub report_top: Option<usize>,
+    /// Set from `max_age`, files older than this are always removed
+    #[serde(default, deserialize_with = "units::deserialize_optional_seconds")]
+    pub max_age_seconds: Option<u64>,
+    /// Set from `clutter_max_age`, dangling symlinks and empty files older
+    /// than this are always removed
+    #[serde(default, deserialize_with = "units::deserialize_optional_seconds")]
+    pub clutter_max_age_seconds: Option<u64>,
+    /// Set from `compress_after`, files older than this are compressed
+    #[serde(default, deserialize_with = "units::deserialize_optional_seconds")]
+    pub compress_after_seconds: Option<u64>,
+    #[serde(default)]
+    pub compression: Compression,
+    /// Set from `compressed_max_size`, limit of the compressed files
+    #[serde(default, deserialize_with = "units::deserialize_optional_size")]
+    pub compressed_max_size_bytes: Option<u64>,
+    #[serde(default)]
+    pub exclude_patterns: Vec<String>,
+    #[serde(skip)]
+    pub exclude: GlobSet,
+    /// Relative to the directory, normalized by [`WatchConfig::compile`]
+    #[serde(default)]
+    pub exclude_dirs: Vec<PathBuf>,
+    #[serde(default)]
+    pub include_patterns: Vec<String>,
+    /// Levels scanned below the directory
+    pub max_depth: Option<usize>,
+    /// Owners of the files that may be removed
+    #[serde(default)]
+    pub owner_users: Vec<String>,
+    #[serde(default)]
+    pub owner_groups: Vec<String>,
+    #[serde(default)]
+    pub require_owner_write: bool,
+    /// Resolved from the above
+    #[serde(skip)]
+    pub owners: Option<Owners>,
+    /// Whether protection files in the directory are honored
+    #[serde(default)]
+    pub ignore_files: bool,
+    /// Whether files pinned with an extended attribute are left alone
+    #[serde(default = "default_pinned_files")]
+    pub pinned_files: bool,
+    /// Scans run with the idle IO class
+    #[serde(default)]
+    pub scan_io_idle: bool,
+    #[serde(skip)]
+    pub include: GlobSet,
+    #[serde(default)]
+    pub watch_mode: WatchMode,
+    #[serde(
+        default = "default_rescan_interval",
+        deserialize_with = "units::deserialize_seconds"
+    )]
+    pub rescan_interval_seconds: u64,
+    pub scan_cache_dir: Option<PathBuf>,
+    /// Append-only record of every removal
+    pub audit_log: Option<PathBuf>,
+    /// Database of every cycle and removal
+    pub history_db: Option<PathBuf>,
+    /// Warned about when the directory reaches its limit within this many
+    /// seconds at the pace it grows
+    #[serde(default, deserialize_with = "units::deserialize_optional_seconds")]
+    pub full_warning_horizon: Option<u64>,
+    /// Notified of every cleanup
+    pub webhook_url: Option<String>,
+    #[serde(default)]
+    pub notify_format: NotifyFormat,
+    /// Collector every cycle is exported to
+    pub otel_exporter_otlp_endpoint: Option<String>,
+    #[serde(default)]
+    pub otel_exporter_otlp_headers: Vec<String>,
+    pub otel_service_name: Option<String>,
+    /// Whether cleanups are notified on the desktop
+    #[serde(default)]
+    pub desktop_notifications: bool,
+    /// Server emailing a summary of the cleanups reaching the `email_min_`
+    /// thresholds, of every cleanup and failure without any
+    pub smtp_host: Option<String>,
+    pub smtp_port: Option<u16>,
+    #[serde(default)]
+    pub smtp_tls: SmtpTls,
+    pub smtp_username: Option<String>,
+    pub smtp_password: Option<String>,
+    pub email_from: Option<String>,
+    #[serde(default)]
+    pub email_to: Vec<String>,
+    pub email_min_files: Option<u64>,
+    #[serde(default, deserialize_with = "units::deserialize_optional_size")]
+    pub email_min_bytes: Option<u64>,
+    pub email_min_errors: Option<u64>,
+    /// Asked about every file before it is removed
+    pub candidate_hook: Option<PathBuf>,
+    /// Run with the shell around every cleanup
+    pub pre_cleanup_cmd: Option<String>,
+    pub post_cleanup_cmd: Option<String>,
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(PathBuf, std::io::Error),
+    Parse(String),
+    Invalid(String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Io(path, e) => write!(f, "Error reading {}: {e}", path.display()),
+            ConfigError::Parse(e) => write!(f, "Invalid configuration: {e}"),
+            ConfigError::Invalid(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl Config {
+    /// Builds the configuration from the config file (if any), overridden by
+    /// environment variables and command line flags.
+    pub fn load(args: &Args) -> Result<Config, ConfigError> {
+        let mut table = match &args.config {
+            Some(path) => {
+                let contents =
+                    std::fs::read_to_string(path).map_err(|e| ConfigError::Io(path.clone(), e))?;
+                let mut table = contents
+                    .parse::<toml::Table>()
+                    .map_err(|e| ConfigError::Parse(e.to_string()))?;
+
+                normalize_units(&mut table)?;
+
+                if let Some(toml::Value::Array(entries)) = table.get_mut("directories") {
+                    for entry in entries {
+                        if let toml::Value::Table(entry) = entry {
+                            normalize_units(entry)?;
+                        }
+                    }
+                }
+
+                table
+            }
+            None => toml::Table::new(),
+        };
+
+        let mut overrides =
+            toml::Table::try_from(args).map_err(|e| ConfigError::Parse(e.to_string()))?;
+        normalize_units(&mut overrides)?;
+        table.extend(overrides);
+
+        let table = expand_directories(table)?;
+
+        let mut config: Config = toml::Value::Table(table)
+            .try_into()
+            .map_err(|e: toml::de::Error| ConfigError::Parse(e.message().to_string()))?;
+
+        for watch in &mut config.directories {
+            watch.compile()?;
+        }
+
+        config.validate()?;
+
+        Ok(config)
+    }
+
+    fn validate(&self) -> Result<(), ConfigError> {
+        if self.directories.is_empty() {
+            return Err(ConfigError::Invalid(
+                "No directory configured, set DIRECTORY or add a [[directories]] entry".to_string(),
+            ));
+        }
+
+        if self.control_addr.is_some() && self.control_token.as_deref().unwrap_or("").is_empty() {
+            return Err(ConfigError::Invalid(
+                "CONTROL_ADDR needs a CONTROL_TOKEN, the control API is never served without one"
+                    .to_string(),
+            ));
+        }
+
+        if self.nice.is_some_and(|nice| !(-20..=19).contains(&nice)) {
+            return Err(ConfigError::Invalid(
+                "NICE must be between -20 and 19".to_string(),
+            ));
+        }
+
+        if self.io_priority > 7 {
+            return Err(ConfigError::Invalid(
+                "IO_PRIORITY must be between 0 and 7".to_string(),
+            ));
+        }
+
+        if cfg!(not(windows)) && self.log_target == LogTarget::Eventlog {
+            return Err(ConfigError::Invalid(
+                "LOG_TARGET=eventlog is only supported on Windows".to_string(),
+            ));
+        }
+
+        if cfg!(not(unix)) && self.daemon {
+            return Err(ConfigError::Invalid(
+                "DAEMON is only supported on Unix".to_string(),
+            ));
+        }
+
+        if self.daemon && self.log_target == LogTarget::Stderr && self.log_file.is_none() {
+            return Err(ConfigError::Invalid(
+                "DAEMON needs LOG_FILE, or LOG_TARGET=syslog or journald, as standard error is closed"
+                    .to_string(),
+            ));
+        }
+
+        if self.daemon && self.events.is_some() {
+            return Err(ConfigError::Invalid(
+                "EVENTS cannot be used with DAEMON, as standard output is closed".to_string(),
+            ));
+        }
+
+        if cfg!(not(unix)) && self.nice.is_some() {
+            return Err(ConfigError::Invalid(
+                "NICE is only supported on Unix".to_string(),
+            ));
+        }
+
+        if cfg!(not(target_os = "linux"))
+            && (self.io_class.is_some() || self.directories.iter().any(|watch| watch.scan_io_idle))
+        {
+            return Err(ConfigError::Invalid(
+                "IO_CLASS and SCAN_IO_IDLE are only supported on Linux".to_string(),
+            ));
+        }
+
+        if cfg!(not(unix)) && self.control_socket.is_some() {
+            return Err(ConfigError::Invalid(
+                "CONTROL_SOCKET is only supported on Unix".to_string(),
+            ));
+        }
+
+        let mut seen = std::collections::HashSet::new();
+
+        for watch in &self.directories {
+            if !seen.insert(&watch.directory) {
+                return Err(ConfigError::Invalid(format!(
+                    "Directory {} is configured more than once",
+                    watch.directory
+                )));
+            }
+
+            watch.validate()?;
+        }
+
+        Ok(())
+    }
+}
+
+impl WatchConfig {
+    /// Builds the matchers derived from the configured patterns. Done by
+    /// [`Config::load`]; call it when deserializing a `WatchConfig` directly.
+    pub fn compile(&mut self) -> Result<(), ConfigError> {
+        self.exclude = glob_set(&self.exclude_patterns)
+            .map_err(|e| ConfigError::Invalid(format!("Invalid EXCLUDE_PATTERNS: {e}")))?;
+        self.include = glob_set(&self.include_patterns)
+            .map_err(|e| ConfigError::Invalid(format!("Invalid INCLUDE_PATTERNS: {e}")))?;
+        self.exclude_dirs = self
+            .exclude_dirs
+            .iter()
+            .map(|dir| relative_dir(dir))
+            .collect::<Option<_>>()
+            .ok_or_else(|| {
+                ConfigError::Invalid(format!(
+                    "EXCLUDE_DIRS must be relative paths below the directory (directory {})",
+                    self.directory
+                ))
+            })?;
+        self.budget_patterns = self
+            .budgets
+            .keys()
+            .map(|pattern| glob_set(std::slice::from_ref(pattern)))
+            .collect::<Result<_, _>>()
+            .map_err(|e| ConfigError::Invalid(format!("Invalid BUDGETS: {e}")))?;
+        if cfg!(not(unix))
+            && (!self.owner_users.is_empty()
+                || !self.owner_groups.is_empty()
+                || self.require_owner_write)
+        {
+            return Err(ConfigError::Invalid(format!(
+                "OWNER_USERS, OWNER_GROUPS and REQUIRE_OWNER_WRITE are only supported on Unix (directory {})",
+                self.directory
+            )));
+        }
+
+        self.owners = Owners::resolve(
+            &self.owner_users,
+            &self.owner_groups,
+            self.require_owner_write,
+        )
+        .map_err(|e| {
+            ConfigError::Invalid(format!(
+                "Invalid OWNER_USERS or OWNER_GROUPS: {e} (directory {})",
+                self.directory
+            ))
+        })?;
+
+        Ok(())
+    }
+
+    /// Percentage of the limits a cleanup removes files down to.
+    pub fn target_percent(&self) -> u8 {
+        self.low_watermark.unwrap_or(self.margin)
+    }
+
+    fn validate(&self) -> Result<(), ConfigError> {
+        if self.min_interval_seconds.is_some() || self.max_interval_seconds.is_some() {
+            let min = self.min_interval_seconds.unwrap_or(self.interval_seconds);
+            let max = self.max_interval_seconds.unwrap_or(self.interval_seconds);
+
+            if self.schedule.is_some() || self.interval_seconds == 0 {
+                return Err(ConfigError::Invalid(format!(
+                    "MIN_INTERVAL and MAX_INTERVAL need a nonzero INTERVAL instead of SCHEDULE (directory {})",
+                    self.directory
+                )));
+            }
+
+            if min == 0 || min > self.interval_seconds || max < self.interval_seconds {
+                return Err(ConfigError::Invalid(format!(
+                    "MIN_INTERVAL must be between 1s and INTERVAL, and MAX_INTERVAL at least INTERVAL (directory {})",
+                    self.directory
+                )));
+            }
+        }
+
+        if self.watch_mode == WatchMode::Cache && self.scan_cache_dir.is_none() {
+            return Err(ConfigError::Invalid(format!(
+                "WATCH_MODE=cache needs SCAN_CACHE_DIR (directory {})",
+                self.directory
+            )));
+        }
+
+        if self.max_depth == Some(0) {
+            return Err(ConfigError::Invalid(format!(
+                "MAX_DEPTH must be at least 1 (directory {})",
+                self.directory
+            )));
+        }
+
+        if self.cycle_timeout_seconds == Some(0) {
+            return Err(ConfigError::Invalid(format!(
+                "CYCLE_TIMEOUT must be at least 1s (directory {})",
+                self.directory
+            )));
+        }
+
+        let disabled = [
+            (
+                self.compress_after_seconds.is_some(),
+                "COMPRESS_AFTER",
+                "compression",
+                cfg!(feature = "compression"),
+            ),
+            (
+                self.desktop_notifications,
+                "DESKTOP_NOTIFICATIONS",
+                "desktop",
+                cfg!(feature = "desktop"),
+            ),
+            (
+                self.smtp_host.is_some(),
+                "SMTP_HOST",
+                "email",
+                cfg!(feature = "email"),
+            ),
+            (
+                self.history_db.is_some(),
+                "HISTORY_DB",
+                "history",
+                cfg!(feature = "history"),
+            ),
+            (
+                self.otel_exporter_otlp_endpoint.is_some(),
+                "OTEL_EXPORTER_OTLP_ENDPOINT",
+                "otel",
+                cfg!(feature = "otel"),
+            ),
+            (
+                self.directory.starts_with(storage::S3_SCHEME),
+                "DIRECTORY=s3://",
+                "s3",
+                cfg!(feature = "s3"),
+            ),
+            (
+                self.webhook_url.is_some(),
+                "WEBHOOK_URL",
+                "webhook",
+                cfg!(feature = "webhook"),
+            ),
+        ];
+
+        if let Some((_, setting, feature, _)) = disabled
+            .iter()
+            .find(|(set, _, _, enabled)| *set && !enabled)
+        {
+            return Err(ConfigError::Invalid(format!(
+                "{setting} needs {APP_NAME} built with the `{feature}` feature (directory {})",
+                self.directory
+            )));
+        }
+
+        if storage::is_remote(&self.directory) {
+            self.validate_remote()?;
+        } else if !self.force_dangerous_path {
+            if let Some(reason) = dangerous(Path::new(&self.directory)) {
+                return Err(ConfigError::Invalid(format!(
+                    "Refusing to clean up {}, {reason}; pass --force-dangerous-path if this is intended",
+                    self.directory
+                )));
+            }
+        }
+
+        if let Some(url) = &self.webhook_url {
+            if !url.starts_with("http://") && !url.starts_with("https://") {
+                return Err(ConfigError::Invalid(format!(
+                    "WEBHOOK_URL must be an http:// or https:// URL (directory {})",
+                    self.directory
+                )));
+            }
+        }
+
+        if let Some(url) = &self.otel_exporter_otlp_endpoint {
+            if !url.starts_with("http://") && !url.starts_with("https://") {
+                return Err(ConfigError::Invalid(format!(
+                    "OTEL_EXPORTER_OTLP_ENDPOINT must be an http:// or https:// URL (directory {})",
+                    self.directory
+                )));
+            }
+        }
+
+        if let Some(header) = self
+            .otel_exporter_otlp_headers
+            .iter()
+            .find(|header| !header.contains('='))
+        {
+            return Err(ConfigError::Invalid(format!(
+                "OTEL_EXPORTER_OTLP_HEADERS must be `name=value` pairs, not `{header}` (directory {})",
+                self.directory
+            )));
+        }
+
+        if self.notify_format != NotifyFormat::Json && self.webhook_url.is_none() {
+            return Err(ConfigError::Invalid(format!(
+                "NOTIFY_FORMAT needs WEBHOOK_URL (directory {})",
+                self.directory
+            )));
+        }
+
+        match &self.smtp_host {
+            Some(_) if self.email_from.is_none() || self.email_to.is_empty() => {
+                return Err(ConfigError::Invalid(format!(
+                    "EMAIL_FROM and EMAIL_TO are required with SMTP_HOST (directory {})",
+                    self.directory
+                )));
+            }
+            Some(_) =>
+            {
+                #[cfg(feature = "email")]
+                for address in self.email_from.iter().chain(&self.email_to) {
+                    if address.parse::<lettre::message::Mailbox>().is_err() {
+                        return Err(ConfigError::Invalid(format!(
+                            "invalid email address `{address}` (directory {})",
+                            self.directory
+                        )));
+                    }
+                }
+            }
+            None if self.email_from.is_some() || !self.email_to.is_empty() => {
+                return Err(ConfigError::Invalid(format!(
+                    "EMAIL_FROM and EMAIL_TO need SMTP_HOST (directory {})",
+                    self.directory
+                )));
+            }
+            None => {}
+        }
+
+        if self.margin > 100 {
+            return Err(ConfigError::Invalid(format!(
+                "MARGIN must be between 0 and 100 (directory {})",
+                self.directory
+            )));
+        }
+
+        if self.high_watermark > 100 {
+            return Err(ConfigError::Invalid(format!(
+                "HIGH_WATERMARK must be between 0 and 100 (directory {})",
+                self.directory
+            )));
+        }
+
+        if !(self.eviction_age_weight >= 0.0 && self.eviction_size_weight >= 0.0) {
+            return Err(ConfigError::Invalid(format!(
+                "EVICTION_AGE_WEIGHT and EVICTION_SIZE_WEIGHT must not be negative (directory {})",
+                self.directory
+            )));
+        }
+
+        if self.target_percent() > self.high_watermark {
+            return Err(ConfigError::Invalid(format!(
+                "LOW_WATERMARK (or MARGIN) must not be above HIGH_WATERMARK (directory {})",
+                self.directory
+            )));
+        }
+
+        if self
+            .delete_rate
+            .is_some_and(|rate| rate.is_nan() || rate <= 0.0)
+            || self.delete_bandwidth == Some(0)
+        {
+            return Err(ConfigError::Invalid(format!(
+                "DELETE_RATE and DELETE_BANDWIDTH must be above 0 (directory {})",
+                self.directory
+            )));
+        }
+
+        if self.max_candidates == Some(0) {
+            return Err(ConfigError::Invalid(format!(
+                "MAX_CANDIDATES must be at least 1 (directory {})",
+                self.directory
+            )));
+        }
+
+        if self.full_warning_horizon == Some(0) {
+            return Err(ConfigError::Invalid(format!(
+                "FULL_WARNING_HORIZON must be above 0 (directory {})",
+                self.directory
+            )));
+        }
+
+        if self.report_top == Some(0) {
+            return Err(ConfigError::Invalid(format!(
+                "REPORT_TOP must be at least 1 (directory {})",
+                self.directory
+            )));
+        }
+
+        if let Some(sidecar) = self.sidecars.iter().find(|sidecar| {
+            let suffix = sidecar.strip_prefix('*').unwrap_or(sidecar);
+            suffix.is_empty() || suffix.contains(['*', '?', '[', '/'])
+        }) {
+            return Err(ConfigError::Invalid(format!(
+                "SIDECARS must be suffixes such as `.srt` or `*.srt`, not `{sidecar}` (directory {})",
+                self.directory
+            )));
+        }
+
+        if self.deduplicate != Deduplication::Off && !self.sidecars.is_empty() {
+            return Err(ConfigError::Invalid(format!(
+                "DEDUPLICATE cannot be combined with SIDECARS (directory {})",
+                self.directory
+            )));
+        }
+
+        if self.eviction_unit == EvictionUnit::Subdirectory {
+            let unsupported = [
+                (!self.quotas.is_empty(), "QUOTAS"),
+                (!self.budgets.is_empty(), "BUDGETS"),
+                (self.keep_newest.is_some(), "KEEP_NEWEST"),
+                (self.max_candidates.is_some(), "MAX_CANDIDATES"),
+                (
+                    self.compressed_max_size_bytes.is_some(),
+                    "COMPRESSED_MAX_SIZE",
+                ),
+                (self.deduplicate != Deduplication::Off, "DEDUPLICATE"),
+                (self.max_depth.is_some(), "MAX_DEPTH"),
+            ];
+
+            if let Some((_, setting)) = unsupported.iter().find(|(set, _)| *set) {
+                return Err(ConfigError::Invalid(format!(
+                    "EVICTION_UNIT=subdirectory cannot be combined with {setting} (directory {})",
+                    self.directory
+                )));
+            }
+        }
+
+        if let Some(max_candidates) = self.max_candidates {
+            let unsupported = [
+                (self.watch_mode == WatchMode::Notify, "WATCH_MODE=notify"),
+                (self.watch_mode == WatchMode::Cache, "WATCH_MODE=cache"),
+                (!self.quotas.is_empty(), "QUOTAS"),
+                (!self.budgets.is_empty(), "BUDGETS"),
+                (self.keep_newest.is_some(), "KEEP_NEWEST"),
+                (!self.sidecars.is_empty(), "SIDECARS"),
+                (self.report_top.is_some(), "REPORT_TOP"),
+                (self.compress_after_seconds.is_some(), "COMPRESS_AFTER"),
+                (self.clutter_max_age_seconds.is_some(), "CLUTTER_MAX_AGE"),
+                (self.deduplicate != Deduplication::Off, "DEDUPLICATE"),
+                (
+                    self.compressed_max_size_bytes.is_some(),
+                    "COMPRESSED_MAX_SIZE",
+                ),
+            ];
+
+            if let Some((_, setting)) = unsupported.iter().find(|(set, _)| *set) {
+                return Err(ConfigError::Invalid(format!(
+                    "MAX_CANDIDATES={max_candidates} cannot be combined with {setting} (directory {})",
+                    self.directory
+                )));
+            }
+        }
+
+        if self.delete_threads == 0 {
+            return Err(ConfigError::Invalid(format!(
+                "DELETE_THREADS must be at least 1 (directory {})",
+                self.directory
+            )));
+        }
+
+        if self.shred_passes == 0 {
+            return Err(ConfigError::Invalid(format!(
+                "SHRED_PASSES must be at least 1 (directory {})",
+                self.directory
+            )));
+        }
+
+        match &self.archive_dir {
+            None if self.delete_mode == DeleteMode::Move => {
+                return Err(ConfigError::Invalid(format!(
+                    "ARCHIVE_DIR is required with DELETE_MODE=move (directory {})",
+                    self.directory
+                )));
+            }
+            Some(archive_dir) if is_inside(archive_dir, Path::new(&self.directory)) => {
+                return Err(ConfigError::Invalid(format!(
+                    "ARCHIVE_DIR must not be inside the directory (directory {})",
+                    self.directory
+                )));
+            }
+            _ => {}
+        }
+
+        match &self.quarantine_dir {
+            None if self.delete_mode == DeleteMode::Quarantine => {
+                return Err(ConfigError::Invalid(format!(
+                    "QUARANTINE_DIR is required with DELETE_MODE=quarantine (directory {})",
+                    self.directory
+                )));
+            }
+            Some(quarantine_dir) if is_inside(quarantine_dir, Path::new(&self.directory)) => {
+                return Err(ConfigError::Invalid(format!(
+                    "QUARANTINE_DIR must not be inside the directory (directory {})",
+                    self.directory
+                )));
+            }
+            _ => {}
+        }
+
+        if let Some(subdir) = self.quotas.keys().find(|subdir| {
+            !Path::new(subdir)
+                .components()
+                .all(|component| matches!(component, Component::Normal(_) | Component::CurDir))
+        }) {
+            return Err(ConfigError::Invalid(format!(
+                "QUOTAS must name subdirectories of the directory, not `{subdir}` (directory {})",
+                self.directory
+            )));
+        }
+
+        if let Some(percent) = self.max_size_percent {
+            if !(percent > 0.0 && percent <= 100.0) {
+                return Err(ConfigError::Invalid(format!(
+                    "MAX_SIZE_PERCENT must be above 0 and at most 100 (directory {})",
+                    self.directory
+                )));
+            }
+        }
+
+        if let Some(percent) = self.min_free_percent {
+            if !(0.0..=100.0).contains(&percent) {
+                return Err(ConfigError::Invalid(format!(
+                    "MIN_FREE_PERCENT must be between 0 and 100 (directory {})",
+                    self.directory
+                )));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Refuses the settings that need a local filesystem.
+    fn validate_remote(&self) -> Result<(), ConfigError> {
+        if self.directory.starts_with(sftp::SftpStorage::SCHEME) {
+            let location = sftp::Location::parse(&self.directory).map_err(ConfigError::Invalid)?;
+            let components = Path::new(&location.path)
+                .components()
+                .filter(|component| matches!(component, Component::Normal(_)))
+                .count();
+
+            if components < MIN_COMPONENTS && !self.force_dangerous_path {
+                return Err(ConfigError::Invalid(format!(
+                    "Refusing to clean up {}, it is less than {MIN_COMPONENTS} levels below the root; pass --force-dangerous-path if this is intended",
+                    self.directory
+                )));
+            }
+        } else {
+            #[cfg(feature = "s3")]
+            s3::Location::parse(&self.directory).map_err(ConfigError::Invalid)?;
+        }
+
+        let unsupported = [
+            (self.max_size_percent.is_some(), "MAX_SIZE_PERCENT"),
+            (self.min_free_percent.is_some(), "MIN_FREE_PERCENT"),
+            (self.one_file_system, "ONE_FILE_SYSTEM"),
+            (self.ignore_files, "IGNORE_FILES"),
+            (self.clutter_max_age_seconds.is_some(), "CLUTTER_MAX_AGE"),
+            (self.deduplicate != Deduplication::Off, "DEDUPLICATE"),
+            (
+                !self.owner_users.is_empty() || !self.owner_groups.is_empty(),
+                "OWNER_USERS and OWNER_GROUPS",
+            ),
+            (self.require_owner_write, "REQUIRE_OWNER_WRITE"),
+            (self.follow_symlinks, "FOLLOW_SYMLINKS"),
+            (self.skip_open_files, "SKIP_OPEN_FILES"),
+            (
+                self.size_accounting != SizeAccounting::Apparent,
+                "SIZE_ACCOUNTING=allocated",
+            ),
+            (self.delete_mode != DeleteMode::Delete, "DELETE_MODE"),
+            (self.delete_threads > 1, "DELETE_THREADS"),
+            (self.watch_mode == WatchMode::Notify, "WATCH_MODE=notify"),
+            (self.watch_mode == WatchMode::Cache, "WATCH_MODE=cache"),
+            (self.compress_after_seconds.is_some(), "COMPRESS_AFTER"),
+            (
+                self.compressed_max_size_bytes.is_some(),
+                "COMPRESSED_MAX_SIZE",
+            ),
+        ];
+
+        if let Some((_, setting)) = unsupported.iter().find(|(set, _)| *set) {
+            return Err(ConfigError::Invalid(format!(
+                "{setting} is not supported on remote directories (directory {})",
+                self.directory
+            )));
+        }
+
+        Ok(())
+    }
+}
+
+/// Directories with fewer components than this are refused without
+/// `force_dangerous_path`, e.g. `/var` but not `/var/cache`.
+const MIN_COMPONENTS: usize = 2;
+
+/// Why cleaning up `directory` is likely a mistake, if it is.
+fn dangerous(directory: &Path) -> Option<String> {
+    let directory = std::fs::canonicalize(directory)
+        .or_else(|_| std::path::absolute(directory))
+        .unwrap_or_else(|_| directory.to_path_buf());
+
+    let home = std::env::var_os("HOME")
+        .or_else(|| std::env::var_os("USERPROFILE"))
+        .map(PathBuf::from);
+
+    if home.is_some_and(|home| home == directory) {
+        return Some("it is the home directory".to_string());
+    }
+
+    if [Path::new("/home"), Path::new("/etc")].contains(&directory.as_path()) {
+        return Some("it is a system directory".to_string());
+    }
+
+    let components = directory
+        .components()
+        .filter(|component| matches!(component, Component::Normal(_)))
+        .count();
+
+    if components < MIN_COMPONENTS {
+        return Some(format!(
+            "it is less than {MIN_COMPONENTS} levels below the root"
+        ));
+    }
+
+    None
+}
+
+fn glob_set(patterns: &[String]) -> Result<GlobSet, globset::Error> {
+    let mut builder = GlobSetBuilder::new();
+
+    for pattern in patterns {
+        builder.add(Glob::new(pattern.trim())?);
+    }
+
+    builder.build()
+}
+
+/// `dir` without `.` components, `None` unless it names a subdirectory.
+fn relative_dir(dir: &Path) -> Option<PathBuf> {
+    let mut relative = PathBuf::new();
+
+    for component in dir.components() {
+        match component {
+            Component::Normal(name) => relative.push(name),
+            Component::CurDir => {}
+            Component::ParentDir | Component::RootDir | Component::Prefix(_) => return None,
+        }
+    }
+
+    (!relative.as_os_str().is_empty()).then_some(relative)
+}
+
+/// Whether `path` is `directory` or below it once both are resolved, so that
+/// neither a relative path nor a symlink gets around it.
+fn is_inside(path: &Path, directory: &Path) -> bool {
+    resolve(path).starts_with(resolve(directory))
+}
+
+/// `path` made absolute with its nearest existing ancestor canonicalized,
+/// the rest of it, not created yet, appended as is.
+fn resolve(path: &Path) -> PathBuf {
+    let absolute = std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf());
+    let mut missing = Vec::new();
+
+    for ancestor in absolute.ancestors() {
+        if let Ok(canonical) = std::fs::canonicalize(ancestor) {
+            return missing
+                .iter()
+                .rev()
+                .fold(canonical, |resolved, name| resolved.join(name));
+        }
+
+        match ancestor.file_name() {
+            Some(name) => missing.push(name),
+            None => break,
+        }
+    }
+
+    absolute
+}
+
+/// Maps the alternative spellings of a setting onto the key `WatchConfig`
+/// reads. Applied to every source separately so precedence between sources
+/// is preserved. The spelling with a unit wins over the plain one:
+///
+/// - `interval` over `interval_seconds`
+/// - `min_interval` over `min_interval_seconds`
+/// - `max_interval` over `max_interval_seconds`
+/// - `cycle_timeout` over `cycle_timeout_seconds`
+/// - `max_age` over `max_age_seconds`
+/// - `clutter_max_age` over `clutter_max_age_seconds`
+/// - `compress_after` over `compress_after_seconds`
+/// - `compressed_max_size` over `compressed_max_size_bytes`
+/// - `quarantine_retention` over `quarantine_retention_seconds`
+/// - `quarantine_max_size` over `quarantine_max_size_bytes`
+/// - `eviction_policy` over `deletion_order`
+/// - `max_size` over `max_size_mb`
+fn normalize_units(table: &mut toml::Table) -> Result<(), ConfigError> {
+    if let Some(interval) = table.remove("interval") {
+        table.insert("interval_seconds".to_string(), interval);
+    }
+
+    for bound in ["min_interval", "max_interval"] {
+        if let Some(interval) = table.remove(bound) {
+            table.insert(format!("{bound}_seconds"), interval);
+        }
+    }
+
+    if let Some(cycle_timeout) = table.remove("cycle_timeout") {
+        table.insert("cycle_timeout_seconds".to_string(), cycle_timeout);
+    }
+
+    if let Some(deletion_order) = table.remove("deletion_order") {
+        table.entry("eviction_policy").or_insert(deletion_order);
+    }
+
+    if let Some(max_age) = table.remove("max_age") {
+        table.insert("max_age_seconds".to_string(), max_age);
+    }
+
+    if let Some(clutter_max_age) = table.remove("clutter_max_age") {
+        table.insert("clutter_max_age_seconds".to_string(), clutter_max_age);
+    }
+
+    if let Some(compress_after) = table.remove("compress_after") {
+        table.insert("compress_after_seconds".to_string(), compress_after);
+    }
+
+    if let Some(compressed_max_size) = table.remove("compressed_max_size") {
+        table.insert("compressed_max_size_bytes".to_string(), compressed_max_size);
+    }
+
+    if let Some(retention) = table.remove("quarantine_retention") {
+        table.insert("quarantine_retention_seconds".to_string(), retention);
+    }
+
+    if let Some(quarantine_max_size) = table.remove("quarantine_max_size") {
+        table.insert("quarantine_max_size_bytes".to_string(), quarantine_max_size);
+    }
+
+    // `QUOTAS` and `BUDGETS` arrive as lists of `subdir=size` and
+    // `pattern=size`
+    for (key, example) in [("quotas", "logs=1GB"), ("budgets", "*.log=2GB")] {
+        let Some(toml::Value::Array(limits)) = table.get(key) else {
+            continue;
+        };
+
+        let mut parsed = toml::Table::new();
+
+        for limit in limits {
+            let Some((name, size)) = limit.as_str().and_then(|limit| limit.rsplit_once('=')) else {
+                return Err(ConfigError::Parse(format!(
+                    "invalid {} {limit}, expected e.g. `{example}`",
+                    key.trim_end_matches('s')
+                )));
+            };
+
+            parsed.insert(
+                name.trim().to_string(),
+                toml::Value::String(size.trim().to_string()),
+            );
+        }
+
+        table.insert(key.to_string(), toml::Value::Table(parsed));
+    }
+
+    let max_size_mb = table.remove("max_size_mb");
+
+    if let Some(max_size) = table.remove("max_size") {
+        table.insert("max_size_bytes".to_string(), max_size);
+    } else if let Some(max_size_mb) = max_size_mb {
+        let Some(mb) = max_size_mb.as_integer().filter(|mb| *mb >= 0) else {
+            return Err(ConfigError::Parse(
+                "`max_size_mb` must be a positive integer".to_string(),
+            ));
+        };
+
+        table.insert(
+            "max_size_bytes".to_string(),
+            toml::Value::Integer(mb.saturating_mul(units::MIB as i64)),
+        );
+    }
+
+    Ok(())
+}
+
+/// Merges the top level settings into every `[[directories]]` entry, and turns
+/// a top level `directory` into an entry of its own.
+fn expand_directories(mut table: toml::Table) -> Result<toml::Table, ConfigError> {
+    let entries = match table.remove("directories") {
+        Some(toml::Value::Array(entries)) => entries,
+        Some(_) => {
+            return Err(ConfigError::Parse(
+                "`directories` must be an array of tables".to_string(),
+            ))
+        }
+        None => Vec::new(),
+    };
+
+    let mut defaults = table.clone();
+    let root = defaults.remove("directory");
+
+    let mut directories = Vec::new();
+
+    if root.is_some() {
+        directories.push(toml::Value::Table(table.clone()));
+    }
+
+    for entry in entries {
+        let toml::Value::Table(entry) = entry else {
+            return Err(ConfigError::Parse(
+                "`directories` must be an array of tables".to_string(),
+            ));
+        };
+
+        let mut merged = defaults.clone();
+        merged.extend(entry);
+        directories.push(toml::Value::Table(merged));
+    }
+
+    let once = table
+        .get("once")
+        .and_then(|once| once.as_bool())
+        .unwrap_or(false);
+
+    for directory in directories.iter_mut().filter_map(|d| d.as_table_mut()) {
+        if once {
+            directory.remove("schedule");
+            directory.remove("min_interval_seconds");
+            directory.remove("max_interval_seconds");
+            directory
+                .entry("interval_seconds")
+                .or_insert(toml::Value::Integer(0));
+        }
+
+        for (keys, setting) in [
+            (
+                &[
+                    "max_size_bytes",
+                    "max_size_percent",
+                    "min_free_percent",
+                    "max_files",
+                    "max_age_seconds",
+                    "clutter_max_age_seconds",
+                    "quotas",
+                    "budgets",
+                    "compressed_max_size_bytes",
+                ][..],
+                "MAX_SIZE, MAX_SIZE_MB, MAX_SIZE_PERCENT, MIN_FREE_PERCENT, MAX_FILES, MAX_AGE, CLUTTER_MAX_AGE, QUOTAS, BUDGETS or COMPRESSED_MAX_SIZE",
+            ),
+            (&["interval_seconds", "schedule"][..], "INTERVAL or SCHEDULE"),
+        ] {
+            if keys.iter().all(|key| directory.get(*key).is_none()) {
+                return Err(ConfigError::Invalid(format!(
+                    "{setting} is required (directory {})",
+                    directory
+                        .get("directory")
+                        .and_then(|directory| directory.as_str())
+                        .unwrap_or("?")
+                )));
+            }
+        }
+    }
+
+    table.insert("directories".to_string(), toml::Value::Array(directories));
+
+    Ok(table)
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dangerous_refuses_directories_close_to_the_root() {
+        for directory in ["/", "/etc", "/home", "/var", "/var/.."] {
+            assert!(dangerous(Path::new(directory)).is_some(), "{directory}");
+        }
+
+        assert!(dangerous(Path::new("/var/cache/app")).is_none());
+    }
+
+    #[test]
+    fn settings_of_features_left_out_are_refused() {
+        let watch: WatchConfig = toml::from_str(
+            "directory = \"/var/cache/app\"\ninterval_seconds = 1\nmax_size_bytes = 1\nwebhook_url = \"http://localhost/hook\"",
+        )
+        .unwrap();
+
+        let refused = watch.validate().err().map(|e| e.to_string());
+
+        assert_eq!(refused.is_some(), !cfg!(feature = "webhook"));
+        if let Some(e) = refused {
+            assert!(e.contains("WEBHOOK_URL needs maxdirsize built with the `webhook` feature"));
+        }
+    }
+
+    #[test]
+    fn archives_inside_the_directory_are_found_through_symlinks() {
+        let root = std::env::temp_dir().join(format!("{APP_NAME}-inside-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        let directory = root.join("watched");
+        std::fs::create_dir_all(directory.join("kept")).unwrap();
+        std::os::unix::fs::symlink(directory.join("kept"), root.join("link")).unwrap();
+
+        assert!(is_inside(&root.join("link/archive"), &directory));
+        assert!(is_inside(&directory.join("sub/../new"), &directory));
+        assert!(is_inside(&root.join("watched"), &directory));
+        assert!(!is_inside(&root.join("archive"), &directory));
+        assert!(!is_inside(&root.join("watched-archive"), &directory));
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
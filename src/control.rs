@@ -0,0 +1,465 @@
+//! Driving the daemon: running a cleanup now, pausing and resuming cleanups,
+//! and reading the stats and the latest removals, through an authenticated
+//! HTTP API for orchestration tooling or the control socket.
+
+use colored::*;
+use log::info;
+use serde_json::json;
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::Sender;
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+use crate::http::{Request, Response};
+use crate::signals::Wake;
+use maxdirsize::audit::{Kind, Record};
+use maxdirsize::{CleanupPlan, CycleOutcome, CycleReport, VERSION};
+
+/// Removals kept for `GET /deletions`.
+const MAX_DELETIONS: usize = 1000;
+
+/// Removals returned by `GET /deletions` and `GET /queue` without a
+/// `limit`.
+const DEFAULT_DELETIONS: usize = 100;
+
+/// Planned removals kept per directory for `GET /queue`.
+const MAX_QUEUED: usize = 1000;
+
+#[derive(Default)]
+struct DirectoryStats {
+    last_run: Option<SystemTime>,
+    last_outcome: Option<CycleOutcome>,
+    /// [`CycleReport::summary`] of the last cycle
+    last_cycle: Option<serde_json::Value>,
+    size_bytes: u64,
+    limit_bytes: u64,
+    files: u64,
+    cycles: u64,
+    files_deleted_total: u64,
+    dirs_deleted_total: u64,
+    bytes_freed_total: u64,
+    errors_total: u64,
+}
+
+struct Deletion {
+    timestamp: SystemTime,
+    directory: String,
+    kind: Kind,
+    path: String,
+    size: u64,
+    reason: serde_json::Value,
+}
+
+/// A removal planned by the cleanup in progress.
+struct Queued {
+    path: String,
+    size: u64,
+    reason: serde_json::Value,
+}
+
+#[derive(Default)]
+struct Queue {
+    /// The first [`MAX_QUEUED`], in removal order
+    files: VecDeque<Queued>,
+    /// All of them
+    remaining: usize,
+}
+
+#[derive(Default)]
+struct State {
+    directories: BTreeMap<String, DirectoryStats>,
+    /// Oldest first
+    deletions: VecDeque<Deletion>,
+    /// What the cleanups in progress are about to remove
+    queues: BTreeMap<String, Queue>,
+}
+
+pub struct Control {
+    /// Required by the HTTP API, which is not served without one
+    token: Option<String>,
+    waker: Sender<Wake>,
+    started: SystemTime,
+    paused: AtomicBool,
+    state: Mutex<State>,
+}
+
+impl Control {
+    pub fn new(token: Option<&str>, waker: Sender<Wake>) -> Control {
+        Control {
+            token: token.map(str::to_string),
+            waker,
+            started: SystemTime::now(),
+            paused: AtomicBool::new(false),
+            state: Mutex::default(),
+        }
+    }
+
+    /// Whether cycles are held back until resumed.
+    pub fn paused(&self) -> bool {
+        self.paused.load(Ordering::SeqCst)
+    }
+
+    pub fn record(&self, directory: &str, report: &CycleReport) {
+        let mut state = self.state.lock().unwrap();
+        let stats = state.directories.entry(directory.to_string()).or_default();
+
+        stats.last_run = Some(SystemTime::now());
+        stats.last_outcome = Some(report.outcome);
+        stats.last_cycle = Some(report.summary());
+        stats.size_bytes = report.size_after;
+        stats.limit_bytes = report.limit_bytes;
+        stats.files = match report.dry_run {
+            true => report.total_files,
+            false => report.total_files - report.removed_files,
+        };
+        stats.cycles += 1;
+        stats.errors_total += report.errors;
+
+        if !report.dry_run {
+            stats.files_deleted_total += report.removed_files;
+            stats.dirs_deleted_total += report.removed_dirs;
+            stats.bytes_freed_total += report.freed_bytes;
+        }
+
+        state.queues.remove(directory);
+    }
+
+    /// Remembers what the cleanup of `directory` starting now removes.
+    pub fn planned(&self, directory: &str, plan: &CleanupPlan) {
+        let files = plan
+            .files
+            .iter()
+            .take(MAX_QUEUED)
+            .map(|eviction| Queued {
+                path: eviction.file.path.display().to_string(),
+                size: eviction.file.size,
+                reason: serde_json::to_value(eviction.reason).unwrap_or_default(),
+            })
+            .collect();
+
+        self.state.lock().unwrap().queues.insert(
+            directory.to_string(),
+            Queue {
+                files,
+                remaining: plan.files.len(),
+            },
+        );
+    }
+
+    /// Remembers a removal from `directory`, forgetting the oldest beyond
+    /// [`MAX_DELETIONS`].
+    ///
+    /// The size of the directory follows the removals while a cleanup is in
+    /// progress.
+    pub fn removed(&self, directory: &str, record: &Record) {
+        let mut state = self.state.lock().unwrap();
+        let path = record.path.display().to_string();
+
+        if let Some(stats) = state.directories.get_mut(directory) {
+            stats.size_bytes = stats.size_bytes.saturating_sub(record.size);
+            if record.kind == Kind::File {
+                stats.files = stats.files.saturating_sub(1);
+            }
+        }
+
+        // Files kept instead, e.g. when they changed, go along with it
+        if let Some(queue) = state.queues.get_mut(directory) {
+            if let Some(position) = queue.files.iter().position(|queued| queued.path == path) {
+                queue.files.drain(..=position);
+                queue.remaining = queue.remaining.saturating_sub(position + 1);
+            }
+        }
+
+        if state.deletions.len() == MAX_DELETIONS {
+            state.deletions.pop_front();
+        }
+
+        state.deletions.push_back(Deletion {
+            timestamp: SystemTime::now(),
+            directory: directory.to_string(),
+            kind: record.kind,
+            path,
+            size: record.size,
+            reason: serde_json::to_value(record.reason).unwrap_or_default(),
+        });
+    }
+
+    /// Forgets the directories no longer watched.
+    pub fn watch<'a>(&self, directories: impl IntoIterator<Item = &'a str>) {
+        let directories: Vec<&str> = directories.into_iter().collect();
+
+        self.state
+            .lock()
+            .unwrap()
+            .directories
+            .retain(|directory, _| directories.contains(&directory.as_str()));
+    }
+
+    pub fn handle(&self, request: &Request) -> Response {
+        if !self.authorized(request) {
+            return Response {
+                status: 401,
+                content_type: "text/plain",
+                body: "unauthorized\n".to_string(),
+            };
+        }
+
+        match (request.method.as_str(), request.path.as_str()) {
+            ("POST", "/run") => match self.run() {
+                Ok(body) => Response {
+                    status: 202,
+                    content_type: "application/json",
+                    body: body.to_string(),
+                },
+                Err(e) => Response {
+                    status: 409,
+                    content_type: "application/json",
+                    body: json!({ "error": e }).to_string(),
+                },
+            },
+            ("POST", "/pause") => Response::ok("application/json", self.pause().to_string()),
+            ("POST", "/resume") => Response::ok("application/json", self.resume().to_string()),
+            ("GET", "/stats") => Response::ok("application/json", self.stats().to_string()),
+            ("GET", path @ ("/deletions" | "/queue")) => {
+                match request.param("limit").map(str::parse::<usize>) {
+                    Some(Err(_)) => Response {
+                        status: 400,
+                        content_type: "application/json",
+                        body: json!({ "error": "limit must be a number" }).to_string(),
+                    },
+                    limit => {
+                        let limit = limit.and_then(Result::ok).unwrap_or(DEFAULT_DELETIONS);
+                        let body = match path {
+                            "/queue" => self.queue(limit),
+                            _ => self.deletions(limit),
+                        };
+
+                        Response::ok("application/json", body.to_string())
+                    }
+                }
+            }
+            (_, "/run" | "/pause" | "/resume" | "/stats" | "/deletions" | "/queue") => Response {
+                status: 405,
+                content_type: "text/plain",
+                body: "method not allowed\n".to_string(),
+            },
+            _ => Response::not_found(),
+        }
+    }
+
+    /// Whether `request` carries the token, compared in constant time.
+    fn authorized(&self, request: &Request) -> bool {
+        let (Some(expected), Some(token)) = (
+            &self.token,
+            request
+                .header("authorization")
+                .and_then(|value| value.strip_prefix("Bearer ")),
+        ) else {
+            return false;
+        };
+
+        token.len() == expected.len()
+            && token
+                .bytes()
+                .zip(expected.bytes())
+                .fold(0, |diff, (a, b)| diff | (a ^ b))
+                == 0
+    }
+
+    /// Asks for every directory to be cleaned up now, refused while paused.
+    pub fn run(&self) -> Result<serde_json::Value, String> {
+        if self.paused() {
+            return Err("cleanups are paused".to_string());
+        }
+
+        let _ = self.waker.send(Wake::RunNow);
+        Ok(json!({ "running": true }))
+    }
+
+    /// Holds back cycles until [`Control::resume`], a cleanup in progress
+    /// is finished.
+    pub fn pause(&self) -> serde_json::Value {
+        if !self.paused.swap(true, Ordering::SeqCst) {
+            info!(
+                event = "paused";
+                "{}",
+                "Cleanups paused".yellow()
+            );
+        }
+
+        json!({ "paused": true })
+    }
+
+    pub fn resume(&self) -> serde_json::Value {
+        if self.paused.swap(false, Ordering::SeqCst) {
+            info!(
+                event = "resumed";
+                "{}",
+                "Cleanups resumed".green()
+            );
+        }
+
+        let _ = self.waker.send(Wake::Resume);
+        json!({ "paused": false })
+    }
+
+    pub fn stats(&self) -> serde_json::Value {
+        let state = self.state.lock().unwrap();
+
+        json!({
+            "version": VERSION,
+            "uptime_seconds": SystemTime::now()
+                .duration_since(self.started)
+                .unwrap_or_default()
+                .as_secs(),
+            "paused": self.paused(),
+            "directories": state
+                .directories
+                .iter()
+                .map(|(directory, stats)| json!({
+                    "directory": directory,
+                    "last_run": stats
+                        .last_run
+                        .map(|time| humantime::format_rfc3339_seconds(time).to_string()),
+                    "last_outcome": stats.last_outcome.map(|outcome| outcome.as_str()),
+                    "last_cycle": stats.last_cycle,
+                    "size_bytes": stats.size_bytes,
+                    "limit_bytes": stats.limit_bytes,
+                    "files": stats.files,
+                    "cycles": stats.cycles,
+                    "files_deleted_total": stats.files_deleted_total,
+                    "dirs_deleted_total": stats.dirs_deleted_total,
+                    "bytes_freed_total": stats.bytes_freed_total,
+                    "errors_total": stats.errors_total,
+                }))
+                .collect::<Vec<_>>(),
+        })
+    }
+
+    /// The latest `limit` removals, newest first.
+    pub fn deletions(&self, limit: usize) -> serde_json::Value {
+        let state = self.state.lock().unwrap();
+
+        state
+            .deletions
+            .iter()
+            .rev()
+            .take(limit)
+            .map(|deletion| {
+                json!({
+                    "timestamp": humantime::format_rfc3339_millis(deletion.timestamp).to_string(),
+                    "directory": deletion.directory,
+                    "kind": deletion.kind,
+                    "path": deletion.path,
+                    "size": deletion.size,
+                    "reason": deletion.reason,
+                })
+            })
+            .collect()
+    }
+
+    /// The next `limit` removals of every cleanup in progress.
+    pub fn queue(&self, limit: usize) -> serde_json::Value {
+        let state = self.state.lock().unwrap();
+
+        state
+            .queues
+            .iter()
+            .map(|(directory, queue)| {
+                json!({
+                    "directory": directory,
+                    "remaining": queue.remaining,
+                    "files": queue
+                        .files
+                        .iter()
+                        .take(limit)
+                        .map(|queued| json!({
+                            "path": queued.path,
+                            "size": queued.size,
+                            "reason": queued.reason,
+                        }))
+                        .collect::<Vec<_>>(),
+                })
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use maxdirsize::config::DeleteMode;
+    use maxdirsize::policy::Reason;
+    use std::path::Path;
+    use std::sync::mpsc;
+
+    fn request(method: &str, target: &str, token: &str) -> Request {
+        let (path, query) = target.split_once('?').unwrap_or((target, ""));
+
+        Request {
+            method: method.to_string(),
+            path: path.to_string(),
+            query: query.to_string(),
+            headers: vec![("Authorization".to_string(), format!("Bearer {token}"))],
+            body: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn runs_are_refused_while_paused_and_deletions_listed_newest_first() {
+        let (waker, wake) = mpsc::channel();
+        let control = Control::new(Some("secret"), waker);
+
+        assert_eq!(
+            control.handle(&request("POST", "/run", "wrong")).status,
+            401
+        );
+        assert_eq!(
+            control.handle(&request("GET", "/run", "secret")).status,
+            405
+        );
+
+        assert_eq!(
+            control.handle(&request("POST", "/pause", "secret")).status,
+            200
+        );
+        assert_eq!(
+            control.handle(&request("POST", "/run", "secret")).status,
+            409
+        );
+        assert!(wake.try_recv().is_err());
+
+        control.handle(&request("POST", "/resume", "secret"));
+        assert_eq!(
+            control.handle(&request("POST", "/run", "secret")).status,
+            202
+        );
+        assert_eq!(
+            wake.try_iter().collect::<Vec<_>>(),
+            [Wake::Resume, Wake::RunNow]
+        );
+
+        for name in ["old", "new"] {
+            control.removed(
+                "/data",
+                &Record {
+                    kind: Kind::File,
+                    path: &Path::new("/data").join(name),
+                    size: 1,
+                    modified: SystemTime::now(),
+                    reason: Reason::SizeLimit,
+                    mode: DeleteMode::Delete,
+                    linked_to: None,
+                    archived_to: None,
+                },
+            );
+        }
+
+        let response = control.handle(&request("GET", "/deletions?limit=1", "secret"));
+        let deletions: serde_json::Value = serde_json::from_str(&response.body).unwrap();
+        assert_eq!(deletions.as_array().unwrap().len(), 1);
+        assert_eq!(deletions[0]["path"], "/data/new");
+        assert_eq!(deletions[0]["reason"], "size_limit");
+    }
+}
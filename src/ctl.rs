@@ -0,0 +1,279 @@
+//! The control socket, a Unix socket `maxdirsize ctl` talks to the daemon
+//! through, for operators on the box where no TCP port may be opened. A
+//! command is a line naming a [`CtlAction`], answered with a line of JSON.
+
+use colored::*;
+use log::error;
+use serde_json::Value;
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::control::Control;
+use maxdirsize::config::CtlAction;
+use maxdirsize::units;
+
+/// Removals answered to `deletions` and `queue`.
+#[cfg_attr(not(unix), allow(dead_code))]
+const LISTED: usize = 100;
+
+/// Sends `action` to the daemon listening on `socket` and prints its answer,
+/// 1 if it could not be sent or was refused.
+pub fn run(socket: Option<&Path>, action: CtlAction) -> i32 {
+    let Some(socket) = socket else {
+        error!(event = "error"; "No control socket configured, set CONTROL_SOCKET");
+        return 1;
+    };
+
+    let reply = match request(socket, action) {
+        Ok(reply) => reply,
+        Err(e) => {
+            error!(event = "error"; "Error sending {} to {}: {e}", name(action), socket.display());
+            return 1;
+        }
+    };
+
+    match action {
+        CtlAction::Status => print_status(&reply),
+        CtlAction::Run => println!("{}", "Cleaning up every directory now".green()),
+        CtlAction::Pause => println!("{}", "Cleanups paused".yellow()),
+        CtlAction::Resume => println!("{}", "Cleanups resumed".green()),
+    }
+
+    0
+}
+
+fn name(action: CtlAction) -> &'static str {
+    match action {
+        CtlAction::Status => "status",
+        CtlAction::Run => "run",
+        CtlAction::Pause => "pause",
+        CtlAction::Resume => "resume",
+    }
+}
+
+/// The answer of [`Control`] to the command `line`.
+#[cfg_attr(not(unix), allow(dead_code))]
+fn answer(control: &Control, line: &str) -> Value {
+    match line.trim() {
+        "status" => control.stats(),
+        "run" => control
+            .run()
+            .unwrap_or_else(|e| serde_json::json!({ "error": e })),
+        "pause" => control.pause(),
+        "resume" => control.resume(),
+        // For `maxdirsize tui`
+        "deletions" => control.deletions(LISTED),
+        "queue" => control.queue(LISTED),
+        command => serde_json::json!({ "error": format!("unknown command {command:?}") }),
+    }
+}
+
+fn print_status(status: &Value) {
+    println!(
+        "{}",
+        format!(
+            "{} v{}, up {} seconds, cleanups {}",
+            maxdirsize::APP_NAME,
+            status["version"].as_str().unwrap_or_default(),
+            status["uptime_seconds"].as_u64().unwrap_or_default(),
+            match status["paused"].as_bool().unwrap_or_default() {
+                true => "paused",
+                false => "running",
+            }
+        )
+        .bold()
+    );
+
+    for directory in status["directories"].as_array().into_iter().flatten() {
+        let size = directory["size_bytes"].as_u64().unwrap_or_default();
+        let limit = directory["limit_bytes"].as_u64().unwrap_or_default();
+
+        println!(
+            "{}: {:.2} MB of {:.2} MB ({}), {} files, last run {} ({})",
+            directory["directory"].as_str().unwrap_or_default(),
+            size as f64 / units::MIB as f64,
+            limit as f64 / units::MIB as f64,
+            match limit {
+                0 => "no size limit".to_string(),
+                limit => format!("{:.1}%", size as f64 * 100.0 / limit as f64),
+            },
+            directory["files"].as_u64().unwrap_or_default(),
+            directory["last_run"].as_str().unwrap_or("never"),
+            directory["last_outcome"].as_str().unwrap_or("none"),
+        );
+
+        let cycle = &directory["last_cycle"];
+
+        if cycle.is_object() {
+            println!(
+                "  last cycle took {:.2}s, scanned {} files, removed {} files and {} directories freeing {:.2} MB, {} errors",
+                cycle["duration_seconds"].as_f64().unwrap_or_default(),
+                cycle["files_scanned"].as_u64().unwrap_or_default(),
+                cycle["files_deleted"].as_u64().unwrap_or_default(),
+                cycle["dirs_deleted"].as_u64().unwrap_or_default(),
+                cycle["bytes_freed"].as_u64().unwrap_or_default() as f64 / units::MIB as f64,
+                cycle["errors"].as_u64().unwrap_or_default(),
+            );
+        }
+    }
+}
+
+#[cfg(unix)]
+mod imp {
+    use log::debug;
+    use serde_json::Value;
+    use std::io::{BufRead, BufReader, ErrorKind, Read, Write};
+    use std::os::unix::fs::PermissionsExt;
+    use std::os::unix::net::{UnixListener, UnixStream};
+    use std::path::Path;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use super::answer;
+    use crate::control::Control;
+
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    /// Longest command line read.
+    const MAX_LINE: u64 = 1024;
+
+    /// Listens on `path`, replacing a socket left behind by a daemon that
+    /// is gone but refusing one still answering.
+    pub fn serve(path: &Path, control: Arc<Control>) -> std::io::Result<()> {
+        if path.exists() {
+            if UnixStream::connect(path).is_ok() {
+                return Err(std::io::Error::new(
+                    ErrorKind::AddrInUse,
+                    "another daemon is listening on it",
+                ));
+            }
+
+            std::fs::remove_file(path)?;
+        }
+
+        let listener = UnixListener::bind(path)?;
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))?;
+
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let Ok(stream) = stream else {
+                    continue;
+                };
+
+                if let Err(e) = handle(stream, &control) {
+                    debug!("Error handling control command: {e:?}");
+                }
+            }
+        });
+
+        Ok(())
+    }
+
+    fn handle(stream: UnixStream, control: &Control) -> std::io::Result<()> {
+        stream.set_read_timeout(Some(TIMEOUT))?;
+
+        let mut line = String::new();
+        BufReader::new(&stream)
+            .take(MAX_LINE)
+            .read_line(&mut line)?;
+
+        writeln!(&stream, "{}", answer(control, &line))
+    }
+
+    /// The answer of the daemon on `socket` to `command`, an error if it
+    /// refused it.
+    pub fn send(socket: &Path, command: &str) -> std::io::Result<Value> {
+        let mut stream = UnixStream::connect(socket)?;
+        stream.set_read_timeout(Some(TIMEOUT))?;
+
+        writeln!(stream, "{command}")?;
+
+        let mut line = String::new();
+        BufReader::new(stream).read_line(&mut line)?;
+
+        let reply: Value = serde_json::from_str(&line).map_err(std::io::Error::other)?;
+
+        match reply["error"].as_str() {
+            Some(e) => Err(std::io::Error::other(e.to_string())),
+            None => Ok(reply),
+        }
+    }
+}
+
+#[cfg(not(unix))]
+mod imp {
+    use serde_json::Value;
+    use std::path::Path;
+    use std::sync::Arc;
+
+    use crate::control::Control;
+
+    fn unsupported() -> std::io::Error {
+        std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            "control sockets are only supported on Unix",
+        )
+    }
+
+    pub fn serve(_path: &Path, _control: Arc<Control>) -> std::io::Result<()> {
+        Err(unsupported())
+    }
+
+    pub fn send(_socket: &Path, _command: &str) -> std::io::Result<Value> {
+        Err(unsupported())
+    }
+}
+
+/// Listens for commands on `path` until the daemon exits.
+pub fn serve(path: &Path, control: Arc<Control>) -> std::io::Result<()> {
+    imp::serve(path, control)
+}
+
+/// The answer of the daemon on `socket` to `command`, one of the
+/// [`CtlAction`]s, `deletions` or `queue`.
+pub fn send(socket: &Path, command: &str) -> std::io::Result<Value> {
+    imp::send(socket, command)
+}
+
+fn request(socket: &Path, action: CtlAction) -> std::io::Result<Value> {
+    send(socket, name(action))
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+
+    #[test]
+    fn commands_are_answered_over_the_socket() {
+        let socket = std::env::temp_dir().join(format!(
+            "{}-ctl-{}.sock",
+            maxdirsize::APP_NAME,
+            std::process::id()
+        ));
+        let (waker, wake) = mpsc::channel();
+        let control = Arc::new(Control::new(None, waker));
+
+        serve(&socket, control.clone()).unwrap();
+        assert_eq!(
+            serve(&socket, control).unwrap_err().kind(),
+            std::io::ErrorKind::AddrInUse
+        );
+
+        assert_eq!(request(&socket, CtlAction::Pause).unwrap()["paused"], true);
+        assert_eq!(
+            request(&socket, CtlAction::Run).unwrap_err().to_string(),
+            "cleanups are paused"
+        );
+        assert_eq!(request(&socket, CtlAction::Status).unwrap()["paused"], true);
+
+        request(&socket, CtlAction::Resume).unwrap();
+        request(&socket, CtlAction::Run).unwrap();
+        assert_eq!(
+            wake.try_iter().collect::<Vec<_>>(),
+            [crate::signals::Wake::Resume, crate::signals::Wake::RunNow]
+        );
+
+        std::fs::remove_file(&socket).unwrap();
+    }
+}
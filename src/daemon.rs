@@ -0,0 +1,177 @@
+//! Running in the background without a service manager, for `DAEMON` and
+//! `PID_FILE`: detaching from the terminal and telling which process to
+//! signal.
+
+use std::fs::{self, File, TryLockError};
+use std::io::{self, Read, Seek, Write};
+use std::path::{Path, PathBuf};
+
+/// A PID file, locked for as long as the process runs and removed when it is
+/// dropped. A file left behind by a process that was killed is unlocked, so
+/// the next start takes it over.
+#[derive(Debug)]
+pub struct PidFile {
+    file: File,
+    path: PathBuf,
+}
+
+impl PidFile {
+    /// Locks `path`, failing with [`io::ErrorKind::WouldBlock`] when a
+    /// running instance holds it.
+    pub fn acquire(path: &Path) -> io::Result<PidFile> {
+        let mut file = File::options()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+
+        match file.try_lock() {
+            Ok(()) => {}
+            Err(TryLockError::WouldBlock) => {
+                let mut owner = String::new();
+                let _ = file.read_to_string(&mut owner);
+
+                return Err(io::Error::new(
+                    io::ErrorKind::WouldBlock,
+                    format!(
+                        "already running as process {} (PID file {})",
+                        match owner.trim() {
+                            "" => "unknown",
+                            pid => pid,
+                        },
+                        path.display()
+                    ),
+                ));
+            }
+            Err(TryLockError::Error(e)) => return Err(e),
+        }
+
+        Ok(PidFile {
+            file,
+            path: path.to_path_buf(),
+        })
+    }
+
+    /// Writes the PID of the calling process, the daemon once detached.
+    pub fn write(&mut self) -> io::Result<()> {
+        self.file.set_len(0)?;
+        self.file.rewind()?;
+        writeln!(self.file, "{}", std::process::id())?;
+        self.file.sync_all()
+    }
+}
+
+impl Drop for PidFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// Detaches from the terminal, returning in the daemon: forks, starts a new
+/// session and forks again so that the daemon can never get a controlling
+/// terminal back, writes `pid_file`, and points standard input and output at
+/// /dev/null and standard error at `log_file`, or /dev/null too.
+///
+/// The process started from the terminal waits for the daemon to get there
+/// and exits then, with 1 if the daemon failed on the way. The working
+/// directory and the umask are kept, for relative paths in the configuration
+/// to keep working. Must be called before any thread is started.
+#[cfg(unix)]
+pub fn detach(log_file: Option<&Path>, pid_file: Option<&mut PidFile>) -> io::Result<()> {
+    use std::os::fd::{AsRawFd, FromRawFd};
+
+    let mut fds = [0; 2];
+    // SAFETY: pipe fills in the two descriptors, which are owned from then on
+    let (mut ready, mut started) = unsafe {
+        if libc::pipe(fds.as_mut_ptr()) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        (File::from_raw_fd(fds[0]), File::from_raw_fd(fds[1]))
+    };
+
+    if fork()? != 0 {
+        drop(started);
+
+        let mut byte = [0];
+        std::process::exit(match ready.read(&mut byte) {
+            Ok(1) => 0,
+            _ => 1,
+        });
+    }
+    drop(ready);
+
+    // SAFETY: no arguments
+    if unsafe { libc::setsid() } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+
+    if fork()? != 0 {
+        // SAFETY: leaves without running anything the daemon still relies on
+        unsafe { libc::_exit(0) };
+    }
+
+    if let Some(pid_file) = pid_file {
+        pid_file.write()?;
+    }
+
+    let null = File::options().read(true).write(true).open("/dev/null")?;
+    let errors = match log_file {
+        Some(log_file) => maxdirsize::logging::open(log_file)?,
+        None => null.try_clone()?,
+    };
+
+    for (file, fd) in [(&null, 0), (&null, 1), (&errors, 2)] {
+        // SAFETY: both descriptors are open
+        if unsafe { libc::dup2(file.as_raw_fd(), fd) } == -1 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+
+    started.write_all(&[1])
+}
+
+/// Refused by the configuration.
+#[cfg(not(unix))]
+pub fn detach(_log_file: Option<&Path>, _pid_file: Option<&mut PidFile>) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "running as a daemon is only supported on Unix",
+    ))
+}
+
+#[cfg(unix)]
+fn fork() -> io::Result<libc::pid_t> {
+    // SAFETY: the process is single threaded, see `detach`
+    match unsafe { libc::fork() } {
+        -1 => Err(io::Error::last_os_error()),
+        pid => Ok(pid),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use maxdirsize::APP_NAME;
+
+    #[test]
+    fn pid_files_are_held_until_dropped() {
+        let path = std::env::temp_dir().join(format!("{APP_NAME}-pid-{}", std::process::id()));
+        let _ = fs::remove_file(&path);
+
+        let mut pid_file = PidFile::acquire(&path).unwrap();
+        pid_file.write().unwrap();
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            format!("{}\n", std::process::id())
+        );
+
+        let e = PidFile::acquire(&path).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::WouldBlock);
+        assert!(e.to_string().contains(&std::process::id().to_string()));
+
+        drop(pid_file);
+        assert!(!path.exists());
+        drop(PidFile::acquire(&path).unwrap());
+    }
+}
@@ -0,0 +1,151 @@
+//! Finding files with identical contents, for `DEDUPLICATE`: the copies are
+//! the cheapest space to reclaim, before any unique data is evicted.
+
+use colored::*;
+use log::warn;
+use rayon::prelude::*;
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::path::Path;
+
+use crate::scan::FileInfo;
+
+/// Sets of at least two `files` with the same contents, found by comparing
+/// sizes and then blake3 hashes of the files sharing one. Empty files, and
+/// the other links of a file with several, are left out.
+pub fn duplicates<'a>(files: &[&'a FileInfo]) -> Vec<Vec<&'a FileInfo>> {
+    let mut inodes = HashSet::new();
+    let mut sizes: HashMap<u64, Vec<&FileInfo>> = HashMap::new();
+
+    for file in files {
+        let first_link = match &file.hard_link {
+            Some(link) => inodes.insert((link.device, link.inode)),
+            None => true,
+        };
+
+        if file.size > 0 && first_link {
+            sizes.entry(file.size).or_default().push(file);
+        }
+    }
+
+    let hashed: Vec<((u64, [u8; 32]), &FileInfo)> = sizes
+        .into_values()
+        .filter(|same_size| same_size.len() > 1)
+        .flatten()
+        .collect::<Vec<_>>()
+        .into_par_iter()
+        .filter_map(|file| match hash(&file.path) {
+            Ok(hash) => Some(((file.size, hash), file)),
+            Err(e) => {
+                warn!(
+                    "{}",
+                    format!(
+                        "Error reading {} to look for duplicates: {e}",
+                        file.path.display()
+                    )
+                    .yellow()
+                );
+                None
+            }
+        })
+        .collect();
+
+    let mut contents: HashMap<(u64, [u8; 32]), Vec<&FileInfo>> = HashMap::new();
+
+    for (key, file) in hashed {
+        contents.entry(key).or_default().push(file);
+    }
+
+    let mut sets: Vec<Vec<&FileInfo>> = contents
+        .into_values()
+        .filter(|same| same.len() > 1)
+        .collect();
+
+    for set in &mut sets {
+        set.sort_by(|a, b| a.path.cmp(&b.path));
+    }
+    sets.sort_by(|a, b| a[0].path.cmp(&b[0].path));
+
+    sets
+}
+
+fn hash(path: &Path) -> std::io::Result<[u8; 32]> {
+    let mut hasher = blake3::Hasher::new();
+    hasher.update_reader(File::open(path)?)?;
+
+    Ok(*hasher.finalize().as_bytes())
+}
+
+/// Replaces `duplicate` with a hard link to `original`, through a link next
+/// to it renamed over it so that the path never goes missing.
+pub fn link(duplicate: &Path, original: &Path) -> std::io::Result<()> {
+    let mut temporary = duplicate.as_os_str().to_owned();
+    temporary.push(format!(".{}-link", crate::APP_NAME));
+
+    let _ = std::fs::remove_file(&temporary);
+    std::fs::hard_link(original, &temporary)?;
+
+    std::fs::rename(&temporary, duplicate).inspect_err(|_| {
+        let _ = std::fs::remove_file(&temporary);
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::APP_NAME;
+    use std::path::PathBuf;
+
+    fn file(path: PathBuf) -> FileInfo {
+        FileInfo {
+            size: std::fs::metadata(&path).unwrap().len(),
+            path,
+            modified: 0,
+            accessed: 0,
+            hard_link: None,
+            inode: 0,
+        }
+    }
+
+    #[test]
+    fn identical_files_are_found_and_linked() {
+        let root = std::env::temp_dir().join(format!("{APP_NAME}-dedup-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(&root).unwrap();
+
+        for (name, contents) in [
+            ("a.bin", "artifact"),
+            ("b.bin", "artifact"),
+            ("c.bin", "artefact"),
+            ("d.bin", "other"),
+            ("e.bin", ""),
+            ("f.bin", ""),
+        ] {
+            std::fs::write(root.join(name), contents).unwrap();
+        }
+
+        let files: Vec<FileInfo> = ["a.bin", "b.bin", "c.bin", "d.bin", "e.bin", "f.bin"]
+            .into_iter()
+            .map(|name| file(root.join(name)))
+            .collect();
+        let found = duplicates(&files.iter().collect::<Vec<_>>());
+
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0][0].path, root.join("a.bin"));
+        assert_eq!(found[0][1].path, root.join("b.bin"));
+
+        link(&root.join("b.bin"), &root.join("a.bin")).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(root.join("b.bin")).unwrap(),
+            "artifact"
+        );
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::MetadataExt;
+            assert_eq!(std::fs::metadata(root.join("a.bin")).unwrap().nlink(), 2);
+        }
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,102 @@
+//! Desktop notifications of cleanups, for directories such as Downloads on a
+//! workstation, where someone is around to notice files going away.
+
+use log::debug;
+use std::sync::mpsc::{self, Sender};
+use std::thread::JoinHandle;
+
+use crate::cleaner::CycleReport;
+use crate::APP_NAME;
+
+/// Shows notifications in order on a background thread, as talking to the
+/// notification daemon may block. Dropping it waits for the notifications
+/// already sent.
+pub struct Desktop {
+    sender: Option<Sender<(String, String)>>,
+    worker: Option<JoinHandle<()>>,
+}
+
+impl Desktop {
+    pub fn new() -> Desktop {
+        let (sender, receiver) = mpsc::channel::<(String, String)>();
+
+        let worker = std::thread::spawn(move || {
+            for (summary, body) in receiver {
+                let shown = notify_rust::Notification::new()
+                    .appname(APP_NAME)
+                    .summary(&summary)
+                    .body(&body)
+                    .show();
+
+                // Routinely fails without a desktop session, e.g. over ssh
+                if let Err(e) = shown {
+                    debug!("Error showing desktop notification: {e}");
+                }
+            }
+        });
+
+        Desktop {
+            sender: Some(sender),
+            worker: Some(worker),
+        }
+    }
+
+    /// Tells that `files` files of `bytes` bytes are about to be removed from
+    /// `directory`.
+    pub fn cleanup_started(&self, directory: &str, files: usize, bytes: u64, dry_run: bool) {
+        self.show(
+            format!("Cleaning up {directory}"),
+            format!(
+                "{} {files} files ({:.2} MB) to stay within the limits",
+                match dry_run {
+                    true => "Would remove",
+                    false => "Removing",
+                },
+                bytes as f64 / 1024.0 / 1024.0
+            ),
+        );
+    }
+
+    pub fn cleanup_finished(&self, directory: &str, report: &CycleReport) {
+        self.show(
+            format!("Cleaned up {directory}"),
+            format!(
+                "{} {} files and {} directories freeing {:.2} MB, {:.2} MB left{}",
+                match report.dry_run {
+                    true => "Would have removed",
+                    false => "Removed",
+                },
+                report.removed_files,
+                report.removed_dirs,
+                report.freed_bytes as f64 / 1024.0 / 1024.0,
+                report.size_after as f64 / 1024.0 / 1024.0,
+                match report.errors {
+                    0 => String::new(),
+                    errors => format!(", {errors} errors"),
+                }
+            ),
+        );
+    }
+
+    fn show(&self, summary: String, body: String) {
+        if let Some(sender) = &self.sender {
+            let _ = sender.send((summary, body));
+        }
+    }
+}
+
+impl Default for Desktop {
+    fn default() -> Desktop {
+        Desktop::new()
+    }
+}
+
+impl Drop for Desktop {
+    fn drop(&mut self) {
+        self.sender.take();
+
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+    }
+}
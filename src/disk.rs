@@ -0,0 +1,150 @@
+//! Capacity of the filesystem holding a path.
+
+use std::path::Path;
+
+#[derive(Debug, Clone, Copy)]
+pub struct DiskUsage {
+    pub total: u64,
+    /// Space available to unprivileged users
+    pub available: u64,
+}
+
+impl DiskUsage {
+    pub fn free_percent(&self) -> f64 {
+        if self.total == 0 {
+            return 0.0;
+        }
+
+        self.available as f64 / self.total as f64 * 100.0
+    }
+}
+
+/// How the filesystem holding a path keeps file access times.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccessTimes {
+    /// Updated on every read, or the mount options could not be read
+    Strict,
+    /// `relatime`: only updated when older than the modification time or a
+    /// day old
+    Relative,
+    /// `noatime`: never updated after a file is created
+    Disabled,
+}
+
+#[cfg(unix)]
+fn statvfs(path: &Path) -> std::io::Result<libc::statvfs> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let path = CString::new(path.as_os_str().as_bytes())
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+
+    let mut stats = std::mem::MaybeUninit::<libc::statvfs>::uninit();
+
+    // SAFETY: `path` is a valid NUL terminated string and `stats` is only
+    // read after statvfs reported success.
+    unsafe {
+        if libc::statvfs(path.as_ptr(), stats.as_mut_ptr()) != 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        Ok(stats.assume_init())
+    }
+}
+
+#[cfg(unix)]
+pub fn usage(path: &Path) -> std::io::Result<DiskUsage> {
+    let stats = statvfs(path)?;
+    let fragment = stats.f_frsize as u64;
+
+    Ok(DiskUsage {
+        total: stats.f_blocks as u64 * fragment,
+        available: stats.f_bavail as u64 * fragment,
+    })
+}
+
+#[cfg(windows)]
+pub fn usage(path: &Path) -> std::io::Result<DiskUsage> {
+    use std::os::windows::ffi::OsStrExt;
+    use windows_sys::Win32::Storage::FileSystem::GetDiskFreeSpaceExW;
+
+    let path: Vec<u16> = path.as_os_str().encode_wide().chain([0]).collect();
+
+    let mut available = 0;
+    let mut total = 0;
+
+    // SAFETY: `path` is NUL terminated and the counters outlive the call.
+    if unsafe {
+        GetDiskFreeSpaceExW(
+            path.as_ptr(),
+            &mut available,
+            &mut total,
+            std::ptr::null_mut(),
+        )
+    } == 0
+    {
+        return Err(std::io::Error::last_os_error());
+    }
+
+    Ok(DiskUsage { total, available })
+}
+
+#[cfg(not(any(unix, windows)))]
+pub fn usage(_path: &Path) -> std::io::Result<DiskUsage> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "filesystem usage is not supported on this platform",
+    ))
+}
+
+#[cfg(target_os = "linux")]
+pub fn access_times(path: &Path) -> AccessTimes {
+    // Not exported by libc for every Linux target
+    const ST_RELATIME: libc::c_ulong = 4096;
+
+    match statvfs(path) {
+        Ok(stats) if stats.f_flag & libc::ST_NOATIME != 0 => AccessTimes::Disabled,
+        Ok(stats) if stats.f_flag & ST_RELATIME != 0 => AccessTimes::Relative,
+        _ => AccessTimes::Strict,
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn access_times(_path: &Path) -> AccessTimes {
+    AccessTimes::Strict
+}
+
+/// Whether the filesystem of `path` writes changes elsewhere rather than in
+/// place (btrfs, ZFS, bcachefs), so overwriting a file leaves its old
+/// contents on disk.
+#[cfg(target_os = "linux")]
+pub fn copy_on_write(path: &Path) -> bool {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    const BTRFS: u32 = 0x9123_683e;
+    const ZFS: u32 = 0x2fc1_2fc1;
+    const BCACHEFS: u32 = 0xca45_1a4e;
+
+    let Ok(path) = CString::new(path.as_os_str().as_bytes()) else {
+        return false;
+    };
+
+    let mut stats = std::mem::MaybeUninit::<libc::statfs>::uninit();
+
+    // SAFETY: as in `statvfs`.
+    unsafe {
+        if libc::statfs(path.as_ptr(), stats.as_mut_ptr()) != 0 {
+            return false;
+        }
+
+        // The width and sign of f_type vary between targets
+        #[allow(clippy::unnecessary_cast)]
+        let kind = stats.assume_init().f_type as u32;
+        [BTRFS, ZFS, BCACHEFS].contains(&kind)
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn copy_on_write(_path: &Path) -> bool {
+    false
+}
@@ -0,0 +1,317 @@
+//! Emailing summaries of cleanups over SMTP, for those without a metrics
+//! stack to notice a cleanup removing far more than usual.
+
+use colored::*;
+use lettre::message::Mailbox;
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{Message, SmtpTransport, Transport};
+use log::warn;
+use std::sync::mpsc::{self, Sender};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use crate::cleaner::{CycleOutcome, CycleReport};
+use crate::config::{SmtpTls, WatchConfig};
+
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Where and to whom emails are sent.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Server {
+    pub host: String,
+    pub port: u16,
+    pub tls: SmtpTls,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    pub from: Mailbox,
+    pub to: Vec<Mailbox>,
+}
+
+impl Server {
+    /// The server configured for `config`, if any; its addresses were
+    /// checked with the configuration.
+    pub fn of(config: &WatchConfig) -> Option<Server> {
+        Some(Server {
+            host: config.smtp_host.clone()?,
+            port: config.smtp_port.unwrap_or(match config.smtp_tls {
+                SmtpTls::Starttls => 587,
+                SmtpTls::Tls => 465,
+                SmtpTls::None => 25,
+            }),
+            tls: config.smtp_tls,
+            username: config.smtp_username.clone(),
+            password: config.smtp_password.clone(),
+            from: config.email_from.as_deref()?.parse().ok()?,
+            to: config
+                .email_to
+                .iter()
+                .filter_map(|to| to.parse().ok())
+                .collect(),
+        })
+    }
+}
+
+/// Sends emails in order on a background thread, so a slow server never
+/// holds up a cleanup. Dropping it waits for the emails already sent.
+pub struct Mailer {
+    server: Server,
+    sender: Option<Sender<Message>>,
+    worker: Option<JoinHandle<()>>,
+}
+
+impl Mailer {
+    pub fn new(server: Server) -> Result<Mailer, lettre::transport::smtp::Error> {
+        let builder = match server.tls {
+            SmtpTls::Starttls => SmtpTransport::starttls_relay(&server.host)?,
+            SmtpTls::Tls => SmtpTransport::relay(&server.host)?,
+            SmtpTls::None => SmtpTransport::builder_dangerous(&server.host),
+        };
+
+        let mut builder = builder.port(server.port).timeout(Some(TIMEOUT));
+
+        if let Some(username) = &server.username {
+            builder = builder.credentials(Credentials::new(
+                username.clone(),
+                server.password.clone().unwrap_or_default(),
+            ));
+        }
+
+        let transport = builder.build();
+        let (sender, receiver) = mpsc::channel::<Message>();
+        let host = server.host.clone();
+
+        let worker = std::thread::spawn(move || {
+            for message in receiver {
+                if let Err(e) = transport.send(&message) {
+                    warn!(
+                        event = "email_failed", host = host.as_str();
+                        "{}",
+                        format!("Error sending email through {host}: {e}").yellow()
+                    );
+                }
+            }
+        });
+
+        Ok(Mailer {
+            server,
+            sender: Some(sender),
+            worker: Some(worker),
+        })
+    }
+
+    pub fn server(&self) -> &Server {
+        &self.server
+    }
+
+    /// Queues an email with `subject` and `body` to every recipient.
+    pub fn send(&self, subject: &str, body: String) -> Result<(), lettre::error::Error> {
+        let mut message = Message::builder()
+            .from(self.server.from.clone())
+            .subject(subject);
+
+        for to in &self.server.to {
+            message = message.to(to.clone());
+        }
+
+        if let Some(sender) = &self.sender {
+            let _ = sender.send(message.body(body)?);
+        }
+
+        Ok(())
+    }
+}
+
+impl Drop for Mailer {
+    fn drop(&mut self) {
+        self.sender.take();
+
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+    }
+}
+
+/// Whether `report` reaches one of the `email_min_` thresholds of `config`,
+/// or without any whether the cycle cleaned up or failed.
+pub fn wanted(config: &WatchConfig, report: &CycleReport) -> bool {
+    let thresholds = [
+        (config.email_min_files, report.removed_files),
+        (config.email_min_bytes, report.freed_bytes),
+        (config.email_min_errors, report.errors),
+    ];
+
+    if thresholds.iter().all(|(threshold, _)| threshold.is_none()) {
+        return matches!(
+            report.outcome,
+            CycleOutcome::CleanedUp | CycleOutcome::Failed
+        );
+    }
+
+    thresholds
+        .iter()
+        .any(|&(threshold, value)| threshold.is_some_and(|threshold| value >= threshold))
+}
+
+/// Subject and body of the email about the cycle of `directory`.
+pub fn summary(directory: &str, report: &CycleReport) -> (String, String) {
+    let megabytes = |bytes: u64| bytes as f64 / 1024.0 / 1024.0;
+    let removed = match report.dry_run {
+        true => "would remove",
+        false => "removed",
+    };
+
+    let subject = match report.outcome {
+        CycleOutcome::Failed => format!("{} failed to clean up {directory}", crate::APP_NAME),
+        _ => format!(
+            "{} {removed} {} files ({:.2} MB) from {directory}",
+            crate::APP_NAME,
+            report.removed_files,
+            megabytes(report.freed_bytes)
+        ),
+    };
+
+    let mut body = format!(
+        "Cycle of {directory} on {}: {}\n\n",
+        hostname(),
+        report.outcome.as_str()
+    );
+
+    body += &format!(
+        "Scanned {} files, {:.2} MB before and {:.2} MB after, the limit being {:.2} MB\n",
+        report.total_files,
+        megabytes(report.size_before),
+        megabytes(report.size_after),
+        megabytes(report.limit_bytes)
+    );
+    body += &format!(
+        "{} {} files and {} directories freeing {:.2} MB\n",
+        match report.dry_run {
+            true => "Would remove",
+            false => "Removed",
+        },
+        report.removed_files,
+        report.removed_dirs,
+        megabytes(report.freed_bytes)
+    );
+    body += &format!(
+        "{} errors, took {:.2}s\n",
+        report.errors,
+        report.duration.as_secs_f64()
+    );
+
+    if report.outcome == CycleOutcome::Failed {
+        body += "\nThe directory could not be scanned, see the logs for why.\n";
+    }
+
+    (subject, body)
+}
+
+fn hostname() -> String {
+    hostname::get()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_else(|_| "an unknown host".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+
+    #[test]
+    fn summaries_reaching_a_threshold_are_emailed() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        // Answers a single session, returning the message sent
+        let server = std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut stream = stream;
+            let mut data = String::new();
+            let mut in_data = false;
+
+            stream.write_all(b"220 localhost ready\r\n").unwrap();
+
+            loop {
+                let mut line = String::new();
+                if reader.read_line(&mut line).unwrap() == 0 {
+                    break;
+                }
+
+                if in_data {
+                    if line == ".\r\n" {
+                        in_data = false;
+                        stream.write_all(b"250 queued\r\n").unwrap();
+                    } else {
+                        data += &line;
+                    }
+                    continue;
+                }
+
+                let reply: &[u8] = match line.get(..4).unwrap_or_default() {
+                    "DATA" => {
+                        in_data = true;
+                        b"354 go ahead\r\n"
+                    }
+                    "QUIT" => {
+                        stream.write_all(b"221 bye\r\n").unwrap();
+                        break;
+                    }
+                    _ => b"250 ok\r\n",
+                };
+                stream.write_all(reply).unwrap();
+            }
+
+            data
+        });
+
+        let config: WatchConfig = toml::from_str(&format!(
+            r#"
+            directory = "/data"
+            interval_seconds = 60
+            max_size_bytes = 100
+            smtp_host = "127.0.0.1"
+            smtp_port = {port}
+            smtp_tls = "none"
+            email_from = "maxdirsize <cleaner@example.com>"
+            email_to = ["ops@example.com"]
+            email_min_files = 10
+            "#
+        ))
+        .unwrap();
+
+        let report = CycleReport {
+            outcome: CycleOutcome::CleanedUp,
+            removed_files: 9,
+            freed_bytes: 3 * 1024 * 1024,
+            ..Default::default()
+        };
+        assert!(!wanted(&config, &report));
+        assert!(wanted(
+            &WatchConfig {
+                email_min_files: None,
+                ..config.clone()
+            },
+            &report
+        ));
+
+        let report = CycleReport {
+            removed_files: 10,
+            ..report
+        };
+        assert!(wanted(&config, &report));
+
+        let (subject, body) = summary(&config.directory, &report);
+        assert_eq!(subject, "maxdirsize removed 10 files (3.00 MB) from /data");
+
+        let mailer = Mailer::new(Server::of(&config).unwrap()).unwrap();
+        mailer.send(&subject, body).unwrap();
+        drop(mailer);
+
+        let data = server.join().unwrap();
+        assert!(data.contains("Subject: maxdirsize removed 10 files (3.00 MB) from /data"));
+        assert!(data.contains("To: ops@example.com"));
+        assert!(data.contains("Removed 10 files and 0 directories freeing 3.00 MB"));
+    }
+}
@@ -0,0 +1,364 @@
+//! Eviction policies decide which files go first when a directory is over
+//! its limits.
+//!
+//! The built-in policies are selected with `EVICTION_POLICY`. Library users
+//! can supply their own through [`crate::Cleaner::with_policy`]:
+//!
+//! ```
+//! use maxdirsize::eviction::EvictionPolicy;
+//! use maxdirsize::scan::FileInfo;
+//!
+//! /// Evicts temporary files before anything else, oldest first.
+//! struct TempFilesFirst;
+//!
+//! impl EvictionPolicy for TempFilesFirst {
+//!     fn score(&self, file: &FileInfo) -> f64 {
+//!         match file.path.extension() {
+//!             Some(extension) if extension == "tmp" => 0.0,
+//!             _ => 1.0,
+//!         }
+//!     }
+//! }
+//! ```
+
+use std::cmp::{Ordering, Reverse};
+use std::collections::BinaryHeap;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::config::{EvictionPolicyKind, WatchConfig};
+use crate::scan::FileInfo;
+use crate::units::MIB;
+
+/// Orders files for eviction: the lowest score is removed first, ties go to
+/// the least recently modified file.
+pub trait EvictionPolicy: Send + Sync {
+    fn score(&self, file: &FileInfo) -> f64;
+
+    /// Sorts `files` so the first one is the first to be removed.
+    fn sort(&self, files: &mut [FileInfo]) {
+        files.sort_by(|a, b| {
+            self.score(a)
+                .total_cmp(&self.score(b))
+                .then(a.modified.cmp(&b.modified))
+        });
+    }
+
+    /// Yields `files` in the order of [`EvictionPolicy::sort`], without
+    /// sorting the ones never asked for.
+    fn order(&self, files: Vec<FileInfo>) -> EvictionOrder {
+        EvictionOrder {
+            heap: files
+                .into_iter()
+                .map(|file| {
+                    Reverse(Ranked {
+                        score: self.score(&file),
+                        file,
+                    })
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Files taken one at a time from a heap, so picking the few files a cleanup
+/// removes out of a big directory costs a single pass over it rather than a
+/// sort.
+pub struct EvictionOrder {
+    heap: BinaryHeap<Reverse<Ranked>>,
+}
+
+impl EvictionOrder {
+    /// The files not yet taken, in no particular order.
+    pub fn into_rest(self) -> impl Iterator<Item = FileInfo> {
+        self.heap.into_vec().into_iter().map(|ranked| ranked.0.file)
+    }
+}
+
+impl Iterator for EvictionOrder {
+    type Item = FileInfo;
+
+    fn next(&mut self) -> Option<FileInfo> {
+        self.heap.pop().map(|ranked| ranked.0.file)
+    }
+}
+
+struct Ranked {
+    score: f64,
+    file: FileInfo,
+}
+
+impl PartialEq for Ranked {
+    fn eq(&self, other: &Ranked) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Ranked {}
+
+impl PartialOrd for Ranked {
+    fn partial_cmp(&self, other: &Ranked) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Ranked {
+    fn cmp(&self, other: &Ranked) -> Ordering {
+        self.score
+            .total_cmp(&other.score)
+            .then(self.file.modified.cmp(&other.file.modified))
+    }
+}
+
+/// Least recently modified first.
+pub struct OldestFirst;
+
+impl EvictionPolicy for OldestFirst {
+    fn score(&self, file: &FileInfo) -> f64 {
+        file.modified as f64
+    }
+}
+
+/// Most recently modified first.
+pub struct NewestFirst;
+
+impl EvictionPolicy for NewestFirst {
+    fn score(&self, file: &FileInfo) -> f64 {
+        -(file.modified as f64)
+    }
+}
+
+/// Biggest first, freeing space with the fewest removals.
+pub struct LargestFirst;
+
+impl EvictionPolicy for LargestFirst {
+    fn score(&self, file: &FileInfo) -> f64 {
+        -(file.size as f64)
+    }
+}
+
+/// Least recently read or written first.
+pub struct LeastRecentlyUsed;
+
+impl EvictionPolicy for LeastRecentlyUsed {
+    fn score(&self, file: &FileInfo) -> f64 {
+        file.accessed as f64
+    }
+}
+
+/// Evicts files scoring highest on `age` per hour since the last
+/// modification plus `size` per MiB first.
+pub struct Weighted {
+    pub age: f64,
+    pub size: f64,
+    now: u64,
+}
+
+impl Weighted {
+    pub fn new(age: f64, size: f64) -> Weighted {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+
+        Weighted { age, size, now }
+    }
+}
+
+impl EvictionPolicy for Weighted {
+    fn score(&self, file: &FileInfo) -> f64 {
+        let hours = self.now.saturating_sub(file.modified) as f64 / 3600.0;
+        let mib = file.size as f64 / MIB as f64;
+
+        -(self.age * hours + self.size * mib)
+    }
+}
+
+/// Smallest files count as a filesystem block, so that no file is given a
+/// grace period long enough to never be removed.
+const GDSF_MIN_SIZE: u64 = 4096;
+
+/// GreedyDual-Size-Frequency, as caches use it: each file is worth the
+/// time it was last used plus a grace inversely proportional to its size,
+/// the least worth is removed first. Hit counts are unknown here, every file
+/// having been used once.
+///
+/// With `age` per hour and `size` hours of grace for a 1 MiB file, a file
+/// last used `h` hours ago of `m` MiB scores `size / m - age * h`: a 100 MiB
+/// file goes 0.99 hours earlier than a 1 MiB file used at the same time, and
+/// a 10 KiB one about 100 hours later. Large stale files go first, but the
+/// grace being bounded, small old files still leave once newer files have
+/// taken over.
+pub struct Gdsf {
+    pub age: f64,
+    pub size: f64,
+    now: u64,
+}
+
+impl Gdsf {
+    pub fn new(age: f64, size: f64) -> Gdsf {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+
+        Gdsf { age, size, now }
+    }
+}
+
+impl EvictionPolicy for Gdsf {
+    fn score(&self, file: &FileInfo) -> f64 {
+        let hours = self.now.saturating_sub(file.accessed) as f64 / 3600.0;
+        let mib = file.size.max(GDSF_MIN_SIZE) as f64 / MIB as f64;
+
+        self.size / mib - self.age * hours
+    }
+}
+
+/// The built-in policy selected by the configuration.
+pub fn from_config(config: &WatchConfig) -> Box<dyn EvictionPolicy> {
+    match config.eviction_policy {
+        EvictionPolicyKind::OldestFirst => Box::new(OldestFirst),
+        EvictionPolicyKind::NewestFirst => Box::new(NewestFirst),
+        EvictionPolicyKind::LargestFirst => Box::new(LargestFirst),
+        EvictionPolicyKind::LeastRecentlyUsed => Box::new(LeastRecentlyUsed),
+        EvictionPolicyKind::Weighted => Box::new(Weighted::new(
+            config.eviction_age_weight,
+            config.eviction_size_weight,
+        )),
+        EvictionPolicyKind::Gdsf => Box::new(Gdsf::new(
+            config.eviction_age_weight,
+            config.eviction_size_weight,
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn file(name: &str, modified: u64, size: u64) -> FileInfo {
+        FileInfo {
+            path: PathBuf::from(name),
+            size,
+            modified,
+            accessed: modified,
+            hard_link: None,
+            inode: 0,
+        }
+    }
+
+    fn sorted(mut files: Vec<FileInfo>, policy: &dyn EvictionPolicy) -> Vec<String> {
+        policy.sort(&mut files);
+        files
+            .into_iter()
+            .map(|file| file.path.display().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn order_matches_sort() {
+        let files: Vec<_> = (0..50)
+            .map(|i| file(&i.to_string(), (i * 37) % 11, (i * 13) % 7))
+            .collect();
+        let policy = Weighted::new(1.0, 1.0);
+
+        let ordered: Vec<_> = policy
+            .order(files.clone())
+            .map(|file| (file.modified, file.size))
+            .collect();
+        let mut sorted = files;
+        policy.sort(&mut sorted);
+
+        assert_eq!(
+            ordered,
+            sorted
+                .iter()
+                .map(|file| (file.modified, file.size))
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn oldest_first_removes_oldest_files_first() {
+        let files = vec![file("b", 20, 1), file("a", 10, 1), file("c", 30, 1)];
+
+        assert_eq!(sorted(files, &OldestFirst), ["a", "b", "c"]);
+    }
+
+    #[test]
+    fn newest_first_removes_newest_files_first() {
+        let files = vec![file("b", 20, 1), file("a", 10, 1), file("c", 30, 1)];
+
+        assert_eq!(sorted(files, &NewestFirst), ["c", "b", "a"]);
+    }
+
+    #[test]
+    fn largest_first_removes_largest_files_first() {
+        let files = vec![file("b", 20, 5), file("a", 30, 9), file("c", 10, 5)];
+
+        assert_eq!(sorted(files, &LargestFirst), ["a", "c", "b"]);
+    }
+
+    #[test]
+    fn least_recently_used_removes_least_recently_read_files_first() {
+        let files = vec![
+            FileInfo {
+                accessed: 50,
+                ..file("a", 10, 1)
+            },
+            file("b", 20, 1),
+            FileInfo {
+                accessed: 40,
+                ..file("c", 30, 1)
+            },
+        ];
+
+        assert_eq!(sorted(files, &LeastRecentlyUsed), ["b", "c", "a"]);
+    }
+
+    #[test]
+    fn weighted_balances_age_and_size() {
+        let policy = Weighted {
+            age: 1.0,
+            size: 1.0,
+            now: 10 * 3600,
+        };
+
+        // Scores: old & small 10 + 1, new & large 0 + 20, mid 5 + 5
+        let files = vec![
+            file("old", 0, MIB),
+            file("large", 10 * 3600, 20 * MIB),
+            file("mid", 5 * 3600, 5 * MIB),
+        ];
+
+        assert_eq!(sorted(files, &policy), ["large", "old", "mid"]);
+    }
+
+    #[test]
+    fn gdsf_gives_small_files_more_time() {
+        let policy = Gdsf {
+            age: 1.0,
+            size: 1.0,
+            now: 1000 * 3600,
+        };
+        let used = |name, hours_ago: u64, size| FileInfo {
+            accessed: 1000 * 3600 - hours_ago * 3600,
+            ..file(name, 0, size)
+        };
+
+        // Scores: 0.01 - 10, 1 - 10, 256 - 10, 256 - 300, 1 - 5
+        let files = vec![
+            used("large", 10, 100 * MIB),
+            used("medium", 10, MIB),
+            used("small", 10, 100),
+            used("ancient", 300, 100),
+            used("recent", 5, MIB),
+        ];
+
+        assert_eq!(
+            sorted(files, &policy),
+            ["ancient", "large", "medium", "recent", "small"]
+        );
+    }
+}
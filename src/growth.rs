@@ -0,0 +1,95 @@
+//! Growth of a directory across cycles and when it will reach its limit at
+//! that pace, for `FULL_WARNING_HORIZON`.
+
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// Bytes added to a directory between cycles, not counting what the
+/// cleanups removed.
+#[derive(Debug, Default)]
+pub struct Growth {
+    /// Scan time and bytes added since the first scan, oldest first
+    points: VecDeque<(Instant, i64)>,
+    /// Size left by the last cycle
+    last_size: Option<u64>,
+}
+
+impl Growth {
+    /// Records a cycle that scanned `before` bytes at `scanned` and left
+    /// `after`, forgetting what was added more than `window` ago.
+    pub fn record(&mut self, scanned: Instant, before: u64, after: u64, window: Duration) {
+        let added = match (self.last_size, self.points.back()) {
+            (Some(last_size), Some(&(_, added))) => added + before as i64 - last_size as i64,
+            _ => 0,
+        };
+
+        self.points.push_back((scanned, added));
+        self.last_size = Some(after);
+
+        // The oldest point within the window is kept to measure from
+        while self
+            .points
+            .get(1)
+            .is_some_and(|&(time, _)| scanned.duration_since(time) >= window)
+        {
+            self.points.pop_front();
+        }
+    }
+
+    /// Bytes added per second over the window, unknown until two cycles
+    /// ran.
+    pub fn rate(&self) -> Option<f64> {
+        let (&(first, first_added), &(last, last_added)) =
+            (self.points.front()?, self.points.back()?);
+        let elapsed = last.duration_since(first).as_secs_f64();
+
+        match elapsed > 0.0 {
+            true => Some((last_added - first_added) as f64 / elapsed),
+            false => None,
+        }
+    }
+
+    /// Time until the size the last cycle left reaches `limit` at the
+    /// current rate, `None` if the directory is not growing.
+    pub fn time_to_full(&self, limit: u64) -> Option<Duration> {
+        let rate = self.rate().filter(|&rate| rate > 0.0)?;
+        let left = limit.saturating_sub(self.last_size?);
+
+        Duration::try_from_secs_f64(left as f64 / rate).ok()
+    }
+
+    /// Forgets every cycle, e.g. when the directory changed.
+    pub fn clear(&mut self) {
+        *self = Growth::default();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn removals_do_not_count_as_shrinking() {
+        let mut growth = Growth::default();
+        let start = Instant::now();
+        let minute = Duration::from_secs(60);
+        let window = Duration::from_secs(3600);
+
+        growth.record(start, 500, 500, window);
+        assert_eq!(growth.rate(), None);
+
+        // 120 bytes a minute, the cleanup of the third cycle freeing 300
+        growth.record(start + minute, 620, 620, window);
+        growth.record(start + 2 * minute, 740, 440, window);
+        growth.record(start + 3 * minute, 560, 560, window);
+        assert_eq!(growth.rate(), Some(2.0));
+        assert_eq!(growth.time_to_full(800), Some(Duration::from_secs(120)));
+        assert_eq!(growth.time_to_full(500), Some(Duration::ZERO));
+
+        // Only the last hour counts once it filled up
+        growth.record(start + 63 * minute, 560, 560, window);
+        assert_eq!(growth.points.len(), 2);
+        assert_eq!(growth.rate(), Some(0.0));
+        assert_eq!(growth.time_to_full(800), None);
+    }
+}
@@ -0,0 +1,184 @@
+//! Liveness and status endpoints for container probes.
+
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+
+use crate::http::{Request, Response};
+use maxdirsize::{CycleOutcome, CycleReport, VERSION};
+
+/// Time a cycle gets on top of twice the interval before the directory is
+/// considered stuck.
+const GRACE: Duration = Duration::from_secs(60);
+
+struct DirectoryHealth {
+    interval_seconds: u64,
+    /// When the directory started being watched
+    since: SystemTime,
+    last_run: Option<SystemTime>,
+    last_outcome: Option<CycleOutcome>,
+    /// [`CycleReport::summary`] of the last cycle
+    last_cycle: Option<serde_json::Value>,
+    size_bytes: u64,
+    limit_bytes: u64,
+    files: u64,
+    errors_total: u64,
+}
+
+impl DirectoryHealth {
+    /// Whether the last cycle succeeded and the next one is not overdue.
+    fn healthy(&self, now: SystemTime) -> bool {
+        if self.last_outcome == Some(CycleOutcome::Failed) {
+            return false;
+        }
+
+        if self.interval_seconds == 0 {
+            return true;
+        }
+
+        let window = Duration::from_secs(self.interval_seconds) * 2 + GRACE;
+
+        !matches!(
+            now.duration_since(self.last_run.unwrap_or(self.since)),
+            Ok(elapsed) if elapsed > window
+        )
+    }
+}
+
+pub struct Health {
+    started: SystemTime,
+    directories: Mutex<BTreeMap<String, DirectoryHealth>>,
+}
+
+impl Health {
+    pub fn new() -> Health {
+        Health {
+            started: SystemTime::now(),
+            directories: Mutex::default(),
+        }
+    }
+
+    /// Expects a cycle of each of `directories` at its interval in seconds,
+    /// 0 for a single cycle, and forgets the directories no longer listed.
+    pub fn watch<'a>(&self, directories: impl IntoIterator<Item = (&'a str, u64)>) {
+        let mut health = self.directories.lock().unwrap();
+        let directories: BTreeMap<&str, u64> = directories.into_iter().collect();
+
+        health.retain(|directory, _| directories.contains_key(directory.as_str()));
+
+        for (directory, interval_seconds) in directories {
+            health
+                .entry(directory.to_string())
+                .or_insert_with(|| DirectoryHealth {
+                    interval_seconds,
+                    since: SystemTime::now(),
+                    last_run: None,
+                    last_outcome: None,
+                    last_cycle: None,
+                    size_bytes: 0,
+                    limit_bytes: 0,
+                    files: 0,
+                    errors_total: 0,
+                })
+                .interval_seconds = interval_seconds;
+        }
+    }
+
+    pub fn record(&self, directory: &str, report: &CycleReport) {
+        let mut directories = self.directories.lock().unwrap();
+        let Some(health) = directories.get_mut(directory) else {
+            return;
+        };
+
+        health.last_run = Some(SystemTime::now());
+        health.last_outcome = Some(report.outcome);
+        health.last_cycle = Some(report.summary());
+        health.size_bytes = report.size_after;
+        health.limit_bytes = report.limit_bytes;
+        health.files = match report.dry_run {
+            true => report.total_files,
+            false => report.total_files - report.removed_files,
+        };
+        health.errors_total += report.errors;
+    }
+
+    pub fn handle(&self, request: &Request) -> Response {
+        match (request.method.as_str(), request.path.as_str()) {
+            ("GET", "/healthz") => {
+                let now = SystemTime::now();
+                let directories = self.directories.lock().unwrap();
+
+                match directories.values().all(|health| health.healthy(now)) {
+                    true => Response::ok("text/plain", "ok\n".to_string()),
+                    false => Response {
+                        status: 503,
+                        content_type: "text/plain",
+                        body: "unhealthy\n".to_string(),
+                    },
+                }
+            }
+            ("GET", "/status") => Response::ok("application/json", self.status().to_string()),
+            _ => Response::not_found(),
+        }
+    }
+
+    fn status(&self) -> serde_json::Value {
+        let now = SystemTime::now();
+        let directories = self.directories.lock().unwrap();
+
+        json!({
+            "version": VERSION,
+            "uptime_seconds": now.duration_since(self.started).unwrap_or_default().as_secs(),
+            "healthy": directories.values().all(|health| health.healthy(now)),
+            "directories": directories
+                .iter()
+                .map(|(directory, health)| json!({
+                    "directory": directory,
+                    "healthy": health.healthy(now),
+                    "interval_seconds": health.interval_seconds,
+                    "last_run": health
+                        .last_run
+                        .map(|time| humantime::format_rfc3339_seconds(time).to_string()),
+                    "last_outcome": health.last_outcome.map(|outcome| outcome.as_str()),
+                    "last_cycle": health.last_cycle,
+                    "size_bytes": health.size_bytes,
+                    "limit_bytes": health.limit_bytes,
+                    "files": health.files,
+                    "errors_total": health.errors_total,
+                }))
+                .collect::<Vec<_>>(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn directories_are_unhealthy_when_failed_or_overdue() {
+        let now = SystemTime::now();
+        let mut health = DirectoryHealth {
+            interval_seconds: 60,
+            since: now,
+            last_run: None,
+            last_outcome: None,
+            last_cycle: None,
+            size_bytes: 0,
+            limit_bytes: 0,
+            files: 0,
+            errors_total: 0,
+        };
+
+        assert!(health.healthy(now + Duration::from_secs(180)));
+        assert!(!health.healthy(now + Duration::from_secs(181)));
+
+        health.last_run = Some(now + Duration::from_secs(100));
+        health.last_outcome = Some(CycleOutcome::CleanedUp);
+        assert!(health.healthy(now + Duration::from_secs(181)));
+
+        health.last_outcome = Some(CycleOutcome::Failed);
+        assert!(!health.healthy(now + Duration::from_secs(101)));
+    }
+}
@@ -0,0 +1,360 @@
+//! An optional SQLite database of every cycle and removal, for long-term
+//! visibility without a metrics stack. Queried by `maxdirsize history`.
+
+use chrono::{DateTime, Local, NaiveDate, NaiveDateTime, TimeZone};
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::Serialize;
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::audit::Record;
+use crate::cleaner::CycleReport;
+
+/// Time a writer waits for another one, e.g. another directory or a
+/// `history` query, to release the database.
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS cycles (
+    id INTEGER PRIMARY KEY,
+    finished_at INTEGER NOT NULL,
+    directory TEXT NOT NULL,
+    outcome TEXT NOT NULL,
+    dry_run INTEGER NOT NULL,
+    duration_seconds REAL NOT NULL,
+    scan_seconds REAL NOT NULL,
+    files_scanned INTEGER NOT NULL,
+    bytes_before INTEGER NOT NULL,
+    bytes_after INTEGER NOT NULL,
+    limit_bytes INTEGER NOT NULL,
+    files_deleted INTEGER NOT NULL,
+    dirs_deleted INTEGER NOT NULL,
+    bytes_freed INTEGER NOT NULL,
+    errors INTEGER NOT NULL
+);
+CREATE INDEX IF NOT EXISTS cycles_finished_at ON cycles (finished_at);
+CREATE TABLE IF NOT EXISTS deletions (
+    id INTEGER PRIMARY KEY,
+    timestamp INTEGER NOT NULL,
+    directory TEXT NOT NULL,
+    kind TEXT NOT NULL,
+    path TEXT NOT NULL,
+    size INTEGER NOT NULL,
+    modified INTEGER NOT NULL,
+    reason TEXT NOT NULL,
+    mode TEXT NOT NULL
+);
+CREATE INDEX IF NOT EXISTS deletions_timestamp ON deletions (timestamp);
+";
+
+/// What [`History::cycles`] and [`History::deletions`] return, newest first.
+#[derive(Debug, Clone)]
+pub struct Filter {
+    pub since: Option<SystemTime>,
+    /// Excluded
+    pub until: Option<SystemTime>,
+    /// Glob the directory of a cycle or the path of a removal matches, `*`
+    /// matching `/` as well
+    pub path: Option<String>,
+    /// Least a cycle freed or a removed file was
+    pub min_bytes: Option<u64>,
+    pub limit: usize,
+}
+
+/// A recorded cycle.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Cycle {
+    pub finished_at: String,
+    pub directory: String,
+    pub outcome: String,
+    pub dry_run: bool,
+    pub duration_seconds: f64,
+    pub scan_seconds: f64,
+    pub files_scanned: u64,
+    pub bytes_before: u64,
+    pub bytes_after: u64,
+    pub limit_bytes: u64,
+    pub files_deleted: u64,
+    pub dirs_deleted: u64,
+    pub bytes_freed: u64,
+    pub errors: u64,
+}
+
+/// A recorded removal.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct Deletion {
+    pub timestamp: String,
+    pub directory: String,
+    pub kind: String,
+    pub path: String,
+    pub size: u64,
+    pub modified: String,
+    pub reason: String,
+    pub mode: String,
+}
+
+pub struct History {
+    connection: Connection,
+}
+
+impl History {
+    /// Opens the database at `path`, creating it if needed.
+    pub fn open(path: &Path) -> rusqlite::Result<History> {
+        let connection = Connection::open(path)?;
+
+        connection.busy_timeout(BUSY_TIMEOUT)?;
+        // A commit per removal stays cheap without syncing every one
+        connection
+            .query_row("PRAGMA journal_mode = WAL", [], |_| Ok(()))
+            .optional()?;
+        connection.execute_batch("PRAGMA synchronous = NORMAL;")?;
+        connection.execute_batch(SCHEMA)?;
+
+        Ok(History { connection })
+    }
+
+    /// Records a cycle of `directory` that just finished.
+    pub fn record_cycle(&self, directory: &str, report: &CycleReport) -> rusqlite::Result<()> {
+        self.connection
+            .prepare_cached(
+                "INSERT INTO cycles (finished_at, directory, outcome, dry_run, duration_seconds,
+                 scan_seconds, files_scanned, bytes_before, bytes_after, limit_bytes,
+                 files_deleted, dirs_deleted, bytes_freed, errors)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14)",
+            )?
+            .execute(params![
+                seconds(SystemTime::now()),
+                directory,
+                report.outcome.as_str(),
+                report.dry_run,
+                report.duration.as_secs_f64(),
+                report.scan_duration.as_secs_f64(),
+                report.total_files,
+                report.size_before,
+                report.size_after,
+                report.limit_bytes,
+                report.removed_files,
+                report.removed_dirs,
+                report.freed_bytes,
+                report.errors,
+            ])?;
+
+        Ok(())
+    }
+
+    /// Records a removal from `directory` made just now.
+    pub fn record_deletion(&self, directory: &str, record: &Record) -> rusqlite::Result<()> {
+        self.connection
+            .prepare_cached(
+                "INSERT INTO deletions (timestamp, directory, kind, path, size, modified, reason, mode)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+            )?
+            .execute(params![
+                seconds(SystemTime::now()),
+                directory,
+                name(record.kind),
+                record.path.display().to_string(),
+                record.size,
+                seconds(record.modified),
+                name(record.reason),
+                record.method(),
+            ])?;
+
+        Ok(())
+    }
+
+    pub fn cycles(&self, filter: &Filter) -> rusqlite::Result<Vec<Cycle>> {
+        let mut statement = self.connection.prepare(
+            "SELECT finished_at, directory, outcome, dry_run, duration_seconds, scan_seconds,
+             files_scanned, bytes_before, bytes_after, limit_bytes, files_deleted, dirs_deleted,
+             bytes_freed, errors
+             FROM cycles
+             WHERE (?1 IS NULL OR finished_at >= ?1) AND (?2 IS NULL OR finished_at < ?2)
+             AND (?3 IS NULL OR directory GLOB ?3) AND (?4 IS NULL OR bytes_freed >= ?4)
+             ORDER BY finished_at DESC, id DESC LIMIT ?5",
+        )?;
+
+        let rows = statement.query_map(params(filter), |row| {
+            Ok(Cycle {
+                finished_at: timestamp(row.get(0)?),
+                directory: row.get(1)?,
+                outcome: row.get(2)?,
+                dry_run: row.get(3)?,
+                duration_seconds: row.get(4)?,
+                scan_seconds: row.get(5)?,
+                files_scanned: row.get(6)?,
+                bytes_before: row.get(7)?,
+                bytes_after: row.get(8)?,
+                limit_bytes: row.get(9)?,
+                files_deleted: row.get(10)?,
+                dirs_deleted: row.get(11)?,
+                bytes_freed: row.get(12)?,
+                errors: row.get(13)?,
+            })
+        })?;
+
+        rows.collect()
+    }
+
+    pub fn deletions(&self, filter: &Filter) -> rusqlite::Result<Vec<Deletion>> {
+        let mut statement = self.connection.prepare(
+            "SELECT timestamp, directory, kind, path, size, modified, reason, mode
+             FROM deletions
+             WHERE (?1 IS NULL OR timestamp >= ?1) AND (?2 IS NULL OR timestamp < ?2)
+             AND (?3 IS NULL OR path GLOB ?3) AND (?4 IS NULL OR size >= ?4)
+             ORDER BY timestamp DESC, id DESC LIMIT ?5",
+        )?;
+
+        let rows = statement.query_map(params(filter), |row| {
+            Ok(Deletion {
+                timestamp: timestamp(row.get(0)?),
+                directory: row.get(1)?,
+                kind: row.get(2)?,
+                path: row.get(3)?,
+                size: row.get(4)?,
+                modified: timestamp(row.get(5)?),
+                reason: row.get(6)?,
+                mode: row.get(7)?,
+            })
+        })?;
+
+        rows.collect()
+    }
+}
+
+fn params(filter: &Filter) -> (Option<i64>, Option<i64>, Option<&str>, Option<u64>, u64) {
+    (
+        filter.since.map(seconds),
+        filter.until.map(seconds),
+        filter.path.as_deref(),
+        filter.min_bytes,
+        filter.limit as u64,
+    )
+}
+
+fn seconds(time: SystemTime) -> i64 {
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(elapsed) => elapsed.as_secs() as i64,
+        Err(e) => -(e.duration().as_secs() as i64),
+    }
+}
+
+fn timestamp(seconds: i64) -> String {
+    let time = UNIX_EPOCH + Duration::from_secs(seconds.max(0) as u64);
+
+    humantime::format_rfc3339_seconds(time).to_string()
+}
+
+/// The name `value` serializes to, e.g. `size_limit`.
+fn name(value: impl Serialize) -> String {
+    serde_json::to_value(value)
+        .ok()
+        .and_then(|value| value.as_str().map(str::to_string))
+        .unwrap_or_default()
+}
+
+/// Parses a time given on the command line: an RFC 3339 timestamp, or a
+/// date or `YYYY-MM-DD HH:MM` in local time.
+pub fn parse_time(value: &str) -> Result<SystemTime, String> {
+    let value = value.trim();
+
+    if let Ok(time) = DateTime::parse_from_rfc3339(value) {
+        return Ok(time.into());
+    }
+
+    let local = NaiveDate::parse_from_str(value, "%Y-%m-%d")
+        .ok()
+        .and_then(|date| date.and_hms_opt(0, 0, 0))
+        .or_else(|| NaiveDateTime::parse_from_str(value, "%Y-%m-%d %H:%M").ok());
+
+    match local.map(|local| Local.from_local_datetime(&local).earliest()) {
+        Some(Some(time)) => Ok(time.into()),
+        _ => Err(format!(
+            "invalid time `{value}`, expected e.g. 2026-10-01, `2026-10-01 22:00` or 2026-10-01T22:00:00Z"
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audit::Kind;
+    use crate::cleaner::CycleOutcome;
+    use crate::config::DeleteMode;
+    use crate::policy::Reason;
+    use crate::APP_NAME;
+
+    #[test]
+    fn cycles_and_deletions_are_queried_by_path_and_size() {
+        let path =
+            std::env::temp_dir().join(format!("{APP_NAME}-history-{}.db", std::process::id()));
+        let history = History::open(&path).unwrap();
+
+        for (directory, freed_bytes) in [("/data/logs", 10), ("/data/cache", 5000)] {
+            history
+                .record_cycle(
+                    directory,
+                    &CycleReport {
+                        outcome: CycleOutcome::CleanedUp,
+                        freed_bytes,
+                        ..Default::default()
+                    },
+                )
+                .unwrap();
+        }
+
+        for (name, size) in [("a.log", 1), ("b.log", 2000), ("c.bin", 3000)] {
+            history
+                .record_deletion(
+                    "/data/logs",
+                    &Record {
+                        kind: Kind::File,
+                        path: &Path::new("/data/logs").join(name),
+                        size,
+                        modified: SystemTime::now(),
+                        reason: Reason::SizeLimit,
+                        mode: DeleteMode::Delete,
+                        linked_to: None,
+                        archived_to: None,
+                    },
+                )
+                .unwrap();
+        }
+
+        let filter = Filter {
+            since: Some(SystemTime::now() - Duration::from_secs(60)),
+            until: None,
+            path: None,
+            min_bytes: Some(1000),
+            limit: 10,
+        };
+
+        let cycles = history.cycles(&filter).unwrap();
+        assert_eq!(cycles.len(), 1);
+        assert_eq!(cycles[0].directory, "/data/cache");
+        assert_eq!(cycles[0].outcome, "cleaned_up");
+
+        let deletions = history
+            .deletions(&Filter {
+                path: Some("*.log".to_string()),
+                ..filter.clone()
+            })
+            .unwrap();
+        assert_eq!(deletions.len(), 1);
+        assert_eq!(deletions[0].path, "/data/logs/b.log");
+        assert_eq!(deletions[0].reason, "size_limit");
+
+        let until = history
+            .deletions(&Filter {
+                until: filter.since,
+                ..filter
+            })
+            .unwrap();
+        assert!(until.is_empty());
+
+        drop(history);
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = std::fs::remove_file(format!("{}{suffix}", path.display()));
+        }
+    }
+}
@@ -0,0 +1,196 @@
+//! Commands run around cleanups: a script consulted about every file before
+//! it is removed, so business rules can keep files the limits alone would
+//! remove, and commands run before and after removing anything.
+
+use std::io::Read;
+use std::path::Path;
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::policy::Eviction;
+use crate::APP_NAME;
+
+/// Time a hook gets to decide about a file before it is killed.
+pub const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Time `PRE_CLEANUP_CMD` and `POST_CLEANUP_CMD` get before they are killed.
+pub const COMMAND_TIMEOUT: Duration = Duration::from_secs(300);
+
+const POLL: Duration = Duration::from_millis(10);
+
+/// What a hook decided about a file.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Verdict {
+    Remove,
+    /// Kept this cycle, with the first line the hook printed
+    Keep(String),
+}
+
+/// Runs `hook` as `hook PATH SIZE AGE_SECONDS` with the same values, the
+/// watched directory, the reason and whether this is a dry run in
+/// `MAXDIRSIZE_*` variables. An exit status of 0 allows the removal. Failing
+/// to run the hook, or it running longer than `timeout`, is an error.
+pub fn consult(
+    hook: &Path,
+    directory: &str,
+    eviction: &Eviction,
+    dry_run: bool,
+    timeout: Duration,
+) -> std::io::Result<Verdict> {
+    let file = &eviction.file;
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let age = now.saturating_sub(file.modified).to_string();
+    let size = file.size.to_string();
+    let reason = serde_json::to_value(eviction.reason)
+        .ok()
+        .and_then(|reason| reason.as_str().map(str::to_string))
+        .unwrap_or_default();
+    let prefix = APP_NAME.to_uppercase();
+
+    let mut child = Command::new(hook)
+        .arg(&file.path)
+        .arg(&size)
+        .arg(&age)
+        .env(format!("{prefix}_PATH"), &file.path)
+        .env(format!("{prefix}_SIZE"), &size)
+        .env(format!("{prefix}_AGE_SECONDS"), &age)
+        .env(format!("{prefix}_MODIFIED"), file.modified.to_string())
+        .env(format!("{prefix}_DIRECTORY"), directory)
+        .env(format!("{prefix}_REASON"), reason)
+        .env(format!("{prefix}_DRY_RUN"), dry_run.to_string())
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .spawn()?;
+
+    // Read on the side so a chatty hook never blocks on a full pipe
+    let mut stdout = child.stdout.take();
+    let output = std::thread::spawn(move || {
+        let mut output = String::new();
+        if let Some(stdout) = &mut stdout {
+            let _ = stdout.read_to_string(&mut output);
+        }
+        output
+    });
+
+    let status = wait(&mut child, timeout, &hook.display().to_string())?;
+
+    if status.success() {
+        return Ok(Verdict::Remove);
+    }
+
+    let output = output.join().unwrap_or_default();
+    let reason = match output.lines().map(str::trim).find(|line| !line.is_empty()) {
+        Some(line) => line.to_string(),
+        None => format!("{} exited with {status}", hook.display()),
+    };
+
+    Ok(Verdict::Keep(reason))
+}
+
+/// Runs `command` with the shell, `vars` being set as `MAXDIRSIZE_*`
+/// variables, failing unless it exits with 0 within `timeout`.
+pub fn run_command(
+    command: &str,
+    vars: &[(&str, String)],
+    timeout: Duration,
+) -> std::io::Result<()> {
+    #[cfg(unix)]
+    let mut shell = {
+        let mut shell = Command::new("sh");
+        shell.arg("-c").arg(command);
+        shell
+    };
+    #[cfg(windows)]
+    let mut shell = {
+        let mut shell = Command::new("cmd");
+        shell.arg("/C").arg(command);
+        shell
+    };
+
+    let prefix = APP_NAME.to_uppercase();
+
+    for (name, value) in vars {
+        shell.env(format!("{prefix}_{name}"), value);
+    }
+
+    let mut child = shell.stdin(Stdio::null()).spawn()?;
+    let status = wait(&mut child, timeout, command)?;
+
+    match status.success() {
+        true => Ok(()),
+        false => Err(std::io::Error::other(format!(
+            "{command} exited with {status}"
+        ))),
+    }
+}
+
+/// Waits for `child`, killing it once it ran longer than `timeout`.
+fn wait(child: &mut Child, timeout: Duration, name: &str) -> std::io::Result<ExitStatus> {
+    let started = Instant::now();
+
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(status);
+        }
+
+        if started.elapsed() >= timeout {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::TimedOut,
+                format!("{name} ran longer than {timeout:?}"),
+            ));
+        }
+
+        std::thread::sleep(POLL);
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::policy::Reason;
+    use crate::scan::FileInfo;
+    use std::os::unix::fs::PermissionsExt;
+
+    #[test]
+    fn a_failing_hook_keeps_the_file() {
+        let dir = std::env::temp_dir().join(format!("{APP_NAME}-hook-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let hook = dir.join("hook.sh");
+        std::fs::write(
+            &hook,
+            "#!/bin/sh\n\
+             [ \"$MAXDIRSIZE_REASON\" = size_limit ] || exit 2\n\
+             case \"$1\" in *keep*) echo \"referenced by order 42\"; exit 1;; esac\n",
+        )
+        .unwrap();
+        std::fs::set_permissions(&hook, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        let eviction = |path: &str| Eviction {
+            file: FileInfo {
+                path: dir.join(path),
+                size: 10,
+                modified: 0,
+                accessed: 0,
+                hard_link: None,
+                inode: 0,
+            },
+            reason: Reason::SizeLimit,
+            freed_bytes: 10,
+            duplicate_of: None,
+        };
+        let consult = |path| consult(&hook, "/data", &eviction(path), false, TIMEOUT).unwrap();
+
+        assert_eq!(consult("old.log"), Verdict::Remove);
+        assert_eq!(
+            consult("keep.log"),
+            Verdict::Keep("referenced by order 42".to_string())
+        );
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,157 @@
+//! In-memory index used by `WATCH_MODE=notify`. Directory listings are kept
+//! between cycles and only directories touched by filesystem events are listed
+//! again; everything is rescanned when events were lost or periodically.
+
+use colored::*;
+use log::{info, warn};
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::protection::{IGNORE_FILE, KEEP_FILE};
+use crate::scan::{assemble, list_dir, DirListing, ScanOptions, ScanResult};
+
+#[derive(Default)]
+struct Pending {
+    dirty: HashSet<PathBuf>,
+    rescan: bool,
+}
+
+pub struct Index {
+    root: PathBuf,
+    listings: HashMap<PathBuf, DirListing>,
+    pending: Arc<Mutex<Pending>>,
+    last_full_scan: Instant,
+    _watcher: RecommendedWatcher,
+}
+
+impl Index {
+    /// Starts watching `root` recursively. The first [`Index::scan`] lists
+    /// the whole tree.
+    pub fn new(root: &Path) -> notify::Result<Index> {
+        let pending = Arc::new(Mutex::new(Pending::default()));
+        let events = pending.clone();
+
+        let mut watcher =
+            notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+                let mut pending = events.lock().unwrap();
+
+                match event {
+                    Ok(event) => {
+                        if event.need_rescan() {
+                            pending.rescan = true;
+                        }
+
+                        for path in event.paths {
+                            // What they protect may be anywhere below
+                            if path
+                                .file_name()
+                                .is_some_and(|name| name == IGNORE_FILE || name == KEEP_FILE)
+                            {
+                                pending.rescan = true;
+                            }
+
+                            if let Some(parent) = path.parent() {
+                                pending.dirty.insert(parent.to_path_buf());
+                            }
+                            pending.dirty.insert(path);
+                        }
+                    }
+                    Err(e) => {
+                        warn!("Filesystem watcher error, scheduling a full rescan: {e}");
+                        pending.rescan = true;
+                    }
+                }
+            })?;
+
+        watcher.watch(root, RecursiveMode::Recursive)?;
+
+        Ok(Index {
+            root: root.to_path_buf(),
+            listings: HashMap::new(),
+            pending,
+            last_full_scan: Instant::now(),
+            _watcher: watcher,
+        })
+    }
+
+    /// Brings the index up to date and returns the same result a full
+    /// [`crate::scan::read_dir`] would.
+    pub fn scan(
+        &mut self,
+        options: &ScanOptions,
+        rescan_interval: Duration,
+    ) -> std::io::Result<ScanResult> {
+        let pending = std::mem::take(&mut *self.pending.lock().unwrap());
+
+        if pending.rescan
+            || self.listings.is_empty()
+            || self.last_full_scan.elapsed() >= rescan_interval
+        {
+            info!(
+                "{}",
+                format!("Full rescan of {}", self.root.display()).green()
+            );
+            self.listings.clear();
+            self.last_full_scan = Instant::now();
+        } else {
+            let mut dirty: Vec<_> = pending.dirty.into_iter().collect();
+            dirty.sort();
+
+            for path in dirty {
+                let Some(old) = self.listings.get(&path) else {
+                    continue;
+                };
+
+                match list_dir(&path, options) {
+                    Ok(listing) => {
+                        let gone: Vec<_> = old
+                            .subdirs
+                            .iter()
+                            .filter(|old| !listing.subdirs.iter().any(|new| new.0 == old.0))
+                            .map(|(path, _)| path.clone())
+                            .collect();
+
+                        for subdir in gone {
+                            self.forget(&subdir);
+                        }
+
+                        self.listings.insert(path, listing);
+                    }
+                    Err(_) => self.forget(&path),
+                }
+            }
+        }
+
+        let root = self.root.clone();
+        let mut scanned = self.build(&root, options)?;
+        scanned.count_hard_links_once();
+
+        Ok(scanned)
+    }
+
+    fn forget(&mut self, path: &Path) {
+        self.listings.retain(|listed, _| !listed.starts_with(path));
+    }
+
+    fn build(&mut self, path: &Path, options: &ScanOptions) -> std::io::Result<ScanResult> {
+        let listing = match self.listings.get(path) {
+            Some(listing) => listing.clone(),
+            None => {
+                let listing = list_dir(path, options)?;
+                self.listings.insert(path.to_path_buf(), listing.clone());
+                listing
+            }
+        };
+
+        let scanned = listing
+            .subdirs
+            .iter()
+            .map(|(subdir, excluded)| (subdir.clone(), *excluded, self.build(subdir, options)))
+            .collect();
+
+        Ok(assemble(listing, scanned))
+    }
+}
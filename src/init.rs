@@ -0,0 +1,194 @@
+//! Writing a starter config file for `maxdirsize init`, with the limit
+//! filled in from the directory as it is now.
+
+use colored::*;
+use log::{error, info};
+use std::io::Write;
+use std::path::Path;
+
+use maxdirsize::disk::{self, DiskUsage};
+use maxdirsize::{units, Cleaner, WatchConfig, APP_NAME};
+
+/// The directory as the starter configuration describes it.
+pub struct Usage {
+    pub size: u64,
+    pub files: u64,
+    pub disk: Option<DiskUsage>,
+}
+
+/// Scans `path`, the current directory if unset, and writes the starter
+/// configuration for it to `output`, or the standard output for `-`. An
+/// existing file is never overwritten.
+pub fn run(path: Option<&Path>, output: &Path) -> i32 {
+    let directory = match path.map_or_else(std::env::current_dir, |path| path.canonicalize()) {
+        Ok(directory) => directory,
+        Err(e) => {
+            error!(event = "error"; "Error reading {}: {e}", path.unwrap_or(Path::new(".")).display());
+            return 1;
+        }
+    };
+
+    let usage = match scan(&directory) {
+        Ok(usage) => usage,
+        Err(e) => {
+            error!(event = "error"; "Error reading {}: {e}", directory.display());
+            return 1;
+        }
+    };
+    let config = render(&directory.to_string_lossy(), &usage);
+
+    if output == Path::new("-") {
+        print!("{config}");
+        return 0;
+    }
+
+    let written =
+        std::fs::File::create_new(output).and_then(|mut file| file.write_all(config.as_bytes()));
+
+    if let Err(e) = written {
+        error!(event = "error"; "Error writing {}: {e}", output.display());
+        return 1;
+    }
+
+    info!(
+        "{}",
+        format!(
+            "Wrote {}, check it with `{APP_NAME} --config {0} check`",
+            output.display()
+        )
+        .green()
+    );
+    0
+}
+
+fn scan(directory: &Path) -> std::io::Result<Usage> {
+    let mut watch = toml::Table::new();
+    watch.insert(
+        "directory".to_string(),
+        directory.to_string_lossy().into_owned().into(),
+    );
+    watch.insert("max_size_bytes".to_string(), i64::MAX.into());
+    let watch: WatchConfig = watch
+        .try_into()
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+
+    let pool = crate::scan_pool(0).map_err(std::io::Error::other)?;
+    let scanned = pool.install(|| Cleaner::new(watch).scan())?;
+
+    Ok(Usage {
+        size: scanned.total_size,
+        files: scanned.total_files,
+        disk: disk::usage(directory).ok(),
+    })
+}
+
+/// The starter configuration of `directory`, limited to about the size it
+/// has now.
+pub fn render(directory: &str, usage: &Usage) -> String {
+    let filesystem = match &usage.disk {
+        Some(disk) => format!(
+            ", on a filesystem of {} with {} available",
+            units::human(disk.total),
+            units::human(disk.available)
+        ),
+        None => String::new(),
+    };
+
+    format!(
+        r#"# {APP_NAME} configuration, written by `{APP_NAME} init`.
+#
+# Every key can also be given as the environment variable or flag of the same
+# name, e.g. MAX_SIZE or --max-size, which take precedence over this file;
+# see `{APP_NAME} --help` for all of them. Check the file with
+# `{APP_NAME} --config <file> check` and start with `{APP_NAME} --config <file>`.
+
+# The directory kept within its limits. It held {size} in {files} when
+# this file was written{filesystem}.
+directory = {directory}
+
+# Once the directory grows past this size, the oldest files are removed.
+# Sizes take B, KB, MB, GB and TB, or KiB, MiB, GiB and TiB.
+max_size = "{limit}"
+
+# Or keep a share of the filesystem free, alone or with max_size.
+# min_free_percent = 10
+
+# Files are removed until the directory is down to this percentage of the
+# limit, so that a cleanup does not run again right away.
+margin = 85
+
+# How often the directory is checked, e.g. "30s", "10m" or "1h".
+interval = "10m"
+
+# Nothing is removed while this is on, the removals are logged instead (with
+# RUST_LOG=info). Turn it off once they look right.
+dry_run = true
+
+# Files never removed, relative to the directory.
+# exclude_patterns = ["*.keep", "important/**"]
+
+# Files younger than this are never removed.
+# min_age_seconds = "1h"
+
+# Move files to the trash instead of removing them.
+# delete_mode = "trash"
+"#,
+        size = units::human(usage.size),
+        files = match usage.files {
+            1 => "1 file".to_string(),
+            files => format!("{files} files"),
+        },
+        directory = toml::Value::String(directory.to_string()),
+        limit = suggested_limit(usage.size),
+    )
+}
+
+/// `size` rounded up to two significant digits, 1GB for an empty directory.
+pub fn suggested_limit(size: u64) -> String {
+    if size == 0 {
+        return "1GB".to_string();
+    }
+
+    let (unit, name) = units::decimal_unit(size);
+    let value = size as f64 / unit as f64;
+
+    match value < 10.0 {
+        true => format!("{}{name}", (value * 10.0).ceil() / 10.0),
+        false => format!("{}{name}", value.ceil()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+    use maxdirsize::config::Args;
+    use maxdirsize::Config;
+
+    #[test]
+    fn starter_configurations_load_as_they_are() {
+        assert_eq!(suggested_limit(0), "1GB");
+        assert_eq!(suggested_limit(3_210_000_000), "3.3GB");
+        assert_eq!(suggested_limit(45_100_000), "46MB");
+        assert_eq!(suggested_limit(512), "512B");
+
+        let root = std::env::temp_dir().join(format!("{APP_NAME}-init-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(&root).unwrap();
+        std::fs::write(root.join("a.bin"), vec![0; 1500]).unwrap();
+
+        let output = root.join("maxdirsize.toml");
+        assert_eq!(run(Some(&root), &output), 0);
+        assert_eq!(run(Some(&root), &output), 1, "never overwritten");
+
+        let args = Args::parse_from([APP_NAME, "--config", &output.to_string_lossy()]);
+        let config = Config::load(&args).unwrap();
+        let watch = &config.directories[0];
+        assert_eq!(Path::new(&watch.directory), root.canonicalize().unwrap());
+        assert_eq!(watch.max_size_bytes, Some(1_500));
+        assert_eq!(watch.interval_seconds, 600);
+        assert!(watch.dry_run);
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,248 @@
+//! Rendering and loading a launchd job running the current configuration on
+//! macOS, for `maxdirsize install-launchd`.
+
+use colored::*;
+use log::{error, info};
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use maxdirsize::{Config, APP_NAME};
+
+use crate::unit::Invocation;
+
+const LABEL: &str = "com.github.hortopan.maxdirsize";
+
+/// Writes the job to the LaunchAgents of the user, or with `daemon` to the
+/// LaunchDaemons of the machine, and loads it, or only prints it with
+/// `print`.
+pub fn install(config: &Config, daemon: bool, print: bool) -> i32 {
+    let invocation = match Invocation::current() {
+        Ok(invocation) => invocation,
+        Err(e) => {
+            error!(event = "error"; "Error locating the executable: {e}");
+            return 1;
+        }
+    };
+
+    let Some((path, log)) = locations(daemon) else {
+        error!(event = "error"; "Error locating the LaunchAgents directory: HOME is not set");
+        return 1;
+    };
+    let plist = render(&invocation, config, &log);
+
+    if print {
+        print!("{plist}");
+        return 0;
+    }
+
+    if cfg!(not(target_os = "macos")) {
+        error!(event = "error"; "launchd is only available on macOS, print the job with --print");
+        return 1;
+    }
+
+    let written = path
+        .parent()
+        .map_or(Ok(()), std::fs::create_dir_all)
+        .and_then(|()| std::fs::write(&path, &plist));
+
+    if let Err(e) = written {
+        error!(event = "error"; "Error writing {}: {e}", path.display());
+        return 1;
+    }
+
+    // Replaces the job loaded by a previous install, if any
+    let _ = launchctl("unload", &path);
+
+    if let Err(e) = launchctl("load", &path) {
+        error!(event = "error"; "Error loading {}: {e}", path.display());
+        return 1;
+    }
+
+    info!(
+        "{}",
+        format!(
+            "Installed and loaded {}, logging to {}",
+            path.display(),
+            log.display()
+        )
+        .green()
+    );
+    0
+}
+
+/// Where the job and its output go, for the user or with `daemon` the
+/// machine.
+fn locations(daemon: bool) -> Option<(PathBuf, PathBuf)> {
+    if daemon {
+        return Some((
+            Path::new("/Library/LaunchDaemons").join(format!("{LABEL}.plist")),
+            Path::new("/var/log").join(format!("{APP_NAME}.log")),
+        ));
+    }
+
+    let home = PathBuf::from(std::env::var_os("HOME")?);
+
+    Some((
+        home.join("Library/LaunchAgents")
+            .join(format!("{LABEL}.plist")),
+        home.join("Library/Logs").join(format!("{APP_NAME}.log")),
+    ))
+}
+
+fn launchctl(subcommand: &str, path: &Path) -> std::io::Result<()> {
+    let output = Command::new("launchctl")
+        .args([subcommand, "-w"])
+        .arg(path)
+        .output()?;
+
+    match output.status.success() {
+        true => Ok(()),
+        false => Err(std::io::Error::other(
+            String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        )),
+    }
+}
+
+/// The job running `invocation` of `config`, its output going to `log`.
+/// Kept alive as the cleanup loop, or with `--once` started by launchd every
+/// interval of the most frequently cleaned directory.
+pub fn render(invocation: &Invocation, config: &Config, log: &Path) -> String {
+    let mut plist = String::new();
+
+    let _ = writeln!(plist, r#"<?xml version="1.0" encoding="UTF-8"?>"#);
+    let _ = writeln!(
+        plist,
+        r#"<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">"#
+    );
+    let _ = writeln!(plist, r#"<plist version="1.0">"#);
+    let _ = writeln!(plist, "<dict>");
+    entry(&mut plist, "Label", LABEL);
+
+    let _ = writeln!(plist, "    <key>ProgramArguments</key>");
+    let _ = writeln!(plist, "    <array>");
+    let mut arguments = vec![invocation.executable.display().to_string()];
+    if let Some(config) = &invocation.config {
+        arguments.push("--config".to_string());
+        arguments.push(config.display().to_string());
+    }
+    for argument in arguments {
+        let _ = writeln!(plist, "        <string>{}</string>", escape(&argument));
+    }
+    let _ = writeln!(plist, "    </array>");
+
+    let _ = writeln!(plist, "    <key>EnvironmentVariables</key>");
+    let _ = writeln!(plist, "    <dict>");
+    for (name, value) in &invocation.environment {
+        let _ = writeln!(plist, "        <key>{}</key>", escape(name));
+        let _ = writeln!(plist, "        <string>{}</string>", escape(value));
+    }
+    let _ = writeln!(plist, "    </dict>");
+
+    if !invocation.secrets.is_empty() {
+        let _ = writeln!(
+            plist,
+            "    <!-- Left out as secrets: {} -->",
+            invocation.secrets.join(", ")
+        );
+    }
+
+    entry(
+        &mut plist,
+        "WorkingDirectory",
+        &invocation.working_directory.display().to_string(),
+    );
+    entry(&mut plist, "StandardOutPath", &log.display().to_string());
+    entry(&mut plist, "StandardErrorPath", &log.display().to_string());
+    entry(&mut plist, "ProcessType", "Background");
+    let _ = writeln!(plist, "    <key>RunAtLoad</key>");
+    let _ = writeln!(plist, "    <true/>");
+
+    let interval = config
+        .directories
+        .iter()
+        .map(|watch| watch.interval_seconds)
+        .filter(|&interval| interval > 0)
+        .min();
+
+    match interval {
+        Some(interval) if invocation.enabled("ONCE") => {
+            let _ = writeln!(plist, "    <key>StartInterval</key>");
+            let _ = writeln!(plist, "    <integer>{interval}</integer>");
+        }
+        _ => {
+            // Restarted when it fails, like Restart=on-failure
+            let _ = writeln!(plist, "    <key>KeepAlive</key>");
+            let _ = writeln!(plist, "    <dict>");
+            let _ = writeln!(plist, "        <key>SuccessfulExit</key>");
+            let _ = writeln!(plist, "        <false/>");
+            let _ = writeln!(plist, "    </dict>");
+        }
+    }
+
+    let _ = writeln!(plist, "    <key>ExitTimeOut</key>");
+    let _ = writeln!(
+        plist,
+        "    <integer>{}</integer>",
+        config.shutdown_timeout_seconds + 5
+    );
+    let _ = writeln!(plist, "</dict>");
+    let _ = writeln!(plist, "</plist>");
+
+    plist
+}
+
+fn entry(plist: &mut String, key: &str, value: &str) {
+    let _ = writeln!(plist, "    <key>{key}</key>");
+    let _ = writeln!(plist, "    <string>{}</string>", escape(value));
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn jobs_run_the_loop_or_every_interval_once() {
+        let config: Config = toml::from_str(
+            "[[directories]]\n\
+             directory = \"/Users/me/Downloads\"\n\
+             interval_seconds = 600\n\
+             max_size_bytes = 100\n\
+             [[directories]]\n\
+             directory = \"/Users/me/Captures\"\n\
+             interval_seconds = 300\n\
+             max_size_bytes = 100",
+        )
+        .unwrap();
+        let mut invocation = Invocation {
+            executable: PathBuf::from("/opt/homebrew/bin/maxdirsize"),
+            working_directory: PathBuf::from("/Users/me"),
+            config: Some(PathBuf::from("/Users/me/.config/maxdirsize.toml")),
+            environment: vec![("EXCLUDE".to_string(), "*.part&<tmp>".to_string())],
+            secrets: Vec::new(),
+        };
+        let log = Path::new("/Users/me/Library/Logs/maxdirsize.log");
+
+        let plist = render(&invocation, &config, log);
+        assert!(plist.contains(
+            "        <string>/opt/homebrew/bin/maxdirsize</string>\n        <string>--config</string>\n"
+        ));
+        assert!(plist.contains("<string>*.part&amp;&lt;tmp&gt;</string>"));
+        assert!(plist.contains("<key>KeepAlive</key>"));
+        assert!(!plist.contains("StartInterval"));
+
+        invocation
+            .environment
+            .push(("ONCE".to_string(), "true".to_string()));
+        let plist = render(&invocation, &config, log);
+        assert!(plist.contains("<key>StartInterval</key>\n    <integer>300</integer>\n"));
+        assert!(!plist.contains("KeepAlive"));
+    }
+}
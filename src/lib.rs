@@ -0,0 +1,87 @@
+//! Keeps directories below a maximum size by removing their oldest files.
+//!
+//! The `maxdirsize` binary runs a [`Cleaner`] per configured directory on a
+//! schedule. The same logic can be embedded:
+//!
+//! ```no_run
+//! use maxdirsize::{Cleaner, WatchConfig};
+//!
+//! let config: WatchConfig = toml::from_str(
+//!     r#"
+//!     directory = "/var/cache/app"
+//!     interval_seconds = 60
+//!     max_size_bytes = "10GiB"
+//!     "#,
+//! )
+//! .unwrap();
+//!
+//! let mut cleaner = Cleaner::new(config);
+//! let scanned = cleaner.scan().unwrap();
+//! let plan = cleaner.plan(scanned).unwrap();
+//!
+//! for eviction in &plan.files {
+//!     println!("would remove {}", eviction.file.path.display());
+//! }
+//!
+//! let report = cleaner.execute(plan);
+//! println!("freed {} bytes", report.freed_bytes);
+//! ```
+
+pub mod adaptive;
+pub mod analysis;
+pub mod archive;
+pub mod audit;
+pub mod candidates;
+pub mod cleaner;
+pub mod compress;
+pub mod config;
+pub mod dedup;
+#[cfg(feature = "desktop")]
+pub mod desktop;
+pub mod disk;
+#[cfg(feature = "email")]
+pub mod email;
+pub mod eviction;
+pub mod growth;
+#[cfg(feature = "history")]
+pub mod history;
+pub mod hook;
+pub mod index;
+pub mod lock;
+pub mod logging;
+pub mod nagios;
+pub mod notification;
+pub mod open_files;
+#[cfg(feature = "otel")]
+pub mod otel;
+pub mod owners;
+pub mod pin;
+pub mod plan_file;
+pub mod policy;
+pub mod priority;
+pub mod protection;
+pub mod quarantine;
+pub mod report;
+pub mod restore;
+#[cfg(feature = "s3")]
+pub mod s3;
+pub mod scan;
+pub mod scan_cache;
+pub mod schedule;
+pub mod sftp;
+pub mod shred;
+pub mod storage;
+pub mod throttle;
+pub mod units;
+pub mod watchdog;
+#[cfg(feature = "webhook")]
+pub mod webhook;
+
+pub use cleaner::{Cleaner, CycleOutcome, CycleReport};
+pub use config::{Config, ConfigError, WatchConfig};
+pub use eviction::EvictionPolicy;
+pub use policy::CleanupPlan;
+pub use scan::ScanResult;
+
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+pub const APP_NAME: &str = env!("CARGO_PKG_NAME");
@@ -0,0 +1,124 @@
+//! Making sure a single instance cleans up each directory.
+
+use std::fs::{self, File, TryLockError};
+use std::io::{self, Read, Seek, Write};
+use std::path::{Path, PathBuf};
+
+use crate::storage;
+use crate::APP_NAME;
+
+/// An exclusive lock on a watched directory, held until it is dropped.
+///
+/// The lock is taken on a file named after the directory in a separate
+/// directory, so it is never counted nor removed by a cleanup. The file holds
+/// the PID of the owner and is left in place when the lock is released.
+#[derive(Debug)]
+pub struct DirectoryLock {
+    _file: File,
+    path: PathBuf,
+}
+
+impl DirectoryLock {
+    /// Locks `directory` with a lock file in `lock_dir`, failing with
+    /// [`io::ErrorKind::WouldBlock`] when another process holds it.
+    pub fn acquire(directory: &Path, lock_dir: &Path) -> io::Result<DirectoryLock> {
+        let path = lock_path(directory, lock_dir)?;
+
+        fs::create_dir_all(lock_dir)?;
+        let mut file = File::options()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)?;
+
+        match file.try_lock() {
+            Ok(()) => {}
+            Err(TryLockError::WouldBlock) => {
+                let mut owner = String::new();
+                let _ = file.read_to_string(&mut owner);
+
+                return Err(io::Error::new(
+                    io::ErrorKind::WouldBlock,
+                    format!(
+                        "held by process {} (lock file {})",
+                        match owner.trim() {
+                            "" => "unknown",
+                            pid => pid,
+                        },
+                        path.display()
+                    ),
+                ));
+            }
+            Err(TryLockError::Error(e)) => return Err(e),
+        }
+
+        file.set_len(0)?;
+        file.rewind()?;
+        writeln!(file, "{}", std::process::id())?;
+
+        Ok(DirectoryLock { _file: file, path })
+    }
+
+    /// The lock file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+/// Lock file of `directory`, the same for every way of spelling its path.
+fn lock_path(directory: &Path, lock_dir: &Path) -> io::Result<PathBuf> {
+    named_after(directory, lock_dir, "lock")
+}
+
+/// A file of `dir` named after the watched `directory`, which may not exist
+/// yet, with `extension`.
+pub(crate) fn named_after(directory: &Path, dir: &Path, extension: &str) -> io::Result<PathBuf> {
+    let directory = match directory.to_str().is_some_and(storage::is_remote) {
+        true => directory.to_path_buf(),
+        false => directory
+            .canonicalize()
+            .or_else(|_| std::path::absolute(directory))?,
+    };
+
+    // FNV-1a, stable across releases unlike the std hasher.
+    let hash = directory
+        .as_os_str()
+        .as_encoded_bytes()
+        .iter()
+        .fold(0xcbf29ce484222325u64, |hash, byte| {
+            (hash ^ *byte as u64).wrapping_mul(0x100000001b3)
+        });
+
+    Ok(dir.join(format!("{APP_NAME}-{hash:016x}.{extension}")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_directory_is_locked_once() {
+        let root = std::env::temp_dir().join(format!("{APP_NAME}-lock-{}", std::process::id()));
+        let directory = root.join("watched");
+        let lock_dir = root.join("locks");
+        fs::create_dir_all(&directory).unwrap();
+
+        let lock = DirectoryLock::acquire(&directory, &lock_dir).unwrap();
+        assert_eq!(
+            fs::read_to_string(lock.path()).unwrap().trim(),
+            std::process::id().to_string()
+        );
+
+        let e = DirectoryLock::acquire(&directory.join("."), &lock_dir).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::WouldBlock);
+        assert!(e
+            .to_string()
+            .contains(&format!("held by process {}", std::process::id())));
+
+        drop(lock);
+        DirectoryLock::acquire(&directory, &lock_dir).unwrap();
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,564 @@
+//! Log output setup. Log calls attach structured fields (`event`, `path`,
+//! `bytes`, ...) as key-values; the text format only prints the message while
+//! the JSON format emits every field so lines can be shipped as is.
+//!
+//! Instead of standard error lines can go to syslog, with their priority, the
+//! systemd journal, with their fields as journal fields, or the Windows event
+//! log, and `LOG_FILE` appends them to a file in place of standard error.
+//! With `EVENTS` the lines carrying an `event` field are also written to
+//! standard output, whatever the level.
+
+use clap::ValueEnum;
+use log::kv::{Key, Value, VisitSource};
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use serde::{Deserialize, Serialize};
+use std::io::Write;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+static CYCLE: AtomicU64 = AtomicU64::new(0);
+
+#[derive(Deserialize, Serialize, ValueEnum, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+#[value(rename_all = "snake_case")]
+pub enum LogFormat {
+    /// Colored human readable lines
+    #[default]
+    Text,
+    /// One JSON object per line
+    Json,
+}
+
+#[derive(Deserialize, Serialize, ValueEnum, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+#[value(rename_all = "snake_case")]
+pub enum LogTarget {
+    /// Standard error, in LOG_FORMAT
+    #[default]
+    Stderr,
+    /// The local syslog daemon
+    Syslog,
+    /// The systemd journal, every field of a line becoming a journal field
+    Journald,
+    /// The Application log of Windows
+    Eventlog,
+}
+
+#[derive(Deserialize, Serialize, ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+#[value(rename_all = "snake_case")]
+pub enum EventFormat {
+    /// One JSON object per line, as LOG_FORMAT=json
+    Ndjson,
+}
+
+/// Starts a new cleanup cycle, returning its id. Every subsequent JSON log
+/// line carries it until the next cycle starts.
+pub fn next_cycle() -> u64 {
+    CYCLE.fetch_add(1, Ordering::Relaxed) + 1
+}
+
+/// Logs to `target`, or if it cannot be reached in `format` to standard
+/// error, appended to `file` instead when set. Levels are filtered by
+/// `RUST_LOG` either way, but for the `events` written to standard output.
+pub fn init(
+    format: LogFormat,
+    target: LogTarget,
+    file: Option<&Path>,
+    events: Option<EventFormat>,
+) {
+    let mut builder = env_logger::Builder::from_default_env();
+
+    let unreachable = match target {
+        LogTarget::Stderr => None,
+        target => match imp::Sink::connect(target) {
+            Ok(sink) => {
+                colored::control::set_override(false);
+
+                let filter = builder.build();
+                let level = filter.filter();
+                install(SystemLogger { filter, sink }, level, events);
+                return;
+            }
+            Err(e) => Some(e),
+        },
+    };
+
+    let unopened = match file.map(open) {
+        Some(Ok(file)) => {
+            colored::control::set_override(false);
+            builder.target(env_logger::Target::Pipe(Box::new(file)));
+            None
+        }
+        Some(Err(e)) => Some(e),
+        None => None,
+    };
+
+    if format == LogFormat::Json {
+        colored::control::set_override(false);
+
+        builder.format(|buf, record| {
+            writeln!(
+                buf,
+                "{}",
+                json_line(&buf.timestamp_millis().to_string(), record)
+            )
+        });
+    }
+
+    let logger = builder.build();
+    let level = logger.filter();
+    install(logger, level, events);
+
+    if let Some(e) = unreachable {
+        log::warn!(event = "error"; "Error connecting to {}: {e}, logging to standard error", target.name());
+    }
+
+    if let (Some(file), Some(e)) = (file, unopened) {
+        log::warn!(event = "error"; "Error opening {}: {e}, logging to standard error", file.display());
+    }
+}
+
+/// Opens the log file for appending, creating it if needed.
+pub fn open(file: &Path) -> std::io::Result<std::fs::File> {
+    std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(file)
+}
+
+/// Sets `logger` letting through `level`, wrapped to write `events` too.
+fn install(logger: impl Log + 'static, level: LevelFilter, events: Option<EventFormat>) {
+    match events {
+        Some(EventFormat::Ndjson) => {
+            // Events carry plain messages, as JSON lines do
+            colored::control::set_override(false);
+            // Removals are logged at debug
+            log::set_max_level(level.max(LevelFilter::Debug));
+            let _ = log::set_boxed_logger(Box::new(Events { logger }));
+        }
+        None => {
+            log::set_max_level(level);
+            let _ = log::set_boxed_logger(Box::new(logger));
+        }
+    }
+}
+
+/// `record` as a JSON object: its timestamp, level, target, message, the
+/// current cycle and every field.
+fn json_line(timestamp: &str, record: &Record) -> serde_json::Value {
+    let mut line = serde_json::Map::new();
+    line.insert("timestamp".to_string(), timestamp.into());
+    line.insert("level".to_string(), record.level().as_str().into());
+    line.insert("target".to_string(), record.target().into());
+    line.insert("message".to_string(), record.args().to_string().into());
+
+    let cycle = CYCLE.load(Ordering::Relaxed);
+    if cycle > 0 {
+        line.insert("cycle".to_string(), cycle.into());
+    }
+
+    let _ = record.key_values().visit(&mut JsonFields(&mut line));
+
+    serde_json::Value::Object(line)
+}
+
+/// Writes every line carrying an `event` field to standard output as a JSON
+/// object, before handing every line on to `logger`.
+struct Events<L> {
+    logger: L,
+}
+
+impl<L: Log> Log for Events<L> {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= Level::Debug || self.logger.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        if record.key_values().get(Key::from("event")).is_some() {
+            let timestamp = humantime::format_rfc3339_millis(std::time::SystemTime::now());
+            let line = json_line(&timestamp.to_string(), record);
+
+            // A closed pipe only loses the events
+            let _ = writeln!(std::io::stdout().lock(), "{line}");
+        }
+
+        self.logger.log(record);
+    }
+
+    fn flush(&self) {
+        let _ = std::io::stdout().flush();
+        self.logger.flush();
+    }
+}
+
+impl LogTarget {
+    fn name(self) -> &'static str {
+        match self {
+            LogTarget::Stderr => "standard error",
+            LogTarget::Syslog => "syslog",
+            LogTarget::Journald => "the journal",
+            LogTarget::Eventlog => "the event log",
+        }
+    }
+}
+
+/// Sends the lines `filter` lets through to syslog, the journal or the event
+/// log.
+struct SystemLogger {
+    filter: env_logger::Logger,
+    sink: imp::Sink,
+}
+
+impl Log for SystemLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.filter.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.filter.matches(record) {
+            return;
+        }
+
+        let message = record.args().to_string();
+
+        // Nowhere left to tell about a line that could not be sent
+        let _ = match self.sink.target {
+            LogTarget::Journald => self.sink.send(&journal_entry(record, &message)),
+            LogTarget::Eventlog => self.sink.report(record.level(), &message),
+            _ => self.sink.send(
+                format!(
+                    "<{}>{}[{}]: {message}",
+                    // The daemon facility
+                    3 * 8 + severity(record.level()),
+                    crate::APP_NAME,
+                    std::process::id()
+                )
+                .as_bytes(),
+            ),
+        };
+    }
+
+    fn flush(&self) {}
+}
+
+/// Syslog severity of `level`.
+fn severity(level: Level) -> u8 {
+    match level {
+        Level::Error => 3,
+        Level::Warn => 4,
+        Level::Info => 6,
+        Level::Debug | Level::Trace => 7,
+    }
+}
+
+/// `record` in the native protocol of the journal, see
+/// systemd.journal-fields(7).
+fn journal_entry(record: &Record, message: &str) -> Vec<u8> {
+    let mut entry = Vec::new();
+
+    journal_field(&mut entry, "MESSAGE", message);
+    journal_field(
+        &mut entry,
+        "PRIORITY",
+        &severity(record.level()).to_string(),
+    );
+    journal_field(&mut entry, "SYSLOG_IDENTIFIER", crate::APP_NAME);
+    journal_field(&mut entry, "TARGET", record.target());
+
+    if let Some(file) = record.file() {
+        journal_field(&mut entry, "CODE_FILE", file);
+    }
+
+    if let Some(line) = record.line() {
+        journal_field(&mut entry, "CODE_LINE", &line.to_string());
+    }
+
+    let cycle = CYCLE.load(Ordering::Relaxed);
+    if cycle > 0 {
+        journal_field(&mut entry, "CYCLE", &cycle.to_string());
+    }
+
+    let mut fields = serde_json::Map::new();
+    let _ = record.key_values().visit(&mut JsonFields(&mut fields));
+
+    for (key, value) in fields {
+        let value = match value {
+            serde_json::Value::String(value) => value,
+            value => value.to_string(),
+        };
+
+        journal_field(&mut entry, &journal_name(&key), &value);
+    }
+
+    entry
+}
+
+fn journal_field(entry: &mut Vec<u8>, name: &str, value: &str) {
+    entry.extend_from_slice(name.as_bytes());
+
+    if value.contains('\n') {
+        // Values spanning lines are prefixed with their length instead
+        entry.push(b'\n');
+        entry.extend_from_slice(&(value.len() as u64).to_le_bytes());
+    } else {
+        entry.push(b'=');
+    }
+
+    entry.extend_from_slice(value.as_bytes());
+    entry.push(b'\n');
+}
+
+/// `key` as a journal field name: uppercase letters, digits and
+/// underscores, starting with a letter.
+fn journal_name(key: &str) -> String {
+    let name: String = key
+        .chars()
+        .map(|c| match c.is_ascii_alphanumeric() {
+            true => c.to_ascii_uppercase(),
+            false => '_',
+        })
+        .skip_while(|c| !c.is_ascii_uppercase())
+        .collect();
+
+    match name.is_empty() {
+        true => "FIELD".to_string(),
+        false => name,
+    }
+}
+
+#[cfg(unix)]
+mod imp {
+    use std::os::unix::net::UnixDatagram;
+    use std::path::Path;
+
+    use super::LogTarget;
+
+    const JOURNAL: &str = "/run/systemd/journal/socket";
+
+    /// Where syslog listens on Linux, macOS and the BSDs.
+    const SYSLOG: [&str; 3] = ["/dev/log", "/var/run/syslog", "/var/run/log"];
+
+    pub struct Sink {
+        pub target: LogTarget,
+        socket: UnixDatagram,
+        path: &'static Path,
+    }
+
+    impl Sink {
+        pub fn connect(target: LogTarget) -> std::io::Result<Sink> {
+            let path = match target {
+                LogTarget::Journald => Some(JOURNAL),
+                LogTarget::Eventlog => None,
+                _ => SYSLOG.into_iter().find(|path| Path::new(path).exists()),
+            };
+
+            let Some(path) = path.map(Path::new).filter(|path| path.exists()) else {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::NotFound,
+                    "no socket to log to",
+                ));
+            };
+
+            Ok(Sink {
+                target,
+                socket: UnixDatagram::unbound()?,
+                path,
+            })
+        }
+
+        /// Sends to the path on every line, so a restarted daemon is
+        /// picked up again.
+        pub fn send(&self, datagram: &[u8]) -> std::io::Result<()> {
+            self.socket.send_to(datagram, self.path).map(|_| ())
+        }
+
+        /// Never connected to the event log.
+        pub fn report(&self, _level: log::Level, _message: &str) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+}
+
+#[cfg(windows)]
+mod imp {
+    use log::Level;
+    use windows_sys::Win32::Foundation::HANDLE;
+    use windows_sys::Win32::System::EventLog::{
+        RegisterEventSourceW, ReportEventW, EVENTLOG_ERROR_TYPE, EVENTLOG_INFORMATION_TYPE,
+        EVENTLOG_WARNING_TYPE,
+    };
+
+    use super::LogTarget;
+
+    /// Event of the message file `maxdirsize service install` registers,
+    /// shown as the message alone.
+    const EVENT_ID: u32 = 1;
+
+    pub struct Sink {
+        pub target: LogTarget,
+        source: HANDLE,
+    }
+
+    // SAFETY: event log handles may be used from any thread
+    unsafe impl Send for Sink {}
+    unsafe impl Sync for Sink {}
+
+    impl Sink {
+        pub fn connect(target: LogTarget) -> std::io::Result<Sink> {
+            if target != LogTarget::Eventlog {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::Unsupported,
+                    "only supported on Unix",
+                ));
+            }
+
+            let name = wide(crate::APP_NAME);
+            // SAFETY: the name is nul terminated, a null server is this machine
+            let source = unsafe { RegisterEventSourceW(std::ptr::null(), name.as_ptr()) };
+
+            match source.is_null() {
+                true => Err(std::io::Error::last_os_error()),
+                false => Ok(Sink { target, source }),
+            }
+        }
+
+        pub fn send(&self, _datagram: &[u8]) -> std::io::Result<()> {
+            Ok(())
+        }
+
+        pub fn report(&self, level: Level, message: &str) -> std::io::Result<()> {
+            let kind = match level {
+                Level::Error => EVENTLOG_ERROR_TYPE,
+                Level::Warn => EVENTLOG_WARNING_TYPE,
+                _ => EVENTLOG_INFORMATION_TYPE,
+            };
+            let message = wide(message);
+            let strings = [message.as_ptr()];
+
+            // SAFETY: one nul terminated string and no raw data
+            let reported = unsafe {
+                ReportEventW(
+                    self.source,
+                    kind,
+                    0,
+                    EVENT_ID,
+                    std::ptr::null_mut(),
+                    1,
+                    0,
+                    strings.as_ptr(),
+                    std::ptr::null(),
+                )
+            };
+
+            match reported {
+                0 => Err(std::io::Error::last_os_error()),
+                _ => Ok(()),
+            }
+        }
+    }
+
+    fn wide(value: &str) -> Vec<u16> {
+        value.encode_utf16().chain([0]).collect()
+    }
+}
+
+#[cfg(not(any(unix, windows)))]
+mod imp {
+    use super::LogTarget;
+
+    pub struct Sink {
+        pub target: LogTarget,
+    }
+
+    impl Sink {
+        pub fn connect(_target: LogTarget) -> std::io::Result<Sink> {
+            Err(std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                "only supported on Unix and Windows",
+            ))
+        }
+
+        pub fn send(&self, _datagram: &[u8]) -> std::io::Result<()> {
+            Ok(())
+        }
+
+        pub fn report(&self, _level: log::Level, _message: &str) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+}
+
+struct JsonFields<'a>(&'a mut serde_json::Map<String, serde_json::Value>);
+
+impl<'kvs> VisitSource<'kvs> for JsonFields<'_> {
+    fn visit_pair(&mut self, key: Key<'kvs>, value: Value<'kvs>) -> Result<(), log::kv::Error> {
+        let value = if let Some(value) = value.to_u64() {
+            value.into()
+        } else if let Some(value) = value.to_i64() {
+            value.into()
+        } else if let Some(value) = value.to_bool() {
+            value.into()
+        } else {
+            value.to_string().into()
+        };
+
+        self.0.insert(key.to_string(), value);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fields_become_journal_fields() {
+        let fields: [(&str, Value); 2] = [
+            ("event", Value::from("file_removed")),
+            ("dry_run", Value::from(true)),
+        ];
+        let record = Record::builder()
+            .level(Level::Warn)
+            .target("maxdirsize::cleaner")
+            .key_values(&fields)
+            .build();
+
+        let entry = journal_entry(&record, "Removed\nfile");
+        let mut message = b"MESSAGE\n".to_vec();
+        message.extend_from_slice(&12u64.to_le_bytes());
+        message.extend_from_slice(b"Removed\nfile\n");
+
+        assert!(entry.starts_with(&message));
+
+        let rest = String::from_utf8_lossy(&entry[message.len()..]).into_owned();
+        assert!(rest
+            .starts_with("PRIORITY=4\nSYSLOG_IDENTIFIER=maxdirsize\nTARGET=maxdirsize::cleaner\n"));
+        // After the cycle, set by other tests
+        assert!(rest.ends_with("EVENT=file_removed\nDRY_RUN=true\n"));
+        assert_eq!(journal_name("_9path"), "PATH");
+    }
+
+    #[test]
+    fn events_carry_every_field() {
+        let fields: [(&str, Value); 3] = [
+            ("event", Value::from("file_removed")),
+            ("path", Value::from("/data/a.log")),
+            ("bytes", Value::from(3000u64)),
+        ];
+        let record = Record::builder()
+            .level(Level::Debug)
+            .target("maxdirsize::cleaner")
+            .args(format_args!("Removed file"))
+            .key_values(&fields)
+            .build();
+
+        let line = json_line("2026-10-14T18:07:14.268Z", &record);
+        assert_eq!(line["timestamp"], "2026-10-14T18:07:14.268Z");
+        assert_eq!(line["level"], "DEBUG");
+        assert_eq!(line["message"], "Removed file");
+        assert_eq!(line["event"], "file_removed");
+        assert_eq!(line["path"], "/data/a.log");
+        assert_eq!(line["bytes"], 3000);
+    }
+}
@@ -1,255 +1,1045 @@
+mod commands;
+mod control;
+mod ctl;
+mod daemon;
+mod health;
+mod http;
+mod init;
+mod launchd;
+mod metrics;
+mod reload;
+mod service;
+mod signals;
+mod statsd;
+mod status;
+mod systemd;
+#[cfg(feature = "tui")]
+mod tui;
+mod unit;
+
+use clap::Parser;
 use colored::*;
-use log::{debug, error, info};
-use serde::Deserialize;
-use std::collections::HashMap;
-use std::path::{Path, PathBuf};
-use std::time::{Duration, UNIX_EPOCH};
+use control::Control;
+use daemon::PidFile;
+use health::Health;
+use log::{debug, error, info, warn};
+use maxdirsize::adaptive::AdaptiveInterval;
+use maxdirsize::config::{Args, Command, ServiceAction};
+use maxdirsize::lock::DirectoryLock;
+use maxdirsize::schedule::{Schedule, Timezone};
+use maxdirsize::{
+    logging, priority, units, Cleaner, Config, CycleOutcome, CycleReport, WatchConfig, APP_NAME,
+    VERSION,
+};
+use metrics::Metrics;
+use signals::Wake;
+use statsd::Statsd;
+use status::StatusFile;
+use std::io::ErrorKind;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender, TryRecvError};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+use systemd::Systemd;
 
-pub const VERSION: &str = env!("CARGO_PKG_VERSION");
-pub const APP_NAME: &str = env!("CARGO_PKG_NAME");
+const RELOAD_SETTLE: Duration = Duration::from_millis(200);
+const LOCK_RETRY: Duration = Duration::from_secs(1);
+/// How long past `CYCLE_TIMEOUT` a cycle is waited for, it aborts at the
+/// next file it gets to and returns its own report in that time unless stuck
+const TIMEOUT_GRACE: Duration = Duration::from_secs(5);
 
-fn default_margin() -> u8 {
-    85
-}
+fn main() {
+    let mut args = Args::parse();
 
-#[derive(Deserialize)]
-struct Config {
-    pub interval_seconds: u64,
-    pub directory: String,
-    pub max_size_mb: u64,
-    #[serde(default = "default_margin")]
-    pub margin: u8,
-}
+    // Only the socket is needed to talk to the daemon, not its directories
+    if let Some(Command::Ctl { action }) = args.command {
+        logging::init(
+            args.log_format.unwrap_or_default(),
+            args.log_target.unwrap_or_default(),
+            args.log_file.as_deref(),
+            None,
+        );
 
-fn main() {
-    env_logger::init();
+        let socket = args
+            .control_socket
+            .clone()
+            .or_else(|| Config::load(&args).ok()?.control_socket);
 
-    let config = envy::from_env::<Config>().unwrap();
+        std::process::exit(ctl::run(socket.as_deref(), action));
+    }
 
-    if config.margin > 100 {
-        error!("MARGIN must be between 0 and 100");
-        std::process::exit(1);
+    // Only the flags are needed
+    if let Some(Command::Completions { shell }) = args.command {
+        std::process::exit(commands::completions(shell));
     }
 
-    let directory = Path::new(&config.directory);
+    // There is no configuration yet
+    if let Some(Command::Init { path, output }) = &args.command {
+        logging::init(
+            args.log_format.unwrap_or_default(),
+            args.log_target.unwrap_or_default(),
+            args.log_file.as_deref(),
+            None,
+        );
 
-    println!(
-        "{}",
-        format!(
-            "Starting {APP_NAME}-v{VERSION} and running every {} seconds on {} with a limit of {} MB",
-            config.interval_seconds,
-            directory.display(),
-            config.max_size_mb
-        )
-        .magenta()
+        std::process::exit(init::run(path.as_deref(), output));
+    }
+
+    // Only the files are needed
+    if let Some(Command::Protect { paths, remove }) = &args.command {
+        logging::init(
+            args.log_format.unwrap_or_default(),
+            args.log_target.unwrap_or_default(),
+            args.log_file.as_deref(),
+            None,
+        );
+
+        std::process::exit(commands::protect(paths, *remove));
+    }
+
+    #[cfg(feature = "tui")]
+    if let Some(Command::Tui) = args.command {
+        logging::init(
+            args.log_format.unwrap_or_default(),
+            args.log_target.unwrap_or_default(),
+            args.log_file.as_deref(),
+            None,
+        );
+
+        std::process::exit(match tui::Daemon::configured(&args) {
+            Some(daemon) => tui::run(daemon),
+            None => {
+                error!(event = "error"; "No control socket or API configured, set CONTROL_SOCKET or CONTROL_ADDR");
+                1
+            }
+        });
+    }
+
+    // Started by the service control manager, to run the cleanup loop
+    let as_service = matches!(
+        args.command,
+        Some(Command::Service {
+            action: ServiceAction::Run
+        })
     );
+    if as_service {
+        args.command = None;
+    }
 
-    loop {
-        info!(
-            "{}",
+    // Only the service control manager is needed, installing goes on to
+    // check the configuration
+    if let Some(Command::Service { action }) = args.command {
+        if action != ServiceAction::Install {
+            logging::init(
+                args.log_format.unwrap_or_default(),
+                args.log_target.unwrap_or_default(),
+                args.log_file.as_deref(),
+                None,
+            );
+
+            std::process::exit(service::control(action));
+        }
+    }
+
+    // Subcommands take a single look at the directories, whatever the interval
+    if args.command.is_some() && !matches!(args.command, Some(Command::Check)) {
+        args.once = true;
+    }
+
+    let config = Config::load(&args);
+
+    let (format, target, file, events) = match &config {
+        Ok(config) => (
+            config.log_format,
+            config.log_target,
+            config.log_file.clone(),
+            config.events,
+        ),
+        Err(_) => (
+            args.log_format.unwrap_or_default(),
+            args.log_target.unwrap_or_default(),
+            args.log_file.clone(),
+            args.events,
+        ),
+    };
+    logging::init(format, target, file.as_deref(), events);
+
+    if as_service {
+        if let Err(e) = service::dispatch() {
+            error!(event = "error"; "Error connecting to the service control manager: {e}");
+            std::process::exit(1);
+        }
+    }
+
+    let mut config = match config {
+        Ok(config) => config,
+        Err(e) => {
+            error!(event = "error"; "{e}");
+            service::stopped(1);
+            std::process::exit(1);
+        }
+    };
+
+    set_priority(&config);
+
+    if let Some(command) = args.command.take() {
+        commands::run(command, &config);
+    }
+
+    let mut pid_file = match config.pid_file.as_deref().map(PidFile::acquire) {
+        Some(Ok(pid_file)) => Some(pid_file),
+        Some(Err(e)) => {
+            error!(event = "error"; "Error locking the PID file: {e}");
+            std::process::exit(1);
+        }
+        None => None,
+    };
+
+    for watch in &config.directories {
+        let schedule = if let Some(schedule) = &watch.schedule {
+            format!("on the schedule `{schedule}`")
+        } else if config.once || watch.interval_seconds == 0 {
+            "once".to_string()
+        } else if let Some(adaptive) = AdaptiveInterval::new(watch) {
             format!(
-                "Running cleanup loop, every {} seconds",
-                config.interval_seconds
+                "every {} to {} seconds depending on the utilization",
+                adaptive.shortest(),
+                adaptive.longest()
             )
-            .green()
-        );
+        } else {
+            format!("every {} seconds", watch.interval_seconds)
+        };
 
-        let data = read_dir(directory);
+        let mut limits = Vec::new();
 
-        match data {
-            Ok(files) => process(
-                files,
-                config.max_size_mb,
-                directory,
-                config.margin as f32 / 100.0,
-            ),
-            Err(e) => {
-                info!(
-                    "{}",
-                    format!("Error while reading {directory:?}: {e:?}").red()
-                );
+        if let Some(max_size_bytes) = watch.max_size_bytes {
+            limits.push(format!(
+                "a limit of {:.2} MB",
+                max_size_bytes as f64 / units::MIB as f64
+            ));
+        }
+
+        if let Some(percent) = watch.max_size_percent {
+            limits.push(format!("a limit of {percent}% of the filesystem"));
+        }
+
+        if let Some(percent) = watch.min_free_percent {
+            limits.push(format!("at least {percent}% of the filesystem free"));
+        }
+
+        if let Some(max_files) = watch.max_files {
+            limits.push(format!("at most {max_files} files"));
+        }
+
+        if let Some(max_age) = watch.max_age_seconds {
+            limits.push(format!("files expiring after {max_age} seconds"));
+        }
+
+        if let Some(compressed_max_size_bytes) = watch.compressed_max_size_bytes {
+            limits.push(format!(
+                "a limit of {:.2} MB on compressed files",
+                compressed_max_size_bytes as f64 / units::MIB as f64
+            ));
+        }
+
+        for (subdir, quota) in &watch.quotas {
+            limits.push(format!(
+                "a quota of {:.2} MB on {subdir}",
+                *quota as f64 / units::MIB as f64
+            ));
+        }
+
+        for (pattern, budget) in &watch.budgets {
+            limits.push(format!(
+                "a budget of {:.2} MB on {pattern}",
+                *budget as f64 / units::MIB as f64
+            ));
+        }
+
+        let starting = format!(
+            "Starting {APP_NAME}-v{VERSION} and running {schedule} on {} with {}{}{}",
+            watch.directory,
+            limits.join(" and "),
+            match watch.compress_after_seconds {
+                Some(seconds) => format!(", compressing files after {seconds} seconds"),
+                None => String::new(),
+            },
+            match watch.quiet_hours.is_empty() {
+                true => String::new(),
+                false => format!(
+                    ", removing nothing during {}",
+                    watch
+                        .quiet_hours
+                        .iter()
+                        .map(|window| window.to_string())
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                ),
             }
+        )
+        .magenta();
+
+        // Standard output is left to the events
+        match config.events {
+            Some(_) => eprintln!("{starting}"),
+            None => println!("{starting}"),
         }
+    }
 
-        std::thread::sleep(Duration::from_secs(config.interval_seconds));
+    if config.daemon {
+        if let Err(e) = daemon::detach(config.log_file.as_deref(), pid_file.as_mut()) {
+            error!(event = "error"; "Error detaching from the terminal: {e}");
+            std::process::exit(1);
+        }
+    } else if let Some(pid_file) = &mut pid_file {
+        if let Err(e) = pid_file.write() {
+            error!(event = "error"; "Error writing the PID file: {e}");
+            std::process::exit(1);
+        }
     }
-}
 
-struct FolderInfo {
-    path: PathBuf,
-}
+    let stop = Arc::new(AtomicBool::new(false));
+    let (waker, wake) = mpsc::channel();
 
-struct FileInfo {
-    path: PathBuf,
-    size: u64,
-    modified: u64,
-}
+    if let Err(e) = signals::spawn(
+        waker.clone(),
+        stop.clone(),
+        Duration::from_secs(config.shutdown_timeout_seconds),
+    ) {
+        error!(event = "error"; "Error installing signal handlers: {e}");
+        std::process::exit(1);
+    }
 
-enum ReadDirResultEntry {
-    Folder(FolderInfo),
-    File(FileInfo),
-}
+    let control = (config.control_addr.is_some() || config.control_socket.is_some())
+        .then(|| Arc::new(Control::new(config.control_token.as_deref(), waker.clone())));
 
-struct ReadDirResult {
-    entries: Vec<ReadDirResultEntry>,
-    total_size: u64,
-}
+    let mut schedule = Vec::new();
+
+    for watch in &config.directories {
+        match lock(&watch.directory, &config, &stop) {
+            Ok(Some(lock)) => schedule.push(Watch::new(watch.clone(), &stop, lock, &control)),
+            Ok(None) => break,
+            Err(e) => {
+                error!(event = "error"; "Error locking {}: {e}", watch.directory);
+                std::process::exit(1);
+            }
+        }
+    }
 
-fn read_dir(path: &Path) -> std::io::Result<ReadDirResult> {
-    let mut entries = Vec::new();
-    let mut total_size = 0;
+    let _config_watcher = args.config.as_ref().and_then(|path| {
+        reload::watch(path, waker.clone())
+            .inspect_err(|e| {
+                warn!(
+                    "{}",
+                    format!(
+                        "Error watching {} for changes, reload with SIGHUP instead: {e}",
+                        path.display()
+                    )
+                    .yellow()
+                )
+            })
+            .ok()
+    });
 
-    for entry in std::fs::read_dir(path)? {
-        let entry = entry?;
-        let path = entry.path();
+    let metrics = config
+        .metrics_addr
+        .as_ref()
+        .map(|_| Arc::new(Metrics::default()));
 
-        let metadata = std::fs::metadata(&path);
+    let statsd =
+        config
+            .statsd_addr
+            .as_ref()
+            .map(|addr| match Statsd::connect(addr, config.statsd_format) {
+                Ok(statsd) => {
+                    info!("{}", format!("Sending metrics to StatsD at {addr}").green());
+                    statsd
+                }
+                Err(e) => {
+                    error!(event = "error"; "Error connecting to StatsD at {addr}: {e}");
+                    std::process::exit(1);
+                }
+            });
 
-        if let Ok(metadata) = metadata {
-            if metadata.is_dir() {
-                entries.push(ReadDirResultEntry::Folder(FolderInfo {
-                    path: path.to_path_buf(),
-                }));
+    let mut status_file = config.status_file.as_deref().map(StatusFile::new);
 
-                let mut items = read_dir(&path)?;
-                entries.append(&mut items.entries);
-                total_size += items.total_size;
-            } else {
-                total_size += metadata.len();
+    let health = config.health_addr.as_ref().map(|_| {
+        let health = Arc::new(Health::new());
+        health.watch(
+            config
+                .directories
+                .iter()
+                .map(|watch| expected(&config, watch)),
+        );
+        health
+    });
 
-                let modified = match metadata.modified() {
-                    Ok(val) => val,
-                    Err(_) => metadata.created().expect("created timestamp not available"),
-                };
-                entries.push(ReadDirResultEntry::File(FileInfo {
-                    path,
-                    size: metadata.len(),
-                    modified: modified.duration_since(UNIX_EPOCH).unwrap().as_secs(),
-                }));
+    if let (Some(addr), Some(metrics)) = (&config.metrics_addr, &metrics) {
+        let metrics = metrics.clone();
+        // Served along with the metrics when configured on the same address
+        let health = health
+            .clone()
+            .filter(|_| config.health_addr.as_ref() == Some(addr));
+
+        if let Err(e) = http::serve(addr, move |request| {
+            match (metrics.handle(request), &health) {
+                (response, Some(health)) if response.status == 404 => health.handle(request),
+                (response, _) => response,
             }
-        } else {
-            error!(
-                "{}",
-                format!(
-                    "Error getting file metadata: {}, {metadata:?}",
-                    path.display()
-                )
-                .red()
-            );
+        }) {
+            error!(event = "error"; "Error binding metrics endpoint on {addr}: {e}");
+            std::process::exit(1);
         }
+
+        info!(
+            "{}",
+            format!("Serving metrics on http://{addr}/metrics").green()
+        );
     }
 
-    Ok(ReadDirResult {
-        entries,
-        total_size,
-    })
-}
+    if let (Some(addr), Some(health)) = (&config.health_addr, &health) {
+        let handler = health.clone();
+
+        if config.metrics_addr.as_ref() != Some(addr) {
+            if let Err(e) = http::serve(addr, move |request| handler.handle(request)) {
+                error!(event = "error"; "Error binding health endpoint on {addr}: {e}");
+                std::process::exit(1);
+            }
+        }
+
+        info!(
+            "{}",
+            format!("Serving health checks on http://{addr}/healthz").green()
+        );
+    }
+
+    if let (Some(addr), Some(control)) = (&config.control_addr, &control) {
+        let handler = control.clone();
+
+        if let Err(e) = http::serve(addr, move |request| handler.handle(request)) {
+            error!(event = "error"; "Error binding control API on {addr}: {e}");
+            std::process::exit(1);
+        }
+
+        info!(
+            "{}",
+            format!("Serving the control API on http://{addr}").green()
+        );
+    }
+
+    if let (Some(path), Some(control)) = (&config.control_socket, &control) {
+        if let Err(e) = ctl::serve(path, control.clone()) {
+            error!(event = "error"; "Error listening on {}: {e}", path.display());
+            std::process::exit(1);
+        }
+
+        info!(
+            "{}",
+            format!("Listening for control commands on {}", path.display()).green()
+        );
+    }
+
+    let mut pool = match scan_pool(config.scan_threads) {
+        Ok(pool) => Arc::new(pool),
+        Err(e) => {
+            error!(event = "error"; "Error starting scan threads: {e}");
+            std::process::exit(1);
+        }
+    };
+
+    let mut systemd = Systemd::from_env().unwrap_or_else(|e| {
+        warn!(
+            "{}",
+            format!("Error connecting to systemd, not notifying it: {e}").yellow()
+        );
+        None
+    });
+
+    let mut outcome = CycleOutcome::WithinLimit;
+    let mut totals = Totals::default();
+
+    while !schedule.is_empty() && !stop.load(Ordering::SeqCst) {
+        schedule.retain_mut(|scheduled| {
+            let report = match scheduled.stuck.take() {
+                // Reported once it returns, however late
+                Some(stuck) => match stuck.try_recv() {
+                    Ok((cleaner, report)) => {
+                        info!(
+                            event = "cycle_returned", directory:% = scheduled.config.directory;
+                            "{}",
+                            format!(
+                                "Cycle of {} given up on returned, scheduling it again",
+                                scheduled.config.directory
+                            )
+                            .green()
+                        );
+                        scheduled.restore(cleaner);
+                        report
+                    }
+                    Err(TryRecvError::Empty) => {
+                        scheduled.stuck = Some(stuck);
+                        return true;
+                    }
+                    Err(TryRecvError::Disconnected) => {
+                        scheduled.restore(new_cleaner(scheduled.config.clone(), &stop, &control));
+                        return true;
+                    }
+                },
+                None => {
+                    if scheduled.next_run > Instant::now()
+                        || stop.load(Ordering::SeqCst)
+                        || control.as_ref().is_some_and(|control| control.paused())
+                    {
+                        return true;
+                    }
 
-fn process(data: ReadDirResult, max_size_mb: u64, directory: &Path, margin: f32) {
-    let mut parent_dirs_files_count = HashMap::new();
+                    scheduled.run(&mut pool, &waker, &stop, &control)
+                }
+            };
 
-    let mut total_files = 0;
+            let Watch {
+                config: watch,
+                next_run,
+                adaptive,
+                ..
+            } = scheduled;
 
-    for file in &data.entries {
-        let file = match file {
-            ReadDirResultEntry::File(file) => {
-                total_files += 1;
-                file
+            if let Some(metrics) = &metrics {
+                metrics.record(&watch.directory, &report);
             }
-            ReadDirResultEntry::Folder(folder) => {
-                parent_dirs_files_count
-                    .entry(folder.path.clone())
-                    .or_insert(0);
-                continue;
+
+            if let Some(statsd) = &statsd {
+                statsd.record(&watch.directory, &report);
             }
-        };
 
-        file.path.ancestors().skip(1).for_each(|component| {
-            if !component.starts_with(directory) || component == directory {
-                return;
+            if let Some(status_file) = &mut status_file {
+                status_file.record(&watch.directory, &report);
             }
 
-            let mut path = PathBuf::new();
-            path.push(component);
+            if let Some(health) = &health {
+                health.record(&watch.directory, &report);
+            }
+
+            if let Some(control) = &control {
+                control.record(&watch.directory, &report);
+            }
+
+            if let Some(systemd) = &mut systemd {
+                systemd.cycle_finished(&watch.directory, &report);
+            }
+
+            outcome = outcome.max(report.outcome);
+            totals.add(&report);
+
+            *next_run = match &watch.schedule {
+                _ if config.once => return false,
+                Some(schedule) => next_scheduled(schedule, watch.timezone),
+                None if watch.interval_seconds == 0 => return false,
+                None => {
+                    let interval = match adaptive {
+                        Some(adaptive) => {
+                            let interval = adaptive.next(&report);
+                            debug!("Next cleanup of {} in {interval} seconds", watch.directory);
+                            interval
+                        }
+                        None => watch.interval_seconds,
+                    };
+
+                    Instant::now() + Duration::from_secs(interval)
+                }
+            };
+            true
+        });
+
+        // Cycles given up on wake the scheduler when they return
+        let next_run = schedule
+            .iter()
+            .filter(|watch| watch.stuck.is_none())
+            .map(|watch| watch.next_run)
+            .min();
+
+        if schedule.is_empty() {
+            break;
+        }
+
+        // Nothing is due while paused, until resumed
+        let next_run = match control.as_ref().is_some_and(|control| control.paused()) {
+            true => None,
+            false => next_run,
+        };
+
+        let wake_at = match systemd.as_mut() {
+            Some(systemd) => {
+                systemd.watchdog();
+                match (systemd.next_ping(), next_run) {
+                    (Some(ping), Some(next_run)) => Some(ping.min(next_run)),
+                    (ping, next_run) => ping.or(next_run),
+                }
+            }
+            None => next_run,
+        };
 
-            *parent_dirs_files_count.entry(path).or_insert(0) += 1;
+        let timeout = wake_at.map_or(Duration::MAX, |wake_at| {
+            wake_at.saturating_duration_since(Instant::now())
         });
+
+        match wake.recv_timeout(timeout) {
+            Ok(Wake::Shutdown) => break,
+            Ok(Wake::Reload) => {
+                // Writing a file takes several events, wait for them to settle.
+                // A shutdown ends the wait and has already set `stop`.
+                while let Ok(Wake::Reload) = wake.recv_timeout(RELOAD_SETTLE) {}
+
+                if stop.load(Ordering::SeqCst) {
+                    break;
+                }
+
+                reload(
+                    &args,
+                    &mut config,
+                    &mut schedule,
+                    &mut pool,
+                    &stop,
+                    &control,
+                );
+
+                if let Some(health) = &health {
+                    health.watch(
+                        schedule
+                            .iter()
+                            .map(|watch| expected(&config, &watch.config)),
+                    );
+                }
+
+                if let Some(status_file) = &mut status_file {
+                    status_file.watch(schedule.iter().map(|watch| watch.config.directory.as_str()));
+                }
+
+                if let Some(control) = &control {
+                    control.watch(schedule.iter().map(|watch| watch.config.directory.as_str()));
+                }
+            }
+            Ok(Wake::RunNow) => {
+                info!(
+                    event = "run_requested";
+                    "{}",
+                    "Cleanup requested, running every directory now".green()
+                );
+
+                for watch in &mut schedule {
+                    watch.next_run = Instant::now();
+                }
+            }
+            Ok(Wake::Resume | Wake::CycleFinished) | Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => unreachable!("`waker` is kept alive"),
+        }
     }
 
-    let max_size_bytes = max_size_mb * 1024 * 1024;
-    let mut total_size = data.total_size;
-    let total_size_mb = total_size as f64 / 1024.0 / 1024.0;
+    if let Some(systemd) = &systemd {
+        systemd.stopping();
+    }
 
-    if total_size < max_size_bytes {
+    if stop.load(Ordering::SeqCst) {
         info!(
+            event = "shutdown_finished", cycles = totals.cycles, files = totals.removed_files, dirs = totals.removed_dirs, bytes = totals.freed_bytes;
             "{}",
             format!(
-                "Total size: {total_size_mb:.2} MB in {total_files} files, limit set to {} MB",
-                max_size_mb
+                "Shut down after {} cycles, removed {} files and {} directories freeing {:.2} MB",
+                totals.cycles,
+                totals.removed_files,
+                totals.removed_dirs,
+                totals.freed_bytes as f64 / units::MIB as f64
             )
             .green()
         );
-        return;
+        log::logger().flush();
+
+        if !config.once {
+            drop(pid_file);
+            service::stopped(0);
+            std::process::exit(0);
+        }
     }
 
-    info!(
-            "{}",
-            format!(
-                "Total size: {total_size_mb:.2} MB in {total_files} files is greater than max size of {max_size_mb} MB... doing cleanup of older files",
-            )
-            .red()
-        );
+    drop(pid_file);
+    service::stopped(outcome.exit_code());
+    std::process::exit(outcome.exit_code());
+}
 
-    let sorted_files = data.entries;
-    let mut sorted_files: Vec<FileInfo> = sorted_files
-        .into_iter()
-        .filter_map(|item| match item {
-            ReadDirResultEntry::File(file) => Some(file),
-            _ => None,
-        })
-        .collect();
+/// Everything removed since startup, summarized on shutdown.
+#[derive(Default)]
+struct Totals {
+    cycles: u64,
+    removed_files: u64,
+    removed_dirs: u64,
+    freed_bytes: u64,
+}
 
-    sorted_files.sort_by(|a, b| a.modified.cmp(&b.modified));
+impl Totals {
+    fn add(&mut self, report: &CycleReport) {
+        self.cycles += 1;
+
+        if !report.dry_run {
+            self.removed_files += report.removed_files;
+            self.removed_dirs += report.removed_dirs;
+            self.freed_bytes += report.freed_bytes;
+        }
+    }
+}
 
-    let margin = margin as f64 * max_size_bytes as f64;
+/// Locks `directory` so no other instance cleans it up, waiting for the
+/// instance holding it with `lock_wait`. Returns `None` when shut down while
+/// waiting.
+fn lock(
+    directory: &str,
+    config: &Config,
+    stop: &AtomicBool,
+) -> std::io::Result<Option<DirectoryLock>> {
+    let lock_dir = config.lock_dir.clone().unwrap_or_else(std::env::temp_dir);
+    let mut waiting = false;
 
-    while total_size > margin as u64 {
-        let file = sorted_files.pop();
+    loop {
+        match DirectoryLock::acquire(Path::new(directory), &lock_dir) {
+            Err(e) if e.kind() == ErrorKind::WouldBlock && config.lock_wait => {
+                if !waiting {
+                    warn!(
+                        event = "lock_wait";
+                        "{}",
+                        format!("Waiting to lock {directory}, {e}").yellow()
+                    );
+                    waiting = true;
+                }
 
-        if file.is_none() {
-            break;
+                if stop.load(Ordering::SeqCst) {
+                    return Ok(None);
+                }
+
+                std::thread::sleep(LOCK_RETRY);
+            }
+            result => return result.map(Some),
+        }
+    }
+}
+
+/// A directory and the interval its cycles are expected at, 0 when it is
+/// cleaned up once, the longest one when it adapts to the utilization.
+/// Scheduled directories are never overdue, their runs may be days apart.
+fn expected<'a>(config: &Config, watch: &'a WatchConfig) -> (&'a str, u64) {
+    match config.once || watch.schedule.is_some() {
+        true => (&watch.directory, 0),
+        false => (
+            &watch.directory,
+            watch
+                .max_interval_seconds
+                .map_or(watch.interval_seconds, |max| {
+                    max.max(watch.interval_seconds)
+                }),
+        ),
+    }
+}
+
+/// The next time `schedule` matches in `timezone`.
+fn next_scheduled(schedule: &Schedule, timezone: Timezone) -> Instant {
+    let now = SystemTime::now();
+
+    // A schedule always matches again, `Schedule::from_str` made sure
+    let wait = schedule
+        .next_after(now, timezone)
+        .and_then(|next| next.duration_since(now).ok())
+        .unwrap_or_default();
+
+    Instant::now() + wait
+}
+
+/// Applies `NICE`, `IO_CLASS` and `IO_PRIORITY` before any thread is
+/// started, every thread inheriting them.
+fn set_priority(config: &Config) {
+    if let Some(nice) = config.nice {
+        if let Err(e) = priority::set_nice(nice) {
+            warn!(
+                "{}",
+                format!("Error setting the niceness to {nice}, keeping the current one: {e}")
+                    .yellow()
+            );
+        }
+    }
+
+    if let Some(class) = config.io_class {
+        if let Err(e) = priority::set_io_priority(class, config.io_priority) {
+            warn!(
+                "{}",
+                format!("Error setting IO_CLASS and IO_PRIORITY, keeping the current ones: {e}")
+                    .yellow()
+            );
         }
+    }
+}
 
-        let file = file.unwrap();
+fn scan_pool(threads: usize) -> Result<rayon::ThreadPool, rayon::ThreadPoolBuildError> {
+    rayon::ThreadPoolBuilder::new()
+        .num_threads(threads)
+        .thread_name(|i| format!("scan-{i}"))
+        .build()
+}
 
-        if let Err(e) = std::fs::remove_file(&file.path) {
+/// Re-reads the configuration and applies it from the next cycle on. Settings
+/// in [`reload::RESTART_REQUIRED`] keep their current value.
+fn reload(
+    args: &Args,
+    config: &mut Config,
+    schedule: &mut Vec<Watch>,
+    pool: &mut Arc<rayon::ThreadPool>,
+    stop: &Arc<AtomicBool>,
+    control: &Option<Arc<Control>>,
+) {
+    let new = match Config::load(args) {
+        Ok(new) => new,
+        Err(e) => {
             error!(
+                event = "error";
                 "{}",
-                format!("Error removing file: {}, {e:?}", file.path.display()).red()
+                format!("Error reloading the configuration, keeping the current one: {e}").red()
+            );
+            return;
+        }
+    };
+
+    let changes = reload::changes(config, &new);
+
+    if changes.is_empty() {
+        debug!("Configuration reloaded without changes");
+        return;
+    }
+
+    for change in &changes {
+        if reload::RESTART_REQUIRED
+            .iter()
+            .any(|key| change.starts_with(&format!("{key} ")))
+        {
+            warn!(
+                event = "config_changed";
+                "{}",
+                format!("Configuration reloaded, {change}, this needs a restart").yellow()
             );
         } else {
-            parent_dirs_files_count
-                .get_mut(file.path.parent().unwrap())
-                .map(|count| {
-                    *count -= 1;
-                });
+            info!(
+                event = "config_changed";
+                "{}",
+                format!("Configuration reloaded, {change}").green()
+            );
+        }
+    }
 
-            debug!("{}", format!("Removed file: {}", file.path.display()).red());
+    if new.scan_threads != config.scan_threads {
+        match scan_pool(new.scan_threads) {
+            Ok(new_pool) => *pool = Arc::new(new_pool),
+            Err(e) => error!(event = "error"; "Error starting scan threads: {e}"),
         }
+    }
+
+    schedule.retain(|watch| {
+        new.directories
+            .iter()
+            .any(|directory| directory.directory == watch.config.directory)
+    });
+
+    for directory in &new.directories {
+        if let Some(watch) = schedule
+            .iter_mut()
+            .find(|watch| watch.config.directory == directory.directory)
+        {
+            let old = &watch.config;
+
+            if (&old.schedule, old.timezone) != (&directory.schedule, directory.timezone) {
+                watch.next_run = match &directory.schedule {
+                    Some(schedule) => next_scheduled(schedule, directory.timezone),
+                    None => Instant::now(),
+                };
+            }
 
-        total_size = total_size - file.size;
+            if (
+                old.interval_seconds,
+                old.min_interval_seconds,
+                old.max_interval_seconds,
+            ) != (
+                directory.interval_seconds,
+                directory.min_interval_seconds,
+                directory.max_interval_seconds,
+            ) {
+                watch.adaptive = AdaptiveInterval::new(directory);
+            }
+
+            // A cycle given up on gets it once it returns
+            if let Some(cleaner) = &mut watch.cleaner {
+                cleaner.set_config(directory.clone());
+            }
+            watch.config = directory.clone();
+        } else if !config
+            .directories
+            .iter()
+            .any(|old| old.directory == directory.directory)
+        {
+            let lock_dir = config.lock_dir.clone().unwrap_or_else(std::env::temp_dir);
+
+            match DirectoryLock::acquire(Path::new(&directory.directory), &lock_dir) {
+                Ok(lock) => schedule.push(Watch::new(directory.clone(), stop, lock, control)),
+                Err(e) => error!(
+                    event = "error";
+                    "{}",
+                    format!("Error locking {}, not watching it: {e}", directory.directory).red()
+                ),
+            }
+        }
     }
 
-    parent_dirs_files_count.iter().for_each(|(path, count)| {
-        if *count <= 0 {
-            if let Err(e) = std::fs::remove_dir(path) {
+    config.directories = new.directories;
+    config.scan_threads = new.scan_threads;
+}
+
+/// A watched directory, its scheduling state and the lock held on it while
+/// it is watched.
+struct Watch {
+    /// Away on the thread of the cycle in progress
+    cleaner: Option<Cleaner>,
+    /// The configuration of the directory, the cleaner's unless reloaded
+    /// while its cycle was given up on
+    config: WatchConfig,
+    next_run: Instant,
+    /// Set with MIN_INTERVAL or MAX_INTERVAL
+    adaptive: Option<AdaptiveInterval>,
+    /// The cycle given up on past CYCLE_TIMEOUT, handing the cleaner back
+    /// once it returns. The directory is not cleaned up again until then.
+    stuck: Option<Receiver<(Cleaner, CycleReport)>>,
+    _lock: DirectoryLock,
+}
+
+impl Watch {
+    /// The cleanups of the directory are reported to `control` when set.
+    fn new(
+        config: WatchConfig,
+        stop: &Arc<AtomicBool>,
+        lock: DirectoryLock,
+        control: &Option<Arc<Control>>,
+    ) -> Watch {
+        // Scheduled directories wait for their first match
+        let next_run = match &config.schedule {
+            Some(schedule) => next_scheduled(schedule, config.timezone),
+            None => Instant::now(),
+        };
+
+        Watch {
+            adaptive: AdaptiveInterval::new(&config),
+            cleaner: Some(new_cleaner(config.clone(), stop, control)),
+            config,
+            next_run,
+            stuck: None,
+            _lock: lock,
+        }
+    }
+
+    /// Runs a cycle on a thread of its own, so that one blocked on a broken
+    /// mount holds up no other directory: past `CYCLE_TIMEOUT` it is given
+    /// up on and reported as failed, and left to return in its own time.
+    fn run(
+        &mut self,
+        pool: &mut Arc<rayon::ThreadPool>,
+        waker: &Sender<Wake>,
+        stop: &Arc<AtomicBool>,
+        control: &Option<Arc<Control>>,
+    ) -> CycleReport {
+        let Some(mut cleaner) = self.cleaner.take() else {
+            unreachable!("only cycles given up on keep the cleaner");
+        };
+        let (done, finished) = mpsc::channel();
+        let (threads, waker) = (pool.clone(), waker.clone());
+
+        let spawned = std::thread::Builder::new()
+            .name(format!("cycle-{}", self.config.directory))
+            .spawn(move || {
+                let report = threads.install(|| cleaner.run());
+                let _ = done.send((cleaner, report));
+                let _ = waker.send(Wake::CycleFinished);
+            });
+
+        if let Err(e) = spawned {
+            self.restore(new_cleaner(self.config.clone(), stop, control));
+            return failed(format!(
+                "Error starting the cycle of {}: {e}",
+                self.config.directory
+            ));
+        }
+
+        let returned = match self.config.cycle_timeout_seconds {
+            Some(timeout) => finished.recv_timeout(Duration::from_secs(timeout) + TIMEOUT_GRACE),
+            None => finished.recv().map_err(|_| RecvTimeoutError::Disconnected),
+        };
+
+        match returned {
+            Ok((cleaner, report)) => {
+                self.restore(cleaner);
+                report
+            }
+            Err(RecvTimeoutError::Timeout) => {
+                let error = format!(
+                    "Cycle of {} stuck past CYCLE_TIMEOUT, given up on, the directory is not cleaned up again until it returns",
+                    self.config.directory
+                );
                 error!(
+                    event = "cycle_abandoned", directory:% = self.config.directory;
                     "{}",
-                    format!("Error removing directory: {}, {e:?}", path.display()).red()
+                    error.clone().red()
                 );
-            } else {
-                debug!("{}", format!("Removed directory: {}", path.display()).red());
+
+                // The cycle holds a scan thread, the others get a pool of their own
+                match scan_pool(pool.current_num_threads()) {
+                    Ok(new_pool) => *pool = Arc::new(new_pool),
+                    Err(e) => error!(event = "error"; "Error starting scan threads: {e}"),
+                }
+
+                self.stuck = Some(finished);
+                failed(error)
+            }
+            Err(RecvTimeoutError::Disconnected) => {
+                self.restore(new_cleaner(self.config.clone(), stop, control));
+                failed(format!("Cycle of {} panicked", self.config.directory))
             }
         }
-    });
+    }
+
+    /// Takes the cleaner back, with the configuration reloaded since it left.
+    fn restore(&mut self, mut cleaner: Cleaner) {
+        cleaner.set_config(self.config.clone());
+        self.cleaner = Some(cleaner);
+    }
+}
+
+/// A cleaner of the directory of `config`, reporting to `control` when set.
+fn new_cleaner(
+    config: WatchConfig,
+    stop: &Arc<AtomicBool>,
+    control: &Option<Arc<Control>>,
+) -> Cleaner {
+    let directory = config.directory.clone();
+    let mut cleaner = Cleaner::new(config).with_stop_flag(stop.clone());
+
+    if let Some(control) = control {
+        let (planned, removed) = (control.clone(), control.clone());
+        let (plan_directory, directory) = (directory.clone(), directory);
+
+        cleaner = cleaner
+            .with_plan_listener(move |plan| planned.planned(&plan_directory, plan))
+            .with_removal_listener(move |record| removed.removed(&directory, record));
+    }
+
+    cleaner
+}
+
+/// The report of a cycle that never returned one.
+fn failed(error: String) -> CycleReport {
+    CycleReport {
+        outcome: CycleOutcome::Failed,
+        error: Some(error),
+        ..Default::default()
+    }
 }
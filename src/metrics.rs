@@ -0,0 +1,174 @@
+//! Prometheus metrics, served in the text exposition format.
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::http::{Request, Response};
+use maxdirsize::{CycleOutcome, CycleReport, APP_NAME};
+
+#[derive(Default)]
+struct DirectoryMetrics {
+    limit_bytes: u64,
+    size_bytes: u64,
+    files: u64,
+    files_deleted_total: u64,
+    directories_deleted_total: u64,
+    bytes_freed_total: u64,
+    cycles_over_limit_total: u64,
+    errors_total: u64,
+    scan_duration_seconds: f64,
+    last_run_timestamp_seconds: u64,
+    seconds_to_full: Option<u64>,
+}
+
+/// Name, type, help text and value of a metric family, an empty value
+/// leaving the directory out.
+type Family = (
+    &'static str,
+    &'static str,
+    &'static str,
+    fn(&DirectoryMetrics) -> String,
+);
+
+#[derive(Default)]
+pub struct Metrics {
+    directories: Mutex<BTreeMap<String, DirectoryMetrics>>,
+}
+
+impl Metrics {
+    pub fn record(&self, directory: &str, report: &CycleReport) {
+        let mut directories = self.directories.lock().unwrap();
+        let metrics = directories.entry(directory.to_string()).or_default();
+
+        metrics.limit_bytes = report.limit_bytes;
+        metrics.size_bytes = report.size_after;
+        metrics.files = report.total_files;
+        metrics.scan_duration_seconds = report.scan_duration.as_secs_f64();
+        metrics.last_run_timestamp_seconds = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        metrics.seconds_to_full = report.time_to_full.map(|time| time.as_secs());
+
+        if !report.dry_run {
+            metrics.files -= report.removed_files;
+            metrics.files_deleted_total += report.removed_files;
+            metrics.directories_deleted_total += report.removed_dirs;
+            metrics.bytes_freed_total += report.freed_bytes;
+        }
+
+        metrics.errors_total += report.errors;
+
+        if matches!(
+            report.outcome,
+            CycleOutcome::CleanedUp | CycleOutcome::Deferred
+        ) {
+            metrics.cycles_over_limit_total += 1;
+        }
+    }
+
+    pub fn render(&self) -> String {
+        let families: [Family; 11] = [
+            ("limit_bytes", "gauge", "Configured size limit", |m| {
+                m.limit_bytes.to_string()
+            }),
+            (
+                "size_bytes",
+                "gauge",
+                "Directory size after the last cycle",
+                |m| m.size_bytes.to_string(),
+            ),
+            (
+                "files",
+                "gauge",
+                "Files in the directory after the last cycle",
+                |m| m.files.to_string(),
+            ),
+            ("files_deleted_total", "counter", "Files removed", |m| {
+                m.files_deleted_total.to_string()
+            }),
+            (
+                "directories_deleted_total",
+                "counter",
+                "Empty directories removed",
+                |m| m.directories_deleted_total.to_string(),
+            ),
+            (
+                "bytes_freed_total",
+                "counter",
+                "Bytes freed by removing files",
+                |m| m.bytes_freed_total.to_string(),
+            ),
+            (
+                "cycles_over_limit_total",
+                "counter",
+                "Cycles that found the directory over its limit",
+                |m| m.cycles_over_limit_total.to_string(),
+            ),
+            (
+                "errors_total",
+                "counter",
+                "Unreadable entries and failed removals",
+                |m| m.errors_total.to_string(),
+            ),
+            (
+                "scan_duration_seconds",
+                "gauge",
+                "Duration of the last directory scan",
+                |m| m.scan_duration_seconds.to_string(),
+            ),
+            (
+                "last_run_timestamp_seconds",
+                "gauge",
+                "Unix time of the last cycle",
+                |m| m.last_run_timestamp_seconds.to_string(),
+            ),
+            (
+                "seconds_to_full",
+                "gauge",
+                "Projected time until the size limit is reached, while the directory grows",
+                |m| m.seconds_to_full.map(|s| s.to_string()).unwrap_or_default(),
+            ),
+        ];
+
+        let directories = self.directories.lock().unwrap();
+        let mut out = String::new();
+
+        for (name, kind, help, value) in families {
+            let _ = writeln!(out, "# HELP {APP_NAME}_{name} {help}");
+            let _ = writeln!(out, "# TYPE {APP_NAME}_{name} {kind}");
+
+            for (directory, metrics) in directories.iter() {
+                let value = value(metrics);
+
+                if value.is_empty() {
+                    continue;
+                }
+
+                let _ = writeln!(
+                    out,
+                    "{APP_NAME}_{name}{{directory=\"{}\"}} {value}",
+                    escape(directory),
+                );
+            }
+        }
+
+        out
+    }
+
+    pub fn handle(&self, request: &Request) -> Response {
+        match (request.method.as_str(), request.path.as_str()) {
+            ("GET", "/metrics") => Response::ok("text/plain; version=0.0.4", self.render()),
+            _ => Response::not_found(),
+        }
+    }
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
@@ -0,0 +1,134 @@
+//! The status of the directories against their limits as a Nagios plugin
+//! reports it, for `maxdirsize check-usage`: one line with performance data,
+//! and the state as exit status, which Icinga, Zabbix and others read too.
+
+use crate::units;
+
+/// States of the plugin API, worst last.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum State {
+    Ok = 0,
+    Warning = 1,
+    Critical = 2,
+    Unknown = 3,
+}
+
+impl State {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            State::Ok => "OK",
+            State::Warning => "WARNING",
+            State::Critical => "CRITICAL",
+            State::Unknown => "UNKNOWN",
+        }
+    }
+}
+
+/// A directory as it was found, the size counted the way the cleaner does.
+pub struct Usage {
+    pub directory: String,
+    /// The limit the cleaner holds the directory to, from `MAX_SIZE`,
+    /// `MAX_SIZE_PERCENT` or `MIN_FREE_PERCENT`, without which there is
+    /// nothing to compare to
+    pub limit: Option<u64>,
+    pub size: Result<u64, String>,
+}
+
+/// The worst state of `directories`, a directory being at `warn` or
+/// `crit` percent of its limit or more, and the line telling it.
+pub fn check(directories: &[Usage], warn: u8, crit: u8) -> (State, String) {
+    let mut state = State::Ok;
+    let mut summaries = Vec::new();
+    let mut perfdata = Vec::new();
+
+    for usage in directories {
+        let (limit, size) = match (usage.limit, &usage.size) {
+            (_, Err(e)) => {
+                state = state.max(State::Unknown);
+                summaries.push(format!("{}: {e}", usage.directory));
+                continue;
+            }
+            (None, Ok(_)) => {
+                state = state.max(State::Unknown);
+                summaries.push(format!("{}: no size limit", usage.directory));
+                continue;
+            }
+            (Some(limit), Ok(size)) => (limit, *size),
+        };
+
+        let percent = match limit {
+            0 => 100.0,
+            limit => size as f64 * 100.0 / limit as f64,
+        };
+
+        state = state.max(match percent {
+            percent if percent >= crit as f64 => State::Critical,
+            percent if percent >= warn as f64 => State::Warning,
+            _ => State::Ok,
+        });
+
+        summaries.push(format!(
+            "{} {percent:.1}% of {}",
+            usage.directory,
+            units::human(limit)
+        ));
+        perfdata.push(format!(
+            "'{}'={size}B;{};{};0;{limit}",
+            usage.directory.replace('\'', "''"),
+            share(limit, warn),
+            share(limit, crit)
+        ));
+    }
+
+    let mut line = format!("USAGE {} - {}", state.as_str(), summaries.join(", "));
+    if !perfdata.is_empty() {
+        line.push_str(" | ");
+        line.push_str(&perfdata.join(" "));
+    }
+
+    (state, line)
+}
+
+/// `percent` of `limit`, rounded down.
+fn share(limit: u64, percent: u8) -> u64 {
+    (limit as u128 * percent as u128 / 100) as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn usage(directory: &str, limit: Option<u64>, size: Result<u64, String>) -> Usage {
+        Usage {
+            directory: directory.to_string(),
+            limit,
+            size,
+        }
+    }
+
+    #[test]
+    fn reports_the_worst_directory_with_perfdata() {
+        let mut directories = vec![
+            usage("/data", Some(1000), Ok(500)),
+            usage("/var/cache", Some(2_097_152), Ok(1_782_579)),
+        ];
+
+        assert_eq!(
+            check(&directories, 80, 95),
+            (
+                State::Warning,
+                "USAGE WARNING - /data 50.0% of 1.0 KB, /var/cache 85.0% of 2.1 MB \
+                 | '/data'=500B;800;950;0;1000 '/var/cache'=1782579B;1677721;1992294;0;2097152"
+                    .to_string()
+            )
+        );
+
+        directories[0].size = Ok(950);
+        assert_eq!(check(&directories, 80, 95).0, State::Critical);
+
+        directories.push(usage("/tmp", None, Ok(0)));
+        let (state, line) = check(&directories, 80, 95);
+        assert_eq!(state, State::Unknown);
+        assert!(line.contains(", /tmp: no size limit |"));
+    }
+}
@@ -0,0 +1,39 @@
+//! What a cleanup tells about itself, posted to the webhook.
+
+use serde::Serialize;
+
+/// Largest files removed by a cleanup listed in its notification.
+pub const LARGEST_REMOVED: usize = 5;
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Event {
+    /// Files are about to be removed
+    CleanupStarted,
+    CleanupFinished,
+    /// The directory could not be scanned or nothing could be removed
+    CleanupFailed,
+    /// The directory grows fast enough to reach its limit within
+    /// `full_warning_horizon`
+    LimitApproaching,
+}
+
+/// State of the directory sent along with an [`Event`].
+#[derive(Debug, Default)]
+pub struct Notification<'a> {
+    pub directory: &'a str,
+    pub size_bytes: u64,
+    pub limit_bytes: u64,
+    pub files: u64,
+    pub files_deleted: u64,
+    pub dirs_deleted: u64,
+    pub bytes_freed: u64,
+    pub errors: u64,
+    pub dry_run: bool,
+    /// Why the cleanup failed
+    pub error: Option<String>,
+    /// When the directory will reach its limit at the pace it grows
+    pub seconds_to_full: Option<u64>,
+    /// Path and size of the largest files removed, largest first
+    pub largest_removed: Vec<(String, u64)>,
+}
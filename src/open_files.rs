@@ -0,0 +1,65 @@
+//! Finding files held open by running processes, so they are not removed
+//! from under a writer.
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+/// Every file open by a process whose descriptors can be read, with the PID
+/// of one of the processes holding it. Processes of other users are only
+/// visible when running as root.
+#[cfg(target_os = "linux")]
+pub fn open_files() -> HashMap<PathBuf, u32> {
+    let mut files = HashMap::new();
+
+    let Ok(processes) = std::fs::read_dir("/proc") else {
+        return files;
+    };
+
+    for process in processes.flatten() {
+        let Some(pid) = process
+            .file_name()
+            .to_str()
+            .and_then(|name| name.parse::<u32>().ok())
+        else {
+            continue;
+        };
+
+        // The process exited or belongs to another user
+        let Ok(descriptors) = std::fs::read_dir(process.path().join("fd")) else {
+            continue;
+        };
+
+        for descriptor in descriptors.flatten() {
+            if let Ok(target) = std::fs::read_link(descriptor.path()) {
+                files.entry(target).or_insert(pid);
+            }
+        }
+    }
+
+    files
+}
+
+/// Open files can only be listed on Linux, everywhere else nothing is
+/// reported as open.
+#[cfg(not(target_os = "linux"))]
+pub fn open_files() -> HashMap<PathBuf, u32> {
+    HashMap::new()
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+    use crate::APP_NAME;
+
+    #[test]
+    fn open_files_finds_files_of_this_process() {
+        let path = std::env::temp_dir().join(format!("{APP_NAME}-open-{}", std::process::id()));
+        let file = std::fs::File::create(&path).unwrap();
+        let path = std::fs::canonicalize(&path).unwrap();
+
+        assert_eq!(open_files().get(&path), Some(&std::process::id()));
+
+        drop(file);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
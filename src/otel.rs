@@ -0,0 +1,443 @@
+//! Exporting every cycle to OpenTelemetry over OTLP/HTTP with JSON
+//! encoding: a trace of the cycle with a child span per phase, and the
+//! figures of the directory as metrics.
+
+use colored::*;
+use log::warn;
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, Sender};
+use std::thread::JoinHandle;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::cleaner::{CycleOutcome, CycleReport};
+use crate::config::WatchConfig;
+use crate::{APP_NAME, VERSION};
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// `SPAN_KIND_INTERNAL`
+const SPAN_KIND: u8 = 1;
+
+/// `STATUS_CODE_ERROR`
+const STATUS_ERROR: u8 = 2;
+
+/// `AGGREGATION_TEMPORALITY_CUMULATIVE`
+const CUMULATIVE: u8 = 2;
+
+/// Where spans and metrics are exported to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Collector {
+    /// Base URL, `/v1/traces` and `/v1/metrics` being appended
+    pub endpoint: String,
+    pub headers: Vec<(String, String)>,
+    pub service_name: String,
+}
+
+impl Collector {
+    /// The collector configured for `config`, if any.
+    pub fn of(config: &WatchConfig) -> Option<Collector> {
+        Some(Collector {
+            endpoint: config
+                .otel_exporter_otlp_endpoint
+                .as_deref()?
+                .trim_end_matches('/')
+                .to_string(),
+            headers: config
+                .otel_exporter_otlp_headers
+                .iter()
+                .filter_map(|header| header.split_once('='))
+                .map(|(name, value)| (name.trim().to_string(), value.trim().to_string()))
+                .collect(),
+            service_name: config
+                .otel_service_name
+                .clone()
+                .unwrap_or_else(|| APP_NAME.to_string()),
+        })
+    }
+}
+
+/// When each phase of a cycle started and ended, in order.
+#[derive(Debug, Clone)]
+pub struct Timeline {
+    started: SystemTime,
+    last: SystemTime,
+    phases: Vec<(&'static str, SystemTime, SystemTime)>,
+}
+
+impl Timeline {
+    pub fn start() -> Timeline {
+        let now = SystemTime::now();
+
+        Timeline {
+            started: now,
+            last: now,
+            phases: Vec::new(),
+        }
+    }
+
+    /// Ends `phase`, which began when the previous one ended.
+    pub fn finish(&mut self, phase: &'static str) {
+        let now = SystemTime::now();
+
+        self.phases.push((phase, self.last, now));
+        self.last = now;
+    }
+}
+
+/// Totals of the cumulative sums, since the exporter started.
+#[derive(Debug, Default, Clone, Copy)]
+struct Totals {
+    files_deleted: u64,
+    directories_deleted: u64,
+    bytes_freed: u64,
+    errors: u64,
+    cycles: u64,
+}
+
+/// Posts traces and metrics in order on a background thread, so a slow
+/// collector never holds up a cleanup. Dropping it waits for the exports
+/// already queued.
+pub struct Exporter {
+    collector: Collector,
+    started: SystemTime,
+    totals: std::sync::Mutex<Totals>,
+    sender: Option<Sender<(&'static str, Value)>>,
+    worker: Option<JoinHandle<()>>,
+}
+
+impl Exporter {
+    pub fn new(collector: Collector) -> Exporter {
+        let (sender, receiver) = mpsc::channel::<(&'static str, Value)>();
+        let agent = ureq::AgentBuilder::new().timeout(TIMEOUT).build();
+        let target = collector.clone();
+
+        let worker = std::thread::spawn(move || {
+            for (signal, payload) in receiver {
+                let url = format!("{}/v1/{signal}", target.endpoint);
+                let mut request = agent.post(&url).set("Content-Type", "application/json");
+
+                for (name, value) in &target.headers {
+                    request = request.set(name, value);
+                }
+
+                if let Err(e) = request.send_string(&payload.to_string()) {
+                    warn!(
+                        event = "otlp_failed", url = url.as_str();
+                        "{}",
+                        format!("Error exporting {signal} to {url}: {e}").yellow()
+                    );
+                }
+            }
+        });
+
+        Exporter {
+            collector,
+            started: SystemTime::now(),
+            totals: Default::default(),
+            sender: Some(sender),
+            worker: Some(worker),
+        }
+    }
+
+    pub fn collector(&self) -> &Collector {
+        &self.collector
+    }
+
+    /// Queues the trace and metrics of the cycle of `directory`, which
+    /// failed with `error` if any.
+    pub fn export(
+        &self,
+        directory: &str,
+        timeline: &Timeline,
+        report: &CycleReport,
+        error: Option<&str>,
+    ) {
+        let totals = {
+            let mut totals = self.totals.lock().unwrap();
+
+            if !report.dry_run {
+                totals.files_deleted += report.removed_files;
+                totals.directories_deleted += report.removed_dirs;
+                totals.bytes_freed += report.freed_bytes;
+            }
+
+            totals.errors += report.errors;
+            totals.cycles += 1;
+            *totals
+        };
+
+        if let Some(sender) = &self.sender {
+            let _ = sender.send(("traces", self.traces(directory, timeline, report, error)));
+            let _ = sender.send(("metrics", self.metrics(directory, report, totals)));
+        }
+    }
+
+    fn resource(&self) -> Value {
+        json!({
+            "attributes": [
+                attribute("service.name", json!({ "stringValue": self.collector.service_name })),
+                attribute("service.version", json!({ "stringValue": VERSION })),
+            ],
+        })
+    }
+
+    fn traces(
+        &self,
+        directory: &str,
+        timeline: &Timeline,
+        report: &CycleReport,
+        error: Option<&str>,
+    ) -> Value {
+        let trace_id = id(16);
+        let root_id = id(8);
+        let directory_attribute = attribute("directory", json!({ "stringValue": directory }));
+
+        let mut root = json!({
+            "traceId": trace_id,
+            "spanId": root_id,
+            "name": "cycle",
+            "kind": SPAN_KIND,
+            "startTimeUnixNano": nanos(timeline.started),
+            "endTimeUnixNano": nanos(timeline.last.max(timeline.started + report.duration)),
+            "attributes": [
+                directory_attribute,
+                attribute("outcome", json!({ "stringValue": report.outcome.as_str() })),
+                attribute("dry_run", json!({ "boolValue": report.dry_run })),
+                int_attribute("files_scanned", report.total_files),
+                int_attribute("bytes_before", report.size_before),
+                int_attribute("bytes_after", report.size_after),
+                int_attribute("limit_bytes", report.limit_bytes),
+                int_attribute("errors", report.errors),
+            ],
+        });
+
+        if report.outcome == CycleOutcome::Failed {
+            root["status"] = json!({
+                "code": STATUS_ERROR,
+                "message": error.unwrap_or("the cycle failed"),
+            });
+        }
+
+        let mut spans = vec![root];
+
+        for &(phase, start, end) in &timeline.phases {
+            let mut attributes = vec![directory_attribute.clone()];
+
+            match phase {
+                "scan" => attributes.push(int_attribute("files", report.total_files)),
+                "compress" => attributes.push(int_attribute("files", report.compressed_files)),
+                "delete" => {
+                    attributes.push(int_attribute("files_deleted", report.removed_files));
+                    attributes.push(int_attribute("dirs_deleted", report.removed_dirs));
+                    attributes.push(int_attribute("bytes_freed", report.freed_bytes));
+                }
+                _ => {}
+            }
+
+            spans.push(json!({
+                "traceId": trace_id,
+                "spanId": id(8),
+                "parentSpanId": root_id,
+                "name": phase,
+                "kind": SPAN_KIND,
+                "startTimeUnixNano": nanos(start),
+                "endTimeUnixNano": nanos(end),
+                "attributes": attributes,
+            }));
+        }
+
+        json!({
+            "resourceSpans": [{
+                "resource": self.resource(),
+                "scopeSpans": [{
+                    "scope": { "name": APP_NAME, "version": VERSION },
+                    "spans": spans,
+                }],
+            }],
+        })
+    }
+
+    fn metrics(&self, directory: &str, report: &CycleReport, totals: Totals) -> Value {
+        let now = nanos(SystemTime::now());
+        let start = nanos(self.started);
+        let attributes = json!([attribute("directory", json!({ "stringValue": directory }))]);
+
+        let gauge = |name: &str, unit: &str, description: &str, value: Value| {
+            json!({
+                "name": format!("{APP_NAME}.{name}"),
+                "unit": unit,
+                "description": description,
+                "gauge": {
+                    "dataPoints": [
+                        merge(value, json!({ "timeUnixNano": now, "attributes": attributes })),
+                    ],
+                },
+            })
+        };
+        let sum = |name: &str, unit: &str, description: &str, value: u64| {
+            json!({
+                "name": format!("{APP_NAME}.{name}"),
+                "unit": unit,
+                "description": description,
+                "sum": {
+                    "aggregationTemporality": CUMULATIVE,
+                    "isMonotonic": true,
+                    "dataPoints": [{
+                        "asInt": value.to_string(),
+                        "startTimeUnixNano": start,
+                        "timeUnixNano": now,
+                        "attributes": attributes,
+                    }],
+                },
+            })
+        };
+        let int = |value: u64| json!({ "asInt": value.to_string() });
+        let files = match report.dry_run {
+            true => report.total_files,
+            false => report.total_files - report.removed_files,
+        };
+
+        json!({
+            "resourceMetrics": [{
+                "resource": self.resource(),
+                "scopeMetrics": [{
+                    "scope": { "name": APP_NAME, "version": VERSION },
+                    "metrics": [
+                        gauge("size", "By", "Directory size after the last cycle", int(report.size_after)),
+                        gauge("limit", "By", "Configured size limit", int(report.limit_bytes)),
+                        gauge("files", "{file}", "Files in the directory after the last cycle", int(files)),
+                        gauge(
+                            "cycle.duration",
+                            "s",
+                            "Duration of the last cycle",
+                            json!({ "asDouble": report.duration.as_secs_f64() }),
+                        ),
+                        sum("files_deleted", "{file}", "Files removed", totals.files_deleted),
+                        sum(
+                            "directories_deleted",
+                            "{directory}",
+                            "Empty directories removed",
+                            totals.directories_deleted,
+                        ),
+                        sum("bytes_freed", "By", "Bytes freed by removing files", totals.bytes_freed),
+                        sum("errors", "{error}", "Unreadable entries and failed removals", totals.errors),
+                        sum("cycles", "{cycle}", "Cycles run", totals.cycles),
+                    ],
+                }],
+            }],
+        })
+    }
+}
+
+impl Drop for Exporter {
+    fn drop(&mut self) {
+        self.sender.take();
+
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+    }
+}
+
+fn attribute(key: &str, value: Value) -> Value {
+    json!({ "key": key, "value": value })
+}
+
+/// 64-bit integers are strings in OTLP JSON.
+fn int_attribute(key: &str, value: u64) -> Value {
+    attribute(key, json!({ "intValue": value.to_string() }))
+}
+
+fn merge(mut value: Value, fields: Value) -> Value {
+    if let (Some(value), Value::Object(fields)) = (value.as_object_mut(), fields) {
+        value.extend(fields);
+    }
+
+    value
+}
+
+fn nanos(time: SystemTime) -> String {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos()
+        .to_string()
+}
+
+/// A random looking id of `bytes` bytes as hex, unique within the process
+/// and unlikely to collide across processes.
+fn id(bytes: usize) -> String {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+
+    let digest = Sha256::new()
+        .chain_update(nanos(SystemTime::now()))
+        .chain_update(std::process::id().to_le_bytes())
+        .chain_update(COUNTER.fetch_add(1, Ordering::Relaxed).to_le_bytes())
+        .finalize();
+
+    digest[..bytes]
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cycles_are_traced_with_a_span_per_phase() {
+        let exporter = Exporter::new(Collector {
+            endpoint: "http://127.0.0.1:1".to_string(),
+            headers: Vec::new(),
+            service_name: "cleaner".to_string(),
+        });
+
+        let mut timeline = Timeline::start();
+        timeline.finish("scan");
+        timeline.finish("select");
+        timeline.finish("delete");
+
+        let report = CycleReport {
+            outcome: CycleOutcome::CleanedUp,
+            total_files: 10,
+            removed_files: 3,
+            freed_bytes: 300,
+            ..Default::default()
+        };
+
+        let traces = exporter.traces("/data", &timeline, &report, None);
+        let resource = &traces["resourceSpans"][0];
+        assert_eq!(
+            resource["resource"]["attributes"][0]["value"]["stringValue"],
+            "cleaner"
+        );
+
+        let spans = resource["scopeSpans"][0]["spans"].as_array().unwrap();
+        let names: Vec<&str> = spans
+            .iter()
+            .map(|span| span["name"].as_str().unwrap())
+            .collect();
+        assert_eq!(names, ["cycle", "scan", "select", "delete"]);
+        assert_eq!(spans[0]["traceId"].as_str().unwrap().len(), 32);
+        assert!(spans[1..]
+            .iter()
+            .all(|span| span["parentSpanId"] == spans[0]["spanId"]
+                && span["traceId"] == spans[0]["traceId"]));
+        assert_eq!(spans[3]["attributes"][3]["value"]["intValue"], "300");
+
+        let metrics = exporter.metrics(
+            "/data",
+            &report,
+            Totals {
+                files_deleted: 3,
+                ..Default::default()
+            },
+        );
+        let metrics = &metrics["resourceMetrics"][0]["scopeMetrics"][0]["metrics"];
+        assert_eq!(metrics[2]["name"], "maxdirsize.files");
+        assert_eq!(metrics[2]["gauge"]["dataPoints"][0]["asInt"], "7");
+        assert_eq!(metrics[4]["sum"]["dataPoints"][0]["asInt"], "3");
+    }
+}
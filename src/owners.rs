@@ -0,0 +1,175 @@
+//! Restricting removals to the files of some users or groups, for
+//! `OWNER_USERS`, `OWNER_GROUPS` and `REQUIRE_OWNER_WRITE`: on shared
+//! scratch space only some of the data may be ours to clean.
+
+use std::fs::Metadata;
+
+/// Which files may be removed, by owner and permissions.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Owners {
+    /// Files owned by one of these users or groups, any file when both are
+    /// empty
+    pub users: Vec<u32>,
+    pub groups: Vec<u32>,
+    /// Files the owner may not write to are left alone
+    pub owner_write: bool,
+}
+
+impl Owners {
+    /// Resolves `users` and `groups`, names or numeric ids. `None` when
+    /// nothing restricts removals.
+    pub fn resolve(
+        users: &[String],
+        groups: &[String],
+        owner_write: bool,
+    ) -> Result<Option<Owners>, String> {
+        if users.is_empty() && groups.is_empty() && !owner_write {
+            return Ok(None);
+        }
+
+        Ok(Some(Owners {
+            users: users
+                .iter()
+                .map(|user| id(user, imp::user_id).ok_or(format!("Unknown user `{user}`")))
+                .collect::<Result<_, _>>()?,
+            groups: groups
+                .iter()
+                .map(|group| id(group, imp::group_id).ok_or(format!("Unknown group `{group}`")))
+                .collect::<Result<_, _>>()?,
+            owner_write,
+        }))
+    }
+
+    /// Whether the file of `metadata` may be removed.
+    pub fn allows(&self, metadata: &Metadata) -> bool {
+        imp::allows(self, metadata)
+    }
+}
+
+fn id(name: &str, lookup: fn(&str) -> Option<u32>) -> Option<u32> {
+    name.trim().parse().ok().or_else(|| lookup(name.trim()))
+}
+
+#[cfg(unix)]
+mod imp {
+    use std::ffi::CString;
+    use std::os::unix::fs::{MetadataExt, PermissionsExt};
+
+    use super::Owners;
+
+    /// Buffer for the strings of a passwd or group entry, large enough for
+    /// any sane one.
+    const BUFFER: usize = 16 * 1024;
+
+    pub fn allows(owners: &Owners, metadata: &std::fs::Metadata) -> bool {
+        if owners.owner_write && metadata.permissions().mode() & 0o200 == 0 {
+            return false;
+        }
+
+        (owners.users.is_empty() && owners.groups.is_empty())
+            || owners.users.contains(&metadata.uid())
+            || owners.groups.contains(&metadata.gid())
+    }
+
+    pub fn user_id(name: &str) -> Option<u32> {
+        let name = CString::new(name).ok()?;
+        let mut entry = std::mem::MaybeUninit::<libc::passwd>::uninit();
+        let mut buffer = vec![0 as libc::c_char; BUFFER];
+        let mut found = std::ptr::null_mut();
+
+        // SAFETY: every pointer is valid for the call, `entry` is only read
+        // after getpwnam_r reported finding it.
+        unsafe {
+            let status = libc::getpwnam_r(
+                name.as_ptr(),
+                entry.as_mut_ptr(),
+                buffer.as_mut_ptr(),
+                buffer.len(),
+                &mut found,
+            );
+
+            (status == 0 && !found.is_null()).then(|| entry.assume_init().pw_uid)
+        }
+    }
+
+    pub fn group_id(name: &str) -> Option<u32> {
+        let name = CString::new(name).ok()?;
+        let mut entry = std::mem::MaybeUninit::<libc::group>::uninit();
+        let mut buffer = vec![0 as libc::c_char; BUFFER];
+        let mut found = std::ptr::null_mut();
+
+        // SAFETY: as in `user_id`.
+        unsafe {
+            let status = libc::getgrnam_r(
+                name.as_ptr(),
+                entry.as_mut_ptr(),
+                buffer.as_mut_ptr(),
+                buffer.len(),
+                &mut found,
+            );
+
+            (status == 0 && !found.is_null()).then(|| entry.assume_init().gr_gid)
+        }
+    }
+}
+
+#[cfg(not(unix))]
+mod imp {
+    use super::Owners;
+
+    /// Refused by the configuration, ownership meaning nothing here.
+    pub fn allows(_owners: &Owners, _metadata: &std::fs::Metadata) -> bool {
+        true
+    }
+
+    pub fn user_id(_name: &str) -> Option<u32> {
+        None
+    }
+
+    pub fn group_id(_name: &str) -> Option<u32> {
+        None
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::APP_NAME;
+    use std::os::unix::fs::{MetadataExt, PermissionsExt};
+
+    #[test]
+    fn files_of_other_owners_or_read_only_are_not_allowed() {
+        let path = std::env::temp_dir().join(format!("{APP_NAME}-owners-{}", std::process::id()));
+        std::fs::write(&path, "data").unwrap();
+        let metadata = std::fs::metadata(&path).unwrap();
+
+        assert_eq!(Owners::resolve(&[], &[], false), Ok(None));
+        assert_eq!(
+            Owners::resolve(&["root".to_string()], &["0".to_string()], false)
+                .unwrap()
+                .unwrap(),
+            Owners {
+                users: vec![0],
+                groups: vec![0],
+                owner_write: false,
+            }
+        );
+        assert!(Owners::resolve(&[format!("{APP_NAME}-nobody")], &[], false).is_err());
+
+        let mine = Owners::resolve(&[metadata.uid().to_string()], &[], true)
+            .unwrap()
+            .unwrap();
+        let others = Owners {
+            users: vec![metadata.uid() + 1],
+            groups: vec![metadata.gid() + 1],
+            owner_write: false,
+        };
+        assert!(mine.allows(&metadata));
+        assert!(!others.allows(&metadata));
+
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o444)).unwrap();
+        assert!(!mine.allows(&std::fs::metadata(&path).unwrap()));
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,77 @@
+//! Pinning single files against eviction with the
+//! `user.maxdirsize.protect=1` extended attribute, set by
+//! `maxdirsize protect`.
+
+use std::path::Path;
+
+pub const ATTRIBUTE: &str = "user.maxdirsize.protect";
+
+/// Whether `path` carries the attribute. Filesystems without extended
+/// attributes pin nothing.
+#[cfg(unix)]
+pub fn is_pinned(path: &Path) -> bool {
+    matches!(
+        xattr::get(path, ATTRIBUTE),
+        Ok(Some(value)) if value.trim_ascii() == b"1"
+    )
+}
+
+#[cfg(not(unix))]
+pub fn is_pinned(_path: &Path) -> bool {
+    false
+}
+
+#[cfg(unix)]
+pub fn pin(path: &Path) -> std::io::Result<()> {
+    xattr::set(path, ATTRIBUTE, b"1")
+}
+
+/// Removes the attribute, if set.
+#[cfg(unix)]
+pub fn unpin(path: &Path) -> std::io::Result<()> {
+    match xattr::remove(path, ATTRIBUTE) {
+        Err(e) if !is_pinned(path) && path.exists() => {
+            log::debug!("{} was not pinned: {e}", path.display());
+            Ok(())
+        }
+        removed => removed,
+    }
+}
+
+#[cfg(not(unix))]
+pub fn pin(_path: &Path) -> std::io::Result<()> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "extended attributes are not supported on this platform",
+    ))
+}
+
+#[cfg(not(unix))]
+pub fn unpin(path: &Path) -> std::io::Result<()> {
+    pin(path)
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::APP_NAME;
+
+    #[test]
+    fn files_are_pinned_and_unpinned() {
+        let path = std::env::temp_dir().join(format!("{APP_NAME}-pin-{}", std::process::id()));
+        std::fs::write(&path, "data").unwrap();
+
+        // tmpfs only has user attributes on recent kernels
+        if pin(&path).is_err() {
+            std::fs::remove_file(&path).unwrap();
+            return;
+        }
+
+        assert!(is_pinned(&path));
+        unpin(&path).unwrap();
+        assert!(!is_pinned(&path));
+        unpin(&path).unwrap();
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
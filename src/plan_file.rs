@@ -0,0 +1,168 @@
+//! Cleanup plans saved as JSON to be reviewed before they are applied.
+
+use colored::*;
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use crate::config::WatchConfig;
+use crate::policy::CleanupPlan;
+use crate::scan::matches_relative;
+use crate::storage::Storage;
+
+/// Bumped whenever a saved plan can no longer be read the same way.
+pub const FORMAT_VERSION: u32 = 1;
+
+/// The plans of every configured directory, as written by `maxdirsize plan`.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PlanFile {
+    pub version: u32,
+    /// When the plans were made, RFC 3339
+    pub created: String,
+    pub directories: Vec<DirectoryPlan>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct DirectoryPlan {
+    pub directory: String,
+    /// Size of the directory once the plan is applied
+    pub projected_size: u64,
+    /// Files left once the plan is applied
+    pub projected_files: u64,
+    #[serde(flatten)]
+    pub plan: CleanupPlan,
+}
+
+impl DirectoryPlan {
+    pub fn new(directory: &str, plan: CleanupPlan) -> DirectoryPlan {
+        let freed: u64 = plan.files.iter().map(|eviction| eviction.freed_bytes).sum();
+
+        DirectoryPlan {
+            directory: directory.to_string(),
+            projected_size: plan.total_size.saturating_sub(freed),
+            projected_files: plan.total_files.saturating_sub(plan.files.len() as u64),
+            plan,
+        }
+    }
+}
+
+impl PlanFile {
+    pub fn new(directories: Vec<DirectoryPlan>) -> PlanFile {
+        PlanFile {
+            version: FORMAT_VERSION,
+            created: humantime::format_rfc3339_seconds(SystemTime::now()).to_string(),
+            directories,
+        }
+    }
+
+    pub fn read(path: &Path) -> std::io::Result<PlanFile> {
+        let plans: PlanFile = serde_json::from_reader(std::fs::File::open(path)?)?;
+
+        if plans.version != FORMAT_VERSION {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!(
+                    "plan format version {} is not supported, expected {FORMAT_VERSION}",
+                    plans.version
+                ),
+            ));
+        }
+
+        Ok(plans)
+    }
+}
+
+/// Drops from `plan` the files that may no longer be removed as planned:
+/// gone, modified, resized or replaced since the plan was made, outside the
+/// directory or excluded by the current configuration. Directories holding
+/// one of them are kept too. Returns how many files were dropped.
+pub fn revalidate(plan: &mut CleanupPlan, watch: &WatchConfig, storage: &dyn Storage) -> u64 {
+    let directory = Path::new(&watch.directory);
+    let mut dropped: Vec<PathBuf> = Vec::new();
+
+    plan.files.retain(|eviction| {
+        let file = &eviction.file;
+
+        let problem = if !file.path.starts_with(directory) {
+            Some("outside the directory".to_string())
+        } else if !watch.exclude.is_empty()
+            && matches_relative(&watch.exclude, directory, &file.path)
+        {
+            Some("excluded".to_string())
+        } else {
+            match storage.changed_since_scan(watch, file) {
+                Ok(false) => None,
+                Ok(true) => Some("changed since the plan was made".to_string()),
+                Err(e) => Some(format!("unreadable, {e}")),
+            }
+        };
+
+        let Some(problem) = problem else {
+            return true;
+        };
+
+        warn!(
+            event = "plan_file_skipped", path:% = file.path.display();
+            "{}",
+            format!("Not removing {}, {problem}", file.path.display()).yellow()
+        );
+        dropped.push(file.path.clone());
+        false
+    });
+
+    let holding: HashSet<&Path> = dropped
+        .iter()
+        .flat_map(|file| file.ancestors().skip(1))
+        .collect();
+
+    plan.dirs
+        .retain(|dir| dir.starts_with(directory) && !holding.contains(dir.as_path()));
+
+    dropped.len() as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Cleaner, APP_NAME};
+
+    #[test]
+    fn revalidate_drops_files_changed_since_the_plan() {
+        let dir = std::env::temp_dir().join(format!("{APP_NAME}-plan-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("sub")).unwrap();
+        std::fs::write(dir.join("kept"), b"kept").unwrap();
+        std::fs::write(dir.join("sub/changed"), b"old").unwrap();
+
+        let watch: WatchConfig = toml::from_str(&format!(
+            "directory = {:?}\ninterval_seconds = 1\nmax_files = 0",
+            dir.display().to_string()
+        ))
+        .unwrap();
+
+        let mut cleaner = Cleaner::new(watch.clone());
+        let scanned = cleaner.scan().unwrap();
+        let plan = cleaner.plan(scanned).unwrap();
+
+        let json = serde_json::to_string(&PlanFile::new(vec![DirectoryPlan::new(
+            &watch.directory,
+            plan,
+        )]))
+        .unwrap();
+        let mut plans: PlanFile = serde_json::from_str(&json).unwrap();
+        assert_eq!(plans.directories[0].projected_size, 0);
+        let plan = &mut plans.directories[0].plan;
+        assert_eq!(plan.files.len(), 2);
+        assert_eq!(plan.dirs, [dir.join("sub")]);
+
+        std::fs::write(dir.join("sub/changed"), b"rewritten").unwrap();
+
+        assert_eq!(revalidate(plan, &watch, cleaner.storage()), 1);
+        assert_eq!(plan.files.len(), 1);
+        assert_eq!(plan.files[0].file.path, dir.join("kept"));
+        assert!(plan.dirs.is_empty());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,1416 @@
+//! Deciding what to remove: the limits that apply to a directory and which of
+//! its files and directories are evicted when they are exceeded.
+
+use colored::*;
+use globset::GlobSet;
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+use std::cmp::Reverse;
+use std::collections::{HashMap, HashSet};
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::compress;
+use crate::config::{Deduplication, EvictionUnit, WatchConfig};
+use crate::dedup;
+use crate::disk;
+use crate::eviction::EvictionPolicy;
+use crate::scan::{matches_relative, FileInfo, ScanEntry, ScanResult};
+use crate::units;
+
+/// What a cleanup of a scanned directory will remove.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct CleanupPlan {
+    /// Size of the directory when it was scanned
+    pub total_size: u64,
+    pub total_files: u64,
+    /// Size limit applied this cycle
+    pub limit_bytes: u64,
+    /// Which overall limit was exceeded, if any
+    pub reason: Option<Reason>,
+    /// Files to remove, in removal order
+    pub files: Vec<Eviction>,
+    /// Directories left empty once `files` are removed, deepest first
+    pub dirs: Vec<PathBuf>,
+    /// Unreadable entries skipped during the scan
+    pub scan_errors: u64,
+}
+
+/// A file to remove and why.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Eviction {
+    pub file: FileInfo,
+    pub reason: Reason,
+    /// Space removing the file gives back, nothing while other hard links to
+    /// it are kept
+    pub freed_bytes: u64,
+    /// The identical file kept for a [`Reason::Duplicate`], which the copy
+    /// is hard linked to with `Deduplication::Hardlink`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub duplicate_of: Option<FileInfo>,
+}
+
+/// Why something is removed.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Reason {
+    /// The directory is larger than `max_size_bytes` or `max_size_percent`
+    SizeLimit,
+    /// The filesystem has less than `min_free_percent` free
+    FreeSpace,
+    /// The directory holds more than `max_files` files
+    FileLimit,
+    /// The file is older than `max_age_seconds`
+    Expired,
+    /// The file is a dangling symlink or empty, and older than
+    /// `clutter_max_age_seconds`
+    Clutter,
+    /// The file's subdirectory is larger than its quota
+    Quota,
+    /// Compressed files take more than `compressed_max_size_bytes`
+    CompressedLimit,
+    /// Files matching a pattern of `budgets` take more than its budget
+    Budget,
+    /// An identical file is kept, see `deduplicate`
+    Duplicate,
+    /// The directory was left empty by the cleanup
+    EmptyDirectory,
+}
+
+/// The size the directory may grow to this cycle: the configured limit or
+/// `max_size_percent` of the current filesystem capacity, lowered so that
+/// the filesystem keeps `min_free_percent` free.
+pub fn size_limit(watch: &WatchConfig, total_size: u64) -> std::io::Result<u64> {
+    let mut limit = watch.max_size_bytes.unwrap_or(u64::MAX);
+
+    if watch.max_size_percent.is_none() && watch.min_free_percent.is_none() {
+        return Ok(limit);
+    }
+
+    let usage = disk::usage(Path::new(&watch.directory))?;
+
+    if let Some(percent) = watch.max_size_percent {
+        let max_size = (usage.total as f64 * percent / 100.0) as u64;
+
+        debug!(
+            "{}",
+            format!(
+                "Filesystem of {} holds {:.2} MB, {percent}% being {:.2} MB",
+                watch.directory,
+                usage.total as f64 / units::MIB as f64,
+                max_size as f64 / units::MIB as f64
+            )
+            .green()
+        );
+
+        limit = limit.min(max_size);
+    }
+
+    if let Some(percent) = watch.min_free_percent {
+        let min_free = (usage.total as f64 * percent / 100.0) as u64;
+
+        debug!(
+            "{}",
+            format!(
+                "Filesystem of {} has {:.2}% free, minimum is {percent}%",
+                watch.directory,
+                usage.free_percent()
+            )
+            .green()
+        );
+
+        limit = limit.min((total_size + usage.available).saturating_sub(min_free));
+    }
+
+    Ok(limit)
+}
+
+/// Picks the files to remove, never one of the `keep_newest` newest of its
+/// subdirectory: every file older than `max_age_seconds`, then enough to
+/// bring each subdirectory over its quota, and compressed files over
+/// `compressed_max_size_bytes`, down to the low watermark of it, then, once
+/// the directory is above the high watermark of `max_size_bytes` or
+/// `max_files`, enough for it to end up below the low watermark of both. At
+/// most `max_deletions_per_cycle` files and `max_bytes_per_cycle` bytes are
+/// picked.
+pub fn plan(
+    data: ScanResult,
+    watch: &WatchConfig,
+    max_size_bytes: u64,
+    policy: &dyn EvictionPolicy,
+) -> CleanupPlan {
+    let directory = Path::new(&watch.directory);
+    let max_size_mb = max_size_bytes as f64 / units::MIB as f64;
+    let high = watch.high_watermark as f64 / 100.0;
+    let low = watch.target_percent() as f64 / 100.0;
+
+    let mut parent_dirs_files_count: HashMap<PathBuf, i64> = HashMap::new();
+
+    let total_files = data.total_files;
+
+    let mut tiers: Vec<Tier> = watch
+        .quotas
+        .iter()
+        .map(|(subdir, quota)| Tier {
+            name: subdir,
+            scope: Scope::Subdirectory(directory.join(subdir)),
+            limit: *quota,
+            used: 0,
+            reason: Reason::Quota,
+        })
+        .collect();
+
+    if let Some(limit) = watch.compressed_max_size_bytes {
+        tiers.push(Tier {
+            name: "compressed files",
+            scope: Scope::Compressed,
+            limit,
+            used: 0,
+            reason: Reason::CompressedLimit,
+        });
+    }
+
+    for ((pattern, budget), set) in watch.budgets.iter().zip(&watch.budget_patterns) {
+        tiers.push(Tier {
+            name: pattern,
+            scope: Scope::Pattern(directory, set),
+            limit: *budget,
+            used: 0,
+            reason: Reason::Budget,
+        });
+    }
+
+    let mut seen_hard_links = HashSet::new();
+
+    for entry in &data.entries {
+        match entry {
+            ScanEntry::File(file) => {
+                let counted = match &file.hard_link {
+                    Some(link) => !seen_hard_links.insert((link.device, link.inode)),
+                    None => false,
+                };
+
+                for tier in tiers.iter_mut().filter(|tier| tier.contains(&file.path)) {
+                    if !counted {
+                        tier.used += file.size;
+                    }
+                }
+            }
+            ScanEntry::Folder(folder) => {
+                parent_dirs_files_count.insert(folder.path.clone(), folder.files as i64);
+            }
+        }
+    }
+
+    let mut plan = CleanupPlan {
+        total_size: data.total_size,
+        total_files,
+        limit_bytes: max_size_bytes,
+        scan_errors: data.errors,
+        ..Default::default()
+    };
+
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let min_modified = now.saturating_sub(watch.min_age_seconds);
+
+    let mut files: Vec<FileInfo> = data
+        .entries
+        .into_iter()
+        .filter_map(|item| match item {
+            ScanEntry::File(file) => Some(file),
+            _ => None,
+        })
+        .collect();
+
+    let kept = match watch.keep_newest {
+        Some(keep) => newest_per_subdirectory(&files, directory, keep),
+        None => HashSet::new(),
+    };
+
+    // Remembered for their sidecars to be kept as well
+    let mut protected = HashSet::new();
+    // Sidecars only removed along with their file
+    let mut unlisted = HashSet::new();
+
+    // Only files matching the include patterns and not kept by the retention
+    // rule may be removed at all
+    files.retain(|file| {
+        if kept.contains(&file.path) {
+            debug!(
+                "{}",
+                format!("Keeping one of the newest files: {}", file.path.display()).yellow()
+            );
+        } else if watch.include.is_empty()
+            || matches_relative(&watch.include, directory, &file.path)
+        {
+            return true;
+        } else if sidecar_of(&watch.sidecars, &file.path).is_some() {
+            unlisted.insert(file.path.clone());
+            return true;
+        }
+
+        if !watch.sidecars.is_empty() {
+            protected.insert(file.path.clone());
+        }
+
+        false
+    });
+
+    let mut total_size = data.total_size;
+    let mut remaining_files = total_files;
+    let mut links = HashMap::new();
+
+    // Taken out before grouping, they are removed on their own
+    if let Some(max_age) = watch.clutter_max_age_seconds {
+        let stale_before = now.saturating_sub(max_age);
+        let (clutter, kept): (Vec<_>, Vec<_>) = files
+            .into_iter()
+            .partition(|file| file.modified < stale_before && data.clutter.contains(&file.path));
+        files = kept;
+
+        if !clutter.is_empty() {
+            info!(
+                event = "clutter_found", directory:% = directory.display(), files = clutter.len();
+                "{}",
+                format!(
+                    "{} dangling symlinks and empty files are older than {max_age} seconds",
+                    clutter.len()
+                )
+                .yellow()
+            );
+        }
+
+        for file in clutter {
+            let freed_bytes = release(&mut parent_dirs_files_count, &mut links, &file);
+
+            for tier in tiers.iter_mut().filter(|tier| tier.contains(&file.path)) {
+                tier.used = tier.used.saturating_sub(freed_bytes);
+            }
+
+            total_size -= freed_bytes;
+            remaining_files -= 1;
+            plan.files.push(Eviction {
+                file,
+                reason: Reason::Clutter,
+                freed_bytes,
+                duplicate_of: None,
+            });
+        }
+    }
+
+    let mut groups = Groups::new(watch, directory, &mut files, &protected, &unlisted);
+
+    let evictable = |file: &FileInfo| {
+        if file.modified > min_modified {
+            debug!(
+                "{}",
+                format!("Skipping recently modified file: {}", file.path.display()).yellow()
+            );
+            return false;
+        }
+
+        true
+    };
+
+    if let Some(max_age) = watch.max_age_seconds {
+        let expires_before = now.saturating_sub(max_age);
+        let (expired, kept): (Vec<_>, Vec<_>) = files
+            .into_iter()
+            .partition(|file| file.modified < expires_before);
+        files = kept;
+        let expired: Vec<FileInfo> = expired
+            .into_iter()
+            .flat_map(|unit| groups.take(unit))
+            .collect();
+
+        if !expired.is_empty() {
+            info!(
+                event = "files_expired", directory:% = directory.display(), files = expired.len();
+                "{}",
+                format!(
+                    "{} files are older than the max age of {max_age} seconds",
+                    expired.len()
+                )
+                .red()
+            );
+        }
+
+        for file in expired {
+            let freed_bytes = release(&mut parent_dirs_files_count, &mut links, &file);
+
+            for tier in tiers.iter_mut().filter(|tier| tier.contains(&file.path)) {
+                tier.used = tier.used.saturating_sub(freed_bytes);
+            }
+
+            total_size -= freed_bytes;
+            remaining_files -= 1;
+            plan.files.push(Eviction {
+                file,
+                reason: Reason::Expired,
+                freed_bytes,
+                duplicate_of: None,
+            });
+        }
+    }
+
+    for tier in &mut tiers {
+        if tier.used < (high * tier.limit as f64) as u64 {
+            continue;
+        }
+
+        info!(
+            event = "quota_exceeded", directory:% = directory.display(), quota = tier.name, bytes = tier.used;
+            "{}",
+            format!(
+                "{} of {}: {:.2} MB is greater than {:.2} MB... doing cleanup of older files",
+                match tier.reason {
+                    Reason::Budget => "Budget",
+                    _ => "Quota",
+                },
+                tier.name,
+                tier.used as f64 / units::MIB as f64,
+                tier.limit as f64 / units::MIB as f64
+            )
+            .red()
+        );
+
+        let (candidates, rest): (Vec<_>, Vec<_>) = files
+            .into_iter()
+            .partition(|file| tier.contains(&file.path) && evictable(file));
+        files = rest;
+
+        let target = (low * tier.limit as f64) as u64;
+        let mut candidates = policy.order(candidates);
+
+        while tier.used > target {
+            let Some(unit) = candidates.next() else {
+                break;
+            };
+
+            for file in groups.take(unit) {
+                let freed_bytes = release(&mut parent_dirs_files_count, &mut links, &file);
+                tier.used = tier.used.saturating_sub(freed_bytes);
+                total_size -= freed_bytes;
+                remaining_files -= 1;
+                plan.files.push(Eviction {
+                    file,
+                    reason: tier.reason,
+                    freed_bytes,
+                    duplicate_of: None,
+                });
+            }
+        }
+
+        files.extend(candidates.into_rest());
+    }
+
+    let total_size_mb = total_size as f64 / 1024.0 / 1024.0;
+    let max_files = watch.max_files.unwrap_or(u64::MAX);
+    let size_trigger = (high * max_size_bytes as f64) as u64;
+    let files_trigger = (high * max_files as f64) as u64;
+
+    if total_size < size_trigger && remaining_files <= files_trigger {
+        let mut limits = Vec::new();
+
+        if max_size_bytes < u64::MAX {
+            limits.push(format!("{max_size_mb:.2} MB"));
+        }
+
+        if let Some(max_files) = watch.max_files {
+            limits.push(format!("{max_files} files"));
+        }
+
+        let limits = match limits.is_empty() {
+            true => "no limit set".to_string(),
+            false => format!("limit set to {}", limits.join(" and ")),
+        };
+
+        info!(
+            event = "scan_finished", directory:% = directory.display(), files = remaining_files, bytes = total_size, over_limit = false;
+            "{}",
+            format!(
+                "Total size: {total_size_mb:.2} MB in {remaining_files} files, {limits}",
+            )
+            .green()
+        );
+
+        if plan.files.is_empty() {
+            return plan;
+        }
+    } else {
+        let summary = if remaining_files > files_trigger {
+            format!("{remaining_files} files is more than the limit of {files_trigger} files")
+        } else {
+            format!(
+                "Total size: {total_size_mb:.2} MB in {remaining_files} files is greater than max size of {:.2} MB",
+                size_trigger as f64 / units::MIB as f64
+            )
+        };
+
+        info!(
+            event = "scan_finished", directory:% = directory.display(), files = remaining_files, bytes = total_size, over_limit = true;
+            "{}",
+            format!("{summary}... doing cleanup of older files").red()
+        );
+
+        let reason = if remaining_files > files_trigger {
+            Reason::FileLimit
+        } else if watch.min_free_percent.is_none() || watch.max_size_bytes == Some(max_size_bytes) {
+            Reason::SizeLimit
+        } else {
+            Reason::FreeSpace
+        };
+        plan.reason = Some(reason);
+
+        let files_target = (low * max_files as f64) as u64;
+        let size_target = (low * max_size_bytes as f64) as u64;
+
+        // Copies of identical files go first, the one evicted last is kept
+        if watch.deduplicate != Deduplication::Off {
+            let hardlink = watch.deduplicate == Deduplication::Hardlink;
+            let mut copies = Vec::new();
+
+            let evictable_files: Vec<&FileInfo> =
+                files.iter().filter(|file| evictable(file)).collect();
+
+            for set in dedup::duplicates(&evictable_files) {
+                let mut set: Vec<FileInfo> = set.into_iter().cloned().collect();
+                policy.sort(&mut set);
+                let kept = set.pop().expect("duplicates come at least in pairs");
+                copies.extend(set.into_iter().map(|copy| (copy, kept.clone())));
+            }
+
+            copies.sort_by(|(a, _), (b, _)| {
+                policy
+                    .score(a)
+                    .total_cmp(&policy.score(b))
+                    .then(a.modified.cmp(&b.modified))
+            });
+
+            if !copies.is_empty() {
+                info!(
+                    event = "duplicates_found", directory:% = directory.display(), files = copies.len();
+                    "{}",
+                    format!("{} files are copies of another one", copies.len()).red()
+                );
+            }
+
+            // Linked files are left alone for the rest of the cycle, their
+            // space only being freed with all of their links
+            let mut taken = HashSet::new();
+
+            for (copy, original) in copies {
+                // Linking keeps the path, it does nothing for MAX_FILES
+                if total_size <= size_target && (hardlink || remaining_files <= files_target) {
+                    break;
+                }
+
+                let freed_bytes = match hardlink {
+                    true => {
+                        taken.insert(original.path.clone());
+                        copy.hard_link.map_or(copy.size, |_| 0)
+                    }
+                    false => {
+                        remaining_files -= 1;
+                        release(&mut parent_dirs_files_count, &mut links, &copy)
+                    }
+                };
+
+                total_size -= freed_bytes;
+                taken.insert(copy.path.clone());
+                plan.files.push(Eviction {
+                    file: copy,
+                    reason: Reason::Duplicate,
+                    freed_bytes,
+                    duplicate_of: Some(original),
+                });
+            }
+
+            files.retain(|file| !taken.contains(&file.path));
+        }
+
+        let candidates = policy.order(files.into_iter().filter(evictable).collect());
+
+        for unit in candidates {
+            if total_size <= size_target && remaining_files <= files_target {
+                break;
+            }
+
+            for file in groups.take(unit) {
+                let freed_bytes = release(&mut parent_dirs_files_count, &mut links, &file);
+                total_size -= freed_bytes;
+                remaining_files -= 1;
+                plan.files.push(Eviction {
+                    file,
+                    reason,
+                    freed_bytes,
+                    duplicate_of: None,
+                });
+            }
+        }
+    }
+
+    cap(&mut plan, watch, &groups, &mut parent_dirs_files_count);
+
+    plan.dirs = parent_dirs_files_count
+        .into_iter()
+        .filter(|(_, count)| *count <= 0)
+        .map(|(path, _)| path)
+        .collect();
+    plan.dirs.sort_by(|a, b| b.cmp(a));
+
+    plan
+}
+
+/// Files sharing a size limit of their own, on top of the overall limits.
+struct Tier<'a> {
+    name: &'a str,
+    scope: Scope<'a>,
+    limit: u64,
+    used: u64,
+    reason: Reason,
+}
+
+/// The files of a [`Tier`].
+enum Scope<'a> {
+    /// Those below a subdirectory
+    Subdirectory(PathBuf),
+    /// Every compressed file
+    Compressed,
+    /// Those matching a pattern relative to the directory
+    Pattern(&'a Path, &'a GlobSet),
+}
+
+impl Tier<'_> {
+    fn contains(&self, path: &Path) -> bool {
+        match &self.scope {
+            Scope::Subdirectory(root) => path.starts_with(root),
+            Scope::Compressed => compress::is_compressed(path),
+            Scope::Pattern(directory, set) => matches_relative(set, directory, path),
+        }
+    }
+}
+
+/// Files evicted together: with `EvictionUnit::Subdirectory` each immediate
+/// subdirectory, and each file with its `sidecars`. A group is a single
+/// candidate, as large as its files together and as recent as the newest of
+/// them, taken apart again once picked.
+#[derive(Default)]
+struct Groups {
+    /// Files of each candidate standing for several, oldest first
+    members: HashMap<PathBuf, Vec<FileInfo>>,
+    /// Candidate each of those files belongs to
+    units: HashMap<PathBuf, PathBuf>,
+}
+
+impl Groups {
+    /// Replaces the grouped `files` with the candidates of their groups,
+    /// leaving out the sidecars of the `protected` files and the `unlisted`
+    /// sidecars of nothing.
+    fn new(
+        watch: &WatchConfig,
+        directory: &Path,
+        files: &mut Vec<FileInfo>,
+        protected: &HashSet<PathBuf>,
+        unlisted: &HashSet<PathBuf>,
+    ) -> Groups {
+        let mut groups = Groups::default();
+
+        if watch.eviction_unit == EvictionUnit::Subdirectory {
+            groups.group_subdirectories(directory, files);
+        }
+
+        if !watch.sidecars.is_empty() {
+            groups.group_sidecars(&watch.sidecars, files, protected, unlisted);
+        }
+
+        groups
+    }
+
+    fn group_subdirectories(&mut self, directory: &Path, files: &mut Vec<FileInfo>) {
+        let mut candidates: HashMap<PathBuf, FileInfo> = HashMap::new();
+        let mut ungrouped = Vec::new();
+
+        for file in files.drain(..) {
+            let Some(subdirectory) = subdirectory_of(directory, &file.path) else {
+                ungrouped.push(file);
+                continue;
+            };
+
+            let path = directory.join(subdirectory);
+            let candidate = candidates.entry(path.clone()).or_insert_with(|| FileInfo {
+                path: path.clone(),
+                size: 0,
+                modified: 0,
+                accessed: 0,
+                hard_link: None,
+                inode: 0,
+            });
+
+            candidate.size += file.size;
+            candidate.modified = candidate.modified.max(file.modified);
+            candidate.accessed = candidate.accessed.max(file.accessed);
+
+            self.units.insert(file.path.clone(), path.clone());
+            self.members.entry(path).or_default().push(file);
+        }
+
+        for members in self.members.values_mut() {
+            members.sort_by_key(|file| file.modified);
+        }
+
+        ungrouped.extend(candidates.into_values());
+        *files = ungrouped;
+    }
+
+    /// Groups each file named like another one of its directory plus one of
+    /// the `sidecars` suffixes with it, e.g. `video.srt` or `video.mp4.json`
+    /// with `video.mp4`. Sidecars of nothing are left on their own.
+    fn group_sidecars(
+        &mut self,
+        sidecars: &[String],
+        files: &mut Vec<FileInfo>,
+        protected: &HashSet<PathBuf>,
+        unlisted: &HashSet<PathBuf>,
+    ) {
+        let (found, mut mains): (Vec<_>, Vec<_>) = files
+            .drain(..)
+            .partition(|file| sidecar_of(sidecars, &file.path).is_some());
+
+        // Each file answers to its name and to its name without extension
+        let names = |path: &Path| {
+            [
+                Some(path.to_path_buf()),
+                path.file_stem().map(|stem| path.with_file_name(stem)),
+            ]
+            .into_iter()
+            .flatten()
+        };
+
+        let mut index = HashMap::new();
+
+        for (position, file) in mains.iter().enumerate() {
+            for name in names(&file.path) {
+                index.entry(name).or_insert(position);
+            }
+        }
+
+        let protected: HashSet<PathBuf> = protected.iter().flat_map(|path| names(path)).collect();
+        let mut attached: HashMap<usize, Vec<FileInfo>> = HashMap::new();
+
+        for sidecar in found {
+            let Some(main) = sidecar_of(sidecars, &sidecar.path) else {
+                continue;
+            };
+
+            match index.get(&main) {
+                Some(&position) => attached.entry(position).or_default().push(sidecar),
+                None if protected.contains(&main) => debug!(
+                    "{}",
+                    format!("Keeping sidecar of a kept file: {}", sidecar.path.display()).yellow()
+                ),
+                None if unlisted.contains(&sidecar.path) => {}
+                None => files.push(sidecar),
+            }
+        }
+
+        for (position, sidecars) in attached {
+            let main = &mut mains[position];
+            let mut members = vec![main.clone()];
+
+            for sidecar in sidecars {
+                main.size += sidecar.size;
+                main.modified = main.modified.max(sidecar.modified);
+                main.accessed = main.accessed.max(sidecar.accessed);
+                members.push(sidecar);
+            }
+
+            for member in &members {
+                self.units.insert(member.path.clone(), main.path.clone());
+            }
+
+            self.members.insert(main.path.clone(), members);
+        }
+
+        files.append(&mut mains);
+    }
+
+    /// The files to remove for `candidate`.
+    fn take(&mut self, candidate: FileInfo) -> Vec<FileInfo> {
+        self.members
+            .remove(&candidate.path)
+            .unwrap_or_else(|| vec![candidate])
+    }
+
+    /// The candidate `path` is removed with, if grouped.
+    fn unit(&self, path: &Path) -> Option<&PathBuf> {
+        self.units.get(path)
+    }
+}
+
+/// The file `path` is a sidecar of when its name ends with one of the
+/// `sidecars` suffixes, e.g. `/data/video` for `/data/video.srt` and `.srt`.
+fn sidecar_of(sidecars: &[String], path: &Path) -> Option<PathBuf> {
+    let name = path.file_name()?.to_str()?;
+
+    sidecars.iter().find_map(|sidecar| {
+        let suffix = sidecar.strip_prefix('*').unwrap_or(sidecar);
+
+        name.strip_suffix(suffix)
+            .filter(|base| !base.is_empty())
+            .map(|base| path.with_file_name(base))
+    })
+}
+
+/// The immediate subdirectory of `directory` holding `path`, however deep,
+/// `None` for a file directly in it.
+fn subdirectory_of<'a>(directory: &Path, path: &'a Path) -> Option<&'a OsStr> {
+    let mut components = path.strip_prefix(directory).unwrap_or(path).components();
+    let first = components.next();
+
+    components.next().and(first).map(|first| first.as_os_str())
+}
+
+/// Paths of the `keep` most recently modified files directly in `directory`
+/// and in each of its immediate subdirectories, however deep they are.
+fn newest_per_subdirectory(files: &[FileInfo], directory: &Path, keep: usize) -> HashSet<PathBuf> {
+    let mut groups: HashMap<Option<&OsStr>, Vec<&FileInfo>> = HashMap::new();
+
+    for file in files {
+        groups
+            .entry(subdirectory_of(directory, &file.path))
+            .or_default()
+            .push(file);
+    }
+
+    groups
+        .into_values()
+        .flat_map(|mut group| {
+            group.sort_by_key(|file| Reverse(file.modified));
+            group.into_iter().take(keep).map(|file| file.path.clone())
+        })
+        .collect()
+}
+
+/// Leaves the files past `max_deletions_per_cycle` or `max_bytes_per_cycle`
+/// for the next cycles, along with the rest of a group cut in two unless it
+/// is the first one.
+fn cap(
+    plan: &mut CleanupPlan,
+    watch: &WatchConfig,
+    groups: &Groups,
+    parent_dirs_files_count: &mut HashMap<PathBuf, i64>,
+) {
+    let max_files = watch.max_deletions_per_cycle.unwrap_or(u64::MAX);
+    let max_bytes = watch.max_bytes_per_cycle.unwrap_or(u64::MAX);
+
+    let mut bytes = 0;
+    let mut within = plan
+        .files
+        .iter()
+        .zip(1..)
+        .take_while(|(eviction, files)| {
+            bytes += eviction.freed_bytes;
+            *files <= max_files && bytes <= max_bytes
+        })
+        .count();
+
+    if within == plan.files.len() {
+        return;
+    }
+
+    let unit = |index: usize| groups.unit(&plan.files[index].file.path);
+    let mut whole = within;
+
+    while whole > 0 && unit(whole).is_some() && unit(whole) == unit(whole - 1) {
+        whole -= 1;
+    }
+
+    if whole > 0 {
+        within = whole;
+    }
+
+    let deferred = plan.files.split_off(within);
+
+    warn!(
+        event = "cleanup_capped", directory:% = watch.directory, files = within, deferred = deferred.len();
+        "{}",
+        format!(
+            "Removing {within} files this cycle, {} more are left for the next ones",
+            deferred.len()
+        )
+        .yellow()
+    );
+
+    for eviction in deferred {
+        for ancestor in eviction.file.path.ancestors().skip(1) {
+            if let Some(count) = parent_dirs_files_count.get_mut(ancestor) {
+                *count += 1;
+            }
+        }
+    }
+}
+
+/// Accounts for `file` being removed from its directory, returning the space
+/// this frees: a file with several hard links only frees it with its last
+/// link, `links` holds how many are left of each.
+fn release(
+    parent_dirs_files_count: &mut HashMap<PathBuf, i64>,
+    links: &mut HashMap<(u64, u64), u64>,
+    file: &FileInfo,
+) -> u64 {
+    // Every directory above counts it, their files being counted however deep
+    for ancestor in file.path.ancestors().skip(1) {
+        if let Some(count) = parent_dirs_files_count.get_mut(ancestor) {
+            *count -= 1;
+        }
+    }
+
+    let Some(link) = &file.hard_link else {
+        return file.size;
+    };
+
+    let remaining = links.entry((link.device, link.inode)).or_insert(link.links);
+    *remaining = remaining.saturating_sub(1);
+
+    match *remaining {
+        0 => file.size,
+        _ => 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::eviction::OldestFirst;
+    use crate::scan::{FolderInfo, HardLink};
+
+    fn file(name: &str, modified: u64) -> FileInfo {
+        FileInfo {
+            path: PathBuf::from(name),
+            size: 1,
+            modified,
+            accessed: modified,
+            hard_link: None,
+            inode: 0,
+        }
+    }
+
+    fn names(files: Vec<Eviction>) -> Vec<String> {
+        files
+            .into_iter()
+            .map(|eviction| eviction.file.path.display().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn plan_removes_oldest_files_over_file_limit() {
+        let watch: WatchConfig = toml::from_str(
+            "directory = \"/data\"\ninterval_seconds = 1\nmax_files = 3\nmargin = 50",
+        )
+        .unwrap();
+
+        let files = [("d", 400), ("a", 100), ("c", 300), ("b", 200)];
+
+        let scanned = ScanResult {
+            entries: files
+                .iter()
+                .map(|(name, modified)| ScanEntry::File(file(&format!("/data/{name}"), *modified)))
+                .collect(),
+            total_size: files.len() as u64,
+            total_files: files.len() as u64,
+            ..Default::default()
+        };
+
+        let plan = plan(scanned, &watch, u64::MAX, &OldestFirst);
+
+        assert_eq!(plan.reason, Some(Reason::FileLimit));
+        assert_eq!(names(plan.files), ["/data/a", "/data/b", "/data/c"]);
+    }
+
+    #[test]
+    fn plan_triggers_at_high_and_cleans_to_low_watermark() {
+        let watch = |max_size_bytes: u64| -> WatchConfig {
+            toml::from_str(&format!(
+                "directory = \"/data\"\ninterval_seconds = 1\nmax_size_bytes = {max_size_bytes}\nhigh_watermark = 90\nlow_watermark = 50"
+            ))
+            .unwrap()
+        };
+
+        let scanned = || ScanResult {
+            entries: (0..10)
+                .map(|i| {
+                    ScanEntry::File(FileInfo {
+                        size: 10,
+                        ..file(&format!("/data/{i}"), i)
+                    })
+                })
+                .collect(),
+            total_size: 100,
+            total_files: 10,
+            ..Default::default()
+        };
+
+        assert_eq!(plan(scanned(), &watch(120), 120, &OldestFirst).reason, None);
+
+        let plan = plan(scanned(), &watch(105), 105, &OldestFirst);
+
+        assert_eq!(plan.reason, Some(Reason::SizeLimit));
+        assert_eq!(plan.files.len(), 5);
+    }
+
+    #[test]
+    fn plan_expires_old_files_within_limits() {
+        let watch: WatchConfig =
+            toml::from_str("directory = \"/data\"\ninterval_seconds = 1\nmax_age_seconds = \"7d\"")
+                .unwrap();
+
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+
+        let scanned = ScanResult {
+            entries: vec![
+                ScanEntry::File(file("/data/stale", now - 8 * 24 * 60 * 60)),
+                ScanEntry::File(file("/data/fresh", now)),
+            ],
+            total_size: 2,
+            total_files: 2,
+            ..Default::default()
+        };
+
+        let plan = plan(scanned, &watch, u64::MAX, &OldestFirst);
+
+        assert_eq!(plan.reason, None);
+        assert_eq!(plan.files[0].reason, Reason::Expired);
+        assert_eq!(names(plan.files), ["/data/stale"]);
+    }
+
+    #[test]
+    fn plan_removes_stale_clutter_within_limits() {
+        let watch: WatchConfig = toml::from_str(
+            "directory = \"/data\"\ninterval_seconds = 1\nclutter_max_age_seconds = \"1h\"",
+        )
+        .unwrap();
+
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+
+        let scanned = ScanResult {
+            entries: vec![
+                ScanEntry::File(file("/data/dangling", now - 2 * 60 * 60)),
+                ScanEntry::File(file("/data/empty", now)),
+                ScanEntry::File(file("/data/old", now - 2 * 60 * 60)),
+            ],
+            total_size: 3,
+            total_files: 3,
+            clutter: ["/data/dangling", "/data/empty"]
+                .into_iter()
+                .map(PathBuf::from)
+                .collect(),
+            ..Default::default()
+        };
+
+        let plan = plan(scanned, &watch, u64::MAX, &OldestFirst);
+
+        assert_eq!(plan.reason, None);
+        assert_eq!(plan.files[0].reason, Reason::Clutter);
+        assert_eq!(names(plan.files), ["/data/dangling"]);
+    }
+
+    #[test]
+    fn plan_removes_copies_before_older_unique_files() {
+        let root = std::env::temp_dir().join(format!(
+            "{}-policy-dedup-{}",
+            crate::APP_NAME,
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(&root).unwrap();
+
+        let files = [
+            ("unique", 100, "unique data"),
+            ("build-1.tar", 200, "artifact data"),
+            ("build-2.tar", 300, "artifact data"),
+            ("build-3.tar", 400, "artifact data"),
+        ];
+        for (name, _, contents) in files {
+            std::fs::write(root.join(name), contents).unwrap();
+        }
+
+        let watch = |mode: &str| -> WatchConfig {
+            toml::from_str(&format!(
+                "directory = {:?}\ninterval_seconds = 1\nmax_size_bytes = 40\nlow_watermark = 75\ndeduplicate = \"{mode}\"",
+                root.display()
+            ))
+            .unwrap()
+        };
+        let scanned = || ScanResult {
+            entries: files
+                .iter()
+                .map(|(name, modified, contents)| {
+                    ScanEntry::File(FileInfo {
+                        size: contents.len() as u64,
+                        ..file(&root.join(name).display().to_string(), *modified)
+                    })
+                })
+                .collect(),
+            total_size: 50,
+            total_files: 4,
+            ..Default::default()
+        };
+
+        let deleting = plan(scanned(), &watch("delete"), 40, &OldestFirst);
+        let kept = deleting.files[0].duplicate_of.as_ref().unwrap();
+
+        assert_eq!(deleting.files[0].reason, Reason::Duplicate);
+        assert_eq!(kept.path, root.join("build-3.tar"));
+        assert_eq!(
+            names(deleting.files),
+            [root.join("build-1.tar"), root.join("build-2.tar")]
+                .map(|path| path.display().to_string())
+        );
+
+        // Linked copies still take a file each, and the unique one goes
+        let plan = plan(scanned(), &watch("hardlink"), 40, &OldestFirst);
+
+        assert_eq!(
+            plan.files
+                .iter()
+                .map(|eviction| eviction.reason)
+                .collect::<Vec<_>>(),
+            [Reason::Duplicate, Reason::Duplicate]
+        );
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn plan_keeps_newest_files_per_subdirectory() {
+        let watch: WatchConfig = toml::from_str(
+            "directory = \"/data\"\ninterval_seconds = 1\nmax_files = 1\nkeep_newest = 2",
+        )
+        .unwrap();
+
+        let files = [
+            ("host-a/1", 100),
+            ("host-a/2", 200),
+            ("host-a/daily/3", 300),
+            ("host-b/4", 50),
+            ("host-b/5", 150),
+            ("6", 10),
+        ];
+
+        let scanned = ScanResult {
+            entries: files
+                .iter()
+                .map(|(name, modified)| ScanEntry::File(file(&format!("/data/{name}"), *modified)))
+                .collect(),
+            total_size: files.len() as u64,
+            total_files: files.len() as u64,
+            ..Default::default()
+        };
+
+        let plan = plan(scanned, &watch, u64::MAX, &OldestFirst);
+
+        assert_eq!(plan.reason, Some(Reason::FileLimit));
+        assert_eq!(names(plan.files), ["/data/host-a/1"]);
+    }
+
+    #[test]
+    fn plan_frees_hard_linked_files_with_their_last_link() {
+        let watch: WatchConfig = toml::from_str(
+            "directory = \"/data\"\ninterval_seconds = 1\nmax_size_bytes = 15\nmargin = 100",
+        )
+        .unwrap();
+
+        let link = HardLink {
+            device: 1,
+            inode: 2,
+            links: 2,
+        };
+
+        let scanned = ScanResult {
+            entries: [
+                ("a", 100, Some(link)),
+                ("b", 200, Some(link)),
+                ("c", 300, None),
+            ]
+            .iter()
+            .map(|(name, modified, hard_link)| {
+                ScanEntry::File(FileInfo {
+                    size: 10,
+                    hard_link: *hard_link,
+                    ..file(&format!("/data/{name}"), *modified)
+                })
+            })
+            .collect(),
+            total_size: 20,
+            total_files: 3,
+            ..Default::default()
+        };
+
+        let plan = plan(scanned, &watch, 15, &OldestFirst);
+
+        let freed: Vec<u64> = plan.files.iter().map(|file| file.freed_bytes).collect();
+        assert_eq!(freed, [0, 10]);
+        assert_eq!(names(plan.files), ["/data/a", "/data/b"]);
+    }
+
+    #[test]
+    fn plan_removes_at_most_max_deletions_per_cycle() {
+        let watch: WatchConfig = toml::from_str(
+            "directory = \"/data\"\ninterval_seconds = 1\nmax_files = 0\nmax_deletions_per_cycle = 2",
+        )
+        .unwrap();
+
+        let scanned = ScanResult {
+            entries: (0..5)
+                .map(|i| ScanEntry::File(file(&format!("/data/dir/{i}"), i)))
+                .chain([ScanEntry::Folder(crate::scan::FolderInfo {
+                    path: PathBuf::from("/data/dir"),
+                    files: 5,
+                })])
+                .collect(),
+            total_size: 5,
+            total_files: 5,
+            ..Default::default()
+        };
+
+        let plan = plan(scanned, &watch, u64::MAX, &OldestFirst);
+
+        assert_eq!(names(plan.files), ["/data/dir/0", "/data/dir/1"]);
+        assert!(plan.dirs.is_empty());
+    }
+
+    #[test]
+    fn plan_cleans_subdirectory_over_quota() {
+        let watch: WatchConfig = toml::from_str(
+            "directory = \"/data\"\ninterval_seconds = 1\nmax_size_bytes = 100\n[quotas]\nlogs = 25",
+        )
+        .unwrap();
+
+        let scanned = ScanResult {
+            entries: [
+                ("logs/a", 100),
+                ("logs/b", 300),
+                ("logs/c", 200),
+                ("videos/d", 50),
+            ]
+            .iter()
+            .map(|(name, modified)| {
+                ScanEntry::File(FileInfo {
+                    size: 10,
+                    ..file(&format!("/data/{name}"), *modified)
+                })
+            })
+            .collect(),
+            total_size: 40,
+            total_files: 4,
+            ..Default::default()
+        };
+
+        let plan = plan(scanned, &watch, 100, &OldestFirst);
+
+        assert_eq!(plan.reason, None);
+        assert!(plan.files.iter().all(|file| file.reason == Reason::Quota));
+        assert_eq!(names(plan.files), ["/data/logs/a"]);
+    }
+
+    #[test]
+    fn plan_cleans_files_over_their_budget() {
+        let mut watch: WatchConfig = toml::from_str(
+            "directory = \"/data\"\ninterval_seconds = 1\nmax_size_bytes = 100\n[budgets]\n\"*.log\" = 25\n\"*.core\" = 50",
+        )
+        .unwrap();
+        watch.compile().unwrap();
+
+        let scanned = ScanResult {
+            entries: [
+                ("app.log", 100),
+                ("nested/worker.log", 300),
+                ("old.log", 50),
+                ("app.core", 10),
+                ("video.mp4", 20),
+            ]
+            .iter()
+            .map(|(name, modified)| {
+                ScanEntry::File(FileInfo {
+                    size: 10,
+                    ..file(&format!("/data/{name}"), *modified)
+                })
+            })
+            .collect(),
+            total_size: 50,
+            total_files: 5,
+            ..Default::default()
+        };
+
+        let plan = plan(scanned, &watch, 100, &OldestFirst);
+
+        assert_eq!(plan.reason, None);
+        assert!(plan.files.iter().all(|file| file.reason == Reason::Budget));
+        assert_eq!(names(plan.files), ["/data/old.log"]);
+    }
+
+    #[test]
+    fn size_limit_follows_the_filesystem_capacity() {
+        let directory = std::env::temp_dir();
+        let watch: WatchConfig = toml::from_str(&format!(
+            "directory = {:?}\ninterval_seconds = 1\nmax_size_percent = 20",
+            directory.display().to_string()
+        ))
+        .unwrap();
+        let total = disk::usage(&directory).unwrap().total;
+
+        assert_eq!(size_limit(&watch, 0).unwrap(), (total as f64 * 0.2) as u64);
+        assert_eq!(
+            size_limit(
+                &WatchConfig {
+                    max_size_bytes: Some(100),
+                    ..watch.clone()
+                },
+                0
+            )
+            .unwrap(),
+            100
+        );
+
+        // A size limit still, not a lack of free space
+        let scanned = ScanResult {
+            entries: vec![ScanEntry::File(file("/data/a", 100))],
+            total_size: 1,
+            total_files: 1,
+            ..Default::default()
+        };
+        assert_eq!(
+            plan(scanned, &watch, 0, &OldestFirst).reason,
+            Some(Reason::SizeLimit)
+        );
+    }
+
+    #[test]
+    fn plan_removes_whole_subdirectories_as_old_as_their_newest_file() {
+        let watch = |extra: &str| -> WatchConfig {
+            toml::from_str(&format!(
+                "directory = \"/data\"\ninterval_seconds = 1\nmax_size_bytes = 3\nmargin = 100\neviction_unit = \"subdirectory\"\n{extra}"
+            ))
+            .unwrap()
+        };
+        let scanned = || ScanResult {
+            entries: [
+                ScanEntry::Folder(FolderInfo {
+                    path: PathBuf::from("/data/s1"),
+                    files: 2,
+                }),
+                ScanEntry::Folder(FolderInfo {
+                    path: PathBuf::from("/data/s3"),
+                    files: 1,
+                }),
+                ScanEntry::Folder(FolderInfo {
+                    path: PathBuf::from("/data/s3/d"),
+                    files: 1,
+                }),
+            ]
+            .into_iter()
+            .chain(
+                [
+                    ("/data/s1/a", 100),
+                    ("/data/s1/b", 300),
+                    ("/data/s2/c", 200),
+                    ("/data/s3/d/e", 150),
+                    ("/data/f", 50),
+                ]
+                .iter()
+                .map(|(name, modified)| ScanEntry::File(file(name, *modified))),
+            )
+            .collect(),
+            total_size: 5,
+            total_files: 5,
+            ..Default::default()
+        };
+
+        let plan = plan(scanned(), &watch(""), 3, &OldestFirst);
+
+        assert_eq!(names(plan.files), ["/data/f", "/data/s3/d/e"]);
+        assert_eq!(
+            plan.dirs,
+            [PathBuf::from("/data/s3/d"), PathBuf::from("/data/s3")]
+        );
+
+        // s1 is left whole for the next cycle rather than split by the cap
+        let plan = super::plan(
+            scanned(),
+            &watch("max_deletions_per_cycle = 4"),
+            1,
+            &OldestFirst,
+        );
+
+        assert_eq!(names(plan.files), ["/data/f", "/data/s3/d/e", "/data/s2/c"]);
+    }
+
+    #[test]
+    fn plan_removes_sidecars_with_their_file() {
+        let watch: WatchConfig = toml::from_str(
+            "directory = \"/data\"\ninterval_seconds = 1\nmax_size_bytes = 3\nmargin = 100\nkeep_newest = 1\nsidecars = [\".srt\", \"*.json\"]",
+        )
+        .unwrap();
+
+        let scanned = || ScanResult {
+            entries: [
+                ("/data/video.mp4", 100),
+                ("/data/video.srt", 120),
+                ("/data/video.mp4.json", 100),
+                ("/data/other.mp4", 110),
+                ("/data/orphan.srt", 50),
+                ("/data/kept.mp4", 500),
+                ("/data/kept.srt", 10),
+            ]
+            .iter()
+            .map(|(name, modified)| ScanEntry::File(file(name, *modified)))
+            .collect(),
+            total_size: 7,
+            total_files: 7,
+            ..Default::default()
+        };
+
+        let plan = super::plan(scanned(), &watch, 3, &OldestFirst);
+
+        // The video goes last, as recent as its subtitles
+        assert_eq!(
+            names(plan.files),
+            [
+                "/data/orphan.srt",
+                "/data/other.mp4",
+                "/data/video.mp4",
+                "/data/video.srt",
+                "/data/video.mp4.json"
+            ]
+        );
+
+        // Sidecars need not be included to go with their file
+        let mut watch = WatchConfig {
+            include_patterns: vec!["*.mp4".to_string()],
+            ..watch
+        };
+        watch.compile().unwrap();
+        let plan = super::plan(scanned(), &watch, 3, &OldestFirst);
+
+        assert_eq!(
+            names(plan.files),
+            [
+                "/data/other.mp4",
+                "/data/video.mp4",
+                "/data/video.srt",
+                "/data/video.mp4.json"
+            ]
+        );
+    }
+}
@@ -0,0 +1,138 @@
+//! Yielding the CPU and the disk to the applications next to the cleaner,
+//! for `NICE`, `IO_CLASS` and `IO_PRIORITY` set at startup, and for
+//! `SCAN_IO_IDLE` lowering the IO class of the scan alone.
+
+use crate::config::IoClass;
+
+/// Sets the niceness of the calling thread, and of every thread it starts
+/// from then on.
+#[cfg(unix)]
+pub fn set_nice(nice: i32) -> std::io::Result<()> {
+    // SAFETY: no pointers are involved. `which` is unsigned on some targets
+    #[allow(clippy::unnecessary_cast)]
+    let status = unsafe { libc::setpriority(libc::PRIO_PROCESS as _, 0, nice) };
+
+    match status {
+        0 => Ok(()),
+        _ => Err(std::io::Error::last_os_error()),
+    }
+}
+
+/// Refused by the configuration.
+#[cfg(not(unix))]
+pub fn set_nice(_nice: i32) -> std::io::Result<()> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "niceness is not supported on this platform",
+    ))
+}
+
+/// Sets the IO class and priority of the calling thread, inherited as with
+/// [`set_nice`].
+pub fn set_io_priority(class: IoClass, priority: u8) -> std::io::Result<()> {
+    imp::set_io_priority(imp::ioprio(class, priority))
+}
+
+/// Runs `scan` with the idle IO class on every thread of the current rayon
+/// pool, which the scan descends on, restoring their priority afterwards.
+pub fn idle_io<T>(scan: impl FnOnce() -> T) -> T {
+    let previous: Vec<Option<i32>> = rayon::broadcast(|_| {
+        let previous = imp::io_priority().ok()?;
+        imp::set_io_priority(imp::ioprio(IoClass::Idle, 0))
+            .ok()
+            .map(|()| previous)
+    });
+
+    let scanned = scan();
+
+    rayon::broadcast(|context| {
+        if let Some(previous) = previous[context.index()] {
+            let _ = imp::set_io_priority(previous);
+        }
+    });
+
+    scanned
+}
+
+#[cfg(target_os = "linux")]
+mod imp {
+    use crate::config::IoClass;
+
+    /// `IOPRIO_WHO_PROCESS`, which for 0 is the calling thread.
+    const WHO_PROCESS: libc::c_int = 1;
+    const CLASS_SHIFT: i32 = 13;
+
+    pub fn ioprio(class: IoClass, priority: u8) -> i32 {
+        let class = match class {
+            IoClass::Realtime => 1,
+            IoClass::BestEffort => 2,
+            IoClass::Idle => 3,
+        };
+
+        (class << CLASS_SHIFT) | i32::from(priority.min(7))
+    }
+
+    pub fn io_priority() -> std::io::Result<i32> {
+        // SAFETY: ioprio_get takes two integers
+        match unsafe { libc::syscall(libc::SYS_ioprio_get, WHO_PROCESS, 0) } {
+            -1 => Err(std::io::Error::last_os_error()),
+            ioprio => Ok(ioprio as i32),
+        }
+    }
+
+    pub fn set_io_priority(ioprio: i32) -> std::io::Result<()> {
+        // SAFETY: ioprio_set takes three integers
+        match unsafe { libc::syscall(libc::SYS_ioprio_set, WHO_PROCESS, 0, ioprio) } {
+            0 => Ok(()),
+            _ => Err(std::io::Error::last_os_error()),
+        }
+    }
+}
+
+/// IO priorities are refused by the configuration elsewhere.
+#[cfg(not(target_os = "linux"))]
+mod imp {
+    use crate::config::IoClass;
+
+    pub fn ioprio(_class: IoClass, _priority: u8) -> i32 {
+        0
+    }
+
+    pub fn io_priority() -> std::io::Result<i32> {
+        Err(unsupported())
+    }
+
+    pub fn set_io_priority(_ioprio: i32) -> std::io::Result<()> {
+        Err(unsupported())
+    }
+
+    fn unsupported() -> std::io::Error {
+        std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            "IO priorities are only supported on Linux",
+        )
+    }
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scans_run_idle_and_restore_the_priority() {
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(2)
+            .build()
+            .unwrap();
+
+        pool.install(|| {
+            let before = imp::io_priority().unwrap();
+
+            let during = idle_io(|| imp::io_priority().unwrap());
+            assert_eq!(during, imp::ioprio(IoClass::Idle, 0));
+            assert_eq!(imp::io_priority().unwrap(), before);
+        });
+
+        assert_eq!(imp::ioprio(IoClass::BestEffort, 9), (2 << 13) | 7);
+    }
+}
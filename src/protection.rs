@@ -0,0 +1,173 @@
+//! Protection files placed by the owners of the data, for `IGNORE_FILES`:
+//! a `.maxdirsizeignore` lists gitignore style patterns of what is never
+//! removed below its directory, an empty `.keep` protects its whole
+//! directory.
+
+use colored::*;
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use ignore::Match;
+use log::warn;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+pub const IGNORE_FILE: &str = ".maxdirsizeignore";
+pub const KEEP_FILE: &str = ".keep";
+
+/// What protects the entries of a directory, its own files and those of the
+/// directories above.
+#[derive(Default)]
+struct Rules {
+    /// A `.keep` was found here or above
+    keep: bool,
+    /// The `.maxdirsizeignore` files from here up, innermost first
+    ignores: Vec<Arc<Gitignore>>,
+}
+
+/// The rules of every directory listed during a scan, read once each.
+pub struct Protection {
+    root: PathBuf,
+    rules: Mutex<HashMap<PathBuf, Arc<Rules>>>,
+}
+
+impl Protection {
+    pub fn new(root: &Path) -> Protection {
+        Protection {
+            root: root.to_path_buf(),
+            rules: Mutex::default(),
+        }
+    }
+
+    /// Whether `path` may never be removed. The protection files themselves
+    /// are always protected.
+    pub fn protects(&self, path: &Path, is_dir: bool) -> bool {
+        if !is_dir
+            && path
+                .file_name()
+                .is_some_and(|name| name == IGNORE_FILE || name == KEEP_FILE)
+        {
+            return true;
+        }
+
+        let Some(parent) = path.parent() else {
+            return false;
+        };
+
+        let rules = self.rules(parent);
+
+        if rules.keep {
+            return true;
+        }
+
+        // The innermost file that has a say decides, as with git
+        for ignore in &rules.ignores {
+            match ignore.matched_path_or_any_parents(path, is_dir) {
+                Match::Ignore(_) => return true,
+                Match::Whitelist(_) => return false,
+                Match::None => {}
+            }
+        }
+
+        false
+    }
+
+    fn rules(&self, directory: &Path) -> Arc<Rules> {
+        if let Some(rules) = self.rules.lock().unwrap().get(directory) {
+            return rules.clone();
+        }
+
+        let above = match directory.parent() {
+            Some(parent) if directory != self.root && directory.starts_with(&self.root) => {
+                self.rules(parent)
+            }
+            _ => Arc::default(),
+        };
+
+        let mut rules = Rules {
+            keep: above.keep || is_keep_file(&directory.join(KEEP_FILE)),
+            ignores: Vec::new(),
+        };
+
+        if !rules.keep {
+            match read_ignore_file(directory) {
+                Ok(Some(ignore)) => rules.ignores.push(Arc::new(ignore)),
+                Ok(None) => {}
+                Err(e) => {
+                    warn!(
+                        "{}",
+                        format!(
+                            "Error reading {}, protecting the whole directory: {e}",
+                            directory.join(IGNORE_FILE).display()
+                        )
+                        .yellow()
+                    );
+                    rules.keep = true;
+                }
+            }
+
+            rules.ignores.extend(above.ignores.iter().cloned());
+        }
+
+        let rules = Arc::new(rules);
+
+        self.rules
+            .lock()
+            .unwrap()
+            .insert(directory.to_path_buf(), rules.clone());
+
+        rules
+    }
+}
+
+/// An empty `.keep`, other `.keep` files being left to whatever made them.
+fn is_keep_file(path: &Path) -> bool {
+    std::fs::metadata(path).is_ok_and(|metadata| metadata.is_file() && metadata.len() == 0)
+}
+
+fn read_ignore_file(directory: &Path) -> Result<Option<Gitignore>, ignore::Error> {
+    let path = directory.join(IGNORE_FILE);
+
+    if !path.is_file() {
+        return Ok(None);
+    }
+
+    let mut builder = GitignoreBuilder::new(directory);
+
+    if let Some(e) = builder.add(&path) {
+        return Err(e);
+    }
+
+    builder.build().map(Some)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::APP_NAME;
+    use std::fs;
+
+    #[test]
+    fn ignore_and_keep_files_protect_what_is_below_them() {
+        let root =
+            std::env::temp_dir().join(format!("{APP_NAME}-protection-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("sessions/raw")).unwrap();
+        fs::create_dir_all(root.join("exports")).unwrap();
+        fs::write(root.join(IGNORE_FILE), "*.mp4\nraw/\n").unwrap();
+        fs::write(root.join("sessions").join(IGNORE_FILE), "!final.mp4\n").unwrap();
+        fs::write(root.join("exports").join(KEEP_FILE), "").unwrap();
+
+        let protection = Protection::new(&root);
+
+        assert!(protection.protects(&root.join(IGNORE_FILE), false));
+        assert!(protection.protects(&root.join("a.mp4"), false));
+        assert!(!protection.protects(&root.join("a.log"), false));
+        assert!(protection.protects(&root.join("sessions/take.mp4"), false));
+        assert!(!protection.protects(&root.join("sessions/final.mp4"), false));
+        assert!(protection.protects(&root.join("sessions/raw"), true));
+        assert!(protection.protects(&root.join("sessions/raw/a.wav"), false));
+        assert!(protection.protects(&root.join("exports/report.pdf"), false));
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
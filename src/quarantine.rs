@@ -0,0 +1,278 @@
+//! Holding evicted files back before they are gone for good, for
+//! `DELETE_MODE=quarantine`. Each file is moved to `<id>/<file name>` below
+//! the quarantine directory, next to an `<id>.json` record of where it came
+//! from, until it is purged after `QUARANTINE_RETENTION`, or earlier past
+//! `QUARANTINE_MAX_SIZE`, or put back by `maxdirsize restore`.
+
+use colored::*;
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::archive;
+
+/// The record of a quarantined file.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Origin {
+    /// Where the file was, resolved
+    pub path: PathBuf,
+    /// When it was quarantined, in seconds since the epoch
+    pub quarantined: u64,
+    pub size: u64,
+}
+
+/// A file held in a quarantine directory.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    /// The `<id>` directory holding the file
+    pub dir: PathBuf,
+    pub origin: Origin,
+}
+
+impl Entry {
+    /// The quarantined file itself.
+    pub fn file(&self) -> PathBuf {
+        self.dir
+            .join(self.origin.path.file_name().unwrap_or_default())
+    }
+
+    fn record(&self) -> PathBuf {
+        self.dir.with_extension("json")
+    }
+
+    /// Removes the file and its record.
+    fn purge(&self) -> io::Result<()> {
+        match fs::remove_dir_all(&self.dir) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+            _ => {}
+        }
+
+        fs::remove_file(self.record())
+    }
+}
+
+/// What a purge removed.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Purged {
+    pub files: u64,
+    pub bytes: u64,
+}
+
+/// Moves `path`, already resolved, below `quarantine_dir`. The record is
+/// written first, so an interruption leaves at worst a record without a
+/// file, which the next purge clears.
+pub fn quarantine(path: &Path, quarantine_dir: &Path) -> io::Result<()> {
+    static ENTRIES: AtomicU64 = AtomicU64::new(0);
+
+    let (Some(parent), Some(_)) = (path.parent(), path.file_name()) else {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{} has no parent directory", path.display()),
+        ));
+    };
+
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    let id = format!(
+        "{}-{:09}-{}-{}",
+        now.as_secs(),
+        now.subsec_nanos(),
+        std::process::id(),
+        ENTRIES.fetch_add(1, Ordering::Relaxed)
+    );
+
+    let entry = Entry {
+        dir: quarantine_dir.join(id),
+        origin: Origin {
+            path: path.to_path_buf(),
+            quarantined: now.as_secs(),
+            size: fs::symlink_metadata(path)?.len(),
+        },
+    };
+
+    fs::create_dir_all(&entry.dir)?;
+    fs::write(entry.record(), serde_json::to_vec_pretty(&entry.origin)?)?;
+
+    archive::move_file(path, parent, &entry.dir)
+        .map(|_| ())
+        .inspect_err(|_| {
+            let _ = entry.purge();
+        })
+}
+
+/// Every entry of `quarantine_dir`, oldest first. Records that cannot be
+/// read are left out, and so never purged nor restored.
+pub fn entries(quarantine_dir: &Path) -> io::Result<Vec<Entry>> {
+    let listing = match fs::read_dir(quarantine_dir) {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        listing => listing?,
+    };
+
+    let mut entries = Vec::new();
+
+    for item in listing {
+        let record = item?.path();
+
+        if record
+            .extension()
+            .is_none_or(|extension| extension != "json")
+        {
+            continue;
+        }
+
+        let origin = match fs::read(&record)
+            .map_err(|e| e.to_string())
+            .and_then(|data| serde_json::from_slice(&data).map_err(|e| e.to_string()))
+        {
+            Ok(origin) => origin,
+            Err(e) => {
+                warn!(
+                    "{}",
+                    format!("Skipping quarantine record {}: {e}", record.display()).yellow()
+                );
+                continue;
+            }
+        };
+
+        entries.push(Entry {
+            dir: record.with_extension(""),
+            origin,
+        });
+    }
+
+    entries.sort_by(|a, b| (a.origin.quarantined, &a.dir).cmp(&(b.origin.quarantined, &b.dir)));
+
+    Ok(entries)
+}
+
+/// Purges the entries of `quarantine_dir` quarantined from below
+/// `directory` once older than `retention` seconds, and the oldest of the
+/// others while they take more than `max_size`.
+pub fn purge(
+    quarantine_dir: &Path,
+    directory: &Path,
+    retention: u64,
+    max_size: Option<u64>,
+) -> io::Result<Purged> {
+    let entries: Vec<Entry> = entries(quarantine_dir)?
+        .into_iter()
+        .filter(|entry| entry.origin.path.starts_with(directory))
+        .collect();
+
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let mut held: u64 = entries.iter().map(|entry| entry.origin.size).sum();
+    let mut purged = Purged::default();
+
+    for entry in &entries {
+        let expired = now.saturating_sub(entry.origin.quarantined) >= retention;
+
+        if !expired && max_size.is_none_or(|max_size| held <= max_size) {
+            continue;
+        }
+
+        entry.purge()?;
+        held -= entry.origin.size;
+        purged.files += 1;
+        purged.bytes += entry.origin.size;
+    }
+
+    Ok(purged)
+}
+
+/// Moves the file of `entry` back to where it was, unless something took
+/// its place meanwhile.
+pub fn restore(entry: &Entry) -> io::Result<()> {
+    let target = &entry.origin.path;
+
+    if fs::symlink_metadata(target).is_ok() {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("{} already exists", target.display()),
+        ));
+    }
+
+    let Some(parent) = target.parent() else {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{} has no parent directory", target.display()),
+        ));
+    };
+
+    archive::move_file(&entry.file(), &entry.dir, parent)?;
+
+    entry.purge()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::APP_NAME;
+
+    #[test]
+    fn files_are_quarantined_restored_and_purged() {
+        let root =
+            std::env::temp_dir().join(format!("{APP_NAME}-quarantine-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        let directory = root.join("watched");
+        let quarantine_dir = root.join("quarantine");
+        fs::create_dir_all(directory.join("logs")).unwrap();
+
+        for name in ["logs/a.log", "logs/b.log", "c.log"] {
+            fs::write(directory.join(name), name).unwrap();
+            quarantine(&directory.join(name), &quarantine_dir).unwrap();
+            assert!(!directory.join(name).exists());
+        }
+
+        let held = entries(&quarantine_dir).unwrap();
+        assert_eq!(held.len(), 3);
+        assert_eq!(held[0].origin.path, directory.join("logs/a.log"));
+        assert_eq!(fs::read(held[0].file()).unwrap(), b"logs/a.log");
+
+        restore(&held[0]).unwrap();
+        assert_eq!(
+            fs::read(directory.join("logs/a.log")).unwrap(),
+            b"logs/a.log"
+        );
+        assert_eq!(entries(&quarantine_dir).unwrap().len(), 2);
+
+        fs::write(directory.join("c.log"), "new").unwrap();
+        assert_eq!(
+            restore(&held[2]).unwrap_err().kind(),
+            io::ErrorKind::AlreadyExists
+        );
+
+        // Within the retention and the size, or from another directory
+        let nothing = purge(&quarantine_dir, &directory, 3600, Some(1024)).unwrap();
+        assert_eq!(nothing, Purged::default());
+        let other = purge(&quarantine_dir, &root.join("other"), 0, None).unwrap();
+        assert_eq!(other, Purged::default());
+
+        // The oldest goes first once over the size
+        let oldest = purge(&quarantine_dir, &directory, 3600, Some(5)).unwrap();
+        assert_eq!(
+            oldest,
+            Purged {
+                files: 1,
+                bytes: 10
+            }
+        );
+        assert_eq!(
+            entries(&quarantine_dir).unwrap()[0].origin.path,
+            directory.join("c.log")
+        );
+
+        let expired = purge(&quarantine_dir, &directory, 0, None).unwrap();
+        assert_eq!(expired, Purged { files: 1, bytes: 5 });
+        assert_eq!(fs::read_dir(&quarantine_dir).unwrap().count(), 0);
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,171 @@
+//! Reloading the configuration on SIGHUP or when the config file changes.
+
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+use serde_json::Value;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::Sender;
+
+use maxdirsize::Config;
+
+use crate::signals::Wake;
+
+/// Settings read once at startup, a change only applies after a restart.
+pub const RESTART_REQUIRED: [&str; 20] = [
+    "once",
+    "metrics_addr",
+    "statsd_addr",
+    "statsd_format",
+    "status_file",
+    "health_addr",
+    "control_addr",
+    "control_socket",
+    "log_format",
+    "log_target",
+    "log_file",
+    "events",
+    "daemon",
+    "pid_file",
+    "shutdown_timeout_seconds",
+    "lock_dir",
+    "lock_wait",
+    "nice",
+    "io_class",
+    "io_priority",
+];
+
+/// Wakes the scheduler with [`Wake::Reload`] whenever `path` is written,
+/// replaced or created. Its parent directory is watched since editors
+/// usually replace the file rather than write it in place.
+pub fn watch(path: &Path, waker: Sender<Wake>) -> notify::Result<RecommendedWatcher> {
+    let path = std::path::absolute(path)?;
+    let parent = path.parent().map(Path::to_path_buf).unwrap_or_default();
+
+    let mut watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+        let Ok(event) = event else {
+            return;
+        };
+
+        if (event.kind.is_modify() || event.kind.is_create()) && event.paths.contains(&path) {
+            let _ = waker.send(Wake::Reload);
+        }
+    })?;
+
+    watcher.watch(&parent, RecursiveMode::NonRecursive)?;
+
+    Ok(watcher)
+}
+
+/// Describes every setting that differs between `old` and `new`, one line
+/// each.
+pub fn changes(old: &Config, new: &Config) -> Vec<String> {
+    let (Ok(Value::Object(mut old)), Ok(Value::Object(mut new))) =
+        (serde_json::to_value(old), serde_json::to_value(new))
+    else {
+        return Vec::new();
+    };
+
+    let mut changes = Vec::new();
+
+    let old_dirs = directories(old.remove("directories"));
+    let new_dirs = directories(new.remove("directories"));
+
+    diff("", &old, &new, &mut changes);
+
+    for (directory, old) in &old_dirs {
+        match new_dirs.iter().find(|(new, _)| new == directory) {
+            Some((_, new)) => diff(
+                &format!("{}: ", directory.display()),
+                old,
+                new,
+                &mut changes,
+            ),
+            None => changes.push(format!("{}: no longer watched", directory.display())),
+        }
+    }
+
+    for (directory, _) in &new_dirs {
+        if !old_dirs.iter().any(|(old, _)| old == directory) {
+            changes.push(format!("{}: now watched", directory.display()));
+        }
+    }
+
+    changes
+}
+
+type Settings = serde_json::Map<String, Value>;
+
+fn directories(directories: Option<Value>) -> Vec<(PathBuf, Settings)> {
+    let Some(Value::Array(directories)) = directories else {
+        return Vec::new();
+    };
+
+    directories
+        .into_iter()
+        .filter_map(|directory| match directory {
+            Value::Object(settings) => Some((
+                PathBuf::from(settings.get("directory")?.as_str()?),
+                settings,
+            )),
+            _ => None,
+        })
+        .collect()
+}
+
+fn diff(prefix: &str, old: &Settings, new: &Settings, changes: &mut Vec<String>) {
+    for (key, value) in new {
+        let previous = old.get(key).unwrap_or(&Value::Null);
+
+        if previous != value {
+            changes.push(format!("{prefix}{key} changed from {previous} to {value}"));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(toml: &str) -> Config {
+        toml::from_str(toml).unwrap()
+    }
+
+    #[test]
+    fn changes_lists_changed_added_and_removed_settings() {
+        let old = config(
+            r#"
+            [[directories]]
+            directory = "/a"
+            interval_seconds = 60
+            max_size_bytes = 100
+
+            [[directories]]
+            directory = "/b"
+            interval_seconds = 60
+            "#,
+        );
+        let new = config(
+            r#"
+            scan_threads = 2
+
+            [[directories]]
+            directory = "/a"
+            interval_seconds = 60
+            max_size_bytes = 200
+
+            [[directories]]
+            directory = "/c"
+            interval_seconds = 60
+            "#,
+        );
+
+        assert_eq!(
+            changes(&old, &new),
+            [
+                "scan_threads changed from 0 to 2",
+                "/a: max_size_bytes changed from 100 to 200",
+                "/b: no longer watched",
+                "/c: now watched",
+            ]
+        );
+    }
+}
@@ -0,0 +1,200 @@
+//! What takes up the space of a watched directory: its largest files and
+//! immediate subdirectories, like a focused `du`.
+
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use crate::scan::{ScanEntry, ScanResult};
+use crate::units;
+
+/// A file or immediate subdirectory and its size.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Usage {
+    pub path: PathBuf,
+    pub size: u64,
+    /// 1 for a file, the files below it for a subdirectory
+    pub files: u64,
+}
+
+/// The largest entries of a directory, biggest first.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UsageReport {
+    pub directory: PathBuf,
+    pub total_size: u64,
+    pub total_files: u64,
+    pub files: Vec<Usage>,
+    pub subdirectories: Vec<Usage>,
+}
+
+impl UsageReport {
+    /// Keeps the `top` largest files and immediate subdirectories of
+    /// `directory` out of a scan of it. Files with several hard links are
+    /// counted once, in the subdirectory they were found in first.
+    pub fn new(directory: &Path, scanned: &ScanResult, top: usize) -> UsageReport {
+        let mut files = Vec::new();
+        let mut subdirectories: BTreeMap<PathBuf, Usage> = BTreeMap::new();
+        let mut hard_links = HashSet::new();
+
+        for entry in &scanned.entries {
+            let ScanEntry::File(file) = entry else {
+                continue;
+            };
+
+            if let Some(link) = &file.hard_link {
+                if !hard_links.insert((link.device, link.inode)) {
+                    continue;
+                }
+            }
+
+            files.push(Usage {
+                path: file.path.clone(),
+                size: file.size,
+                files: 1,
+            });
+
+            let subdirectory = file
+                .path
+                .strip_prefix(directory)
+                .ok()
+                .filter(|relative| relative.components().count() > 1)
+                .and_then(|relative| relative.components().next());
+
+            if let Some(subdirectory) = subdirectory {
+                let path = directory.join(subdirectory);
+                let usage = subdirectories.entry(path.clone()).or_insert_with(|| Usage {
+                    path,
+                    size: 0,
+                    files: 0,
+                });
+                usage.size += file.size;
+                usage.files += 1;
+            }
+        }
+
+        UsageReport {
+            directory: directory.to_path_buf(),
+            total_size: scanned.total_size,
+            total_files: scanned.total_files,
+            files: largest(files, top),
+            subdirectories: largest(subdirectories.into_values().collect(), top),
+        }
+    }
+}
+
+/// The `top` biggest of `entries`, biggest first and by path on a tie.
+fn largest(mut entries: Vec<Usage>, top: usize) -> Vec<Usage> {
+    entries.sort_by(|a, b| b.size.cmp(&a.size).then_with(|| a.path.cmp(&b.path)));
+    entries.truncate(top);
+    entries
+}
+
+fn megabytes(bytes: u64) -> f64 {
+    bytes as f64 / units::MIB as f64
+}
+
+impl fmt::Display for UsageReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{}: {:.2} MB in {} files",
+            self.directory.display(),
+            megabytes(self.total_size),
+            self.total_files
+        )?;
+
+        if !self.subdirectories.is_empty() {
+            writeln!(f, "\nLargest subdirectories:")?;
+
+            for usage in &self.subdirectories {
+                writeln!(
+                    f,
+                    "{:>12.2} MB  {} ({} files)",
+                    megabytes(usage.size),
+                    usage.path.display(),
+                    usage.files
+                )?;
+            }
+        }
+
+        if !self.files.is_empty() {
+            writeln!(f, "\nLargest files:")?;
+
+            for usage in &self.files {
+                writeln!(
+                    f,
+                    "{:>12.2} MB  {}",
+                    megabytes(usage.size),
+                    usage.path.display()
+                )?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scan::FileInfo;
+
+    fn file(path: &str, size: u64) -> ScanEntry {
+        ScanEntry::File(FileInfo {
+            path: PathBuf::from(path),
+            size,
+            modified: 0,
+            accessed: 0,
+            hard_link: None,
+            inode: 0,
+        })
+    }
+
+    #[test]
+    fn sums_immediate_subdirectories() {
+        let scanned = ScanResult {
+            entries: vec![
+                file("/data/top.bin", 50),
+                file("/data/logs/a.log", 30),
+                file("/data/logs/old/b.log", 40),
+                file("/data/videos/c.mp4", 60),
+            ],
+            total_size: 180,
+            total_files: 4,
+            ..Default::default()
+        };
+
+        let report = UsageReport::new(Path::new("/data"), &scanned, 2);
+
+        assert_eq!(
+            report.files,
+            [
+                Usage {
+                    path: PathBuf::from("/data/videos/c.mp4"),
+                    size: 60,
+                    files: 1,
+                },
+                Usage {
+                    path: PathBuf::from("/data/top.bin"),
+                    size: 50,
+                    files: 1,
+                },
+            ]
+        );
+        assert_eq!(
+            report.subdirectories,
+            [
+                Usage {
+                    path: PathBuf::from("/data/logs"),
+                    size: 70,
+                    files: 2,
+                },
+                Usage {
+                    path: PathBuf::from("/data/videos"),
+                    size: 60,
+                    files: 1,
+                },
+            ]
+        );
+    }
+}
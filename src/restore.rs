@@ -0,0 +1,367 @@
+//! Putting evicted files back where they came from, for `maxdirsize
+//! restore`: quarantined files from their own records, archived and trashed
+//! ones from the removals of `AUDIT_LOG`.
+
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use crate::archive;
+use crate::config::WatchConfig;
+use crate::quarantine::{self, Entry};
+
+/// Where an evicted file went.
+#[derive(Debug, Clone)]
+pub enum Source {
+    Quarantine(Entry),
+    /// The file below `archive_dir`
+    Archive(PathBuf),
+    Trash(imp::TrashItem),
+}
+
+/// An evicted file that can still be put back.
+#[derive(Debug, Clone)]
+pub struct Restorable {
+    /// Where it was
+    pub path: PathBuf,
+    /// When it was evicted, in seconds since the epoch
+    pub removed: u64,
+    pub size: u64,
+    pub source: Source,
+}
+
+impl Restorable {
+    /// The `DELETE_MODE` that evicted the file.
+    pub fn mode(&self) -> &'static str {
+        match self.source {
+            Source::Quarantine(_) => "quarantine",
+            Source::Archive(_) => "move",
+            Source::Trash(_) => "trash",
+        }
+    }
+
+    /// Moves the file back, recreating its directories, unless something
+    /// took its place meanwhile.
+    pub fn restore(&self) -> io::Result<()> {
+        if fs::symlink_metadata(&self.path).is_ok() {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("{} already exists", self.path.display()),
+            ));
+        }
+
+        match &self.source {
+            Source::Quarantine(entry) => quarantine::restore(entry),
+            Source::Archive(archived) => archive::move_to(archived, &self.path),
+            Source::Trash(item) => imp::restore(item),
+        }
+    }
+}
+
+/// A file removal of an audit log.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Removal {
+    pub path: PathBuf,
+    /// In seconds since the epoch
+    pub removed: u64,
+    pub size: u64,
+    pub mode: String,
+    /// Where a file moved to `ARCHIVE_DIR` went, unset in lines written
+    /// before it was recorded
+    pub archived_to: Option<PathBuf>,
+}
+
+#[derive(Deserialize)]
+struct Line {
+    timestamp: String,
+    kind: String,
+    path: PathBuf,
+    size: u64,
+    mode: String,
+    #[serde(default)]
+    archived_to: Option<PathBuf>,
+}
+
+/// The file removals of `audit_log`, in the order they happened. Lines that
+/// cannot be read as one, such as one cut short by a crash, are skipped.
+pub fn journal(audit_log: &Path) -> io::Result<Vec<Removal>> {
+    let contents = match fs::read_to_string(audit_log) {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        contents => contents?,
+    };
+
+    Ok(contents
+        .lines()
+        .filter_map(|line| serde_json::from_str::<Line>(line).ok())
+        .filter(|line| line.kind == "file")
+        .filter_map(|line| {
+            Some(Removal {
+                removed: humantime::parse_rfc3339(&line.timestamp)
+                    .ok()?
+                    .duration_since(UNIX_EPOCH)
+                    .ok()?
+                    .as_secs(),
+                path: line.path,
+                size: line.size,
+                mode: line.mode,
+                archived_to: line.archived_to,
+            })
+        })
+        .collect())
+}
+
+/// Everything evicted from `watches` that can still be restored, oldest
+/// first: the quarantine of each, and what their audit logs record as moved
+/// to the archive, still there, or trashed, still in the trash.
+pub fn restorable(watches: &[WatchConfig]) -> io::Result<Vec<Restorable>> {
+    let mut found = Vec::new();
+    let mut quarantine_dirs: Vec<&Path> = Vec::new();
+    let mut journals: HashMap<&Path, Vec<Removal>> = HashMap::new();
+    // Trashed paths as the trash records them, resolved
+    let mut trashed: HashMap<PathBuf, u64> = HashMap::new();
+
+    for watch in watches {
+        if let Some(quarantine_dir) = watch.quarantine_dir.as_deref() {
+            if !quarantine_dirs.contains(&quarantine_dir) {
+                quarantine_dirs.push(quarantine_dir);
+
+                found.extend(
+                    quarantine::entries(quarantine_dir)?
+                        .into_iter()
+                        .map(|entry| Restorable {
+                            path: entry.origin.path.clone(),
+                            removed: entry.origin.quarantined,
+                            size: entry.origin.size,
+                            source: Source::Quarantine(entry),
+                        }),
+                );
+            }
+        }
+
+        let Some(audit_log) = watch.audit_log.as_deref() else {
+            continue;
+        };
+
+        if !journals.contains_key(audit_log) {
+            journals.insert(audit_log, journal(audit_log)?);
+        }
+
+        let directory = Path::new(&watch.directory);
+        let root = fs::canonicalize(directory).unwrap_or_else(|_| directory.to_path_buf());
+
+        for removal in &journals[audit_log] {
+            let Ok(relative) = removal.path.strip_prefix(directory) else {
+                continue;
+            };
+
+            match (removal.mode.as_str(), &watch.archive_dir) {
+                ("move", Some(archive_dir)) => {
+                    let archived = removal
+                        .archived_to
+                        .clone()
+                        .unwrap_or_else(|| archive_dir.join(relative));
+
+                    if fs::symlink_metadata(&archived).is_ok() {
+                        found.push(Restorable {
+                            path: removal.path.clone(),
+                            removed: removal.removed,
+                            size: removal.size,
+                            source: Source::Archive(archived),
+                        });
+                    }
+                }
+                ("trash", _) => {
+                    trashed.insert(root.join(relative), removal.size);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    if !trashed.is_empty() {
+        found.extend(imp::in_trash(&trashed)?);
+    }
+
+    found.sort_by_key(|restorable| restorable.removed);
+
+    Ok(found)
+}
+
+/// The latest eviction of every path of `restorable`, sorted oldest first,
+/// that `wanted` accepts, newest first.
+pub fn newest(restorable: &[Restorable], wanted: impl Fn(&Path) -> bool) -> Vec<&Restorable> {
+    let mut seen: Vec<&Path> = Vec::new();
+
+    restorable
+        .iter()
+        .rev()
+        .filter(|restorable| {
+            if seen.contains(&restorable.path.as_path()) {
+                return false;
+            }
+
+            seen.push(&restorable.path);
+            wanted(&restorable.path)
+        })
+        .collect()
+}
+
+/// The trash where it can be listed back: freedesktop and Windows.
+#[cfg(any(
+    windows,
+    all(
+        unix,
+        not(target_os = "macos"),
+        not(target_os = "ios"),
+        not(target_os = "android")
+    )
+))]
+mod imp {
+    use std::collections::HashMap;
+    use std::io;
+    use std::path::PathBuf;
+
+    use super::{Restorable, Source};
+
+    pub use trash::TrashItem;
+
+    /// The items of the trash that were evicted from one of `trashed`.
+    pub fn in_trash(trashed: &HashMap<PathBuf, u64>) -> io::Result<Vec<Restorable>> {
+        Ok(trash::os_limited::list()
+            .map_err(io::Error::other)?
+            .into_iter()
+            .filter_map(|item| {
+                let path = item.original_path();
+
+                Some(Restorable {
+                    size: *trashed.get(&path)?,
+                    path,
+                    removed: item.time_deleted.max(0) as u64,
+                    source: Source::Trash(item),
+                })
+            })
+            .collect())
+    }
+
+    pub fn restore(item: &TrashItem) -> io::Result<()> {
+        trash::os_limited::restore_all([item.clone()]).map_err(io::Error::other)
+    }
+}
+
+#[cfg(not(any(
+    windows,
+    all(
+        unix,
+        not(target_os = "macos"),
+        not(target_os = "ios"),
+        not(target_os = "android")
+    )
+)))]
+mod imp {
+    use std::collections::HashMap;
+    use std::io;
+    use std::path::PathBuf;
+
+    use super::Restorable;
+
+    /// Nothing is ever found in a trash that cannot be listed.
+    #[derive(Debug, Clone)]
+    pub enum TrashItem {}
+
+    pub fn in_trash(_trashed: &HashMap<PathBuf, u64>) -> io::Result<Vec<Restorable>> {
+        Ok(Vec::new())
+    }
+
+    pub fn restore(item: &TrashItem) -> io::Result<()> {
+        match *item {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::APP_NAME;
+
+    #[test]
+    fn archived_files_of_the_journal_are_restored() {
+        let root = std::env::temp_dir().join(format!("{APP_NAME}-restore-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        let directory = root.join("watched");
+        let archive_dir = root.join("archive");
+        let audit_log = root.join("audit.jsonl");
+        fs::create_dir_all(archive_dir.join("logs")).unwrap();
+        fs::create_dir_all(&directory).unwrap();
+
+        fs::write(archive_dir.join("logs/a.log"), "archived").unwrap();
+        // Archived again later, next to the first one
+        fs::write(archive_dir.join("logs/a.log.1759320000"), "newer").unwrap();
+        fs::write(
+            &audit_log,
+            [
+                ("2026-10-01T10:00:00.000Z", "logs/a.log", "move", None),
+                ("2026-10-01T11:00:00.000Z", "logs/gone.log", "move", None),
+                (
+                    "2026-10-01T12:00:00.000Z",
+                    "logs/a.log",
+                    "move",
+                    Some(archive_dir.join("logs/a.log.1759320000")),
+                ),
+                ("2026-10-01T12:00:00.000Z", "logs/b.log", "delete", None),
+            ]
+            .map(|(timestamp, path, mode, archived_to)| {
+                let mut line = serde_json::json!({
+                    "timestamp": timestamp,
+                    "kind": "file",
+                    "path": directory.join(path),
+                    "size": 8,
+                    "modified": timestamp,
+                    "reason": "size_limit",
+                    "mode": mode,
+                });
+                if let Some(archived_to) = archived_to {
+                    line["archived_to"] = archived_to.display().to_string().into();
+                }
+                line.to_string()
+            })
+            .join("\n")
+                + "\n{\"timestamp\": \"2026-10-01T13:00",
+        )
+        .unwrap();
+
+        assert_eq!(journal(&audit_log).unwrap().len(), 4);
+
+        let watch: WatchConfig = toml::from_str(&format!(
+            "directory = {:?}\ninterval_seconds = 1\nmax_size_bytes = 1024\ndelete_mode = \"move\"\narchive_dir = {:?}\naudit_log = {:?}",
+            directory.display().to_string(),
+            archive_dir.display().to_string(),
+            audit_log.display().to_string()
+        ))
+        .unwrap();
+
+        let found = restorable(&[watch]).unwrap();
+        assert_eq!(found.len(), 2);
+        assert_eq!(found[0].path, directory.join("logs/a.log"));
+        assert_eq!(found[0].mode(), "move");
+        assert_eq!(
+            newest(&found, |path| path.starts_with(&archive_dir)).len(),
+            0
+        );
+
+        // Its directory went with it
+        let latest = newest(&found, |path| path.starts_with(&directory));
+        assert_eq!(latest.len(), 1);
+        latest[0].restore().unwrap();
+        assert_eq!(fs::read(directory.join("logs/a.log")).unwrap(), b"newer");
+        assert!(!archive_dir.join("logs/a.log.1759320000").exists());
+        assert!(archive_dir.join("logs/a.log").exists());
+        assert_eq!(
+            found[0].restore().unwrap_err().kind(),
+            io::ErrorKind::AlreadyExists
+        );
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,767 @@
+//! Listing the contents of a watched directory.
+
+use colored::*;
+use globset::GlobSet;
+use log::{debug, error, warn};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fs::Metadata;
+use std::path::{Path, PathBuf};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use crate::candidates::Candidates;
+use crate::config::SizeAccounting;
+use crate::owners::Owners;
+use crate::pin;
+use crate::protection::Protection;
+
+/// A directory below the watched root that may be removed once empty.
+pub struct FolderInfo {
+    pub path: PathBuf,
+    /// Files below it, however deep
+    pub files: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FileInfo {
+    pub path: PathBuf,
+    /// Size in bytes, measured as configured by `size_accounting`
+    pub size: u64,
+    /// Modification time in seconds since the Unix epoch
+    pub modified: u64,
+    /// Last read or write in seconds since the Unix epoch, the modification
+    /// time when access times are unavailable or older
+    pub accessed: u64,
+    /// Set when the file has more than one hard link
+    pub hard_link: Option<HardLink>,
+    /// Inode number, 0 where there is none: another one at the same path
+    /// when the file is removed means it was replaced since the scan
+    #[serde(default)]
+    pub inode: u64,
+}
+
+/// Identifies a file with several hard links, whose size is only counted
+/// once and only freed with its last link.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HardLink {
+    pub device: u64,
+    pub inode: u64,
+    /// Number of links, including those outside the watched directory
+    pub links: u64,
+}
+
+pub enum ScanEntry {
+    Folder(FolderInfo),
+    File(FileInfo),
+}
+
+/// Everything found below a watched directory, excluded entries left out.
+/// Files with several hard links count towards `total_size` once. When
+/// scanning with [`ScanOptions::candidates`] the files are left out of
+/// `entries` but still counted.
+#[derive(Default)]
+pub struct ScanResult {
+    pub entries: Vec<ScanEntry>,
+    pub total_size: u64,
+    pub total_files: u64,
+    /// Entries that could not be read and were skipped
+    pub errors: u64,
+    /// Whether excluded entries were found, in which case neither this
+    /// directory nor its parents may be removed
+    pub protected: bool,
+    /// Files of `entries` that are dangling symlinks or empty, when
+    /// [`ScanOptions::clutter`] is set
+    pub clutter: HashSet<PathBuf>,
+}
+
+/// What to scan and which entries to leave out.
+pub struct ScanOptions<'a> {
+    pub root: &'a Path,
+    pub exclude: &'a GlobSet,
+    /// Directories relative to the root never descended into
+    pub exclude_dirs: &'a [PathBuf],
+    /// Only descend into directories on this device, see [`device`]
+    pub device: Option<u64>,
+    /// Count what symlinks point to instead of the links themselves
+    pub follow_symlinks: bool,
+    pub size_accounting: SizeAccounting,
+    /// Hands the files found to a bounded selection instead of listing them
+    pub candidates: Option<&'a Candidates<'a>>,
+    /// Only counts what protection files protect, see `IGNORE_FILES`
+    pub protection: Option<&'a Protection>,
+    /// Only counts the files pinned with [`crate::pin`]
+    pub pinned: bool,
+    /// Only counts the files these do not allow to remove
+    pub owners: Option<&'a Owners>,
+    /// Tells apart the dangling symlinks and empty files, see
+    /// [`ScanResult::clutter`]
+    pub clutter: bool,
+    /// Aborts the scan once passed, see `CYCLE_TIMEOUT`
+    pub deadline: Option<Instant>,
+    /// Levels below the root the scan descends to, 1 for the files directly
+    /// inside it
+    pub max_depth: Option<usize>,
+}
+
+impl ScanOptions<'_> {
+    /// Whether `path` is one of `exclude_dirs`.
+    pub fn is_excluded_dir(&self, path: &Path) -> bool {
+        !self.exclude_dirs.is_empty()
+            && path
+                .strip_prefix(self.root)
+                .is_ok_and(|relative| self.exclude_dirs.iter().any(|dir| dir == relative))
+    }
+
+    /// Whether `path`, a file or with `is_dir` a directory, holds files
+    /// deeper than `max_depth`.
+    pub fn beyond_max_depth(&self, path: &Path, is_dir: bool) -> bool {
+        self.max_depth.is_some_and(|max_depth| {
+            let depth = path
+                .strip_prefix(self.root)
+                .map_or(0, |relative| relative.components().count());
+
+            depth + usize::from(is_dir) > max_depth
+        })
+    }
+
+    /// Fails with [`std::io::ErrorKind::TimedOut`] once the deadline passed.
+    pub fn check_deadline(&self) -> std::io::Result<()> {
+        match self.deadline {
+            Some(deadline) if Instant::now() >= deadline => Err(std::io::Error::new(
+                std::io::ErrorKind::TimedOut,
+                "the scan exceeded CYCLE_TIMEOUT",
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    pub(crate) fn is_excluded(&self, path: &Path) -> bool {
+        !self.exclude.is_empty() && matches_relative(self.exclude, self.root, path)
+    }
+}
+
+/// Matches `path` relative to the watched `root` against `set`.
+pub fn matches_relative(set: &GlobSet, root: &Path, path: &Path) -> bool {
+    path.strip_prefix(root)
+        .is_ok_and(|relative| set.is_match(relative))
+}
+
+/// The direct contents of a single directory.
+#[derive(Clone, Default)]
+pub struct DirListing {
+    pub files: Vec<FileInfo>,
+    /// Subdirectories and whether they matched an exclude pattern or are
+    /// protected, either way never removed as a whole
+    pub subdirs: Vec<(PathBuf, bool)>,
+    pub errors: u64,
+    /// Whether an excluded or unreadable entry was found directly inside
+    pub protected: bool,
+    /// Files counted but not listed, as they may not be removed
+    pub counted_files: u64,
+    pub counted_size: u64,
+    /// Files that are dangling symlinks or empty
+    pub clutter: Vec<PathBuf>,
+}
+
+/// The device holding a file, `None` where it cannot be told.
+#[cfg(unix)]
+pub fn device(metadata: &Metadata) -> Option<u64> {
+    use std::os::unix::fs::MetadataExt;
+
+    Some(metadata.dev())
+}
+
+#[cfg(not(unix))]
+pub fn device(_metadata: &Metadata) -> Option<u64> {
+    None
+}
+
+/// Size of a file as measured by `accounting`.
+pub fn file_size(metadata: &Metadata, accounting: SizeAccounting) -> u64 {
+    match accounting {
+        SizeAccounting::Apparent => metadata.len(),
+        #[cfg(unix)]
+        SizeAccounting::Allocated => {
+            use std::os::unix::fs::MetadataExt;
+
+            // Always in 512 byte units, whatever the block size
+            metadata.blocks() * 512
+        }
+        #[cfg(not(unix))]
+        SizeAccounting::Allocated => metadata.len(),
+    }
+}
+
+/// Whether `file` was modified, resized or replaced by another file since it
+/// was scanned, reading it the way the scan did.
+pub fn changed_since_scan(
+    file: &FileInfo,
+    follow_symlinks: bool,
+    accounting: SizeAccounting,
+) -> std::io::Result<bool> {
+    let metadata = match follow_symlinks {
+        true => std::fs::metadata(&file.path).or_else(|_| std::fs::symlink_metadata(&file.path)),
+        false => std::fs::symlink_metadata(&file.path),
+    }?;
+
+    Ok(modified_seconds(&metadata) != file.modified
+        || file_size(&metadata, accounting) != file.size
+        || (file.inode != 0 && inode(&metadata) != file.inode))
+}
+
+#[cfg(unix)]
+fn hard_link(metadata: &Metadata) -> Option<HardLink> {
+    use std::os::unix::fs::MetadataExt;
+
+    (metadata.nlink() > 1).then(|| HardLink {
+        device: metadata.dev(),
+        inode: metadata.ino(),
+        links: metadata.nlink(),
+    })
+}
+
+#[cfg(not(unix))]
+fn hard_link(_metadata: &Metadata) -> Option<HardLink> {
+    None
+}
+
+#[cfg(unix)]
+fn inode(metadata: &Metadata) -> u64 {
+    std::os::unix::fs::MetadataExt::ino(metadata)
+}
+
+#[cfg(not(unix))]
+fn inode(_metadata: &Metadata) -> u64 {
+    0
+}
+
+fn seconds_since_epoch(time: SystemTime) -> Option<u64> {
+    time.duration_since(UNIX_EPOCH)
+        .ok()
+        .map(|since| since.as_secs())
+}
+
+/// The modification time, else the status change time (the creation time on
+/// Windows), else now: a file without a usable timestamp is treated as new
+/// rather than as the oldest one.
+fn modified_seconds(metadata: &Metadata) -> u64 {
+    metadata
+        .modified()
+        .ok()
+        .and_then(seconds_since_epoch)
+        .or_else(|| changed_seconds(metadata))
+        .or_else(|| seconds_since_epoch(SystemTime::now()))
+        .unwrap_or_default()
+}
+
+#[cfg(unix)]
+fn changed_seconds(metadata: &Metadata) -> Option<u64> {
+    use std::os::unix::fs::MetadataExt;
+
+    u64::try_from(metadata.ctime()).ok()
+}
+
+#[cfg(not(unix))]
+fn changed_seconds(metadata: &Metadata) -> Option<u64> {
+    metadata.created().ok().and_then(seconds_since_epoch)
+}
+
+/// Whether the directory `link` points to is scanned through another path:
+/// it is below `root` or holds the directory `link` is in, which would be
+/// an endless loop.
+fn is_scanned_elsewhere(link: &Path, root: &Path) -> bool {
+    let Ok(target) = std::fs::canonicalize(link) else {
+        return true;
+    };
+
+    if std::fs::canonicalize(root).is_ok_and(|root| target.starts_with(root)) {
+        return true;
+    }
+
+    link.ancestors()
+        .skip(1)
+        .take_while(|dir| dir.starts_with(root))
+        .any(|dir| std::fs::canonicalize(dir).is_ok_and(|dir| dir.starts_with(&target)))
+}
+
+/// A dangling symlink or an empty file. `metadata` is that of the link when
+/// symlinks are not followed or lead nowhere.
+fn is_clutter(path: &Path, metadata: &Metadata) -> bool {
+    match metadata.is_symlink() {
+        true => {
+            matches!(std::fs::metadata(path), Err(e) if e.kind() == std::io::ErrorKind::NotFound)
+        }
+        false => metadata.is_file() && metadata.len() == 0,
+    }
+}
+
+/// Lists `path` without descending into subdirectories. Only a failure to
+/// read `path` itself is an error; unreadable entries are logged, counted and
+/// skipped.
+pub fn list_dir(path: &Path, options: &ScanOptions) -> std::io::Result<DirListing> {
+    let mut listing = DirListing::default();
+
+    for entry in std::fs::read_dir(path)? {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(e) => {
+                error!(
+                    event = "error", path:% = path.display();
+                    "{}",
+                    format!("Error reading entry in {}, {e:?}", path.display()).red()
+                );
+                listing.errors += 1;
+                continue;
+            }
+        };
+        let path = entry.path();
+        let excluded = options.is_excluded(&path);
+
+        let metadata = match options.follow_symlinks {
+            // A dangling link is counted as the link itself
+            true => std::fs::metadata(&path).or_else(|_| std::fs::symlink_metadata(&path)),
+            false => std::fs::symlink_metadata(&path),
+        };
+
+        if let Ok(metadata) = metadata {
+            let protected = options
+                .protection
+                .is_some_and(|protection| protection.protects(&path, metadata.is_dir()));
+
+            if metadata.is_dir()
+                && options
+                    .device
+                    .is_some_and(|root| device(&metadata) != Some(root))
+            {
+                debug!(
+                    "{}",
+                    format!("Skipping mount point: {}", path.display()).yellow()
+                );
+                listing.protected = true;
+            } else if metadata.is_dir()
+                && entry.file_type().is_ok_and(|kind| kind.is_symlink())
+                && is_scanned_elsewhere(&path, options.root)
+            {
+                warn!(
+                    "{}",
+                    format!(
+                        "Not following symlink to a directory scanned anyway: {}",
+                        path.display()
+                    )
+                    .yellow()
+                );
+                listing.protected = true;
+            } else if metadata.is_dir() && options.is_excluded_dir(&path) {
+                debug!(
+                    "{}",
+                    format!("Skipping excluded directory: {}", path.display()).yellow()
+                );
+                listing.protected = true;
+            } else if metadata.is_dir() && options.beyond_max_depth(&path, true) {
+                debug!(
+                    "{}",
+                    format!("Not descending below MAX_DEPTH: {}", path.display()).yellow()
+                );
+                listing.protected = true;
+            } else if metadata.is_dir() {
+                listing.subdirs.push((path, excluded || protected));
+            } else if excluded {
+                debug!(
+                    "{}",
+                    format!("Skipping excluded file: {}", path.display()).yellow()
+                );
+                listing.protected = true;
+            } else if protected {
+                debug!(
+                    "{}",
+                    format!("Keeping protected file: {}", path.display()).yellow()
+                );
+                listing.counted_files += 1;
+                listing.counted_size += file_size(&metadata, options.size_accounting);
+                listing.protected = true;
+            } else if options.pinned && pin::is_pinned(&path) {
+                debug!(
+                    "{}",
+                    format!("Keeping pinned file: {}", path.display()).yellow()
+                );
+                listing.counted_files += 1;
+                listing.counted_size += file_size(&metadata, options.size_accounting);
+                listing.protected = true;
+            } else if options
+                .owners
+                .is_some_and(|owners| !owners.allows(&metadata))
+            {
+                debug!(
+                    "{}",
+                    format!("Keeping file of another owner: {}", path.display()).yellow()
+                );
+                listing.counted_files += 1;
+                listing.counted_size += file_size(&metadata, options.size_accounting);
+                listing.protected = true;
+            } else {
+                if options.clutter && is_clutter(&path, &metadata) {
+                    listing.clutter.push(path.clone());
+                }
+
+                let modified = modified_seconds(&metadata);
+                let accessed = metadata
+                    .accessed()
+                    .ok()
+                    .and_then(seconds_since_epoch)
+                    .map_or(modified, |accessed| accessed.max(modified));
+                listing.files.push(FileInfo {
+                    path,
+                    size: file_size(&metadata, options.size_accounting),
+                    modified,
+                    accessed,
+                    hard_link: hard_link(&metadata),
+                    inode: inode(&metadata),
+                });
+            }
+        } else {
+            error!(
+                event = "error", path:% = path.display();
+                "{}",
+                format!(
+                    "Error getting file metadata: {}, {metadata:?}",
+                    path.display()
+                )
+                .red()
+            );
+            listing.errors += 1;
+            listing.protected = true;
+        }
+    }
+
+    Ok(listing)
+}
+
+/// Recursively scans `path`, descending into subdirectories in parallel on
+/// the current rayon pool.
+pub fn read_dir(path: &Path, options: &ScanOptions) -> std::io::Result<ScanResult> {
+    let mut scanned = read_tree(path, options)?;
+    scanned.count_hard_links_once();
+
+    Ok(scanned)
+}
+
+fn read_tree(path: &Path, options: &ScanOptions) -> std::io::Result<ScanResult> {
+    options.check_deadline()?;
+    let mut listing = list_dir(path, options)?;
+
+    // Handed over before descending so only the directories being listed
+    // are held in memory
+    let (files, size) = match options.candidates {
+        Some(candidates) => candidates.offer(std::mem::take(&mut listing.files)),
+        None => (0, 0),
+    };
+
+    let scanned = listing
+        .subdirs
+        .par_iter()
+        .map(|(path, excluded)| (path.clone(), *excluded, read_tree(path, options)))
+        .collect();
+
+    // Rather than logging every subdirectory left unread
+    options.check_deadline()?;
+
+    let mut result = assemble(listing, scanned);
+    result.total_files += files;
+    result.total_size += size;
+
+    Ok(result)
+}
+
+impl ScanResult {
+    /// Removes every hard link but the first one of a file from
+    /// `total_size`, once the whole tree has been assembled.
+    pub fn count_hard_links_once(&mut self) {
+        let mut seen = std::collections::HashSet::new();
+
+        for entry in &self.entries {
+            if let ScanEntry::File(FileInfo {
+                size,
+                hard_link: Some(link),
+                ..
+            }) = entry
+            {
+                if !seen.insert((link.device, link.inode)) {
+                    self.total_size -= size;
+                }
+            }
+        }
+    }
+}
+
+/// Combines a directory's own files with the results of its subdirectories.
+pub fn assemble(
+    listing: DirListing,
+    scanned: Vec<(PathBuf, bool, std::io::Result<ScanResult>)>,
+) -> ScanResult {
+    let mut result = ScanResult {
+        total_size: listing.files.iter().map(|file| file.size).sum::<u64>() + listing.counted_size,
+        total_files: listing.files.len() as u64 + listing.counted_files,
+        entries: listing.files.into_iter().map(ScanEntry::File).collect(),
+        errors: listing.errors,
+        protected: listing.protected,
+        clutter: listing.clutter.into_iter().collect(),
+    };
+
+    for (path, excluded, items) in scanned {
+        match items {
+            Ok(mut items) => {
+                if excluded || items.protected {
+                    result.protected = true;
+                } else {
+                    result.entries.push(ScanEntry::Folder(FolderInfo {
+                        path,
+                        files: items.total_files,
+                    }));
+                }
+                result.entries.append(&mut items.entries);
+                result.clutter.extend(items.clutter);
+                result.total_size += items.total_size;
+                result.total_files += items.total_files;
+                result.errors += items.errors;
+            }
+            Err(e) => {
+                error!(
+                    event = "error", path:% = path.display();
+                    "{}",
+                    format!("Error reading directory, skipping: {}, {e:?}", path.display()).red()
+                );
+                result.errors += 1;
+                result.protected = true;
+            }
+        }
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::APP_NAME;
+    use std::time::Duration;
+
+    fn scan(root: &Path) -> ScanResult {
+        scan_with(root, |_| {})
+    }
+
+    /// Scans `root` with the defaults of [`scan`] changed by `configure`.
+    fn scan_with<'a>(root: &'a Path, configure: impl FnOnce(&mut ScanOptions<'a>)) -> ScanResult {
+        static EXCLUDE: std::sync::LazyLock<GlobSet> = std::sync::LazyLock::new(GlobSet::empty);
+
+        let mut options = ScanOptions {
+            root,
+            exclude: &EXCLUDE,
+            exclude_dirs: &[],
+            device: None,
+            follow_symlinks: false,
+            size_accounting: SizeAccounting::Apparent,
+            candidates: None,
+            protection: None,
+            pinned: false,
+            owners: None,
+            clutter: false,
+            deadline: None,
+            max_depth: None,
+        };
+        configure(&mut options);
+
+        read_dir(root, &options).unwrap()
+    }
+
+    #[test]
+    fn scans_stop_at_max_depth() {
+        let root = std::env::temp_dir().join(format!("{APP_NAME}-depth-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(root.join("app/cache")).unwrap();
+
+        for (name, size) in [("syslog", 1), ("app/app.log", 10), ("app/cache/blob", 100)] {
+            std::fs::write(root.join(name), vec![0; size]).unwrap();
+        }
+
+        for (max_depth, size) in [(Some(1), 1), (Some(2), 11), (Some(3), 111), (None, 111)] {
+            let scanned = scan_with(&root, |options| options.max_depth = max_depth);
+            assert_eq!(scanned.total_size, size, "{max_depth:?}");
+            // Nothing above a directory left unscanned is removed as empty
+            assert_eq!(scanned.protected, size < 111, "{max_depth:?}");
+        }
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn excluded_dirs_are_not_descended_into() {
+        let root =
+            std::env::temp_dir().join(format!("{APP_NAME}-exclude-dirs-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(root.join("app/data/deep")).unwrap();
+
+        for (name, size) in [("app/app.log", 10), ("app/data/deep/blob", 100)] {
+            std::fs::write(root.join(name), vec![0; size]).unwrap();
+        }
+
+        let mut watch: crate::WatchConfig = toml::from_str(&format!(
+            "directory = {:?}\ninterval_seconds = 1\nmax_size_bytes = 1\nexclude_dirs = [\"./app/data/\"]",
+            root.display().to_string()
+        ))
+        .unwrap();
+        watch.compile().unwrap();
+        assert_eq!(watch.exclude_dirs, [PathBuf::from("app/data")]);
+
+        let scanned = scan_with(&root, |options| options.exclude_dirs = &watch.exclude_dirs);
+
+        assert_eq!((scanned.total_files, scanned.total_size), (1, 10));
+        assert!(scanned.protected);
+
+        watch.exclude_dirs = vec![PathBuf::from("../elsewhere")];
+        assert!(watch.compile().is_err());
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn pinned_files_count_but_are_not_listed() {
+        let dir = std::env::temp_dir().join(format!("{APP_NAME}-pinned-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("pinned"), vec![0; 100]).unwrap();
+        std::fs::write(dir.join("other"), vec![0; 10]).unwrap();
+
+        // tmpfs only has user attributes on recent kernels
+        if pin::pin(&dir.join("pinned")).is_err() {
+            std::fs::remove_dir_all(&dir).unwrap();
+            return;
+        }
+
+        let scanned = scan_with(&dir, |options| options.pinned = true);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!((scanned.total_files, scanned.total_size), (2, 110));
+        assert!(scanned.protected);
+        assert!(matches!(
+            scanned.entries.as_slice(),
+            [ScanEntry::File(file)] if file.path.ends_with("other")
+        ));
+    }
+
+    #[test]
+    fn protected_files_count_but_are_not_listed() {
+        let root =
+            std::env::temp_dir().join(format!("{APP_NAME}-protected-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(root.join("exports")).unwrap();
+
+        std::fs::write(root.join(crate::protection::IGNORE_FILE), "*.mp4\n").unwrap();
+        std::fs::write(root.join("exports/.keep"), "").unwrap();
+        for (name, size) in [("a.mp4", 100), ("a.log", 10), ("exports/report.pdf", 1000)] {
+            std::fs::write(root.join(name), vec![0; size]).unwrap();
+        }
+
+        let protection = Protection::new(&root);
+        let scanned = scan_with(&root, |options| options.protection = Some(&protection));
+        std::fs::remove_dir_all(&root).unwrap();
+
+        assert_eq!((scanned.total_files, scanned.total_size), (5, 1116));
+        assert!(scanned.protected);
+        assert!(matches!(
+            scanned.entries.as_slice(),
+            [ScanEntry::File(file)] if file.path.ends_with("a.log")
+        ));
+    }
+
+    #[test]
+    fn file_modified_before_the_epoch_is_treated_as_new() {
+        let dir = std::env::temp_dir().join(format!("{APP_NAME}-epoch-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let path = dir.join("file");
+        let file = std::fs::File::create(&path).unwrap();
+        if file
+            .set_modified(UNIX_EPOCH - Duration::from_secs(3600))
+            .is_err()
+        {
+            // Not every filesystem stores times before the epoch
+            std::fs::remove_dir_all(&dir).unwrap();
+            return;
+        }
+
+        let scanned = scan(&dir);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let Some(ScanEntry::File(file)) = scanned.entries.first() else {
+            panic!("file not found");
+        };
+        assert!(file.modified > 0);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn allocated_size_of_sparse_file_is_smaller() {
+        let path = std::env::temp_dir().join(format!("{APP_NAME}-sparse-{}", std::process::id()));
+        std::fs::File::create(&path)
+            .unwrap()
+            .set_len(64 * 1024 * 1024)
+            .unwrap();
+
+        let metadata = std::fs::metadata(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(
+            file_size(&metadata, SizeAccounting::Apparent),
+            64 * 1024 * 1024
+        );
+        assert!(file_size(&metadata, SizeAccounting::Allocated) < 64 * 1024 * 1024);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn file_replaced_with_same_size_and_time_is_changed() {
+        let dir = std::env::temp_dir().join(format!("{APP_NAME}-replaced-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        for name in ["file", "replacement"] {
+            let file = std::fs::File::create(dir.join(name)).unwrap();
+            file.set_modified(UNIX_EPOCH + Duration::from_secs(1000))
+                .unwrap();
+        }
+
+        let scanned = scan(&dir);
+        let Some(ScanEntry::File(file)) = scanned
+            .entries
+            .iter()
+            .find(|entry| matches!(entry, ScanEntry::File(file) if file.path.ends_with("file")))
+        else {
+            panic!("file not found");
+        };
+
+        assert!(!changed_since_scan(file, false, SizeAccounting::Apparent).unwrap());
+        std::fs::rename(dir.join("replacement"), dir.join("file")).unwrap();
+        assert!(changed_since_scan(file, false, SizeAccounting::Apparent).unwrap());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn scans_paths_longer_than_max_path() {
+        let root = std::env::temp_dir().join(format!("{APP_NAME}-long-{}", std::process::id()));
+        let dir = (0..10).fold(root.clone(), |dir, i| {
+            dir.join(format!("{i}{}", "a".repeat(40)))
+        });
+        std::fs::create_dir_all(&dir).unwrap();
+        assert!(dir.as_os_str().len() > 260);
+
+        std::fs::write(dir.join("file"), b"contents").unwrap();
+
+        let scanned = scan(&root);
+        std::fs::remove_dir_all(&root).unwrap();
+
+        assert_eq!(scanned.total_size, 8);
+    }
+}
@@ -0,0 +1,505 @@
+//! On-disk cache of directory listings used by `WATCH_MODE=cache`. Listings
+//! are kept between cycles, and across restarts, and a directory is only
+//! listed again once its modification time changed, which happens when
+//! entries are added, removed or renamed in it. Everything is rescanned
+//! periodically, and when the settings deciding what a listing holds changed.
+
+use colored::*;
+use log::{info, warn};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::config::WatchConfig;
+use crate::protection::{IGNORE_FILE, KEEP_FILE};
+use crate::scan::{assemble, list_dir, DirListing, FileInfo, HardLink, ScanOptions, ScanResult};
+
+/// Format of the cache file, a cache of another format is ignored.
+const VERSION: u32 = 1;
+
+/// Seconds and nanoseconds since the epoch.
+type Stamp = (u64, u32);
+
+/// A listing and what tells whether it is still current.
+struct Cached {
+    /// Modification time of the directory
+    modified: Stamp,
+    /// Latest modification time of its protection files, with IGNORE_FILES
+    protection: Option<Stamp>,
+    listing: DirListing,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Header {
+    version: u32,
+    /// See [`settings`]
+    settings: String,
+    /// Time of the last full scan, in seconds since the epoch
+    full_scan: u64,
+}
+
+/// A line of the cache file, with the paths of the listing relative to `dir`.
+#[derive(Serialize, Deserialize)]
+struct Line {
+    dir: PathBuf,
+    modified: Stamp,
+    protection: Option<Stamp>,
+    files: Vec<CachedFile>,
+    subdirs: Vec<(PathBuf, bool)>,
+    protected: bool,
+    counted: (u64, u64),
+    clutter: Vec<PathBuf>,
+}
+
+/// Name, size, modification and access times, hard link and inode of a file.
+#[derive(Serialize, Deserialize)]
+struct CachedFile(PathBuf, u64, u64, u64, Option<HardLink>, u64);
+
+pub struct ScanCache {
+    /// The cache file
+    path: PathBuf,
+    listings: HashMap<PathBuf, Cached>,
+    settings: String,
+    full_scan: u64,
+    loaded: bool,
+}
+
+impl ScanCache {
+    /// A cache kept in `path`, read on the first [`ScanCache::scan`].
+    pub fn new(path: PathBuf) -> ScanCache {
+        ScanCache {
+            path,
+            listings: HashMap::new(),
+            settings: String::new(),
+            full_scan: 0,
+            loaded: false,
+        }
+    }
+
+    /// Brings the cache up to date, saves it and returns the same result a
+    /// full [`crate::scan::read_dir`] would, but for files changed in place.
+    pub fn scan(
+        &mut self,
+        watch: &WatchConfig,
+        options: &ScanOptions,
+        rescan_interval: Duration,
+    ) -> io::Result<ScanResult> {
+        if !self.loaded {
+            self.loaded = true;
+
+            if let Err(e) = self.load() {
+                warn!(
+                    "{}",
+                    format!(
+                        "Error reading scan cache {}, rescanning everything: {e}",
+                        self.path.display()
+                    )
+                    .yellow()
+                );
+                self.listings.clear();
+            }
+        }
+
+        let settings = settings(watch);
+        let now = seconds_since_epoch(SystemTime::now());
+
+        if settings != self.settings
+            || self.listings.is_empty()
+            || now.saturating_sub(self.full_scan) >= rescan_interval.as_secs()
+        {
+            info!(
+                "{}",
+                format!("Full rescan of {}", options.root.display()).green()
+            );
+            self.listings.clear();
+            self.settings = settings;
+            self.full_scan = now;
+        }
+
+        let cached = Mutex::new(std::mem::take(&mut self.listings));
+        let fresh = Mutex::new(HashMap::new());
+        let scanned = build(options.root, options, true, &cached, &fresh);
+        let fresh = fresh.into_inner().unwrap();
+
+        let mut scanned = match scanned {
+            Ok(scanned) => scanned,
+            Err(e) => {
+                // What was not listed again remains usable
+                self.listings = cached.into_inner().unwrap();
+                self.listings.extend(fresh);
+                return Err(e);
+            }
+        };
+
+        // Directories gone since are left out
+        self.listings = fresh;
+
+        if let Err(e) = self.save() {
+            warn!(
+                "{}",
+                format!("Error saving scan cache {}: {e}", self.path.display()).yellow()
+            );
+        }
+
+        scanned.count_hard_links_once();
+
+        Ok(scanned)
+    }
+
+    fn load(&mut self) -> io::Result<()> {
+        let file = match File::open(&self.path) {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+            file => file?,
+        };
+        let mut lines = reader(file)?.lines();
+
+        let Some(header) = lines.next() else {
+            return Ok(());
+        };
+        let header: Header = serde_json::from_str(&header?)?;
+
+        if header.version != VERSION {
+            return Ok(());
+        }
+
+        for line in lines {
+            let line: Line = serde_json::from_str(&line?)?;
+            let dir = line.dir;
+
+            let listing = DirListing {
+                files: line
+                    .files
+                    .into_iter()
+                    .map(
+                        |CachedFile(name, size, modified, accessed, hard_link, inode)| FileInfo {
+                            path: dir.join(name),
+                            size,
+                            modified,
+                            accessed,
+                            hard_link,
+                            inode,
+                        },
+                    )
+                    .collect(),
+                subdirs: line
+                    .subdirs
+                    .into_iter()
+                    .map(|(name, excluded)| (dir.join(name), excluded))
+                    .collect(),
+                errors: 0,
+                protected: line.protected,
+                counted_files: line.counted.0,
+                counted_size: line.counted.1,
+                clutter: line.clutter.iter().map(|name| dir.join(name)).collect(),
+            };
+
+            self.listings.insert(
+                dir,
+                Cached {
+                    modified: line.modified,
+                    protection: line.protection,
+                    listing,
+                },
+            );
+        }
+
+        self.settings = header.settings;
+        self.full_scan = header.full_scan;
+
+        Ok(())
+    }
+
+    /// Writes the cache next to its file and renames it over, so that an
+    /// interrupted save leaves the previous cache.
+    fn save(&self) -> io::Result<()> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let mut temporary = self.path.clone().into_os_string();
+        temporary.push(".tmp");
+
+        write_to(File::create(&temporary)?, |writer| {
+            serde_json::to_writer(
+                &mut *writer,
+                &Header {
+                    version: VERSION,
+                    settings: self.settings.clone(),
+                    full_scan: self.full_scan,
+                },
+            )?;
+            writer.write_all(b"\n")?;
+
+            for (dir, cached) in &self.listings {
+                // Paths that cannot be written are listed again next time
+                let Some(line) = line(dir, cached).and_then(|line| serde_json::to_vec(&line).ok())
+                else {
+                    continue;
+                };
+
+                writer.write_all(&line)?;
+                writer.write_all(b"\n")?;
+            }
+
+            Ok(())
+        })?;
+
+        fs::rename(&temporary, &self.path)
+    }
+}
+
+/// Reads a cache file, compressed with zstd unless built without the
+/// `compression` feature. A cache of the other build fails to read and is
+/// rebuilt.
+#[cfg(feature = "compression")]
+fn reader(file: File) -> io::Result<impl BufRead> {
+    Ok(BufReader::new(zstd::Decoder::new(file)?))
+}
+
+#[cfg(not(feature = "compression"))]
+fn reader(file: File) -> io::Result<impl BufRead> {
+    Ok(BufReader::new(file))
+}
+
+/// Writes a cache file with `write` the way [`reader`] reads it.
+#[cfg(feature = "compression")]
+fn write_to(file: File, write: impl FnOnce(&mut dyn Write) -> io::Result<()>) -> io::Result<()> {
+    let mut writer = zstd::Encoder::new(BufWriter::new(file), 0)?;
+    write(&mut writer)?;
+    writer.finish()?.flush()
+}
+
+#[cfg(not(feature = "compression"))]
+fn write_to(file: File, write: impl FnOnce(&mut dyn Write) -> io::Result<()>) -> io::Result<()> {
+    let mut writer = BufWriter::new(file);
+    write(&mut writer)?;
+    writer.flush()
+}
+
+fn line(dir: &Path, cached: &Cached) -> Option<Line> {
+    let name = |path: &Path| path.strip_prefix(dir).ok().map(Path::to_path_buf);
+    let listing = &cached.listing;
+
+    Some(Line {
+        dir: dir.to_path_buf(),
+        modified: cached.modified,
+        protection: cached.protection,
+        files: listing
+            .files
+            .iter()
+            .map(|file| {
+                Some(CachedFile(
+                    name(&file.path)?,
+                    file.size,
+                    file.modified,
+                    file.accessed,
+                    file.hard_link,
+                    file.inode,
+                ))
+            })
+            .collect::<Option<_>>()?,
+        subdirs: listing
+            .subdirs
+            .iter()
+            .map(|(path, excluded)| Some((name(path)?, *excluded)))
+            .collect::<Option<_>>()?,
+        protected: listing.protected,
+        counted: (listing.counted_files, listing.counted_size),
+        clutter: listing
+            .clutter
+            .iter()
+            .map(|path| name(path))
+            .collect::<Option<_>>()?,
+    })
+}
+
+/// Scans `path` from the listings of `cached` still current, moving every
+/// listing to `fresh`. Once the protection files of a directory changed,
+/// nothing below it is `trusted` anymore.
+fn build(
+    path: &Path,
+    options: &ScanOptions,
+    trusted: bool,
+    cached: &Mutex<HashMap<PathBuf, Cached>>,
+    fresh: &Mutex<HashMap<PathBuf, Cached>>,
+) -> io::Result<ScanResult> {
+    options.check_deadline()?;
+
+    let modified = stamp(fs::metadata(path)?.modified()?);
+    let protection = match options.protection {
+        Some(_) => [IGNORE_FILE, KEEP_FILE]
+            .iter()
+            .filter_map(|name| fs::symlink_metadata(path.join(name)).ok())
+            .filter_map(|metadata| metadata.modified().ok())
+            .map(stamp)
+            .max(),
+        None => None,
+    };
+
+    let previous = cached.lock().unwrap().remove(path);
+    let listed = SystemTime::now();
+
+    let (listing, trusted, settled) = match previous {
+        Some(previous)
+            if trusted && (previous.modified, previous.protection) == (modified, protection) =>
+        {
+            (previous.listing, true, true)
+        }
+        previous => {
+            let listing = list_dir(path, options)?;
+            let trusted =
+                trusted && previous.is_none_or(|previous| previous.protection == protection);
+            // Changes within the resolution of the modification time would
+            // go unnoticed, so a directory just modified is listed again
+            let settled = listed
+                .duration_since(UNIX_EPOCH + Duration::new(modified.0, modified.1))
+                .is_ok_and(|since| since >= Duration::from_secs(1));
+
+            let settled = settled && listing.errors == 0;
+
+            (listing, trusted, settled)
+        }
+    };
+
+    let scanned = listing
+        .subdirs
+        .par_iter()
+        .map(|(subdir, excluded)| {
+            let items = build(subdir, options, trusted, cached, fresh);
+            (subdir.clone(), *excluded, items)
+        })
+        .collect();
+
+    // Rather than logging every subdirectory left unread
+    options.check_deadline()?;
+
+    if settled {
+        fresh.lock().unwrap().insert(
+            path.to_path_buf(),
+            Cached {
+                modified,
+                protection,
+                listing: listing.clone(),
+            },
+        );
+    }
+
+    Ok(assemble(listing, scanned))
+}
+
+/// The settings a listing depends on, a cache made with others is dropped.
+fn settings(watch: &WatchConfig) -> String {
+    serde_json::json!({
+        "exclude_patterns": watch.exclude_patterns,
+        "exclude_dirs": watch.exclude_dirs,
+        "owner_users": watch.owner_users,
+        "owner_groups": watch.owner_groups,
+        "require_owner_write": watch.require_owner_write,
+        "ignore_files": watch.ignore_files,
+        "pinned_files": watch.pinned_files,
+        "follow_symlinks": watch.follow_symlinks,
+        "one_file_system": watch.one_file_system,
+        "size_accounting": watch.size_accounting,
+        "clutter": watch.clutter_max_age_seconds.is_some(),
+        "max_depth": watch.max_depth,
+    })
+    .to_string()
+}
+
+fn stamp(time: SystemTime) -> Stamp {
+    let since = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+
+    (since.as_secs(), since.subsec_nanos())
+}
+
+fn seconds_since_epoch(time: SystemTime) -> u64 {
+    stamp(time).0
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::config::SizeAccounting;
+    use crate::APP_NAME;
+    use globset::GlobSet;
+
+    fn options<'a>(root: &'a Path, exclude: &'a GlobSet) -> ScanOptions<'a> {
+        ScanOptions {
+            root,
+            exclude,
+            exclude_dirs: &[],
+            device: None,
+            follow_symlinks: false,
+            size_accounting: SizeAccounting::Apparent,
+            candidates: None,
+            protection: None,
+            pinned: false,
+            owners: None,
+            clutter: false,
+            deadline: None,
+            max_depth: None,
+        }
+    }
+
+    /// Makes the directories settled, as if created some time ago.
+    fn settle(dirs: &[&Path]) {
+        for dir in dirs {
+            File::open(dir)
+                .unwrap()
+                .set_modified(UNIX_EPOCH + Duration::from_secs(1000))
+                .unwrap();
+        }
+    }
+
+    #[test]
+    fn only_modified_directories_are_listed_again() {
+        let root =
+            std::env::temp_dir().join(format!("{APP_NAME}-scan-cache-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        let directory = root.join("watched");
+        let cache_file = root.join("cache/watched.cache");
+        let (a, b) = (directory.join("a"), directory.join("b"));
+        fs::create_dir_all(&a).unwrap();
+        fs::create_dir_all(&b).unwrap();
+        fs::write(a.join("1"), [0; 10]).unwrap();
+        fs::write(b.join("2"), [0; 100]).unwrap();
+        settle(&[&directory, &a, &b]);
+
+        let watch: WatchConfig = toml::from_str(&format!(
+            "directory = {:?}\ninterval_seconds = 1\nmax_size_bytes = 1024",
+            directory.display().to_string()
+        ))
+        .unwrap();
+        let exclude = GlobSet::empty();
+        let options = options(&directory, &exclude);
+        let hour = Duration::from_secs(3600);
+
+        let scanned = ScanCache::new(cache_file.clone())
+            .scan(&watch, &options, hour)
+            .unwrap();
+        assert_eq!((scanned.total_files, scanned.total_size), (2, 110));
+        assert!(cache_file.exists());
+
+        // Growing in place leaves the directory alone, adding a file does not
+        fs::write(b.join("2"), [0; 200]).unwrap();
+        fs::write(a.join("3"), [0; 1000]).unwrap();
+        settle(&[&b]);
+
+        let mut cache = ScanCache::new(cache_file.clone());
+        let scanned = cache.scan(&watch, &options, hour).unwrap();
+        assert_eq!((scanned.total_files, scanned.total_size), (3, 1110));
+        assert!(scanned.entries.iter().any(
+            |entry| matches!(entry, crate::scan::ScanEntry::File(file) if file.path == a.join("3"))
+        ));
+
+        let rescanned = cache.scan(&watch, &options, Duration::ZERO).unwrap();
+        assert_eq!((rescanned.total_files, rescanned.total_size), (3, 1210));
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,496 @@
+//! When cleanups happen: cron expressions (`*/10 2-6 * * *`) a directory can
+//! be cleaned up on instead of at a fixed interval, and quiet hours
+//! (`mon-fri 09:00-17:00`) nothing is removed during. Both are evaluated in
+//! the local time unless a [`Timezone`] is set.
+//!
+//! The five fields are minute, hour, day of the month, month and day of the
+//! week, each `*`, a value, a range `a-b` or a list of those, optionally with
+//! a step (`*/15`, `1-30/2`). Months and days of the week may be named
+//! (`jan`, `mon`) and Sunday is both 0 and 7. As with cron, a time matches
+//! either day field when both are restricted. `@hourly`, `@daily`,
+//! `@weekly`, `@monthly` and `@yearly` are accepted as shorthands.
+
+use chrono::{
+    DateTime, Datelike, Duration, Local, LocalResult, NaiveDate, NaiveDateTime, NaiveTime,
+    TimeZone, Timelike, Utc,
+};
+#[cfg(feature = "timezones")]
+use chrono_tz::Tz;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+use std::time::SystemTime;
+
+#[cfg(not(feature = "timezones"))]
+use crate::APP_NAME;
+
+/// Longest a time matching a valid expression can be away, `0 0 29 2 *`
+/// skips 2100 and waits 8 years.
+const HORIZON_DAYS: i64 = 9 * 366;
+
+const MONTHS: [&str; 12] = [
+    "jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec",
+];
+const WEEKDAYS: [&str; 7] = ["sun", "mon", "tue", "wed", "thu", "fri", "sat"];
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
+#[serde(try_from = "String", into = "String")]
+pub struct Schedule {
+    expression: String,
+    /// Bit `n` set when the field matches `n`
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    /// Whether the day fields are `*`
+    any_day: bool,
+    any_weekday: bool,
+}
+
+impl Schedule {
+    /// The first matching time after `time` in `timezone`, `None` if there
+    /// is none.
+    pub fn next_after(&self, time: SystemTime, timezone: Timezone) -> Option<SystemTime> {
+        let mut local = timezone.local(time);
+
+        loop {
+            local = self.next_local(local)?;
+
+            // Local times skipped by a DST change never come
+            if let Some(time) = timezone.resolve(local) {
+                return Some(time);
+            }
+        }
+    }
+
+    /// The first matching minute after `time`, in the same local time.
+    fn next_local(&self, time: NaiveDateTime) -> Option<NaiveDateTime> {
+        let start = time.with_second(0)?.with_nanosecond(0)? + Duration::minutes(1);
+        let end = start + Duration::days(HORIZON_DAYS);
+        let mut time = start;
+
+        while time < end {
+            if !matches(self.months, time.month()) {
+                let (year, month) = match time.month() {
+                    12 => (time.year() + 1, 1),
+                    month => (time.year(), month + 1),
+                };
+                time = NaiveDate::from_ymd_opt(year, month, 1)?.and_hms_opt(0, 0, 0)?;
+            } else if !self.matches_day(time.date()) {
+                time = time.date().succ_opt()?.and_hms_opt(0, 0, 0)?;
+            } else if !matches(self.hours, time.hour()) {
+                time = time.with_minute(0)? + Duration::hours(1);
+            } else if !matches(self.minutes, time.minute()) {
+                time += Duration::minutes(1);
+            } else {
+                return Some(time);
+            }
+        }
+
+        None
+    }
+
+    fn matches_day(&self, date: NaiveDate) -> bool {
+        let day = matches(self.days, date.day());
+        let weekday = matches(self.weekdays, date.weekday().num_days_from_sunday());
+
+        match (self.any_day, self.any_weekday) {
+            (false, false) => day || weekday,
+            _ => day && weekday,
+        }
+    }
+}
+
+fn matches(field: u64, value: u32) -> bool {
+    field & (1 << value) != 0
+}
+
+impl FromStr for Schedule {
+    type Err = String;
+
+    fn from_str(expression: &str) -> Result<Schedule, String> {
+        let expanded = match expression.trim() {
+            "@hourly" => "0 * * * *",
+            "@daily" | "@midnight" => "0 0 * * *",
+            "@weekly" => "0 0 * * 0",
+            "@monthly" => "0 0 1 * *",
+            "@yearly" | "@annually" => "0 0 1 1 *",
+            expanded => expanded,
+        };
+
+        let fields: Vec<&str> = expanded.split_whitespace().collect();
+
+        let [minutes, hours, days, months, weekdays] = fields[..] else {
+            return Err(format!(
+                "invalid schedule `{expression}`, expected 5 fields: minute hour day month weekday"
+            ));
+        };
+
+        let invalid = |e: String| format!("invalid schedule `{expression}`, {e}");
+
+        let mut weekdays_field = field(weekdays, 0, 7, &WEEKDAYS).map_err(invalid)?;
+        // Sunday is both 0 and 7
+        if matches(weekdays_field, 7) {
+            weekdays_field |= 1;
+        }
+
+        let schedule = Schedule {
+            expression: expression.trim().to_string(),
+            minutes: field(minutes, 0, 59, &[]).map_err(invalid)?,
+            hours: field(hours, 0, 23, &[]).map_err(invalid)?,
+            days: field(days, 1, 31, &[]).map_err(invalid)?,
+            months: field(months, 1, 12, &MONTHS).map_err(invalid)?,
+            weekdays: weekdays_field,
+            any_day: days == "*",
+            any_weekday: weekdays == "*",
+        };
+
+        let start = NaiveDate::from_ymd_opt(2000, 1, 1)
+            .and_then(|date| date.and_hms_opt(0, 0, 0))
+            .unwrap_or_default();
+
+        if schedule.next_local(start).is_none() {
+            return Err(format!("schedule `{expression}` never matches"));
+        }
+
+        Ok(schedule)
+    }
+}
+
+/// The bits of the values `field` matches, between `min` and `max`. `names`
+/// are accepted for the values from `min` on.
+fn field(field: &str, min: u32, max: u32, names: &[&str]) -> Result<u64, String> {
+    let value = |value: &str| -> Result<u32, String> {
+        let position = names
+            .iter()
+            .position(|name| name.eq_ignore_ascii_case(value));
+
+        let parsed = match position {
+            Some(position) => position as u32 + min,
+            None => value
+                .parse()
+                .map_err(|_| format!("`{value}` is not a number"))?,
+        };
+
+        match (min..=max).contains(&parsed) {
+            true => Ok(parsed),
+            false => Err(format!("{parsed} is not between {min} and {max}")),
+        }
+    };
+
+    let mut bits = 0;
+
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => match step.parse::<u32>() {
+                Ok(step) if step > 0 => (range, step),
+                _ => return Err(format!("invalid step in `{part}`")),
+            },
+            None => (part, 1),
+        };
+
+        let (first, last) = match range.split_once('-') {
+            _ if range == "*" => (min, max),
+            // `fri-sun` ends with Sunday as 7
+            Some((first, last)) => match (value(first)?, value(last)?) {
+                (first, 0) if max == 7 => (first, 7),
+                range => range,
+            },
+            // `5/15` runs from 5 to the end
+            None if step > 1 => (value(range)?, max),
+            None => {
+                let value = value(range)?;
+                (value, value)
+            }
+        };
+
+        if first > last {
+            return Err(format!("range `{range}` is backwards"));
+        }
+
+        for value in (first..=last).step_by(step as usize) {
+            bits |= 1 << value;
+        }
+    }
+
+    Ok(bits)
+}
+
+impl TryFrom<String> for Schedule {
+    type Error = String;
+
+    fn try_from(expression: String) -> Result<Schedule, String> {
+        expression.parse()
+    }
+}
+
+impl From<Schedule> for String {
+    fn from(schedule: Schedule) -> String {
+        schedule.expression
+    }
+}
+
+impl fmt::Display for Schedule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.expression)
+    }
+}
+
+/// Where schedules and quiet hours are evaluated, an IANA name such as
+/// `Europe/Berlin` or `local`. Names need the `timezones` feature.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(try_from = "String", into = "String")]
+pub enum Timezone {
+    #[default]
+    Local,
+    #[cfg(feature = "timezones")]
+    Named(Tz),
+}
+
+impl Timezone {
+    fn local(self, time: SystemTime) -> NaiveDateTime {
+        let time = DateTime::<Utc>::from(time);
+
+        match self {
+            Timezone::Local => time.with_timezone(&Local).naive_local(),
+            #[cfg(feature = "timezones")]
+            Timezone::Named(tz) => time.with_timezone(&tz).naive_local(),
+        }
+    }
+
+    /// The time `local` is in this timezone, the first one when it happens
+    /// twice and `None` when a DST change skips it.
+    fn resolve(self, local: NaiveDateTime) -> Option<SystemTime> {
+        fn first<Z: TimeZone>(result: LocalResult<DateTime<Z>>) -> Option<SystemTime> {
+            match result {
+                LocalResult::Single(time) | LocalResult::Ambiguous(time, _) => Some(time.into()),
+                LocalResult::None => None,
+            }
+        }
+
+        match self {
+            Timezone::Local => first(Local.from_local_datetime(&local)),
+            #[cfg(feature = "timezones")]
+            Timezone::Named(tz) => first(tz.from_local_datetime(&local)),
+        }
+    }
+}
+
+impl FromStr for Timezone {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Timezone, String> {
+        match name.trim() {
+            name if name.eq_ignore_ascii_case("local") => Ok(Timezone::Local),
+            #[cfg(feature = "timezones")]
+            name => name
+                .parse()
+                .map(Timezone::Named)
+                .map_err(|_| format!("unknown timezone `{name}`, expected e.g. Europe/Berlin")),
+            #[cfg(not(feature = "timezones"))]
+            name => Err(format!(
+                "timezone `{name}` needs {APP_NAME} built with the `timezones` feature"
+            )),
+        }
+    }
+}
+
+impl TryFrom<String> for Timezone {
+    type Error = String;
+
+    fn try_from(name: String) -> Result<Timezone, String> {
+        name.parse()
+    }
+}
+
+impl From<Timezone> for String {
+    fn from(timezone: Timezone) -> String {
+        match timezone {
+            Timezone::Local => "local".to_string(),
+            #[cfg(feature = "timezones")]
+            Timezone::Named(tz) => tz.name().to_string(),
+        }
+    }
+}
+
+/// A daily window nothing is removed during, `09:00-17:00`, optionally on
+/// some days of the week only, `mon-fri 09:00-17:00`. A window ending
+/// before it starts runs past midnight, into the day after the days given.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
+#[serde(try_from = "String", into = "String")]
+pub struct QuietWindow {
+    expression: String,
+    /// Bit `n` set when the window starts on day `n` of the week, Sunday
+    /// being 0
+    weekdays: u64,
+    /// Minutes since midnight, the end is excluded
+    start: u32,
+    end: u32,
+}
+
+impl QuietWindow {
+    fn contains(&self, time: NaiveDateTime) -> bool {
+        let minute = time.hour() * 60 + time.minute();
+        let today = time.weekday().num_days_from_sunday();
+        let yesterday = (today + 6) % 7;
+
+        if self.start < self.end {
+            matches(self.weekdays, today) && (self.start..self.end).contains(&minute)
+        } else {
+            (matches(self.weekdays, today) && minute >= self.start)
+                || (matches(self.weekdays, yesterday) && minute < self.end)
+        }
+    }
+}
+
+/// The window of `windows` that `time` falls into in `timezone`, if any.
+pub fn quiet_window(
+    windows: &[QuietWindow],
+    timezone: Timezone,
+    time: SystemTime,
+) -> Option<&QuietWindow> {
+    if windows.is_empty() {
+        return None;
+    }
+
+    let local = timezone.local(time);
+
+    windows.iter().find(|window| window.contains(local))
+}
+
+impl FromStr for QuietWindow {
+    type Err = String;
+
+    fn from_str(expression: &str) -> Result<QuietWindow, String> {
+        let expression = expression.trim();
+        let invalid = |e: &str| {
+            format!("invalid quiet hours `{expression}`, {e}, expected e.g. `mon-fri 09:00-17:00`")
+        };
+
+        let (days, hours) = match expression.rsplit_once(char::is_whitespace) {
+            Some((days, hours)) => (days.trim(), hours),
+            None => ("*", expression),
+        };
+
+        let Some((start, end)) = hours.split_once('-') else {
+            return Err(invalid("no time range"));
+        };
+
+        let minutes = |time: &str| {
+            NaiveTime::parse_from_str(time, "%H:%M")
+                .map(|time| time.hour() * 60 + time.minute())
+                .map_err(|_| invalid(&format!("`{time}` is not a time")))
+        };
+
+        let mut weekdays = field(days, 0, 7, &WEEKDAYS).map_err(|e| invalid(&e))?;
+        if matches(weekdays, 7) {
+            weekdays |= 1;
+        }
+
+        Ok(QuietWindow {
+            expression: expression.to_string(),
+            weekdays,
+            start: minutes(start)?,
+            end: minutes(end)?,
+        })
+    }
+}
+
+impl TryFrom<String> for QuietWindow {
+    type Error = String;
+
+    fn try_from(expression: String) -> Result<QuietWindow, String> {
+        expression.parse()
+    }
+}
+
+impl From<QuietWindow> for String {
+    fn from(window: QuietWindow) -> String {
+        window.expression
+    }
+}
+
+impl fmt::Display for QuietWindow {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.expression)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(text: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(text, "%Y-%m-%d %H:%M").unwrap()
+    }
+
+    #[test]
+    fn finds_the_next_matching_minute() {
+        let next = |expression: &str, time: &str| {
+            expression
+                .parse::<Schedule>()
+                .unwrap()
+                .next_local(at(time))
+                .map(|time| time.format("%Y-%m-%d %H:%M").to_string())
+        };
+
+        let night = "*/10 2-6 * * *";
+        assert_eq!(next(night, "2026-03-01 12:34").unwrap(), "2026-03-02 02:00");
+        assert_eq!(next(night, "2026-03-02 02:00").unwrap(), "2026-03-02 02:10");
+        assert_eq!(next(night, "2026-03-02 06:55").unwrap(), "2026-03-03 02:00");
+
+        // Either day field matches when both are restricted, Sunday is 7
+        assert_eq!(
+            next("0 0 13 * 7", "2026-03-01 00:00").unwrap(),
+            "2026-03-08 00:00"
+        );
+        assert_eq!(
+            next("30 4 * mar-may mon", "2026-06-01 00:00").unwrap(),
+            "2027-03-01 04:30"
+        );
+        assert_eq!(
+            next("@monthly", "2026-12-15 08:00").unwrap(),
+            "2027-01-01 00:00"
+        );
+        assert_eq!(
+            next("0 0 29 2 *", "2026-03-01 00:00").unwrap(),
+            "2028-02-29 00:00"
+        );
+
+        for invalid in [
+            "* * * *",
+            "60 * * * *",
+            "*/0 * * * *",
+            "5-1 * * * *",
+            "0 0 30 feb *",
+        ] {
+            assert!(invalid.parse::<Schedule>().is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn quiet_windows_may_run_past_midnight() {
+        let quiet =
+            |window: &str, time: &str| window.parse::<QuietWindow>().unwrap().contains(at(time));
+
+        // 2026-10-14 is a Wednesday
+        assert!(quiet("mon-fri 09:00-17:00", "2026-10-14 09:00"));
+        assert!(!quiet("mon-fri 09:00-17:00", "2026-10-14 17:00"));
+        assert!(!quiet("mon-fri 09:00-17:00", "2026-10-17 12:00"));
+
+        assert!(quiet("22:00-02:00", "2026-10-14 23:30"));
+        assert!(quiet("22:00-02:00", "2026-10-15 01:59"));
+        assert!(!quiet("22:00-02:00", "2026-10-15 02:00"));
+
+        assert!(quiet("fri 22:00-06:00", "2026-10-17 05:00"));
+        assert!(!quiet("fri 22:00-06:00", "2026-10-16 05:00"));
+
+        assert!(quiet("sat-sun 00:00-00:00", "2026-10-18 23:59"));
+        assert!(!quiet("sat-sun 00:00-00:00", "2026-10-19 00:00"));
+
+        for invalid in ["09:00", "mon-fri 9-17", "someday 09:00-17:00"] {
+            assert!(invalid.parse::<QuietWindow>().is_err(), "{invalid}");
+        }
+    }
+}
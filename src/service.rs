@@ -0,0 +1,500 @@
+//! Running as a Windows service, for `maxdirsize service`: registering it
+//! with the service control manager, starting and stopping it, and reporting
+//! to the manager while the cleanup loop runs as the service.
+
+use colored::*;
+use log::{error, info};
+
+use maxdirsize::config::ServiceAction;
+use maxdirsize::logging::LogTarget;
+use maxdirsize::{Config, APP_NAME};
+
+use crate::unit::Invocation;
+
+/// Registers the service with the current flags, environment variables and
+/// config file, logging to the event log unless told otherwise.
+pub fn install(config: &Config) -> i32 {
+    let mut invocation = match Invocation::current() {
+        Ok(invocation) => invocation,
+        Err(e) => {
+            error!(event = "error"; "Error locating the executable: {e}");
+            return 1;
+        }
+    };
+
+    // Standard error goes nowhere in a service
+    if config.log_target == LogTarget::Stderr && config.log_file.is_none() {
+        invocation
+            .environment
+            .push(("LOG_TARGET".to_string(), "eventlog".to_string()));
+    }
+
+    let mut command_line = format!("\"{}\"", invocation.executable.display());
+    if let Some(config) = &invocation.config {
+        command_line.push_str(&format!(" --config \"{}\"", config.display()));
+    }
+    command_line.push_str(" service run");
+
+    if let Err(e) = imp::install(&command_line, &invocation.environment) {
+        error!(event = "error"; "Error installing the service: {e}");
+        return 1;
+    }
+
+    if !invocation.secrets.is_empty() {
+        info!(
+            "{}",
+            format!(
+                "Left out {} as secrets, set them as system environment variables",
+                invocation.secrets.join(", ")
+            )
+            .yellow()
+        );
+    }
+
+    info!(
+        "{}",
+        format!("Installed the {APP_NAME} service, start it with `{APP_NAME} service start`")
+            .green()
+    );
+    0
+}
+
+/// Removes, starts or stops the service, waiting for it to get there.
+pub fn control(action: ServiceAction) -> i32 {
+    let (done, controlled) = match action {
+        ServiceAction::Uninstall => ("Removed", imp::uninstall()),
+        ServiceAction::Start => ("Started", imp::start()),
+        ServiceAction::Stop => ("Stopped", imp::stop()),
+        ServiceAction::Install | ServiceAction::Run => {
+            unreachable!("handled with the configuration loaded")
+        }
+    };
+
+    match controlled {
+        Ok(()) => {
+            info!("{}", format!("{done} the {APP_NAME} service").green());
+            0
+        }
+        Err(e) => {
+            error!(event = "error"; "Error controlling the service: {e}");
+            1
+        }
+    }
+}
+
+/// Connects to the service control manager, which started the process, and
+/// reports the service running. Stopping it shuts the cleanup loop down as
+/// SIGTERM does.
+pub fn dispatch() -> std::io::Result<()> {
+    imp::dispatch()
+}
+
+/// Reports the service stopped with the exit `code` of the process, right
+/// before exiting. Does nothing unless [`dispatch`] connected.
+pub fn stopped(code: i32) {
+    imp::stopped(code)
+}
+
+#[cfg(windows)]
+mod imp {
+    use std::io;
+    use std::sync::mpsc::{self, Sender};
+    use std::sync::{Mutex, OnceLock};
+    use std::time::{Duration, Instant};
+
+    use windows_sys::core::PWSTR;
+    use windows_sys::Win32::Foundation::{
+        ERROR_CALL_NOT_IMPLEMENTED, ERROR_SERVICE_NOT_ACTIVE, ERROR_SERVICE_SPECIFIC_ERROR,
+        NO_ERROR,
+    };
+    use windows_sys::Win32::Storage::FileSystem::DELETE;
+    use windows_sys::Win32::System::Registry::{
+        RegDeleteTreeW, RegSetKeyValueW, HKEY_LOCAL_MACHINE, REG_DWORD, REG_EXPAND_SZ,
+        REG_MULTI_SZ, REG_VALUE_TYPE,
+    };
+    use windows_sys::Win32::System::Services::*;
+
+    use maxdirsize::APP_NAME;
+
+    const DESCRIPTION: &str = "Keeps directories within their limits by removing the oldest files";
+
+    /// How long `start` and `stop` wait for the service to get there.
+    const WAIT: Duration = Duration::from_secs(120);
+    const STOP_WAIT_HINT_MS: u32 = 60_000;
+
+    /// Messages `%1` for every event id, the message file `eventcreate`
+    /// registers.
+    const MESSAGE_FILE: &str = r"%SystemRoot%\System32\EventCreate.exe";
+
+    /// `SERVICE_STATUS_HANDLE` of the running service.
+    static STATUS: OnceLock<usize> = OnceLock::new();
+    /// Tells [`dispatch`] whether the service main connected.
+    static CONNECTED: Mutex<Option<Sender<io::Result<()>>>> = Mutex::new(None);
+
+    /// A handle of the service control manager or a service.
+    struct Handle(SC_HANDLE);
+
+    impl Drop for Handle {
+        fn drop(&mut self) {
+            // SAFETY: opened and not closed yet
+            unsafe { CloseServiceHandle(self.0) };
+        }
+    }
+
+    fn manager(access: u32) -> io::Result<Handle> {
+        // SAFETY: null machine and database names are the local ones
+        let manager = unsafe { OpenSCManagerW(std::ptr::null(), std::ptr::null(), access) };
+
+        match manager.is_null() {
+            true => Err(io::Error::last_os_error()),
+            false => Ok(Handle(manager)),
+        }
+    }
+
+    fn service(access: u32) -> io::Result<Handle> {
+        let manager = manager(SC_MANAGER_CONNECT)?;
+        let name = wide(APP_NAME);
+        // SAFETY: the name is nul terminated
+        let service = unsafe { OpenServiceW(manager.0, name.as_ptr(), access) };
+
+        match service.is_null() {
+            true => Err(io::Error::last_os_error()),
+            false => Ok(Handle(service)),
+        }
+    }
+
+    pub fn install(command_line: &str, environment: &[(String, String)]) -> io::Result<()> {
+        let manager = manager(SC_MANAGER_CONNECT | SC_MANAGER_CREATE_SERVICE)?;
+        let name = wide(APP_NAME);
+        let command_line = wide(command_line);
+
+        // SAFETY: the strings are nul terminated, null ones are left unset
+        // and a null account is LocalSystem
+        let service = unsafe {
+            CreateServiceW(
+                manager.0,
+                name.as_ptr(),
+                name.as_ptr(),
+                SERVICE_ALL_ACCESS,
+                SERVICE_WIN32_OWN_PROCESS,
+                SERVICE_AUTO_START,
+                SERVICE_ERROR_NORMAL,
+                command_line.as_ptr(),
+                std::ptr::null(),
+                std::ptr::null_mut(),
+                std::ptr::null(),
+                std::ptr::null(),
+                std::ptr::null(),
+            )
+        };
+        if service.is_null() {
+            return Err(io::Error::last_os_error());
+        }
+        let service = Handle(service);
+
+        let mut description = wide(DESCRIPTION);
+        let info = SERVICE_DESCRIPTIONW {
+            lpDescription: description.as_mut_ptr(),
+        };
+        // SAFETY: `info` outlives the call
+        unsafe {
+            ChangeServiceConfig2W(
+                service.0,
+                SERVICE_CONFIG_DESCRIPTION,
+                &info as *const SERVICE_DESCRIPTIONW as *const _,
+            )
+        };
+
+        let mut variables: Vec<u16> = Vec::new();
+        for (name, value) in environment {
+            variables.extend(format!("{name}={value}").encode_utf16().chain([0]));
+        }
+        variables.push(0);
+        set_value(
+            &format!(r"SYSTEM\CurrentControlSet\Services\{APP_NAME}"),
+            "Environment",
+            REG_MULTI_SZ,
+            &u16_bytes(&variables),
+        )?;
+
+        let source = event_source();
+        set_value(
+            &source,
+            "EventMessageFile",
+            REG_EXPAND_SZ,
+            &u16_bytes(&wide(MESSAGE_FILE)),
+        )?;
+        set_value(&source, "TypesSupported", REG_DWORD, &7u32.to_le_bytes())
+    }
+
+    pub fn uninstall() -> io::Result<()> {
+        match stop() {
+            Err(e) if e.raw_os_error() == Some(ERROR_SERVICE_NOT_ACTIVE as i32) => {}
+            stopped => stopped?,
+        }
+
+        let service = service(DELETE)?;
+        // SAFETY: opened with DELETE access
+        if unsafe { DeleteService(service.0) } == 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let source = wide(&event_source());
+        // SAFETY: the key name is nul terminated
+        let _ = unsafe { RegDeleteTreeW(HKEY_LOCAL_MACHINE, source.as_ptr()) };
+
+        Ok(())
+    }
+
+    pub fn start() -> io::Result<()> {
+        let service = service(SERVICE_START | SERVICE_QUERY_STATUS)?;
+
+        // SAFETY: no arguments
+        if unsafe { StartServiceW(service.0, 0, std::ptr::null()) } == 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        wait_for(&service, SERVICE_RUNNING)
+    }
+
+    pub fn stop() -> io::Result<()> {
+        let service = service(SERVICE_STOP | SERVICE_QUERY_STATUS)?;
+        let mut status = empty_status();
+
+        // SAFETY: `status` is written to
+        if unsafe { ControlService(service.0, SERVICE_CONTROL_STOP, &mut status) } == 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        wait_for(&service, SERVICE_STOPPED)
+    }
+
+    fn wait_for(service: &Handle, state: SERVICE_STATUS_CURRENT_STATE) -> io::Result<()> {
+        let deadline = Instant::now() + WAIT;
+
+        loop {
+            let mut status = empty_status();
+            // SAFETY: `status` is written to
+            if unsafe { QueryServiceStatus(service.0, &mut status) } == 0 {
+                return Err(io::Error::last_os_error());
+            }
+
+            if status.dwCurrentState == state {
+                return Ok(());
+            }
+
+            if status.dwCurrentState == SERVICE_STOPPED {
+                return Err(io::Error::other(format!(
+                    "the service exited with status {}, see the event log",
+                    status.dwServiceSpecificExitCode
+                )));
+            }
+
+            if Instant::now() > deadline {
+                return Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    "the service did not get there in time",
+                ));
+            }
+
+            std::thread::sleep(Duration::from_millis(250));
+        }
+    }
+
+    /// Runs the dispatcher on a thread of its own, the cleanup loop staying
+    /// on the main thread.
+    pub fn dispatch() -> io::Result<()> {
+        let (connected, connection) = mpsc::channel();
+        *CONNECTED.lock().unwrap() = Some(connected.clone());
+
+        std::thread::Builder::new()
+            .name("service".to_string())
+            .spawn(move || {
+                let mut name = wide(APP_NAME);
+                let table = [
+                    SERVICE_TABLE_ENTRYW {
+                        lpServiceName: name.as_mut_ptr(),
+                        lpServiceProc: Some(service_main),
+                    },
+                    SERVICE_TABLE_ENTRYW {
+                        lpServiceName: std::ptr::null_mut(),
+                        lpServiceProc: None,
+                    },
+                ];
+
+                // SAFETY: the table ends with a null entry and outlives the
+                // dispatcher, which only returns once the service stopped
+                if unsafe { StartServiceCtrlDispatcherW(table.as_ptr()) } == 0 {
+                    let _ = connected.send(Err(io::Error::last_os_error()));
+                }
+            })?;
+
+        connection
+            .recv()
+            .unwrap_or_else(|_| Err(io::Error::other("the dispatcher exited")))
+    }
+
+    extern "system" fn service_main(_argc: u32, _argv: *mut PWSTR) {
+        let name = wide(APP_NAME);
+        // SAFETY: the name is nul terminated and no context is passed
+        let status = unsafe {
+            RegisterServiceCtrlHandlerExW(name.as_ptr(), Some(handler), std::ptr::null())
+        };
+
+        let connected = CONNECTED.lock().unwrap().take();
+        let result = match status.is_null() {
+            true => Err(io::Error::last_os_error()),
+            false => {
+                let _ = STATUS.set(status as usize);
+                set_status(SERVICE_RUNNING, 0);
+                Ok(())
+            }
+        };
+        let failed = result.is_err();
+
+        if let Some(connected) = connected {
+            let _ = connected.send(result);
+        }
+
+        if failed {
+            return;
+        }
+
+        // The cleanup loop runs on the main thread, which reports the service
+        // stopped and exits the process once it ends
+        loop {
+            std::thread::park();
+        }
+    }
+
+    extern "system" fn handler(
+        control: u32,
+        _event_type: u32,
+        _event_data: *mut std::ffi::c_void,
+        _context: *mut std::ffi::c_void,
+    ) -> u32 {
+        match control {
+            SERVICE_CONTROL_STOP | SERVICE_CONTROL_SHUTDOWN => {
+                set_status(SERVICE_STOP_PENDING, 0);
+                // SAFETY: the handler installed for SIGTERM only sets a flag
+                unsafe { libc::raise(libc::SIGTERM) };
+                NO_ERROR
+            }
+            SERVICE_CONTROL_INTERROGATE => NO_ERROR,
+            _ => ERROR_CALL_NOT_IMPLEMENTED,
+        }
+    }
+
+    pub fn stopped(code: i32) {
+        set_status(SERVICE_STOPPED, code);
+    }
+
+    fn set_status(state: SERVICE_STATUS_CURRENT_STATE, code: i32) {
+        let Some(&handle) = STATUS.get() else {
+            return;
+        };
+
+        let status = SERVICE_STATUS {
+            dwServiceType: SERVICE_WIN32_OWN_PROCESS,
+            dwCurrentState: state,
+            dwControlsAccepted: match state {
+                SERVICE_RUNNING => SERVICE_ACCEPT_STOP | SERVICE_ACCEPT_SHUTDOWN,
+                _ => 0,
+            },
+            dwWin32ExitCode: match code {
+                0 => NO_ERROR,
+                _ => ERROR_SERVICE_SPECIFIC_ERROR,
+            },
+            dwServiceSpecificExitCode: code as u32,
+            dwCheckPoint: 0,
+            dwWaitHint: match state {
+                SERVICE_STOP_PENDING => STOP_WAIT_HINT_MS,
+                _ => 0,
+            },
+        };
+
+        // SAFETY: the handle was returned by RegisterServiceCtrlHandlerExW
+        unsafe { SetServiceStatus(handle as SERVICE_STATUS_HANDLE, &status) };
+    }
+
+    fn empty_status() -> SERVICE_STATUS {
+        SERVICE_STATUS {
+            dwServiceType: 0,
+            dwCurrentState: 0,
+            dwControlsAccepted: 0,
+            dwWin32ExitCode: 0,
+            dwServiceSpecificExitCode: 0,
+            dwCheckPoint: 0,
+            dwWaitHint: 0,
+        }
+    }
+
+    /// Key registering the event source the logs are reported with.
+    fn event_source() -> String {
+        format!(r"SYSTEM\CurrentControlSet\Services\EventLog\Application\{APP_NAME}")
+    }
+
+    /// Sets `name` of `key` below HKEY_LOCAL_MACHINE, creating the key.
+    fn set_value(key: &str, name: &str, kind: REG_VALUE_TYPE, data: &[u8]) -> io::Result<()> {
+        let key = wide(key);
+        let name = wide(name);
+
+        // SAFETY: the names are nul terminated and `data` is `kind` encoded
+        match unsafe {
+            RegSetKeyValueW(
+                HKEY_LOCAL_MACHINE,
+                key.as_ptr(),
+                name.as_ptr(),
+                kind,
+                data.as_ptr() as *const _,
+                data.len() as u32,
+            )
+        } {
+            NO_ERROR => Ok(()),
+            code => Err(io::Error::from_raw_os_error(code as i32)),
+        }
+    }
+
+    fn wide(value: &str) -> Vec<u16> {
+        value.encode_utf16().chain([0]).collect()
+    }
+
+    fn u16_bytes(value: &[u16]) -> Vec<u8> {
+        value.iter().flat_map(|unit| unit.to_le_bytes()).collect()
+    }
+}
+
+/// Refused on other platforms.
+#[cfg(not(windows))]
+mod imp {
+    use std::io;
+
+    pub fn install(_command_line: &str, _environment: &[(String, String)]) -> io::Result<()> {
+        Err(unsupported())
+    }
+
+    pub fn uninstall() -> io::Result<()> {
+        Err(unsupported())
+    }
+
+    pub fn start() -> io::Result<()> {
+        Err(unsupported())
+    }
+
+    pub fn stop() -> io::Result<()> {
+        Err(unsupported())
+    }
+
+    pub fn dispatch() -> io::Result<()> {
+        Err(unsupported())
+    }
+
+    pub fn stopped(_code: i32) {}
+
+    fn unsupported() -> io::Error {
+        io::Error::new(
+            io::ErrorKind::Unsupported,
+            "services are only supported on Windows, see `install-systemd` for systemd",
+        )
+    }
+}
@@ -0,0 +1,918 @@
+//! Keeping a directory of a remote server under its limits over SFTP, version
+//! 3 of the protocol spoken through `ssh -s sftp`. Authentication, host keys
+//! and `~/.ssh/config` are those of the ssh client, so nothing needs to be
+//! installed on the server.
+
+use colored::*;
+use log::{debug, error, warn};
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::io::{BufRead, BufReader, Error, ErrorKind, Read, Write};
+use std::path::{Component, Path};
+use std::process::{Child, Command, Stdio};
+use std::sync::{Mutex, PoisonError};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::config::{DeleteMode, WatchConfig};
+use crate::scan::{self, DirListing, FileInfo, ScanOptions, ScanResult};
+use crate::storage::{Removed, Storage};
+
+/// Removals sent before waiting for their replies.
+const REMOVE_BATCH: usize = 64;
+
+/// Larger packets are taken for a broken connection.
+const MAX_PACKET: usize = 1 << 20;
+
+const FXP_INIT: u8 = 1;
+const FXP_VERSION: u8 = 2;
+const FXP_CLOSE: u8 = 4;
+const FXP_LSTAT: u8 = 7;
+const FXP_OPENDIR: u8 = 11;
+const FXP_READDIR: u8 = 12;
+const FXP_REMOVE: u8 = 13;
+const FXP_RMDIR: u8 = 15;
+const FXP_STATUS: u8 = 101;
+const FXP_HANDLE: u8 = 102;
+const FXP_NAME: u8 = 104;
+const FXP_ATTRS: u8 = 105;
+
+const FX_OK: u32 = 0;
+const FX_EOF: u32 = 1;
+const FX_NO_SUCH_FILE: u32 = 2;
+const FX_PERMISSION_DENIED: u32 = 3;
+const FX_OP_UNSUPPORTED: u32 = 8;
+
+const ATTR_SIZE: u32 = 0x1;
+const ATTR_UIDGID: u32 = 0x2;
+const ATTR_PERMISSIONS: u32 = 0x4;
+const ATTR_ACMODTIME: u32 = 0x8;
+const ATTR_EXTENDED: u32 = 0x8000_0000;
+
+const S_IFMT: u32 = 0o170000;
+const S_IFDIR: u32 = 0o040000;
+
+/// Files of `watch.directory`, `sftp://user@host:port/path`, over a single
+/// connection kept open between cycles.
+pub struct SftpStorage {
+    session: Mutex<Option<Session>>,
+}
+
+/// A server and the absolute path of the watched directory on it.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Location {
+    pub user: Option<String>,
+    pub host: String,
+    pub port: Option<u16>,
+    /// Without a trailing `/`
+    pub path: String,
+}
+
+impl Location {
+    /// Parses `sftp://[user@]host[:port]/path`, IPv6 hosts in brackets.
+    pub fn parse(directory: &str) -> Result<Location, String> {
+        let rest = directory
+            .strip_prefix(SftpStorage::SCHEME)
+            .ok_or_else(|| format!("{directory} is not an sftp:// URL"))?;
+        let (authority, path) = rest.split_at(rest.find('/').unwrap_or(rest.len()));
+
+        let (user, address) = match authority.rsplit_once('@') {
+            Some((user, address)) => (Some(user), address),
+            None => (None, authority),
+        };
+
+        let (host, port) = match address.strip_prefix('[') {
+            Some(address) => match address.split_once(']') {
+                Some((host, "")) => (host, None),
+                Some((host, port)) => (host, Some(port.strip_prefix(':').unwrap_or(port))),
+                None => ("", None),
+            },
+            None => match address.rsplit_once(':') {
+                Some((host, port)) => (host, Some(port)),
+                None => (address, None),
+            },
+        };
+
+        if host.is_empty() || authority.starts_with('-') || user.is_some_and(str::is_empty) {
+            return Err(format!("{directory} does not name a valid host"));
+        }
+
+        let port = port
+            .map(|port| {
+                port.parse::<u16>()
+                    .map_err(|_| format!("{directory} does not name a valid port"))
+            })
+            .transpose()?;
+
+        let path = path.trim_end_matches('/');
+
+        if path.is_empty() {
+            return Err(format!(
+                "{directory} does not name a directory on the server"
+            ));
+        }
+
+        Ok(Location {
+            user: user.map(str::to_string),
+            host: host.to_string(),
+            port,
+            path: path.to_string(),
+        })
+    }
+
+    /// The destination given to ssh, `[user@]host`.
+    fn destination(&self) -> String {
+        match &self.user {
+            Some(user) => format!("{user}@{}", self.host),
+            None => self.host.clone(),
+        }
+    }
+
+    /// The path on the server of `path`, scanned below `root`, refused
+    /// outside of it.
+    fn remote(&self, root: &Path, path: &Path) -> std::io::Result<String> {
+        let outside = || {
+            Error::new(
+                ErrorKind::PermissionDenied,
+                format!(
+                    "{} is outside of {}, not removing it",
+                    path.display(),
+                    root.display()
+                ),
+            )
+        };
+
+        let mut remote = self.path.clone();
+
+        for component in path.strip_prefix(root).map_err(|_| outside())?.components() {
+            let Component::Normal(name) = component else {
+                return Err(outside());
+            };
+
+            remote.push('/');
+            remote.push_str(name.to_str().ok_or_else(outside)?);
+        }
+
+        Ok(remote)
+    }
+}
+
+/// What the server tells of a file, as far as it does.
+#[derive(Debug, Default, PartialEq, Eq)]
+struct Attrs {
+    size: Option<u64>,
+    permissions: Option<u32>,
+    accessed: Option<u64>,
+    modified: Option<u64>,
+}
+
+impl Attrs {
+    fn is_dir(&self) -> bool {
+        self.permissions
+            .is_some_and(|permissions| permissions & S_IFMT == S_IFDIR)
+    }
+}
+
+/// Reads the fields of a reply.
+struct Fields<'a>(&'a [u8]);
+
+impl<'a> Fields<'a> {
+    fn bytes(&mut self, len: usize) -> std::io::Result<&'a [u8]> {
+        if self.0.len() < len {
+            return Err(Error::new(ErrorKind::InvalidData, "truncated SFTP packet"));
+        }
+
+        let (bytes, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(bytes)
+    }
+
+    fn u32(&mut self) -> std::io::Result<u32> {
+        let bytes = self.bytes(4)?;
+        Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    fn u64(&mut self) -> std::io::Result<u64> {
+        Ok((u64::from(self.u32()?) << 32) | u64::from(self.u32()?))
+    }
+
+    fn string(&mut self) -> std::io::Result<&'a [u8]> {
+        let len = self.u32()? as usize;
+        self.bytes(len)
+    }
+
+    fn attrs(&mut self) -> std::io::Result<Attrs> {
+        let flags = self.u32()?;
+        let mut attrs = Attrs::default();
+
+        if flags & ATTR_SIZE != 0 {
+            attrs.size = Some(self.u64()?);
+        }
+
+        if flags & ATTR_UIDGID != 0 {
+            self.bytes(8)?;
+        }
+
+        if flags & ATTR_PERMISSIONS != 0 {
+            attrs.permissions = Some(self.u32()?);
+        }
+
+        if flags & ATTR_ACMODTIME != 0 {
+            attrs.accessed = Some(u64::from(self.u32()?));
+            attrs.modified = Some(u64::from(self.u32()?));
+        }
+
+        if flags & ATTR_EXTENDED != 0 {
+            for _ in 0..self.u32()? {
+                self.string()?;
+                self.string()?;
+            }
+        }
+
+        Ok(attrs)
+    }
+}
+
+fn put_string(buf: &mut Vec<u8>, value: &[u8]) {
+    buf.extend_from_slice(&(value.len() as u32).to_be_bytes());
+    buf.extend_from_slice(value);
+}
+
+/// An SFTP connection, requests being answered one at a time but for
+/// removals.
+struct Session {
+    child: Option<Child>,
+    input: Box<dyn Write + Send>,
+    output: BufReader<Box<dyn Read + Send>>,
+    next_id: u32,
+    /// Replies received so far
+    received: u64,
+    /// Set once the connection failed, it is not used again
+    broken: bool,
+}
+
+impl Session {
+    /// Runs the ssh client for `location`, its errors being logged.
+    fn connect(location: &Location, watch: &WatchConfig) -> std::io::Result<Session> {
+        let mut command = Command::new("ssh");
+        command.args([
+            "-o",
+            "BatchMode=yes",
+            "-o",
+            "ConnectTimeout=30",
+            "-o",
+            "ServerAliveInterval=15",
+        ]);
+
+        if let Some(port) = location.port {
+            command.arg("-p").arg(port.to_string());
+        }
+
+        if let Some(identity_file) = &watch.sftp_identity_file {
+            command.arg("-i").arg(identity_file);
+        }
+
+        if let Some(known_hosts) = &watch.sftp_known_hosts {
+            let mut option = OsString::from("UserKnownHostsFile=");
+            option.push(known_hosts);
+            command.arg("-o").arg(option);
+        }
+
+        let mut child = command
+            .arg("-s")
+            .arg(location.destination())
+            .arg("sftp")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| Error::new(e.kind(), format!("Error running ssh: {e}")))?;
+
+        let (Some(input), Some(output), Some(stderr)) =
+            (child.stdin.take(), child.stdout.take(), child.stderr.take())
+        else {
+            return Err(Error::other("ssh was started without pipes"));
+        };
+
+        // Logged while the session lasts, the last line explaining why it
+        // could not start
+        let destination = location.destination();
+        let errors = std::thread::spawn(move || {
+            let mut last = String::new();
+
+            for line in BufReader::new(stderr).lines().map_while(Result::ok) {
+                warn!("{}", format!("ssh {destination}: {line}").yellow());
+                last = line;
+            }
+
+            last
+        });
+
+        // Dropping a session that failed to start ends ssh
+        Session::new(Some(child), Box::new(input), Box::new(output)).map_err(|e| {
+            let reason = match errors.join() {
+                Ok(last) if !last.is_empty() => last,
+                _ => e.to_string(),
+            };
+
+            Error::new(
+                e.kind(),
+                format!(
+                    "Error starting SFTP on {}: {reason}",
+                    location.destination()
+                ),
+            )
+        })
+    }
+
+    /// Negotiates version 3 of the protocol over `input` and `output`.
+    fn new(
+        child: Option<Child>,
+        input: Box<dyn Write + Send>,
+        output: Box<dyn Read + Send>,
+    ) -> std::io::Result<Session> {
+        let mut session = Session {
+            child,
+            input,
+            output: BufReader::new(output),
+            next_id: 1,
+            received: 0,
+            broken: false,
+        };
+
+        let mut init = Vec::new();
+        init.extend_from_slice(&5u32.to_be_bytes());
+        init.push(FXP_INIT);
+        init.extend_from_slice(&3u32.to_be_bytes());
+        session.input.write_all(&init)?;
+        session.input.flush()?;
+
+        let (kind, payload) = session.read_packet()?;
+        let version = Fields(&payload).u32()?;
+
+        if kind != FXP_VERSION || version < 3 {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("unexpected reply to SFTP init, type {kind} version {version}"),
+            ));
+        }
+
+        Ok(session)
+    }
+
+    fn read_packet(&mut self) -> std::io::Result<(u8, Vec<u8>)> {
+        let mut len = [0; 4];
+        self.output.read_exact(&mut len)?;
+        let len = u32::from_be_bytes(len) as usize;
+
+        if len == 0 || len > MAX_PACKET {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("SFTP packet of {len} bytes"),
+            ));
+        }
+
+        let mut packet = vec![0; len];
+        self.output.read_exact(&mut packet)?;
+        let kind = packet.remove(0);
+
+        Ok((kind, packet))
+    }
+
+    /// Sends a request of `kind`, returning its id.
+    fn send(&mut self, kind: u8, fields: &[u8]) -> std::io::Result<u32> {
+        let id = self.next_id;
+        self.next_id = self.next_id.wrapping_add(1);
+
+        let mut packet = Vec::with_capacity(fields.len() + 9);
+        packet.extend_from_slice(&(fields.len() as u32 + 5).to_be_bytes());
+        packet.push(kind);
+        packet.extend_from_slice(&id.to_be_bytes());
+        packet.extend_from_slice(fields);
+
+        self.input
+            .write_all(&packet)
+            .inspect_err(|_| self.broken = true)?;
+
+        Ok(id)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.input.flush().inspect_err(|_| self.broken = true)
+    }
+
+    /// Reads a reply, its type, request id and remaining fields.
+    fn receive(&mut self) -> std::io::Result<(u8, u32, Vec<u8>)> {
+        let (kind, payload) = self.read_packet().inspect_err(|_| self.broken = true)?;
+        let id = Fields(&payload).u32().inspect_err(|_| self.broken = true)?;
+        self.received += 1;
+
+        Ok((kind, id, payload[4..].to_vec()))
+    }
+
+    /// Sends a request about `path` and waits for its reply.
+    fn request(&mut self, kind: u8, path: &[u8]) -> std::io::Result<(u8, Vec<u8>)> {
+        let mut fields = Vec::new();
+        put_string(&mut fields, path);
+
+        let id = self.send(kind, &fields)?;
+        self.flush()?;
+        let (kind, reply, payload) = self.receive()?;
+
+        if reply != id {
+            self.broken = true;
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("SFTP reply to request {reply} while waiting for {id}"),
+            ));
+        }
+
+        Ok((kind, payload))
+    }
+
+    /// Lists `path`, `.` and `..` included.
+    fn read_dir(&mut self, path: &str) -> std::io::Result<Vec<(Vec<u8>, Attrs)>> {
+        let (kind, payload) = self.request(FXP_OPENDIR, path.as_bytes())?;
+        let handle = match kind {
+            FXP_HANDLE => Fields(&payload).string()?.to_vec(),
+            kind => return Err(reply_error(kind, &payload, path)),
+        };
+
+        let mut entries = Vec::new();
+
+        let listed = loop {
+            let (kind, payload) = match self.request(FXP_READDIR, &handle) {
+                Ok(reply) => reply,
+                Err(e) => break Err(e),
+            };
+
+            if kind == FXP_STATUS && Fields(&payload).u32()? == FX_EOF {
+                break Ok(());
+            }
+
+            if kind != FXP_NAME {
+                break Err(reply_error(kind, &payload, path));
+            }
+
+            let mut fields = Fields(&payload);
+
+            for _ in 0..fields.u32()? {
+                let name = fields.string()?.to_vec();
+                // The `ls -l` line, of no use
+                fields.string()?;
+                entries.push((name, fields.attrs()?));
+            }
+        };
+
+        let closed = match self.broken {
+            true => Ok(()),
+            false => self
+                .request(FXP_CLOSE, &handle)
+                .and_then(|(kind, payload)| status(kind, &payload, path)),
+        };
+
+        listed.and(closed).map(|()| entries)
+    }
+
+    fn lstat(&mut self, path: &str) -> std::io::Result<Attrs> {
+        match self.request(FXP_LSTAT, path.as_bytes())? {
+            (FXP_ATTRS, payload) => Fields(&payload).attrs(),
+            (kind, payload) => Err(reply_error(kind, &payload, path)),
+        }
+    }
+
+    fn remove_dir(&mut self, path: &str) -> std::io::Result<()> {
+        let (kind, payload) = self.request(FXP_RMDIR, path.as_bytes())?;
+        status(kind, &payload, path)
+    }
+
+    /// Removes `paths`, sending every request before reading the replies so
+    /// the round trips overlap.
+    fn remove_files(&mut self, paths: &[&str]) -> std::io::Result<Vec<std::io::Result<()>>> {
+        let mut pending = HashMap::new();
+
+        for (i, path) in paths.iter().enumerate() {
+            let mut fields = Vec::new();
+            put_string(&mut fields, path.as_bytes());
+            pending.insert(self.send(FXP_REMOVE, &fields)?, i);
+        }
+
+        self.flush()?;
+
+        let mut results: Vec<_> = paths.iter().map(|_| None).collect();
+
+        while !pending.is_empty() {
+            let (kind, id, payload) = self.receive()?;
+
+            // Servers may reply out of order
+            let Some(i) = pending.remove(&id) else {
+                self.broken = true;
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!("SFTP reply to unknown request {id}"),
+                ));
+            };
+
+            results[i] = Some(status(kind, &payload, paths[i]));
+        }
+
+        Ok(results.into_iter().flatten().collect())
+    }
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        if let Some(child) = &mut self.child {
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+    }
+}
+
+/// `Ok` for a successful status reply, the error it reports otherwise.
+fn status(kind: u8, payload: &[u8], path: &str) -> std::io::Result<()> {
+    match (kind, Fields(payload).u32()) {
+        (FXP_STATUS, Ok(FX_OK)) => Ok(()),
+        _ => Err(reply_error(kind, payload, path)),
+    }
+}
+
+fn reply_error(kind: u8, payload: &[u8], path: &str) -> Error {
+    if kind != FXP_STATUS {
+        return Error::new(
+            ErrorKind::InvalidData,
+            format!("unexpected SFTP reply of type {kind} about {path}"),
+        );
+    }
+
+    let mut fields = Fields(payload);
+    let code = fields.u32().unwrap_or(u32::MAX);
+    let message = fields
+        .string()
+        .map(String::from_utf8_lossy)
+        .unwrap_or_default();
+
+    let kind = match code {
+        FX_NO_SUCH_FILE => ErrorKind::NotFound,
+        FX_PERMISSION_DENIED => ErrorKind::PermissionDenied,
+        FX_OP_UNSUPPORTED => ErrorKind::Unsupported,
+        _ => ErrorKind::Other,
+    };
+
+    Error::new(kind, format!("{path}: {message} (SFTP status {code})"))
+}
+
+impl SftpStorage {
+    pub const SCHEME: &'static str = "sftp://";
+
+    pub fn new() -> SftpStorage {
+        SftpStorage {
+            session: Mutex::new(None),
+        }
+    }
+
+    /// Runs `f` on the connection to the server of `watch`, opened on first
+    /// use and again once it failed.
+    fn with_session<T>(
+        &self,
+        watch: &WatchConfig,
+        mut f: impl FnMut(&mut Session) -> std::io::Result<T>,
+    ) -> std::io::Result<T> {
+        let location = Location::parse(&watch.directory).map_err(Error::other)?;
+        let mut session = self.session.lock().unwrap_or_else(PoisonError::into_inner);
+
+        let open = match session.take().filter(|session| !session.broken) {
+            Some(open) => session.insert(open),
+            None => return f(session.insert(Session::connect(&location, watch)?)),
+        };
+
+        let received = open.received;
+
+        match f(open) {
+            // A connection left idle since the last cycle may have been
+            // dropped, it is opened again if nothing got through
+            Err(_) if open.broken && open.received == received => {
+                f(session.insert(Session::connect(&location, watch)?))
+            }
+            result => result,
+        }
+    }
+}
+
+impl Default for SftpStorage {
+    fn default() -> SftpStorage {
+        SftpStorage::new()
+    }
+}
+
+impl Storage for SftpStorage {
+    fn scan(&mut self, watch: &WatchConfig, options: &ScanOptions) -> std::io::Result<ScanResult> {
+        let location = Location::parse(&watch.directory).map_err(Error::other)?;
+
+        let mut scanned = self.with_session(watch, |session| {
+            read_tree(
+                session,
+                Path::new(&watch.directory),
+                &location.path,
+                options,
+            )
+        })?;
+        scanned.count_hard_links_once();
+
+        Ok(scanned)
+    }
+
+    fn changed_since_scan(&self, watch: &WatchConfig, file: &FileInfo) -> std::io::Result<bool> {
+        let remote = remote_path(watch, &file.path)?;
+        let attrs = self.with_session(watch, |session| session.lstat(&remote))?;
+
+        Ok(attrs.modified != Some(file.modified) || attrs.size != Some(file.size))
+    }
+
+    fn batch_size(&self) -> usize {
+        REMOVE_BATCH
+    }
+
+    fn remove_files(&self, watch: &WatchConfig, files: &[&FileInfo]) -> Vec<Removed> {
+        if watch.delete_mode != DeleteMode::Delete {
+            return files
+                .iter()
+                .map(|_| {
+                    Err(Error::new(
+                        ErrorKind::Unsupported,
+                        format!(
+                            "DELETE_MODE={} is not supported over SFTP",
+                            watch.delete_mode
+                        ),
+                    ))
+                })
+                .collect();
+        }
+
+        let remotes: Vec<_> = files
+            .iter()
+            .map(|file| remote_path(watch, &file.path))
+            .collect();
+        let paths: Vec<&str> = remotes
+            .iter()
+            .filter_map(|remote| remote.as_deref().ok())
+            .collect();
+
+        let mut removed = match self.with_session(watch, |session| session.remove_files(&paths)) {
+            Ok(removed) => removed.into_iter(),
+            Err(e) => {
+                return files
+                    .iter()
+                    .map(|_| Err(Error::new(e.kind(), e.to_string())))
+                    .collect()
+            }
+        };
+
+        remotes
+            .into_iter()
+            .map(|remote| {
+                remote.and_then(|_| {
+                    removed
+                        .next()
+                        .unwrap_or_else(|| Err(Error::other("no reply from the server")))
+                        .map(|()| None)
+                })
+            })
+            .collect()
+    }
+
+    fn remove_dir(&self, watch: &WatchConfig, path: &Path) -> std::io::Result<()> {
+        let remote = remote_path(watch, path)?;
+
+        self.with_session(watch, |session| session.remove_dir(&remote))
+    }
+}
+
+/// The path on the server of `path`, a file or directory scanned below
+/// `watch.directory`.
+fn remote_path(watch: &WatchConfig, path: &Path) -> std::io::Result<String> {
+    Location::parse(&watch.directory)
+        .map_err(Error::other)?
+        .remote(Path::new(&watch.directory), path)
+}
+
+/// Scans `remote`, reported as `path`, and the directories below it one at a
+/// time over the single connection.
+fn read_tree(
+    session: &mut Session,
+    path: &Path,
+    remote: &str,
+    options: &ScanOptions,
+) -> std::io::Result<ScanResult> {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let mut listing = DirListing::default();
+    options.check_deadline()?;
+
+    for (name, attrs) in session.read_dir(remote)? {
+        if name == b"." || name == b".." {
+            continue;
+        }
+
+        let Ok(name) = String::from_utf8(name) else {
+            error!(
+                event = "error", path:% = path.display();
+                "{}",
+                format!("Skipping an entry of {} whose name is not UTF-8", path.display()).red()
+            );
+            listing.errors += 1;
+            listing.protected = true;
+            continue;
+        };
+
+        let path = path.join(&name);
+        let excluded = options.is_excluded(&path);
+
+        if attrs.is_dir() && options.is_excluded_dir(&path) {
+            debug!(
+                "{}",
+                format!("Skipping excluded directory: {}", path.display()).yellow()
+            );
+            listing.protected = true;
+        } else if attrs.is_dir() && options.beyond_max_depth(&path, true) {
+            debug!(
+                "{}",
+                format!("Not descending below MAX_DEPTH: {}", path.display()).yellow()
+            );
+            listing.protected = true;
+        } else if attrs.is_dir() {
+            listing.subdirs.push((path, excluded));
+        } else if excluded {
+            debug!(
+                "{}",
+                format!("Skipping excluded file: {}", path.display()).yellow()
+            );
+            listing.protected = true;
+        } else {
+            let modified = attrs.modified.unwrap_or(now);
+            listing.files.push(FileInfo {
+                path,
+                size: attrs.size.unwrap_or(0),
+                modified,
+                accessed: attrs
+                    .accessed
+                    .map_or(modified, |accessed| accessed.max(modified)),
+                hard_link: None,
+                inode: 0,
+            });
+        }
+    }
+
+    let (files, size) = match options.candidates {
+        Some(candidates) => candidates.offer(std::mem::take(&mut listing.files)),
+        None => (0, 0),
+    };
+
+    let mut scanned = Vec::new();
+
+    for (subdir, excluded) in &listing.subdirs {
+        let name = subdir
+            .file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or_default();
+
+        match read_tree(session, subdir, &format!("{remote}/{name}"), options) {
+            // Nothing more can be listed
+            Err(e) if session.broken || options.check_deadline().is_err() => return Err(e),
+            items => scanned.push((subdir.clone(), *excluded, items)),
+        }
+    }
+
+    let mut result = scan::assemble(listing, scanned);
+    result.total_files += files;
+    result.total_size += size;
+
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::SizeAccounting;
+    use crate::scan::ScanEntry;
+    use globset::GlobSet;
+
+    fn reply(kind: u8, id: u32, fields: &[u8]) -> Vec<u8> {
+        let mut packet = Vec::new();
+        packet.extend_from_slice(&(fields.len() as u32 + 5).to_be_bytes());
+        packet.push(kind);
+        packet.extend_from_slice(&id.to_be_bytes());
+        packet.extend_from_slice(fields);
+        packet
+    }
+
+    fn status(id: u32, code: u32) -> Vec<u8> {
+        let mut fields = code.to_be_bytes().to_vec();
+        put_string(&mut fields, b"");
+        put_string(&mut fields, b"");
+        reply(FXP_STATUS, id, &fields)
+    }
+
+    fn entry(fields: &mut Vec<u8>, name: &[u8], size: u64, permissions: u32, modified: u32) {
+        put_string(fields, name);
+        put_string(fields, b"");
+        fields.extend_from_slice(&(ATTR_SIZE | ATTR_PERMISSIONS | ATTR_ACMODTIME).to_be_bytes());
+        fields.extend_from_slice(&size.to_be_bytes());
+        fields.extend_from_slice(&permissions.to_be_bytes());
+        fields.extend_from_slice(&modified.to_be_bytes());
+        fields.extend_from_slice(&modified.to_be_bytes());
+    }
+
+    #[test]
+    fn scans_the_replies_to_directory_listings() {
+        let mut handle = Vec::new();
+        put_string(&mut handle, b"h");
+
+        let mut names = 4u32.to_be_bytes().to_vec();
+        entry(&mut names, b".", 0, S_IFDIR | 0o755, 0);
+        entry(&mut names, b"..", 0, S_IFDIR | 0o755, 0);
+        entry(&mut names, b"old.log", 10, 0o100644, 100);
+        entry(&mut names, b"sub", 0, S_IFDIR | 0o755, 200);
+
+        // Version 3, then the replies to listing the directory and `sub`
+        let mut output = vec![0, 0, 0, 5, FXP_VERSION, 0, 0, 0, 3];
+        for packet in [
+            reply(FXP_HANDLE, 1, &handle),
+            reply(FXP_NAME, 2, &names),
+            status(3, FX_EOF),
+            status(4, FX_OK),
+            reply(FXP_HANDLE, 5, &handle),
+            status(6, FX_EOF),
+            status(7, FX_OK),
+        ] {
+            output.extend(packet);
+        }
+
+        let mut session = Session::new(
+            None,
+            Box::new(std::io::sink()),
+            Box::new(std::io::Cursor::new(output)),
+        )
+        .unwrap();
+
+        let root = Path::new("sftp://backup@host/srv/uploads");
+        let exclude = GlobSet::empty();
+        let scanned = read_tree(
+            &mut session,
+            root,
+            "/srv/uploads",
+            &ScanOptions {
+                root,
+                exclude: &exclude,
+                exclude_dirs: &[],
+                device: None,
+                follow_symlinks: false,
+                size_accounting: SizeAccounting::Apparent,
+                candidates: None,
+                protection: None,
+                pinned: false,
+                owners: None,
+                clutter: false,
+                deadline: None,
+                max_depth: None,
+            },
+        )
+        .unwrap();
+
+        assert_eq!((scanned.total_files, scanned.total_size), (1, 10));
+        assert!(matches!(
+            &scanned.entries[..],
+            [ScanEntry::File(file), ScanEntry::Folder(folder)]
+                if file.path == root.join("old.log") && file.modified == 100
+                    && folder.path == root.join("sub") && folder.files == 0
+        ));
+    }
+
+    #[test]
+    fn locations_map_paths_to_the_server() {
+        let location = Location::parse("sftp://backup@[::1]:2222/srv/uploads/").unwrap();
+        assert_eq!(
+            location,
+            Location {
+                user: Some("backup".to_string()),
+                host: "::1".to_string(),
+                port: Some(2222),
+                path: "/srv/uploads".to_string(),
+            }
+        );
+
+        let root = Path::new("sftp://backup@[::1]:2222/srv/uploads/");
+        assert_eq!(
+            location.remote(root, &root.join("a/b.log")).unwrap(),
+            "/srv/uploads/a/b.log"
+        );
+        assert!(location
+            .remote(root, Path::new("sftp://other/b.log"))
+            .is_err());
+        assert!(location.remote(root, &root.join("../b.log")).is_err());
+
+        assert!(Location::parse("sftp://host").is_err());
+        assert!(Location::parse("sftp://-oProxyCommand=x/srv").is_err());
+    }
+}
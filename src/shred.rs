@@ -0,0 +1,152 @@
+//! Overwriting files before unlinking them, for `DELETE_MODE=shred`.
+//!
+//! This only makes the data unrecoverable where the filesystem writes in
+//! place: copy-on-write filesystems, snapshots, journals keeping data and
+//! the wear leveling of SSDs all leave the old blocks around.
+
+use std::collections::hash_map::RandomState;
+use std::fs::{File, OpenOptions};
+use std::hash::BuildHasher;
+use std::io::{Seek, SeekFrom, Write};
+use std::path::Path;
+
+/// Overwritten at once.
+const CHUNK: usize = 64 * 1024;
+
+/// Overwrites `path` with `passes` passes of random data, the last one of
+/// zeros, then truncates and unlinks it. Symlinks and files with other hard
+/// links, whose data stays reachable anyway, are only unlinked.
+pub fn shred(path: &Path, passes: u32) -> std::io::Result<()> {
+    let metadata = std::fs::symlink_metadata(path)?;
+
+    if metadata.is_file() && !has_other_links(&metadata) {
+        let mut file = open_writable(path, &metadata)?;
+        let len = metadata.len();
+        let mut random = Random::new();
+
+        for pass in 1..=passes {
+            match pass == passes {
+                true => overwrite(&mut file, len, |chunk| chunk.fill(0))?,
+                false => overwrite(&mut file, len, |chunk| random.fill(chunk))?,
+            }
+        }
+
+        file.set_len(0)?;
+        file.sync_all()?;
+    }
+
+    std::fs::remove_file(path)
+}
+
+fn overwrite(file: &mut File, len: u64, mut fill: impl FnMut(&mut [u8])) -> std::io::Result<()> {
+    let mut chunk = vec![0; CHUNK];
+    let mut left = len;
+
+    file.seek(SeekFrom::Start(0))?;
+
+    while left > 0 {
+        let size = left.min(CHUNK as u64) as usize;
+        fill(&mut chunk[..size]);
+        file.write_all(&chunk[..size])?;
+        left -= size as u64;
+    }
+
+    file.sync_data()
+}
+
+/// Opens for writing, read-only files being made writable first as they
+/// are about to be removed.
+fn open_writable(path: &Path, metadata: &std::fs::Metadata) -> std::io::Result<File> {
+    match open(path) {
+        Err(e) if e.kind() == std::io::ErrorKind::PermissionDenied => {
+            let mut permissions = metadata.permissions();
+            if !permissions.readonly() {
+                return Err(e);
+            }
+
+            #[allow(clippy::permissions_set_readonly_false)]
+            permissions.set_readonly(false);
+            std::fs::set_permissions(path, permissions)?;
+            open(path)
+        }
+        result => result,
+    }
+}
+
+/// Opens for writing without following a symlink swapped in since the
+/// file was checked.
+#[cfg(unix)]
+fn open(path: &Path) -> std::io::Result<File> {
+    use std::os::unix::fs::OpenOptionsExt;
+
+    OpenOptions::new()
+        .write(true)
+        .custom_flags(libc::O_NOFOLLOW)
+        .open(path)
+}
+
+#[cfg(not(unix))]
+fn open(path: &Path) -> std::io::Result<File> {
+    OpenOptions::new().write(true).open(path)
+}
+
+#[cfg(unix)]
+fn has_other_links(metadata: &std::fs::Metadata) -> bool {
+    use std::os::unix::fs::MetadataExt;
+
+    metadata.nlink() > 1
+}
+
+#[cfg(not(unix))]
+fn has_other_links(_metadata: &std::fs::Metadata) -> bool {
+    false
+}
+
+/// xorshift64*, random enough to leave nothing recognizable and much faster
+/// than the system's generator.
+struct Random(u64);
+
+impl Random {
+    fn new() -> Random {
+        Random(RandomState::new().hash_one(std::time::SystemTime::now()) | 1)
+    }
+
+    fn fill(&mut self, chunk: &mut [u8]) {
+        for bytes in chunk.chunks_mut(8) {
+            self.0 ^= self.0 >> 12;
+            self.0 ^= self.0 << 25;
+            self.0 ^= self.0 >> 27;
+            let value = self.0.wrapping_mul(0x2545_f491_4f6c_dd1d).to_le_bytes();
+            bytes.copy_from_slice(&value[..bytes.len()]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::APP_NAME;
+
+    #[test]
+    fn files_are_overwritten_then_removed() {
+        let root = std::env::temp_dir().join(format!("{APP_NAME}-shred-{}", std::process::id()));
+        std::fs::create_dir_all(&root).unwrap();
+        let path = root.join("export.csv");
+        std::fs::write(&path, vec![b'x'; CHUNK * 2 + 10]).unwrap();
+
+        let mut file = open(&path).unwrap();
+        let mut random = Random::new();
+        overwrite(&mut file, CHUNK as u64 + 5, |chunk| random.fill(chunk)).unwrap();
+        drop(file);
+
+        let contents = std::fs::read(&path).unwrap();
+        assert_eq!(contents.len(), CHUNK * 2 + 10);
+        assert!(contents[..CHUNK + 5].iter().filter(|&&b| b == b'x').count() < 1024);
+        assert!(contents[CHUNK + 5..].iter().all(|&b| b == b'x'));
+
+        shred(&path, 2).unwrap();
+        assert!(!path.exists());
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
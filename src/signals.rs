@@ -0,0 +1,125 @@
+//! Signal handling. Signals are turned into [`Wake`] messages for the
+//! scheduler so it never has to poll.
+
+use std::sync::atomic::AtomicBool;
+use std::sync::mpsc::Sender;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Why the scheduler was woken up before its next run was due.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Wake {
+    /// SIGTERM or SIGINT was received
+    Shutdown,
+    /// SIGHUP was received or the config file changed
+    Reload,
+    /// SIGUSR1 was received or a run was requested through the control API,
+    /// every directory is cleaned up right away
+    RunNow,
+    /// Cleanups were resumed through the control API
+    Resume,
+    /// A cycle run on its own thread returned
+    CycleFinished,
+}
+
+/// Listens for SIGTERM and SIGINT. The first one sets `stop`, which ends the
+/// current cleanup after the file being removed, and wakes the scheduler;
+/// the process is exited if that takes longer than `timeout`. SIGHUP asks
+/// for the configuration to be reloaded and SIGUSR1 for an immediate cycle.
+#[cfg(unix)]
+pub fn spawn(waker: Sender<Wake>, stop: Arc<AtomicBool>, timeout: Duration) -> std::io::Result<()> {
+    use signal_hook::consts::{SIGHUP, SIGINT, SIGTERM, SIGUSR1};
+    use signal_hook::iterator::Signals;
+
+    let mut signals = Signals::new([SIGTERM, SIGINT, SIGHUP, SIGUSR1])?;
+
+    std::thread::Builder::new()
+        .name("signals".to_string())
+        .spawn(move || {
+            for signal in signals.forever() {
+                match signal {
+                    SIGHUP => {
+                        let _ = waker.send(Wake::Reload);
+                    }
+                    SIGUSR1 => {
+                        let _ = waker.send(Wake::RunNow);
+                    }
+                    _ => shut_down(signal, &waker, &stop, timeout),
+                }
+            }
+        })?;
+
+    Ok(())
+}
+
+/// Windows only delivers Ctrl-C (SIGINT) and Ctrl-Break (SIGTERM) and only
+/// lets a flag be set from the handler, which is polled instead.
+#[cfg(windows)]
+pub fn spawn(waker: Sender<Wake>, stop: Arc<AtomicBool>, timeout: Duration) -> std::io::Result<()> {
+    use signal_hook::consts::{SIGINT, SIGTERM};
+    use std::sync::atomic::Ordering;
+
+    let received = [SIGINT, SIGTERM].map(|signal| (signal, Arc::new(AtomicBool::new(false))));
+
+    for (signal, flag) in &received {
+        signal_hook::flag::register(*signal, Arc::clone(flag))?;
+    }
+
+    std::thread::Builder::new()
+        .name("signals".to_string())
+        .spawn(move || loop {
+            std::thread::sleep(Duration::from_millis(100));
+
+            for (signal, flag) in &received {
+                if flag.swap(false, Ordering::SeqCst) {
+                    shut_down(*signal, &waker, &stop, timeout);
+                }
+            }
+        })?;
+
+    Ok(())
+}
+
+#[cfg(not(any(unix, windows)))]
+pub fn spawn(
+    _waker: Sender<Wake>,
+    _stop: Arc<AtomicBool>,
+    _timeout: Duration,
+) -> std::io::Result<()> {
+    Ok(())
+}
+
+/// Sets `stop` and wakes the scheduler on the first shutdown signal, then
+/// exits the process if the cleanup in progress has not ended by `timeout`.
+#[cfg(any(unix, windows))]
+fn shut_down(signal: i32, waker: &Sender<Wake>, stop: &AtomicBool, timeout: Duration) {
+    use colored::*;
+    use log::{error, warn};
+    use std::sync::atomic::Ordering;
+
+    if stop.swap(true, Ordering::SeqCst) {
+        return;
+    }
+
+    warn!(
+        event = "shutdown", signal;
+        "{}",
+        format!("Received signal {signal}, shutting down after the current removal").yellow()
+    );
+    let _ = waker.send(Wake::Shutdown);
+
+    std::thread::spawn(move || {
+        std::thread::sleep(timeout);
+        error!(
+            event = "error";
+            "{}",
+            format!(
+                "Shutdown did not finish within {} seconds, exiting",
+                timeout.as_secs()
+            )
+            .red()
+        );
+        log::logger().flush();
+        std::process::exit(1);
+    });
+}
@@ -0,0 +1,171 @@
+//! Pushing the figures of every cycle over UDP in the StatsD or DogStatsD
+//! format, for setups without a Prometheus to scrape `/metrics`.
+
+use log::debug;
+use std::net::UdpSocket;
+
+use maxdirsize::config::StatsdFormat;
+use maxdirsize::{CycleOutcome, CycleReport, APP_NAME};
+
+/// Largest datagram sent, below the usual MTU so packets are not
+/// fragmented.
+const MAX_PACKET: usize = 1432;
+
+pub struct Statsd {
+    socket: UdpSocket,
+    format: StatsdFormat,
+}
+
+impl Statsd {
+    /// Sends to `addr`, e.g. `127.0.0.1:8125`, resolved once.
+    pub fn connect(addr: &str, format: StatsdFormat) -> std::io::Result<Statsd> {
+        let socket = UdpSocket::bind(match addr.starts_with('[') {
+            true => "[::]:0",
+            false => "0.0.0.0:0",
+        })?;
+        socket.connect(addr)?;
+
+        Ok(Statsd { socket, format })
+    }
+
+    pub fn record(&self, directory: &str, report: &CycleReport) {
+        let files = match report.dry_run {
+            true => report.total_files,
+            false => report.total_files - report.removed_files,
+        };
+        let (removed_files, removed_dirs, freed_bytes) = match report.dry_run {
+            true => (0, 0, 0),
+            false => (
+                report.removed_files,
+                report.removed_dirs,
+                report.freed_bytes,
+            ),
+        };
+
+        let mut metrics = vec![
+            ("size_bytes", report.size_after, "g"),
+            ("limit_bytes", report.limit_bytes, "g"),
+            ("files", files, "g"),
+            ("files_deleted", removed_files, "c"),
+            ("directories_deleted", removed_dirs, "c"),
+            ("bytes_freed", freed_bytes, "c"),
+            ("errors", report.errors, "c"),
+            ("cycle_duration", report.duration.as_millis() as u64, "ms"),
+            (
+                "scan_duration",
+                report.scan_duration.as_millis() as u64,
+                "ms",
+            ),
+        ];
+
+        if matches!(
+            report.outcome,
+            CycleOutcome::CleanedUp | CycleOutcome::Deferred
+        ) {
+            metrics.push(("cycles_over_limit", 1, "c"));
+        }
+
+        let lines: Vec<String> = metrics
+            .into_iter()
+            .map(|(name, value, kind)| self.line(directory, name, value, kind))
+            .collect();
+
+        for packet in packets(&lines) {
+            // Nobody listening is no reason to stop, as with any UDP sink
+            if let Err(e) = self.socket.send(packet.as_bytes()) {
+                debug!("Error sending metrics to StatsD: {e}");
+            }
+        }
+    }
+
+    fn line(&self, directory: &str, name: &str, value: u64, kind: &str) -> String {
+        match self.format {
+            StatsdFormat::Statsd => {
+                format!("{APP_NAME}.{}.{name}:{value}|{kind}", sanitize(directory))
+            }
+            StatsdFormat::Dogstatsd => format!(
+                "{APP_NAME}.{name}:{value}|{kind}|#directory:{}",
+                directory.replace([',', '|', '#'], "_")
+            ),
+        }
+    }
+}
+
+/// `directory` as a component of a metric name, e.g. `var_log_app` for
+/// `/var/log/app`.
+fn sanitize(directory: &str) -> String {
+    let name: String = directory
+        .chars()
+        .map(|c| match c.is_ascii_alphanumeric() || c == '-' {
+            true => c,
+            false => '_',
+        })
+        .collect();
+
+    name.trim_matches('_').to_string()
+}
+
+/// `lines` joined by newlines into as few packets as fit `MAX_PACKET`.
+fn packets(lines: &[String]) -> Vec<String> {
+    let mut packets: Vec<String> = Vec::new();
+
+    for line in lines {
+        match packets.last_mut() {
+            Some(packet) if packet.len() + 1 + line.len() <= MAX_PACKET => {
+                packet.push('\n');
+                packet.push_str(line);
+            }
+            _ => packets.push(line.clone()),
+        }
+    }
+
+    packets
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn cycles_are_sent_as_gauges_counters_and_timers() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        receiver
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let addr = receiver.local_addr().unwrap().to_string();
+
+        let report = CycleReport {
+            outcome: CycleOutcome::CleanedUp,
+            total_files: 10,
+            size_after: 800,
+            limit_bytes: 1000,
+            removed_files: 3,
+            freed_bytes: 300,
+            duration: Duration::from_millis(1500),
+            ..Default::default()
+        };
+
+        let mut packet = [0; MAX_PACKET];
+
+        Statsd::connect(&addr, StatsdFormat::Statsd)
+            .unwrap()
+            .record("/var/log/app", &report);
+        let length = receiver.recv(&mut packet).unwrap();
+        let lines = String::from_utf8_lossy(&packet[..length]).into_owned();
+
+        assert!(lines.starts_with("maxdirsize.var_log_app.size_bytes:800|g\n"));
+        assert!(lines.contains("\nmaxdirsize.var_log_app.files:7|g\n"));
+        assert!(lines.contains("\nmaxdirsize.var_log_app.bytes_freed:300|c\n"));
+        assert!(lines.contains("\nmaxdirsize.var_log_app.cycle_duration:1500|ms\n"));
+        assert!(lines.ends_with("\nmaxdirsize.var_log_app.cycles_over_limit:1|c"));
+
+        Statsd::connect(&addr, StatsdFormat::Dogstatsd)
+            .unwrap()
+            .record("/var/log/app", &report);
+        let length = receiver.recv(&mut packet).unwrap();
+        let lines = String::from_utf8_lossy(&packet[..length]).into_owned();
+
+        assert!(lines.starts_with("maxdirsize.size_bytes:800|g|#directory:/var/log/app\n"));
+    }
+}
@@ -0,0 +1,193 @@
+//! A JSON status file rewritten after every cycle, for monitoring that polls
+//! files rather than network endpoints.
+
+use colored::*;
+use log::warn;
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use maxdirsize::{CycleOutcome, CycleReport, VERSION};
+
+#[derive(Default)]
+struct DirectoryStatus {
+    last_run: Option<SystemTime>,
+    last_outcome: Option<CycleOutcome>,
+    /// [`CycleReport::summary`] of the last cycle
+    last_cycle: Option<serde_json::Value>,
+    size_bytes: u64,
+    limit_bytes: u64,
+    files: u64,
+    cycles: u64,
+    failed_cycles: u64,
+    errors_total: u64,
+}
+
+pub struct StatusFile {
+    path: PathBuf,
+    started: SystemTime,
+    directories: BTreeMap<String, DirectoryStatus>,
+}
+
+impl StatusFile {
+    pub fn new(path: &Path) -> StatusFile {
+        StatusFile {
+            path: path.to_path_buf(),
+            started: SystemTime::now(),
+            directories: BTreeMap::new(),
+        }
+    }
+
+    /// Records the cycle of `directory` and rewrites the file.
+    pub fn record(&mut self, directory: &str, report: &CycleReport) {
+        let status = self.directories.entry(directory.to_string()).or_default();
+
+        status.last_run = Some(SystemTime::now());
+        status.last_outcome = Some(report.outcome);
+        status.last_cycle = Some(report.summary());
+        status.size_bytes = report.size_after;
+        status.limit_bytes = report.limit_bytes;
+        status.files = match report.dry_run {
+            true => report.total_files,
+            false => report.total_files - report.removed_files,
+        };
+        status.cycles += 1;
+        status.errors_total += report.errors;
+
+        if report.outcome == CycleOutcome::Failed {
+            status.failed_cycles += 1;
+        }
+
+        if let Err(e) = self.write() {
+            warn!(
+                event = "status_file_failed", path:% = self.path.display();
+                "{}",
+                format!("Error writing status file {}: {e}", self.path.display()).yellow()
+            );
+        }
+    }
+
+    /// Forgets the directories no longer watched.
+    pub fn watch<'a>(&mut self, directories: impl IntoIterator<Item = &'a str>) {
+        let directories: Vec<&str> = directories.into_iter().collect();
+
+        self.directories
+            .retain(|directory, _| directories.contains(&directory.as_str()));
+    }
+
+    fn status(&self) -> serde_json::Value {
+        let now = SystemTime::now();
+
+        json!({
+            "version": VERSION,
+            "pid": std::process::id(),
+            "updated": humantime::format_rfc3339_seconds(now).to_string(),
+            "uptime_seconds": now.duration_since(self.started).unwrap_or_default().as_secs(),
+            "directories": self
+                .directories
+                .iter()
+                .map(|(directory, status)| json!({
+                    "directory": directory,
+                    "last_run": status
+                        .last_run
+                        .map(|time| humantime::format_rfc3339_seconds(time).to_string()),
+                    "last_outcome": status.last_outcome.map(|outcome| outcome.as_str()),
+                    "last_cycle": status.last_cycle,
+                    "size_bytes": status.size_bytes,
+                    "limit_bytes": status.limit_bytes,
+                    "utilization": match status.limit_bytes {
+                        0 => None,
+                        limit => Some(status.size_bytes as f64 / limit as f64),
+                    },
+                    "files": status.files,
+                    "cycles": status.cycles,
+                    "failed_cycles": status.failed_cycles,
+                    "errors_total": status.errors_total,
+                }))
+                .collect::<Vec<_>>(),
+        })
+    }
+
+    /// Writes next to the file and renames over it, so readers never see a
+    /// partial one.
+    fn write(&self) -> std::io::Result<()> {
+        let mut temporary = self.path.clone().into_os_string();
+        temporary.push(".tmp");
+        let temporary = PathBuf::from(temporary);
+
+        let mut file = File::create(&temporary)?;
+        serde_json::to_writer_pretty(&mut file, &self.status())?;
+        file.write_all(b"\n")?;
+        file.sync_all()?;
+        drop(file);
+
+        std::fs::rename(&temporary, &self.path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use maxdirsize::APP_NAME;
+
+    #[test]
+    fn cycles_are_written_with_utilization_and_error_counts() {
+        let root = std::env::temp_dir().join(format!("{APP_NAME}-status-{}", std::process::id()));
+        std::fs::create_dir_all(&root).unwrap();
+        let path = root.join("status.json");
+        let mut status = StatusFile::new(&path);
+
+        status.record(
+            "/var/log/app",
+            &CycleReport {
+                outcome: CycleOutcome::CleanedUp,
+                total_files: 10,
+                removed_files: 4,
+                size_after: 250,
+                limit_bytes: 1000,
+                errors: 2,
+                ..Default::default()
+            },
+        );
+        status.record(
+            "/var/log/app",
+            &CycleReport {
+                outcome: CycleOutcome::Failed,
+                size_after: 250,
+                limit_bytes: 1000,
+                ..Default::default()
+            },
+        );
+
+        let written: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        let app = &written["directories"][0];
+
+        assert_eq!(app["directory"], "/var/log/app");
+        assert_eq!(app["last_outcome"], "failed");
+        assert_eq!(app["utilization"], 0.25);
+        assert_eq!(app["cycles"], 2);
+        assert_eq!(app["failed_cycles"], 1);
+        assert_eq!(app["errors_total"], 2);
+        assert!(!root.join("status.json.tmp").exists());
+
+        status.watch([]);
+        status.record(
+            "/data",
+            &CycleReport {
+                limit_bytes: 0,
+                ..Default::default()
+            },
+        );
+
+        let written: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(written["directories"].as_array().unwrap().len(), 1);
+        assert_eq!(written["directories"][0]["utilization"], json!(null));
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,405 @@
+//! Where the files of a watched directory live: the local filesystem, an
+//! object store or a server reached over SFTP the same limits are enforced
+//! on.
+
+use colored::*;
+use log::warn;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+use crate::archive;
+use crate::config::{DeleteMode, WatchConfig, WatchMode};
+use crate::index::Index;
+use crate::lock;
+use crate::quarantine;
+#[cfg(feature = "s3")]
+use crate::s3::S3Storage;
+use crate::scan::{self, read_dir, FileInfo, ScanOptions, ScanResult};
+use crate::scan_cache::ScanCache;
+use crate::sftp::SftpStorage;
+use crate::shred;
+
+/// Lists and removes the files of a watched directory. Paths are those of
+/// [`ScanResult`], below `Path::new(&watch.directory)` however the storage
+/// addresses them.
+pub trait Storage: Send {
+    /// Lists every file below the directory, handing them to
+    /// `options.candidates` when set.
+    fn scan(&mut self, watch: &WatchConfig, options: &ScanOptions) -> std::io::Result<ScanResult>;
+
+    /// Whether `file` was modified or resized since it was listed, `false`
+    /// where that cannot be told without listing it again.
+    fn changed_since_scan(&self, watch: &WatchConfig, file: &FileInfo) -> std::io::Result<bool>;
+
+    /// Most files [`Storage::remove_files`] is given at once.
+    fn batch_size(&self) -> usize {
+        1
+    }
+
+    /// Removes `files` as `watch.delete_mode` says, with a result for each
+    /// in the same order: where the file went when it was moved to
+    /// `archive_dir`.
+    fn remove_files(&self, watch: &WatchConfig, files: &[&FileInfo]) -> Vec<Removed>;
+
+    /// Removes a directory left empty by a cleanup.
+    fn remove_dir(&self, watch: &WatchConfig, path: &Path) -> std::io::Result<()>;
+}
+
+/// The outcome of a removal, and where the file went when it was kept
+/// elsewhere rather than removed.
+pub type Removed = std::io::Result<Option<PathBuf>>;
+
+/// Scheme of the URLs of S3 prefixes, known to be remote without the `s3`
+/// feature too.
+pub const S3_SCHEME: &str = "s3://";
+
+/// Whether `directory` is a URL of a remote storage rather than a local
+/// path.
+pub fn is_remote(directory: &str) -> bool {
+    [S3_SCHEME, SftpStorage::SCHEME]
+        .iter()
+        .any(|scheme| directory.starts_with(scheme))
+}
+
+/// The storage of `watch.directory`. In notify mode a local directory is
+/// watched for changes, falling back to full scans if that fails, and in
+/// cache mode its listings are kept in `scan_cache_dir`.
+pub fn open(watch: &WatchConfig) -> Box<dyn Storage> {
+    #[cfg(feature = "s3")]
+    if watch.directory.starts_with(S3_SCHEME) {
+        return Box::new(S3Storage::new());
+    }
+
+    if watch.directory.starts_with(SftpStorage::SCHEME) {
+        Box::new(SftpStorage::new())
+    } else {
+        Box::new(LocalStorage::new(watch))
+    }
+}
+
+/// Files given to [`Storage::remove_files`] at once for each deletion
+/// worker, so that workers seldom wait for the slowest removal of a batch.
+const BATCH_PER_WORKER: usize = 16;
+
+/// A directory of the local filesystem.
+pub struct LocalStorage {
+    index: Option<Index>,
+    cache: Option<ScanCache>,
+    /// Files removed at once, each on a worker of its own
+    delete_threads: usize,
+}
+
+impl LocalStorage {
+    pub fn new(watch: &WatchConfig) -> LocalStorage {
+        let index = match watch.watch_mode {
+            WatchMode::Scan | WatchMode::Cache => None,
+            WatchMode::Notify => match Index::new(Path::new(&watch.directory)) {
+                Ok(index) => Some(index),
+                Err(e) => {
+                    warn!(
+                        "{}",
+                        format!(
+                            "Error watching {} for changes, falling back to full scans: {e}",
+                            watch.directory
+                        )
+                        .red()
+                    );
+                    None
+                }
+            },
+        };
+
+        let cache = match (watch.watch_mode, &watch.scan_cache_dir) {
+            (WatchMode::Cache, Some(cache_dir)) => {
+                match lock::named_after(Path::new(&watch.directory), cache_dir, "cache") {
+                    Ok(path) => Some(ScanCache::new(path)),
+                    Err(e) => {
+                        warn!(
+                            "{}",
+                            format!(
+                                "Error naming the scan cache of {}, falling back to full scans: {e}",
+                                watch.directory
+                            )
+                            .red()
+                        );
+                        None
+                    }
+                }
+            }
+            _ => None,
+        };
+
+        LocalStorage {
+            index,
+            cache,
+            delete_threads: watch.delete_threads.max(1),
+        }
+    }
+}
+
+impl Storage for LocalStorage {
+    fn scan(&mut self, watch: &WatchConfig, options: &ScanOptions) -> std::io::Result<ScanResult> {
+        let rescan_interval = Duration::from_secs(watch.rescan_interval_seconds);
+
+        match (&mut self.index, &mut self.cache) {
+            (Some(index), _) => index.scan(options, rescan_interval),
+            (None, Some(cache)) => cache.scan(watch, options, rescan_interval),
+            (None, None) => read_dir(Path::new(&watch.directory), options),
+        }
+    }
+
+    fn changed_since_scan(&self, watch: &WatchConfig, file: &FileInfo) -> std::io::Result<bool> {
+        scan::changed_since_scan(file, watch.follow_symlinks, watch.size_accounting)
+    }
+
+    fn batch_size(&self) -> usize {
+        match self.delete_threads {
+            1 => 1,
+            threads => threads * BATCH_PER_WORKER,
+        }
+    }
+
+    fn remove_files(&self, watch: &WatchConfig, files: &[&FileInfo]) -> Vec<Removed> {
+        let root = root(watch);
+        let remove = |file: &FileInfo| {
+            resolve(&file.path, &root).and_then(|path| remove_file(&path, &root, watch))
+        };
+
+        match self.delete_threads {
+            1 => files.iter().map(|file| remove(file)).collect(),
+            threads => in_parallel(files, threads, remove),
+        }
+    }
+
+    fn remove_dir(&self, watch: &WatchConfig, path: &Path) -> std::io::Result<()> {
+        resolve(path, &root(watch)).and_then(std::fs::remove_dir)
+    }
+}
+
+/// Runs `remove` on `files` with up to `threads` workers, each taking the
+/// next file in order so that removals start in the order given. Workers
+/// keep the results of their own files, put back in the same order, and
+/// failures are counted per file like those of single removals.
+fn in_parallel<T: Send>(
+    files: &[&FileInfo],
+    threads: usize,
+    remove: impl Fn(&FileInfo) -> std::io::Result<T> + Sync,
+) -> Vec<std::io::Result<T>> {
+    let next = AtomicUsize::new(0);
+    let mut results: Vec<Option<std::io::Result<T>>> = files.iter().map(|_| None).collect();
+
+    std::thread::scope(|scope| {
+        let (next, remove) = (&next, &remove);
+
+        let workers: Vec<_> = (0..threads.min(files.len()))
+            .map(|_| {
+                scope.spawn(move || {
+                    let mut done = Vec::new();
+
+                    loop {
+                        let i = next.fetch_add(1, Ordering::Relaxed);
+                        let Some(file) = files.get(i) else {
+                            break;
+                        };
+
+                        done.push((i, remove(file)));
+                    }
+
+                    done
+                })
+            })
+            .collect();
+
+        for worker in workers {
+            for (i, result) in worker.join().unwrap_or_default() {
+                results[i] = Some(result);
+            }
+        }
+    });
+
+    results
+        .into_iter()
+        .map(|result| {
+            result.unwrap_or_else(|| Err(std::io::Error::other("deletion worker panicked")))
+        })
+        .collect()
+}
+
+/// The canonical directory: nothing outside of it is ever removed, wherever
+/// symlinks led the scan.
+fn root(watch: &WatchConfig) -> PathBuf {
+    let directory = Path::new(&watch.directory);
+
+    std::fs::canonicalize(directory).unwrap_or_else(|_| directory.to_path_buf())
+}
+
+/// `path` with its parent directories resolved, refused unless it is inside
+/// `root`, the canonical directory. The file name is kept as is so a symlink
+/// is removed rather than what it points to.
+fn resolve(path: &Path, root: &Path) -> std::io::Result<PathBuf> {
+    let (Some(parent), Some(name)) = (path.parent(), path.file_name()) else {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("{} has no parent directory", path.display()),
+        ));
+    };
+
+    let resolved = std::fs::canonicalize(parent)?.join(name);
+
+    if !resolved.starts_with(root) {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::PermissionDenied,
+            format!(
+                "{} is outside of {}, not removing it",
+                resolved.display(),
+                root.display()
+            ),
+        ));
+    }
+
+    Ok(resolved)
+}
+
+/// Removes `path`, resolved by [`resolve`] against `root`.
+fn remove_file(path: &Path, root: &Path, watch: &WatchConfig) -> Removed {
+    let removed = match (watch.delete_mode, &watch.archive_dir) {
+        (DeleteMode::Delete, _) => delete(path),
+        (DeleteMode::Trash, _) => trash::delete(path).map_err(std::io::Error::other),
+        (DeleteMode::Move, Some(archive_dir)) => {
+            return archive::archive(path, root, archive_dir).map(Some)
+        }
+        (DeleteMode::Move, None) => Err(std::io::Error::other("no archive directory set")),
+        (DeleteMode::Shred, _) => shred::shred(path, watch.shred_passes),
+        (DeleteMode::Quarantine, _) => match &watch.quarantine_dir {
+            Some(quarantine_dir) => quarantine::quarantine(path, quarantine_dir),
+            None => Err(std::io::Error::other("no quarantine directory set")),
+        },
+    };
+
+    removed.map(|()| None)
+}
+
+/// Windows refuses to remove read-only files, they are made writable first.
+fn delete(path: &Path) -> std::io::Result<()> {
+    match std::fs::remove_file(path) {
+        #[cfg(windows)]
+        Err(e) if e.kind() == std::io::ErrorKind::PermissionDenied => {
+            let mut permissions = std::fs::metadata(path)?.permissions();
+            if !permissions.readonly() {
+                return Err(e);
+            }
+
+            // Only clears the read-only attribute, this is not Unix
+            #[allow(clippy::permissions_set_readonly_false)]
+            permissions.set_readonly(false);
+            std::fs::set_permissions(path, permissions)?;
+            std::fs::remove_file(path)
+        }
+        result => result,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::APP_NAME;
+
+    #[test]
+    fn parallel_removals_keep_the_order_of_results() {
+        let root = std::env::temp_dir().join(format!("{APP_NAME}-parallel-{}", std::process::id()));
+        std::fs::create_dir_all(&root).unwrap();
+
+        let files: Vec<FileInfo> = (0..50)
+            .map(|i| {
+                let path = root.join(format!("{i}"));
+
+                // Every fifth file is already gone
+                if i % 5 != 0 {
+                    std::fs::write(&path, "data").unwrap();
+                }
+
+                FileInfo {
+                    path,
+                    size: 4,
+                    modified: 0,
+                    accessed: 0,
+                    hard_link: None,
+                    inode: 0,
+                }
+            })
+            .collect();
+
+        let results = in_parallel(&files.iter().collect::<Vec<_>>(), 8, |file| {
+            std::fs::remove_file(&file.path)
+        });
+
+        assert_eq!(results.len(), 50);
+        for (i, result) in results.iter().enumerate() {
+            assert_eq!(result.is_err(), i % 5 == 0);
+        }
+        assert_eq!(std::fs::read_dir(&root).unwrap().count(), 0);
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+    #[test]
+    fn batches_removed_by_several_threads_keep_the_eviction_order() {
+        let root = std::env::temp_dir().join(format!("{APP_NAME}-batch-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(&root).unwrap();
+
+        let mut watch: WatchConfig = toml::from_str(&format!(
+            "directory = {:?}\ninterval_seconds = 1\nmax_size_bytes = 1\ndelete_threads = 4",
+            root.display().to_string()
+        ))
+        .unwrap();
+        watch.compile().unwrap();
+        let storage = LocalStorage::new(&watch);
+        assert_eq!(storage.batch_size(), 4 * BATCH_PER_WORKER);
+
+        // Evicted newest name first, with every third file already gone
+        let files: Vec<FileInfo> = (0..storage.batch_size())
+            .rev()
+            .map(|i| {
+                let path = root.join(format!("{i:02}"));
+                if i % 3 != 0 {
+                    std::fs::write(&path, "data").unwrap();
+                }
+
+                FileInfo {
+                    path,
+                    size: 4,
+                    modified: i as u64,
+                    accessed: i as u64,
+                    hard_link: None,
+                    inode: 0,
+                }
+            })
+            .collect();
+
+        let removed = storage.remove_files(&watch, &files.iter().collect::<Vec<_>>());
+
+        assert_eq!(removed.len(), files.len());
+        for (file, result) in files.iter().zip(&removed) {
+            let i: usize = file
+                .path
+                .file_name()
+                .unwrap()
+                .to_str()
+                .unwrap()
+                .parse()
+                .unwrap();
+            match result {
+                Ok(None) => assert_ne!(i % 3, 0, "{}", file.path.display()),
+                Err(e) => {
+                    assert_eq!(i % 3, 0, "{}", file.path.display());
+                    assert_eq!(e.kind(), std::io::ErrorKind::NotFound);
+                }
+                Ok(Some(path)) => panic!("{} moved to {}", file.path.display(), path.display()),
+            }
+        }
+        assert_eq!(std::fs::read_dir(&root).unwrap().count(), 0);
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,146 @@
+//! Readiness, watchdog and status notifications to systemd, see
+//! sd_notify(3). Only active when started by systemd with `Type=notify`.
+
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+use maxdirsize::{units, CycleOutcome, CycleReport};
+
+#[cfg_attr(not(unix), allow(dead_code))]
+pub struct Systemd {
+    #[cfg(unix)]
+    socket: std::os::unix::net::UnixDatagram,
+    #[cfg(unix)]
+    addr: std::os::unix::net::SocketAddr,
+    /// Half of `WatchdogSec`, how often the watchdog is pinged
+    watchdog: Option<Duration>,
+    last_ping: Instant,
+    ready: bool,
+    statuses: BTreeMap<String, String>,
+}
+
+impl Systemd {
+    /// Connects to `NOTIFY_SOCKET`, `None` when not run by systemd.
+    #[cfg(unix)]
+    pub fn from_env() -> std::io::Result<Option<Systemd>> {
+        use std::os::unix::net::{SocketAddr, UnixDatagram};
+
+        let Some(path) = std::env::var_os("NOTIFY_SOCKET") else {
+            return Ok(None);
+        };
+
+        let addr = match path.to_str().and_then(|path| path.strip_prefix('@')) {
+            #[cfg(target_os = "linux")]
+            Some(name) => {
+                use std::os::linux::net::SocketAddrExt;
+                SocketAddr::from_abstract_name(name)?
+            }
+            #[cfg(not(target_os = "linux"))]
+            Some(_) => {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::Unsupported,
+                    "abstract sockets are only supported on Linux",
+                ))
+            }
+            None => SocketAddr::from_pathname(&path)?,
+        };
+
+        Ok(Some(Systemd {
+            socket: UnixDatagram::unbound()?,
+            addr,
+            watchdog: watchdog_interval(),
+            last_ping: Instant::now(),
+            ready: false,
+            statuses: BTreeMap::new(),
+        }))
+    }
+
+    #[cfg(not(unix))]
+    pub fn from_env() -> std::io::Result<Option<Systemd>> {
+        Ok(None)
+    }
+
+    /// Reports the last cycle of `directory` in the status line, and the
+    /// service as ready after the first cycle whose scan succeeded.
+    pub fn cycle_finished(&mut self, directory: &str, report: &CycleReport) {
+        let failed = report.outcome == CycleOutcome::Failed;
+
+        let status = match report.limit_bytes {
+            _ if failed => "last cycle failed".to_string(),
+            0 => format!(
+                "{:.2} MB in {} files",
+                report.size_after as f64 / units::MIB as f64,
+                report.total_files
+            ),
+            limit => format!(
+                "{:.1}% of {:.2} MB used",
+                report.size_after as f64 * 100.0 / limit as f64,
+                limit as f64 / units::MIB as f64
+            ),
+        };
+        self.statuses.insert(directory.to_string(), status);
+
+        let mut message = format!("STATUS={}\nWATCHDOG=1\n", self.status());
+
+        if !self.ready && !failed {
+            message.push_str("READY=1\n");
+            self.ready = true;
+        }
+
+        self.send(&message);
+        self.last_ping = Instant::now();
+    }
+
+    /// Pings the watchdog if it is due.
+    pub fn watchdog(&mut self) {
+        if self
+            .watchdog
+            .is_some_and(|interval| self.last_ping.elapsed() >= interval)
+        {
+            self.send("WATCHDOG=1\n");
+            self.last_ping = Instant::now();
+        }
+    }
+
+    /// When the watchdog is next due, if enabled.
+    pub fn next_ping(&self) -> Option<Instant> {
+        self.watchdog.map(|interval| self.last_ping + interval)
+    }
+
+    pub fn stopping(&self) {
+        self.send("STOPPING=1\n");
+    }
+
+    fn status(&self) -> String {
+        self.statuses
+            .iter()
+            .map(|(directory, status)| format!("{directory}: {status}"))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    #[cfg(unix)]
+    fn send(&self, message: &str) {
+        if let Err(e) = self.socket.send_to_addr(message.as_bytes(), &self.addr) {
+            log::debug!("Error notifying systemd: {e}");
+        }
+    }
+
+    #[cfg(not(unix))]
+    fn send(&self, _message: &str) {}
+}
+
+/// Half of the watchdog timeout systemd passed in `WATCHDOG_USEC`, unless it
+/// is meant for another process.
+#[cfg_attr(not(unix), allow(dead_code))]
+fn watchdog_interval() -> Option<Duration> {
+    if let Ok(pid) = std::env::var("WATCHDOG_PID") {
+        if pid.parse() != Ok(std::process::id()) {
+            return None;
+        }
+    }
+
+    let usec: u64 = std::env::var("WATCHDOG_USEC").ok()?.parse().ok()?;
+
+    (usec > 0).then(|| Duration::from_micros(usec / 2))
+}
@@ -0,0 +1,95 @@
+//! Pacing removals so a big cleanup does not saturate the disk.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+/// Longest sleep between two checks of the stop flag.
+const STOP_CHECK: Duration = Duration::from_millis(100);
+
+/// Spaces out removals to at most `files_per_second` files and
+/// `bytes_per_second` bytes on average since the first one.
+pub struct Throttle {
+    files_per_second: Option<f64>,
+    bytes_per_second: Option<u64>,
+    started: Instant,
+    files: u64,
+    bytes: u64,
+    /// Time spent waiting so far
+    pub waited: Duration,
+}
+
+impl Throttle {
+    pub fn new(files_per_second: Option<f64>, bytes_per_second: Option<u64>) -> Throttle {
+        Throttle {
+            files_per_second,
+            bytes_per_second,
+            started: Instant::now(),
+            files: 0,
+            bytes: 0,
+            waited: Duration::ZERO,
+        }
+    }
+
+    /// Accounts for a removal of `bytes`.
+    pub fn removed(&mut self, bytes: u64) {
+        self.files += 1;
+        self.bytes += bytes;
+    }
+
+    /// Waits until the next removal is allowed or `stop` is set.
+    pub fn wait(&mut self, stop: &AtomicBool) {
+        let wait = self.next().saturating_duration_since(Instant::now());
+
+        if wait.is_zero() {
+            return;
+        }
+
+        let waiting = Instant::now();
+
+        while !stop.load(Ordering::SeqCst) {
+            let left = wait.saturating_sub(waiting.elapsed());
+
+            if left.is_zero() {
+                break;
+            }
+
+            std::thread::sleep(left.min(STOP_CHECK));
+        }
+
+        self.waited += waiting.elapsed();
+    }
+
+    /// When the next removal may start.
+    fn next(&self) -> Instant {
+        let by_files = self
+            .files_per_second
+            .map_or(0.0, |rate| self.files as f64 / rate);
+        let by_bytes = self
+            .bytes_per_second
+            .map_or(0.0, |rate| self.bytes as f64 / rate as f64);
+
+        self.started + Duration::from_secs_f64(by_files.max(by_bytes))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn next_removal_waits_for_the_slowest_limit() {
+        let mut throttle = Throttle::new(Some(10.0), Some(1000));
+        throttle.files = 5;
+        throttle.bytes = 200;
+        assert_eq!(
+            throttle.next() - throttle.started,
+            Duration::from_millis(500)
+        );
+
+        throttle.bytes = 2000;
+        assert_eq!(throttle.next() - throttle.started, Duration::from_secs(2));
+
+        let unlimited = Throttle::new(None, None);
+        assert_eq!(unlimited.next(), unlimited.started);
+    }
+}
@@ -0,0 +1,507 @@
+//! `maxdirsize tui`, a live dashboard of the daemon fed by its control
+//! socket or API: the utilization of every directory against its limit, how
+//! it grew, what the cleanup in progress is about to remove and what
+//! happened lately.
+
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Color, Style, Stylize};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, Gauge, List, ListItem, Paragraph, Sparkline, Tabs};
+use ratatui::Frame;
+use serde_json::Value;
+use std::collections::{BTreeMap, VecDeque};
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use crate::ctl;
+use maxdirsize::config::{Args, Config};
+use maxdirsize::{units, APP_NAME};
+
+const REFRESH: Duration = Duration::from_secs(1);
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Size samples kept per directory for the sparkline, one per refresh.
+const SAMPLES: usize = 600;
+
+/// Cycles kept for the recent events.
+const CYCLES: usize = 100;
+
+/// Where the daemon is reached.
+pub enum Daemon {
+    Socket(PathBuf),
+    Api { addr: String, token: String },
+}
+
+impl Daemon {
+    /// The daemon configured by `args` or the configuration file, preferring
+    /// its socket, which needs no token.
+    pub fn configured(args: &Args) -> Option<Daemon> {
+        let config = Config::load(args).ok();
+        let setting = |arg: &Option<String>, configured: fn(&Config) -> &Option<String>| {
+            arg.clone().or_else(|| configured(config.as_ref()?).clone())
+        };
+
+        if let Some(socket) = args
+            .control_socket
+            .clone()
+            .or_else(|| config.as_ref()?.control_socket.clone())
+        {
+            return Some(Daemon::Socket(socket));
+        }
+
+        Some(Daemon::Api {
+            addr: setting(&args.control_addr, |config| &config.control_addr)?,
+            token: setting(&args.control_token, |config| &config.control_token).unwrap_or_default(),
+        })
+    }
+
+    /// The answer to `command`, one the control socket understands.
+    fn send(&self, command: &str) -> Result<Value, String> {
+        let (addr, token) = match self {
+            Daemon::Socket(path) => return ctl::send(path, command).map_err(|e| e.to_string()),
+            Daemon::Api { addr, token } => (addr, token),
+        };
+
+        let (method, path) = match command {
+            "status" => ("GET", "/stats".to_string()),
+            "deletions" | "queue" => ("GET", format!("/{command}")),
+            command => ("POST", format!("/{command}")),
+        };
+
+        let response = ureq::AgentBuilder::new()
+            .timeout(TIMEOUT)
+            .build()
+            .request(method, &format!("http://{addr}{path}"))
+            .set("Authorization", &format!("Bearer {token}"))
+            .call();
+
+        let body = match response {
+            Ok(response) => response.into_string().map_err(|e| e.to_string())?,
+            Err(ureq::Error::Status(status, response)) => {
+                let body = response.into_string().unwrap_or_default();
+                return Err(serde_json::from_str::<Value>(&body)
+                    .ok()
+                    .and_then(|body| body["error"].as_str().map(str::to_string))
+                    .unwrap_or_else(|| format!("{method} {path} returned {status}")));
+            }
+            Err(e) => return Err(e.to_string()),
+        };
+
+        serde_json::from_str(&body).map_err(|e| e.to_string())
+    }
+}
+
+/// What the dashboard shows, refreshed from the daemon.
+#[derive(Default)]
+struct Dashboard {
+    status: Value,
+    queue: Value,
+    deletions: Value,
+    /// Sizes of every directory, oldest first
+    samples: BTreeMap<String, VecDeque<u64>>,
+    /// Timestamp and description of the latest cycles, oldest first
+    cycles: VecDeque<(String, String)>,
+    last_runs: BTreeMap<String, String>,
+    selected: usize,
+    /// Outcome of the last key pressed or error talking to the daemon
+    message: Option<String>,
+}
+
+impl Dashboard {
+    fn refresh(&mut self, daemon: &Daemon) {
+        let fetched = daemon
+            .send("status")
+            .and_then(|status| Ok((status, daemon.send("queue")?, daemon.send("deletions")?)));
+
+        match fetched {
+            Ok((status, queue, deletions)) => {
+                if self
+                    .message
+                    .as_deref()
+                    .is_some_and(|m| m.starts_with("Error"))
+                {
+                    self.message = None;
+                }
+                self.update(status, queue, deletions);
+            }
+            Err(e) => self.message = Some(format!("Error talking to the daemon: {e}")),
+        }
+    }
+
+    fn update(&mut self, status: Value, queue: Value, deletions: Value) {
+        for directory in status["directories"].as_array().into_iter().flatten() {
+            let name = directory["directory"]
+                .as_str()
+                .unwrap_or_default()
+                .to_string();
+
+            let samples = self.samples.entry(name.clone()).or_default();
+            if samples.len() == SAMPLES {
+                samples.pop_front();
+            }
+            samples.push_back(directory["size_bytes"].as_u64().unwrap_or_default());
+
+            let Some(last_run) = directory["last_run"].as_str() else {
+                continue;
+            };
+
+            if self.last_runs.get(&name).map(String::as_str) == Some(last_run) {
+                continue;
+            }
+
+            self.last_runs.insert(name.clone(), last_run.to_string());
+
+            let cycle = &directory["last_cycle"];
+            if self.cycles.len() == CYCLES {
+                self.cycles.pop_front();
+            }
+            self.cycles.push_back((
+                last_run.to_string(),
+                format!(
+                    "{name} {}, removed {} files freeing {}",
+                    cycle["outcome"].as_str().unwrap_or("finished"),
+                    cycle["files_deleted"].as_u64().unwrap_or_default(),
+                    megabytes(cycle["bytes_freed"].as_u64().unwrap_or_default())
+                ),
+            ));
+        }
+
+        self.status = status;
+        self.queue = queue;
+        self.deletions = deletions;
+    }
+
+    fn directories(&self) -> &[Value] {
+        self.status["directories"]
+            .as_array()
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+    }
+
+    fn render(&self, frame: &mut Frame) {
+        let [header, tabs, gauge, sparkline, lists, footer] = Layout::vertical([
+            Constraint::Length(1),
+            Constraint::Length(1),
+            Constraint::Length(3),
+            Constraint::Length(6),
+            Constraint::Min(4),
+            Constraint::Length(1),
+        ])
+        .areas(frame.area());
+
+        let paused = self.status["paused"].as_bool().unwrap_or_default();
+
+        frame.render_widget(
+            Line::from(format!(
+                "{APP_NAME} v{}, up {} seconds, cleanups {}",
+                self.status["version"].as_str().unwrap_or("?"),
+                self.status["uptime_seconds"].as_u64().unwrap_or_default(),
+                match paused {
+                    true => "paused",
+                    false => "running",
+                }
+            ))
+            .bold(),
+            header,
+        );
+
+        let directories = self.directories();
+        let names: Vec<&str> = directories
+            .iter()
+            .map(|directory| directory["directory"].as_str().unwrap_or_default())
+            .collect();
+
+        frame.render_widget(
+            Tabs::new(names.clone())
+                .select(self.selected)
+                .highlight_style(Style::new().reversed()),
+            tabs,
+        );
+
+        let directory = directories.get(self.selected).unwrap_or(&Value::Null);
+        let name = names.get(self.selected).copied().unwrap_or_default();
+        let size = directory["size_bytes"].as_u64().unwrap_or_default();
+        let limit = directory["limit_bytes"].as_u64().unwrap_or_default();
+        let ratio = match limit {
+            0 => 0.0,
+            limit => (size as f64 / limit as f64).min(1.0),
+        };
+
+        frame.render_widget(
+            Gauge::default()
+                .block(Block::bordered().title(format!(
+                    " {} files, last run {} ",
+                    directory["files"].as_u64().unwrap_or_default(),
+                    directory["last_run"].as_str().unwrap_or("never")
+                )))
+                .gauge_style(match ratio {
+                    ratio if ratio >= 0.9 => Color::Red,
+                    ratio if ratio >= 0.75 => Color::Yellow,
+                    _ => Color::Green,
+                })
+                .ratio(ratio)
+                .label(format!(
+                    "{} of {} ({:.1}%)",
+                    megabytes(size),
+                    megabytes(limit),
+                    ratio * 100.0
+                )),
+            gauge,
+        );
+
+        let samples: Vec<u64> = self
+            .samples
+            .get(name)
+            .map(|samples| samples.iter().copied().collect())
+            .unwrap_or_default();
+        // Only the most recent samples fit
+        let width = usize::from(sparkline.width.saturating_sub(2));
+        let shown = &samples[samples.len().saturating_sub(width)..];
+
+        frame.render_widget(
+            Sparkline::default()
+                .block(Block::bordered().title(" Size "))
+                .data(shown)
+                .max(limit.max(shown.iter().copied().max().unwrap_or_default())),
+            sparkline,
+        );
+
+        let [queue, events] =
+            Layout::horizontal([Constraint::Percentage(50), Constraint::Percentage(50)])
+                .areas(lists);
+
+        let queued = self
+            .queue
+            .as_array()
+            .into_iter()
+            .flatten()
+            .find(|queue| queue["directory"] == name);
+
+        let items: Vec<ListItem> = queued
+            .and_then(|queued| queued["files"].as_array())
+            .into_iter()
+            .flatten()
+            .map(|file| {
+                ListItem::new(format!(
+                    "{} {} ({})",
+                    file["path"].as_str().unwrap_or_default(),
+                    megabytes(file["size"].as_u64().unwrap_or_default()),
+                    file["reason"].as_str().unwrap_or_default()
+                ))
+            })
+            .collect();
+
+        frame.render_widget(
+            List::new(items).block(Block::bordered().title(format!(
+                " Deletion queue, {} files ",
+                queued
+                    .and_then(|queued| queued["remaining"].as_u64())
+                    .unwrap_or_default()
+            ))),
+            queue,
+        );
+
+        let mut recent: Vec<(&str, String)> = self
+            .deletions
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter(|deletion| deletion["directory"] == name)
+            .map(|deletion| {
+                (
+                    deletion["timestamp"].as_str().unwrap_or_default(),
+                    format!(
+                        "removed {} ({})",
+                        deletion["path"].as_str().unwrap_or_default(),
+                        megabytes(deletion["size"].as_u64().unwrap_or_default())
+                    ),
+                )
+            })
+            .collect();
+        recent.extend(
+            self.cycles
+                .iter()
+                .filter(|(_, cycle)| cycle.starts_with(name))
+                .map(|(timestamp, cycle)| (timestamp.as_str(), format!("cycle of {cycle}"))),
+        );
+        // RFC 3339 in UTC, as text they sort by time
+        recent.sort_by(|(a, _), (b, _)| b.cmp(a));
+
+        frame.render_widget(
+            List::new(
+                recent
+                    .into_iter()
+                    .map(|(timestamp, event)| ListItem::new(format!("{timestamp} {event}"))),
+            )
+            .block(Block::bordered().title(" Recent events ")),
+            events,
+        );
+
+        frame.render_widget(
+            Paragraph::new(match &self.message {
+                Some(message) => message.clone(),
+                None => format!(
+                    "q quit, tab next directory, r run now, p {}",
+                    match paused {
+                        true => "resume",
+                        false => "pause",
+                    }
+                ),
+            })
+            .dim(),
+            footer,
+        );
+    }
+
+    /// Handles a key press, `false` to quit.
+    fn key(&mut self, code: KeyCode, daemon: &Daemon) -> bool {
+        let count = self.directories().len().max(1);
+
+        let command = match code {
+            KeyCode::Char('q') | KeyCode::Esc => return false,
+            KeyCode::Tab | KeyCode::Right => {
+                self.selected = (self.selected + 1) % count;
+                return true;
+            }
+            KeyCode::BackTab | KeyCode::Left => {
+                self.selected = (self.selected + count - 1) % count;
+                return true;
+            }
+            KeyCode::Char('r') => "run",
+            KeyCode::Char('p') if self.status["paused"] == true => "resume",
+            KeyCode::Char('p') => "pause",
+            _ => return true,
+        };
+
+        self.message = Some(match daemon.send(command) {
+            Ok(_) => format!("Sent {command}"),
+            Err(e) => format!("Error sending {command}: {e}"),
+        });
+        self.refresh(daemon);
+
+        true
+    }
+}
+
+fn megabytes(bytes: u64) -> String {
+    format!("{:.2} MB", bytes as f64 / units::MIB as f64)
+}
+
+/// Shows the dashboard until quit, 1 if the terminal could not be used.
+pub fn run(daemon: Daemon) -> i32 {
+    let mut dashboard = Dashboard::default();
+    dashboard.refresh(&daemon);
+
+    let mut terminal = ratatui::init();
+    let result = (|| -> std::io::Result<()> {
+        let mut refreshed = Instant::now();
+
+        loop {
+            terminal.draw(|frame| dashboard.render(frame))?;
+
+            let timeout = REFRESH.saturating_sub(refreshed.elapsed());
+
+            if event::poll(timeout)? {
+                if let Event::Key(key) = event::read()? {
+                    if key.kind == KeyEventKind::Press && !dashboard.key(key.code, &daemon) {
+                        return Ok(());
+                    }
+                }
+            }
+
+            if refreshed.elapsed() >= REFRESH {
+                dashboard.refresh(&daemon);
+                refreshed = Instant::now();
+            }
+        }
+    })();
+    ratatui::restore();
+
+    match result {
+        Ok(()) => 0,
+        Err(e) => {
+            log::error!(event = "error"; "Error drawing the dashboard: {e}");
+            1
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ratatui::backend::TestBackend;
+    use ratatui::Terminal;
+    use serde_json::json;
+
+    #[test]
+    fn shows_the_utilization_and_queue_of_the_selected_directory() {
+        let mut dashboard = Dashboard::default();
+        let status = |size: u64, last_run: &str| {
+            json!({
+                "version": "1.0.0",
+                "uptime_seconds": 5,
+                "paused": false,
+                "directories": [{
+                    "directory": "/data",
+                    "size_bytes": size,
+                    "limit_bytes": 4 * units::MIB,
+                    "files": 3,
+                    "last_run": last_run,
+                    "last_cycle": { "outcome": "cleaned_up", "files_deleted": 1, "bytes_freed": units::MIB },
+                }],
+            })
+        };
+        let queue = json!([{
+            "directory": "/data",
+            "remaining": 1,
+            "files": [{ "path": "/data/next.log", "size": units::MIB, "reason": "size_limit" }],
+        }]);
+
+        dashboard.update(
+            status(units::MIB, "2026-10-14T10:00:00Z"),
+            json!([]),
+            json!([]),
+        );
+        dashboard.update(
+            status(3 * units::MIB, "2026-10-14T10:00:00Z"),
+            queue,
+            json!([]),
+        );
+        dashboard.update(
+            status(2 * units::MIB, "2026-10-14T10:01:00Z"),
+            json!([]),
+            json!([]),
+        );
+        assert_eq!(
+            dashboard.samples["/data"],
+            [units::MIB, 3 * units::MIB, 2 * units::MIB]
+        );
+        // A cycle is told once per run
+        assert_eq!(dashboard.cycles.len(), 2);
+
+        dashboard.update(
+            status(3 * units::MIB, "2026-10-14T10:01:00Z"),
+            json!([{
+                "directory": "/data",
+                "remaining": 1,
+                "files": [{ "path": "/data/next.log", "size": units::MIB, "reason": "size_limit" }],
+            }]),
+            json!([]),
+        );
+
+        let mut terminal = Terminal::new(TestBackend::new(160, 24)).unwrap();
+        terminal.draw(|frame| dashboard.render(frame)).unwrap();
+        let screen: String = terminal
+            .backend()
+            .buffer()
+            .content()
+            .iter()
+            .map(|cell| cell.symbol())
+            .collect();
+
+        assert!(screen.contains("3.00 MB of 4.00 MB (75.0%)"));
+        assert!(screen.contains("/data/next.log 1.00 MB (size_limit)"));
+        assert!(screen.contains("cycle of /data cleaned_up, removed 1 files"));
+    }
+}
@@ -0,0 +1,306 @@
+//! Rendering a systemd service unit running the current configuration, for
+//! `maxdirsize install-systemd`.
+
+use clap::parser::ValueSource;
+use clap::CommandFactory;
+use std::collections::BTreeSet;
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+
+use maxdirsize::config::{Args, IoClass};
+use maxdirsize::storage;
+use maxdirsize::{Config, APP_NAME};
+
+/// Settings that make no sense under systemd, which keeps the service in the
+/// foreground and tracks its PID itself.
+const LEFT_OUT: [&str; 2] = ["CONFIG", "DAEMON"];
+
+/// What the service is started with.
+pub struct Invocation {
+    pub executable: PathBuf,
+    pub working_directory: PathBuf,
+    pub config: Option<PathBuf>,
+    /// Environment variables set, from the flags and environment variables
+    /// given, in the order of `--help`
+    pub environment: Vec<(String, String)>,
+    /// Variables left out as they hold secrets
+    pub secrets: Vec<String>,
+}
+
+impl Invocation {
+    /// The invocation of the current process, with every flag turned into
+    /// its environment variable.
+    pub fn current() -> std::io::Result<Invocation> {
+        let command = Args::command();
+        let matches = command.clone().get_matches();
+
+        let mut environment = Vec::new();
+        let mut secrets = Vec::new();
+
+        for arg in command.get_arguments() {
+            let Some(env) = arg.get_env().and_then(|env| env.to_str()) else {
+                continue;
+            };
+
+            if LEFT_OUT.contains(&env)
+                || !matches!(
+                    matches.value_source(arg.get_id().as_str()),
+                    Some(ValueSource::CommandLine | ValueSource::EnvVariable)
+                )
+            {
+                continue;
+            }
+
+            if arg.is_hide_env_values_set() {
+                secrets.push(env.to_string());
+                continue;
+            }
+
+            let values: Vec<String> = matches
+                .get_raw(arg.get_id().as_str())
+                .into_iter()
+                .flatten()
+                .map(|value| value.to_string_lossy().into_owned())
+                .collect();
+            environment.push((env.to_string(), values.join(",")));
+        }
+
+        environment.push((
+            "RUST_LOG".to_string(),
+            std::env::var("RUST_LOG").unwrap_or_else(|_| "info".to_string()),
+        ));
+
+        let config = match matches.get_one::<PathBuf>("config") {
+            Some(config) => Some(std::path::absolute(config)?),
+            None => None,
+        };
+
+        Ok(Invocation {
+            executable: std::env::current_exe()?,
+            working_directory: std::env::current_dir()?,
+            config,
+            environment,
+            secrets,
+        })
+    }
+
+    /// Whether the switch `name` is given and on.
+    pub fn enabled(&self, name: &str) -> bool {
+        self.environment.iter().any(|(variable, value)| {
+            variable == name
+                && matches!(
+                    value.to_ascii_lowercase().as_str(),
+                    "y" | "yes" | "t" | "true" | "on" | "1"
+                )
+        })
+    }
+}
+
+/// The unit running `invocation` of `config`, sandboxed with `hardened` so
+/// that it may only write to the watched directories and the files it keeps.
+pub fn render(invocation: &Invocation, config: &Config, hardened: bool) -> String {
+    let mut unit = String::new();
+
+    let _ = writeln!(unit, "[Unit]");
+    let _ = writeln!(
+        unit,
+        "Description={APP_NAME} keeping directories within their limits"
+    );
+    let _ = writeln!(unit, "Wants=network-online.target");
+    let _ = writeln!(unit, "After=local-fs.target network-online.target");
+    let _ = writeln!(unit);
+    let _ = writeln!(unit, "[Service]");
+    let _ = writeln!(unit, "Type=notify");
+
+    let mut exec_start = quote(&invocation.executable.to_string_lossy());
+    if let Some(config) = &invocation.config {
+        let _ = write!(exec_start, " --config {}", quote(&config.to_string_lossy()));
+    }
+    let _ = writeln!(unit, "ExecStart={exec_start}");
+    let _ = writeln!(unit, "ExecReload=/bin/kill -HUP $MAINPID");
+    let _ = writeln!(
+        unit,
+        "WorkingDirectory={}",
+        quote(&invocation.working_directory.to_string_lossy())
+    );
+    let _ = writeln!(unit, "Restart=on-failure");
+    let _ = writeln!(
+        unit,
+        "TimeoutStopSec={}",
+        config.shutdown_timeout_seconds + 5
+    );
+
+    for (name, value) in &invocation.environment {
+        let _ = writeln!(unit, "Environment={}", quote(&format!("{name}={value}")));
+    }
+
+    if !invocation.secrets.is_empty() {
+        let _ = writeln!(
+            unit,
+            "# Left out as secrets, set in an EnvironmentFile= only root can read: {}",
+            invocation.secrets.join(", ")
+        );
+    }
+
+    if hardened {
+        let writable = writable(config);
+
+        let _ = writeln!(unit);
+        for setting in [
+            "NoNewPrivileges=yes",
+            "ProtectSystem=strict",
+            "ProtectHome=read-only",
+            "PrivateDevices=yes",
+            "ProtectKernelTunables=yes",
+            "ProtectKernelModules=yes",
+            "ProtectKernelLogs=yes",
+            "ProtectControlGroups=yes",
+            "ProtectClock=yes",
+            "ProtectHostname=yes",
+            "RestrictNamespaces=yes",
+            "RestrictRealtime=yes",
+            "RestrictSUIDSGID=yes",
+            "LockPersonality=yes",
+            "MemoryDenyWriteExecute=yes",
+            "SystemCallArchitectures=native",
+            "RestrictAddressFamilies=AF_UNIX AF_INET AF_INET6",
+        ] {
+            let _ = writeln!(unit, "{setting}");
+        }
+
+        // A private /tmp would hide what the service is meant to write there
+        if !writable
+            .iter()
+            .any(|path| path.starts_with("/tmp") || path.starts_with("/var/tmp"))
+        {
+            let _ = writeln!(unit, "PrivateTmp=yes");
+        }
+
+        let mut capabilities = vec!["CAP_DAC_OVERRIDE", "CAP_DAC_READ_SEARCH", "CAP_FOWNER"];
+        if config.nice.is_some_and(|nice| nice < 0) {
+            capabilities.push("CAP_SYS_NICE");
+        }
+        if config.io_class == Some(IoClass::Realtime) {
+            capabilities.push("CAP_SYS_ADMIN");
+        }
+        let _ = writeln!(unit, "CapabilityBoundingSet={}", capabilities.join(" "));
+
+        for path in writable {
+            let _ = writeln!(
+                unit,
+                "ReadWritePaths={}",
+                quote(&format!("-{}", path.display()))
+            );
+        }
+    }
+
+    let _ = writeln!(unit);
+    let _ = writeln!(unit, "[Install]");
+    let _ = writeln!(unit, "WantedBy=multi-user.target");
+
+    unit
+}
+
+/// Every local directory `config` writes to: the watched ones, and those
+/// holding the files it keeps.
+fn writable(config: &Config) -> BTreeSet<PathBuf> {
+    let mut directories = Vec::new();
+    let mut files = Vec::new();
+
+    directories.extend(config.lock_dir.clone());
+    files.extend(config.status_file.clone());
+    files.extend(config.control_socket.clone());
+    files.extend(config.log_file.clone());
+    files.extend(config.pid_file.clone());
+
+    for watch in &config.directories {
+        if !storage::is_remote(&watch.directory) {
+            directories.push(PathBuf::from(&watch.directory));
+        }
+
+        directories.extend(watch.archive_dir.clone());
+        directories.extend(watch.quarantine_dir.clone());
+        directories.extend(watch.scan_cache_dir.clone());
+        files.extend(watch.audit_log.clone());
+        files.extend(watch.history_db.clone());
+    }
+
+    // Files are replaced through a temporary file next to them
+    directories.extend(
+        files
+            .iter()
+            .filter_map(|file| file.parent())
+            .map(Path::to_path_buf),
+    );
+
+    directories
+        .into_iter()
+        .filter(|directory| !directory.as_os_str().is_empty())
+        .map(|directory| std::path::absolute(&directory).unwrap_or(directory))
+        .collect()
+}
+
+/// Quotes `value` for a unit file when needed, with specifiers escaped.
+fn quote(value: &str) -> String {
+    let escaped = value.replace('%', "%%");
+
+    match escaped.contains(|c: char| c.is_whitespace() || matches!(c, '"' | '\'' | '\\' | ';')) {
+        true => format!("\"{}\"", escaped.replace('\\', "\\\\").replace('"', "\\\"")),
+        false => escaped,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn units_carry_the_configuration_and_sandbox_it() {
+        let config: Config = toml::from_str(
+            "lock_dir = \"/var/lib/maxdirsize\"\n\
+             status_file = \"/run/maxdirsize/status.json\"\n\
+             [[directories]]\n\
+             directory = \"/data/uploads\"\n\
+             max_size_bytes = 100\n\
+             history_db = \"/var/lib/maxdirsize/history.db\"\n\
+             [[directories]]\n\
+             directory = \"s3://bucket/prefix\"\n\
+             max_size_bytes = 100",
+        )
+        .unwrap();
+        let invocation = Invocation {
+            executable: PathBuf::from("/usr/local/bin/maxdirsize"),
+            working_directory: PathBuf::from("/"),
+            config: Some(PathBuf::from("/etc/maxdirsize.toml")),
+            environment: vec![
+                ("MAX_SIZE".to_string(), "1GB".to_string()),
+                ("QUIET_HOURS".to_string(), "mon-fri 08:00-20:00".to_string()),
+            ],
+            secrets: vec!["SMTP_PASSWORD".to_string()],
+        };
+
+        let unit = render(&invocation, &config, false);
+        assert!(
+            unit.contains("ExecStart=/usr/local/bin/maxdirsize --config /etc/maxdirsize.toml\n")
+        );
+        assert!(unit.contains("Environment=MAX_SIZE=1GB\n"));
+        assert!(unit.contains("Environment=\"QUIET_HOURS=mon-fri 08:00-20:00\"\n"));
+        assert!(unit.contains("set in an EnvironmentFile= only root can read: SMTP_PASSWORD\n"));
+        assert!(!unit.contains("ProtectSystem"));
+
+        let unit = render(&invocation, &config, true);
+        assert!(unit.contains("ProtectSystem=strict\n"));
+        assert!(unit.contains("PrivateTmp=yes\n"));
+        let writable: Vec<&str> = unit
+            .lines()
+            .filter_map(|line| line.strip_prefix("ReadWritePaths="))
+            .collect();
+        assert_eq!(
+            writable,
+            ["-/data/uploads", "-/run/maxdirsize", "-/var/lib/maxdirsize"]
+        );
+
+        assert_eq!(quote("100%"), "100%%");
+        assert_eq!(quote("a \"b\""), "\"a \\\"b\\\"\"");
+    }
+}
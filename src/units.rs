@@ -0,0 +1,216 @@
+//! Human readable sizes (`250GB`, `1.5TiB`) and durations (`15m`, `1h30m`).
+//!
+//! Sizes use decimal multiples for `KB`/`MB`/`GB`/`TB` and binary ones for
+//! `KiB`/`MiB`/`GiB`/`TiB` as well as the bare `K`/`M`/`G`/`T` suffixes. A
+//! number without a suffix is bytes for sizes and seconds for durations.
+
+use serde::de::{self, Deserializer};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+
+pub const MIB: u64 = 1024 * 1024;
+
+/// The longest duration accepted, 100 years, well within what an `Instant`
+/// can be moved by.
+pub const MAX_SECONDS: u64 = 100 * 365 * 24 * 60 * 60;
+
+pub fn parse_size(value: &str) -> Result<u64, String> {
+    let value = value.trim();
+    let split = value
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+
+    let number: f64 = number
+        .parse()
+        .map_err(|_| format!("invalid size `{value}`"))?;
+
+    let multiplier: u64 = match unit.trim().to_ascii_lowercase().as_str() {
+        "" | "b" => 1,
+        "kb" => 1000,
+        "mb" => 1000_u64.pow(2),
+        "gb" => 1000_u64.pow(3),
+        "tb" => 1000_u64.pow(4),
+        "k" | "kib" => 1024,
+        "m" | "mib" => 1024_u64.pow(2),
+        "g" | "gib" => 1024_u64.pow(3),
+        "t" | "tib" => 1024_u64.pow(4),
+        _ => return Err(format!("invalid size unit in `{value}`")),
+    };
+
+    Ok((number * multiplier as f64).round() as u64)
+}
+
+/// `bytes` in the largest decimal unit it reaches, e.g. `3.2 GB`.
+pub fn human(bytes: u64) -> String {
+    let (unit, name) = decimal_unit(bytes);
+    format!("{:.1} {name}", bytes as f64 / unit as f64)
+}
+
+/// The largest decimal unit `bytes` reaches, and its name.
+pub fn decimal_unit(bytes: u64) -> (u64, &'static str) {
+    [
+        (1000_u64.pow(4), "TB"),
+        (1000_u64.pow(3), "GB"),
+        (1000_u64.pow(2), "MB"),
+        (1000, "KB"),
+    ]
+    .into_iter()
+    .find(|&(unit, _)| bytes >= unit)
+    .unwrap_or((1, "B"))
+}
+
+/// Parses a duration into whole seconds. Units can be combined, e.g. `1h30m`.
+/// Durations beyond [`MAX_SECONDS`] are refused.
+pub fn parse_seconds(value: &str) -> Result<u64, String> {
+    let value = value.trim();
+
+    if let Ok(seconds) = value.parse::<u64>() {
+        return within_range(seconds, value);
+    }
+
+    let mut total: u64 = 0;
+    let mut rest = value;
+
+    while !rest.is_empty() {
+        let split = rest
+            .find(|c: char| !c.is_ascii_digit())
+            .ok_or_else(|| format!("missing unit in duration `{value}`"))?;
+        let (number, tail) = rest.split_at(split);
+        let number: u64 = number
+            .parse()
+            .map_err(|e: std::num::ParseIntError| match e.kind() {
+                std::num::IntErrorKind::PosOverflow => out_of_range(value),
+                _ => format!("invalid duration `{value}`"),
+            })?;
+
+        let unit_len = tail
+            .find(|c: char| c.is_ascii_digit())
+            .unwrap_or(tail.len());
+        let (unit, tail) = tail.split_at(unit_len);
+
+        let multiplier = match unit.trim() {
+            "s" | "sec" | "secs" => 1,
+            "m" | "min" | "mins" => 60,
+            "h" | "hour" | "hours" => 60 * 60,
+            "d" | "day" | "days" => 24 * 60 * 60,
+            "w" | "week" | "weeks" => 7 * 24 * 60 * 60,
+            _ => return Err(format!("invalid duration unit in `{value}`")),
+        };
+
+        total = number
+            .checked_mul(multiplier)
+            .and_then(|seconds| total.checked_add(seconds))
+            .ok_or_else(|| out_of_range(value))?;
+        rest = tail.trim_start();
+    }
+
+    within_range(total, value)
+}
+
+fn within_range(seconds: u64, value: &str) -> Result<u64, String> {
+    match seconds <= MAX_SECONDS {
+        true => Ok(seconds),
+        false => Err(out_of_range(value)),
+    }
+}
+
+fn out_of_range(value: &str) -> String {
+    format!("duration out of range `{value}`, at most 100 years")
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum NumberOrString {
+    Number(u64),
+    String(String),
+}
+
+/// Deserializes an optional size given either as a number of bytes or a
+/// string.
+pub fn deserialize_optional_size<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<u64>, D::Error> {
+    match Option::<NumberOrString>::deserialize(deserializer)? {
+        None => Ok(None),
+        Some(NumberOrString::Number(bytes)) => Ok(Some(bytes)),
+        Some(NumberOrString::String(value)) => {
+            parse_size(&value).map(Some).map_err(de::Error::custom)
+        }
+    }
+}
+
+/// Deserializes a map of sizes, each given either as a number of bytes or a
+/// string.
+pub fn deserialize_sizes<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<BTreeMap<String, u64>, D::Error> {
+    BTreeMap::<String, NumberOrString>::deserialize(deserializer)?
+        .into_iter()
+        .map(|(key, value)| {
+            let bytes = match value {
+                NumberOrString::Number(bytes) => bytes,
+                NumberOrString::String(value) => parse_size(&value).map_err(de::Error::custom)?,
+            };
+
+            Ok((key, bytes))
+        })
+        .collect()
+}
+
+/// Deserializes a duration given either as a number of seconds or a string.
+pub fn deserialize_seconds<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u64, D::Error> {
+    match NumberOrString::deserialize(deserializer)? {
+        NumberOrString::Number(seconds) => {
+            within_range(seconds, &seconds.to_string()).map_err(de::Error::custom)
+        }
+        NumberOrString::String(value) => parse_seconds(&value).map_err(de::Error::custom),
+    }
+}
+
+/// Deserializes an optional duration given either as a number of seconds or
+/// a string.
+pub fn deserialize_optional_seconds<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<u64>, D::Error> {
+    match Option::<NumberOrString>::deserialize(deserializer)? {
+        None => Ok(None),
+        Some(NumberOrString::Number(seconds)) => within_range(seconds, &seconds.to_string())
+            .map(Some)
+            .map_err(de::Error::custom),
+        Some(NumberOrString::String(value)) => {
+            parse_seconds(&value).map(Some).map_err(de::Error::custom)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sizes() {
+        assert_eq!(parse_size("128"), Ok(128));
+        assert_eq!(parse_size("250GB"), Ok(250_000_000_000));
+        assert_eq!(parse_size("1.5TiB"), Ok(1_649_267_441_664));
+        assert_eq!(parse_size("10 M"), Ok(10 * MIB));
+        assert!(parse_size("10 parsecs").is_err());
+    }
+
+    #[test]
+    fn durations() {
+        assert_eq!(parse_seconds("60"), Ok(60));
+        assert_eq!(parse_seconds("15m"), Ok(900));
+        assert_eq!(parse_seconds("1h30m"), Ok(5400));
+        assert_eq!(parse_seconds("7d"), Ok(604_800));
+        assert!(parse_seconds("15").is_ok());
+        assert!(parse_seconds("15x").is_err());
+        assert_eq!(
+            parse_seconds("99999999999999999w"),
+            Err("duration out of range `99999999999999999w`, at most 100 years".to_string())
+        );
+        assert!(parse_seconds("999999999999999999999s").is_err());
+        assert!(parse_seconds(&u64::MAX.to_string()).is_err());
+        assert_eq!(parse_seconds("5200w"), Ok(5200 * 604_800));
+    }
+}
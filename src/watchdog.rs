@@ -0,0 +1,57 @@
+//! Telling about cycles running past `CYCLE_TIMEOUT`. A cycle aborts itself
+//! at the next directory or file it gets to once past its deadline, but a
+//! call blocked on a broken mount never returns to check it, so a watchdog
+//! thread keeps logging that the cycle is stuck for as long as it is. The
+//! daemon gives up waiting on it meanwhile and cleans up other directories.
+
+use colored::*;
+use log::error;
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+/// Watches a cycle until it is dropped, when the cycle finished.
+pub struct Watchdog {
+    done: Option<Sender<()>>,
+    worker: Option<JoinHandle<()>>,
+}
+
+impl Watchdog {
+    /// Logs an error once the cycle of `directory` runs for `timeout`, and
+    /// again after every further `timeout`.
+    pub fn start(directory: String, timeout: Duration) -> Watchdog {
+        let (done, finished) = mpsc::channel::<()>();
+
+        let worker = std::thread::spawn(move || {
+            let mut overdue = timeout;
+
+            while let Err(RecvTimeoutError::Timeout) = finished.recv_timeout(timeout) {
+                error!(
+                    event = "cycle_stuck", directory:% = directory, seconds = overdue.as_secs();
+                    "{}",
+                    format!(
+                        "Cycle of {directory} still running after {}s, past CYCLE_TIMEOUT; it aborts once the operation in progress returns",
+                        overdue.as_secs()
+                    )
+                    .red()
+                );
+                overdue += timeout;
+            }
+        });
+
+        Watchdog {
+            done: Some(done),
+            worker: Some(worker),
+        }
+    }
+}
+
+impl Drop for Watchdog {
+    fn drop(&mut self) {
+        drop(self.done.take());
+
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+    }
+}
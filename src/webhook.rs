@@ -0,0 +1,447 @@
+//! Posting cleanup events to a webhook as JSON, either as is or shaped as
+//! a Slack, Discord or Teams message.
+
+use colored::*;
+use log::warn;
+use serde_json::Value;
+use std::sync::mpsc::{self, Sender};
+use std::thread::JoinHandle;
+use std::time::{Duration, SystemTime};
+
+use crate::config::NotifyFormat;
+use crate::notification::{Event, Notification};
+use crate::APP_NAME;
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Characters of the utilization bar in chat messages.
+const BAR_WIDTH: usize = 20;
+
+/// Posts events to a URL in order on a background thread, so a slow
+/// endpoint never holds up a cleanup. Dropping it waits for the events
+/// already sent.
+pub struct Webhook {
+    url: String,
+    format: NotifyFormat,
+    sender: Option<Sender<Value>>,
+    worker: Option<JoinHandle<()>>,
+}
+
+impl Webhook {
+    pub fn new(url: &str, format: NotifyFormat) -> Webhook {
+        let (sender, receiver) = mpsc::channel::<Value>();
+        let agent = ureq::AgentBuilder::new().timeout(TIMEOUT).build();
+        let target = url.to_string();
+
+        let worker = std::thread::spawn(move || {
+            for payload in receiver {
+                let result = agent
+                    .post(&target)
+                    .set("Content-Type", "application/json")
+                    .send_string(&payload.to_string());
+
+                if let Err(e) = result {
+                    warn!(
+                        event = "webhook_failed", url = target.as_str();
+                        "{}",
+                        format!("Error posting to webhook {target}: {e}").yellow()
+                    );
+                }
+            }
+        });
+
+        Webhook {
+            url: url.to_string(),
+            format,
+            sender: Some(sender),
+            worker: Some(worker),
+        }
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    pub fn format(&self) -> NotifyFormat {
+        self.format
+    }
+
+    /// Queues `event` for posting.
+    pub fn send(&self, event: Event, notification: &Notification) {
+        let payload = match self.format {
+            NotifyFormat::Json => payload(event, notification),
+            NotifyFormat::Slack => slack(event, notification),
+            NotifyFormat::Discord => discord(event, notification),
+            NotifyFormat::Teams => teams(event, notification),
+        };
+
+        if let Some(sender) = &self.sender {
+            let _ = sender.send(payload);
+        }
+    }
+}
+
+impl Drop for Webhook {
+    fn drop(&mut self) {
+        self.sender.take();
+
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+    }
+}
+
+fn payload(event: Event, notification: &Notification) -> Value {
+    serde_json::json!({
+        "event": event,
+        "timestamp": humantime::format_rfc3339_millis(SystemTime::now()).to_string(),
+        "directory": notification.directory,
+        "size_bytes": notification.size_bytes,
+        "limit_bytes": notification.limit_bytes,
+        "utilization_percent": match notification.limit_bytes {
+            0 => None,
+            limit => Some(notification.size_bytes as f64 * 100.0 / limit as f64),
+        },
+        "files": notification.files,
+        "files_deleted": notification.files_deleted,
+        "dirs_deleted": notification.dirs_deleted,
+        "bytes_freed": notification.bytes_freed,
+        "errors": notification.errors,
+        "dry_run": notification.dry_run,
+        "error": notification.error,
+        "seconds_to_full": notification.seconds_to_full,
+        "largest_removed": notification
+            .largest_removed
+            .iter()
+            .map(|(path, size)| serde_json::json!({ "path": path, "size": size }))
+            .collect::<Vec<_>>(),
+    })
+}
+
+fn megabytes(bytes: u64) -> String {
+    format!("{:.2} MB", bytes as f64 / 1024.0 / 1024.0)
+}
+
+/// One-line summary of `event`, the title of chat messages.
+fn title(event: Event, notification: &Notification) -> String {
+    let directory = notification.directory;
+    let dry_run = match notification.dry_run {
+        true => " (dry run)",
+        false => "",
+    };
+
+    match event {
+        Event::CleanupStarted => format!("{APP_NAME} is cleaning up {directory}{dry_run}"),
+        Event::CleanupFinished => format!(
+            "{APP_NAME} {} {} files from {directory}, freeing {}{dry_run}",
+            match notification.dry_run {
+                true => "would have removed",
+                false => "removed",
+            },
+            notification.files_deleted,
+            megabytes(notification.bytes_freed)
+        ),
+        Event::CleanupFailed => format!("{APP_NAME} failed to clean up {directory}"),
+        Event::LimitApproaching => format!(
+            "{directory} will reach its limit in {}",
+            humantime::format_duration(Duration::from_secs(
+                notification.seconds_to_full.unwrap_or_default()
+            ))
+        ),
+    }
+}
+
+/// `[████████░░░░] 66.7% of 1.00 MB`, how full the directory is.
+fn utilization(notification: &Notification) -> String {
+    if notification.limit_bytes == 0 {
+        return format!("{}, no size limit", megabytes(notification.size_bytes));
+    }
+
+    let percent = notification.size_bytes as f64 * 100.0 / notification.limit_bytes as f64;
+    let filled = ((percent / 100.0 * BAR_WIDTH as f64).round() as usize).min(BAR_WIDTH);
+
+    format!(
+        "[{}{}] {percent:.1}% of {}",
+        "█".repeat(filled),
+        "░".repeat(BAR_WIDTH - filled),
+        megabytes(notification.limit_bytes)
+    )
+}
+
+/// Name and value of every figure worth showing for `event`.
+fn facts(event: Event, notification: &Notification) -> Vec<(&'static str, String)> {
+    let mut facts = vec![
+        ("Size", megabytes(notification.size_bytes)),
+        ("Files", notification.files.to_string()),
+    ];
+
+    if event == Event::CleanupFinished {
+        facts.push((
+            "Removed",
+            format!(
+                "{} files and {} directories",
+                notification.files_deleted, notification.dirs_deleted
+            ),
+        ));
+        facts.push(("Freed", megabytes(notification.bytes_freed)));
+    }
+
+    if notification.errors > 0 {
+        facts.push(("Errors", notification.errors.to_string()));
+    }
+
+    if let Some(error) = &notification.error {
+        facts.push(("Error", error.clone()));
+    }
+
+    facts
+}
+
+/// The largest files removed as lines of `path (size)`.
+fn largest_removed(notification: &Notification) -> Option<String> {
+    if notification.largest_removed.is_empty() {
+        return None;
+    }
+
+    Some(
+        notification
+            .largest_removed
+            .iter()
+            .map(|(path, size)| format!("{path} ({})", megabytes(*size)))
+            .collect::<Vec<_>>()
+            .join("\n"),
+    )
+}
+
+/// Colour of `event` in Discord embeds, as RGB.
+fn color(event: Event) -> u32 {
+    match event {
+        Event::CleanupFinished => 0x2eb67d,
+        Event::CleanupStarted | Event::LimitApproaching => 0xecb22e,
+        Event::CleanupFailed => 0xe01e5a,
+    }
+}
+
+/// A Slack message in Block Kit, `text` being the fallback of
+/// notifications.
+fn slack(event: Event, notification: &Notification) -> Value {
+    let title = title(event, notification);
+    let facts: Vec<String> = facts(event, notification)
+        .into_iter()
+        .map(|(name, value)| format!("*{name}:* {value}"))
+        .collect();
+
+    let mut blocks = vec![
+        serde_json::json!({
+            "type": "section",
+            "text": { "type": "mrkdwn", "text": format!("*{title}*\n`{}`", utilization(notification)) },
+        }),
+        serde_json::json!({
+            "type": "section",
+            "text": { "type": "mrkdwn", "text": facts.join("\n") },
+        }),
+    ];
+
+    if let Some(largest) = largest_removed(notification) {
+        blocks.push(serde_json::json!({
+            "type": "section",
+            "text": { "type": "mrkdwn", "text": format!("*Largest removed*\n```{largest}```") },
+        }));
+    }
+
+    serde_json::json!({ "text": title, "blocks": blocks })
+}
+
+fn discord(event: Event, notification: &Notification) -> Value {
+    let mut fields: Vec<Value> = facts(event, notification)
+        .into_iter()
+        .map(|(name, value)| serde_json::json!({ "name": name, "value": value, "inline": true }))
+        .collect();
+
+    if let Some(largest) = largest_removed(notification) {
+        fields.push(serde_json::json!({
+            "name": "Largest removed",
+            "value": format!("```{largest}```"),
+            "inline": false,
+        }));
+    }
+
+    serde_json::json!({
+        "username": APP_NAME,
+        "embeds": [{
+            "title": title(event, notification),
+            "description": format!("`{}`", utilization(notification)),
+            "color": color(event),
+            "fields": fields,
+            "timestamp": humantime::format_rfc3339_millis(SystemTime::now()).to_string(),
+        }],
+    })
+}
+
+/// A message holding an Adaptive Card, as taken by the Workflows webhooks
+/// of Teams channels.
+fn teams(event: Event, notification: &Notification) -> Value {
+    let mut body = vec![
+        serde_json::json!({
+            "type": "TextBlock",
+            "text": title(event, notification),
+            "weight": "Bolder",
+            "size": "Medium",
+            "wrap": true,
+        }),
+        serde_json::json!({
+            "type": "TextBlock",
+            "text": utilization(notification),
+            "fontType": "Monospace",
+        }),
+        serde_json::json!({
+            "type": "FactSet",
+            "facts": facts(event, notification)
+                .into_iter()
+                .map(|(name, value)| serde_json::json!({ "title": name, "value": value }))
+                .collect::<Vec<_>>(),
+        }),
+    ];
+
+    if let Some(largest) = largest_removed(notification) {
+        body.push(serde_json::json!({
+            "type": "TextBlock",
+            "text": "Largest removed",
+            "weight": "Bolder",
+        }));
+        body.push(serde_json::json!({
+            "type": "TextBlock",
+            "text": largest.replace('\n', "\n\n"),
+            "fontType": "Monospace",
+            "wrap": true,
+        }));
+    }
+
+    serde_json::json!({
+        "type": "message",
+        "attachments": [{
+            "contentType": "application/vnd.microsoft.card.adaptive",
+            "content": {
+                "$schema": "http://adaptivecards.io/schemas/adaptive-card.json",
+                "type": "AdaptiveCard",
+                "version": "1.4",
+                "body": body,
+            },
+        }],
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader, Read};
+    use std::net::TcpListener;
+
+    #[test]
+    fn events_are_posted_in_order() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+
+        let server = std::thread::spawn(move || {
+            let mut bodies = Vec::new();
+
+            for stream in listener.incoming().take(2) {
+                let mut stream = BufReader::new(stream.unwrap());
+                let mut length = 0;
+
+                loop {
+                    let mut line = String::new();
+                    stream.read_line(&mut line).unwrap();
+
+                    if let Some(value) = line.to_lowercase().strip_prefix("content-length:") {
+                        length = value.trim().parse().unwrap();
+                    }
+
+                    if line == "\r\n" {
+                        break;
+                    }
+                }
+
+                let mut body = vec![0; length];
+                stream.read_exact(&mut body).unwrap();
+                std::io::Write::write_all(
+                    stream.get_mut(),
+                    b"HTTP/1.1 204 No Content\r\nConnection: close\r\n\r\n",
+                )
+                .unwrap();
+                bodies.push(serde_json::from_slice::<Value>(&body).unwrap());
+            }
+
+            bodies
+        });
+
+        let webhook = Webhook::new(&url, NotifyFormat::Json);
+        let notification = Notification {
+            directory: "/data",
+            size_bytes: 150,
+            limit_bytes: 100,
+            ..Default::default()
+        };
+        webhook.send(Event::CleanupStarted, &notification);
+        webhook.send(
+            Event::CleanupFinished,
+            &Notification {
+                size_bytes: 80,
+                files_deleted: 2,
+                bytes_freed: 70,
+                ..notification
+            },
+        );
+        drop(webhook);
+
+        let bodies = server.join().unwrap();
+        assert_eq!(bodies[0]["event"], "cleanup_started");
+        assert_eq!(bodies[0]["utilization_percent"], 150.0);
+        assert_eq!(bodies[1]["event"], "cleanup_finished");
+        assert_eq!(bodies[1]["files_deleted"], 2);
+        assert_eq!(bodies[1]["bytes_freed"], 70);
+    }
+
+    #[test]
+    fn chat_messages_show_utilization_and_largest_removals() {
+        let notification = Notification {
+            directory: "/data",
+            size_bytes: 3 * 1024 * 1024,
+            limit_bytes: 4 * 1024 * 1024,
+            files_deleted: 2,
+            bytes_freed: 2 * 1024 * 1024,
+            largest_removed: vec![
+                ("/data/a.log".to_string(), 1536 * 1024),
+                ("/data/b.log".to_string(), 512 * 1024),
+            ],
+            ..Default::default()
+        };
+        let bar = "[███████████████░░░░░] 75.0% of 4.00 MB";
+
+        let slack = slack(Event::CleanupFinished, &notification);
+        assert_eq!(
+            slack["text"],
+            "maxdirsize removed 2 files from /data, freeing 2.00 MB"
+        );
+        assert!(slack["blocks"][0]["text"]["text"]
+            .as_str()
+            .unwrap()
+            .contains(bar));
+        assert!(slack["blocks"][2]["text"]["text"]
+            .as_str()
+            .unwrap()
+            .contains("/data/a.log (1.50 MB)\n/data/b.log (0.50 MB)"));
+
+        let discord = discord(Event::CleanupFinished, &notification);
+        assert_eq!(discord["embeds"][0]["description"], format!("`{bar}`"));
+        assert_eq!(discord["embeds"][0]["color"], 0x2eb67d);
+
+        let teams = teams(Event::CleanupFailed, &notification);
+        let card = &teams["attachments"][0]["content"];
+        assert_eq!(
+            card["body"][0]["text"],
+            "maxdirsize failed to clean up /data"
+        );
+        assert_eq!(card["body"][2]["facts"][0]["value"], "3.00 MB");
+    }
+}